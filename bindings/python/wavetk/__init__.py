@@ -1,8 +1,9 @@
 import ctypes
 import enum
 import json
+import os
 from ctypes import POINTER, byref
-from typing import List, Optional, Tuple
+from typing import Any, Dict, Iterator, List, Optional, Tuple
 
 import numpy as np
 import pkg_resources
@@ -27,12 +28,34 @@ def load_wavetk_lib(debug=False):
 
     # Some type info are needed for the API to be properly working
     lib.wavetk_version.restype = ctypes.c_uint32
+    lib.wavetk_features.restype = ctypes.c_uint32
+    lib.wavetk_logic_level_name.argtypes = (ctypes.c_int8,)
+    lib.wavetk_logic_level_name.restype = ctypes.c_char_p
 
     lib.wave_sim_create.argtypes = (ctypes.c_char_p, POINTER(ctypes.c_int32),)
     lib.wave_sim_create.restype = POINTER(_StateSimS)
-
-    lib.wave_sim_header_info.restype = ctypes.c_char_p
-    lib.wave_str_destroy.argtypes = (ctypes.c_char_p,)
+    lib.wave_sim_create_bytes.argtypes = (ctypes.c_char_p, ctypes.c_size_t,
+                                          POINTER(ctypes.c_int32),)
+    lib.wave_sim_create_bytes.restype = POINTER(_StateSimS)
+    lib.wave_sim_set_demangler.argtypes = (POINTER(_StateSimS), ctypes.c_char_p)
+
+    # Strings allocated by Rust must be given back as-is, hence the c_void_p
+    lib.wave_sim_header_info.restype = ctypes.c_void_p
+    lib.wave_sim_aliases.restype = ctypes.c_void_p
+    lib.wave_str_destroy.argtypes = (ctypes.c_void_p,)
+    lib.wavetk_sim_layout.argtypes = (POINTER(_StateSimS),
+                                      POINTER(ctypes.c_void_p),
+                                      POINTER(ctypes.c_uint64),
+                                      POINTER(ctypes.c_uint64),
+                                      ctypes.c_size_t)
+    lib.wavetk_sim_layout.restype = ctypes.c_uint64
+    lib.wavetk_sim_layout_free.argtypes = (POINTER(ctypes.c_void_p),
+                                           ctypes.c_size_t)
+    lib.wavetk_sim_read_block.argtypes = (POINTER(_StateSimS),
+                                          ctypes.c_uint64,
+                                          ctypes.c_void_p,
+                                          ctypes.c_void_p,
+                                          POINTER(ctypes.c_uint64))
     _LIBS[cfg] = lib
     return lib
 
@@ -48,6 +71,14 @@ class Status(enum.IntEnum):
     PARTIAL_HEADER = 4
     UTF8_ERROR = 5
     END_OF_INPUT = 6
+    MEMORY_LIMIT = 7
+    UNKNOWN_VARIABLE = 8
+    INVALID_HEADER = 9
+    USER_ERROR = 10
+    INVALID_CHANGE = 11
+    NON_MONOTONIC_TIME = 12
+    TRUNCATED_HEADER = 13
+    INVALID_ARGUMENT = 14
     UNKNOWN = 255
 
 
@@ -137,7 +168,11 @@ class HeaderInfo(_ObjWrapper):
     def __init__(self, obj):
         super().__init__(obj)
         self.variables = {v[1]['id']: VariableInfo(v[1], offset=v[0]) for v in
-                          self.obj.values()}
+                          self.obj['variables'].values()}
+        #: Text of the `$date` and `$version` directives of the header (None
+        #: when absent) and list of its top-level `$comment` directives, under
+        #: the keys `date`, `version` and `comments`
+        self.metadata: Dict[str, Any] = self.obj['metadata']
 
     @property
     def state_variables(self) -> List[VariableInfo]:
@@ -145,7 +180,8 @@ class HeaderInfo(_ObjWrapper):
         Returns the list of variables that appears in the state (were not
         excluded explicitly)
         """
-        variables = [VariableInfo(x[1], offset=x[0]) for x in self.obj.values()
+        variables = [VariableInfo(x[1], offset=x[0])
+                     for x in self.obj['variables'].values()
                      if x[0] is not None]
         variables.sort(key=lambda x: x.offset)
         return variables
@@ -155,8 +191,10 @@ class StateSim:
     def __init__(self, filename, lib=None):
         self.lib = lib or load_wavetk_lib(debug=False)
         status = ctypes.c_int32(0)
-        self.handle = self.lib.wave_sim_create(filename.encode('utf-8'),
-                                               ctypes.byref(status))
+        # os.fsencode gives back the raw OS path, which may not be valid UTF-8
+        path = os.fsencode(filename)
+        self.handle = self.lib.wave_sim_create_bytes(path, len(path),
+                                                     ctypes.byref(status))
         if not self.handle:
             raise WaveError(Status(status.value),
                             "unable to create StateSim instance")
@@ -170,11 +208,47 @@ class StateSim:
         v = self.lib.wavetk_version()
         return (v >> 16) & 255, (v >> 8) & 255, v & 255
 
+    def lib_features(self) -> 'Feature':
+        """
+        Returns the optional features compiled in the native library
+        """
+        return Feature(self.lib.wavetk_features())
+
     def load_header(self):
         status = Status(self.lib.wave_sim_load_header(self.handle))
         if status != Status.OK:
             raise WaveError(status, "unable to load header")
 
+    def set_memory_limit(self, limit: Optional[int]):
+        """Bounds the memory (in bytes) used by the simulation state and
+        parser buffers, exceeding it raises a MEMORY_LIMIT error instead of
+        allocating. None (or 0) removes the limit.
+        """
+        status = Status(self.lib.wave_sim_set_memory_limit(
+            self.handle, ctypes.c_uint64(limit or 0)))
+        if status != Status.OK:
+            raise WaveError(status, "unable to set memory limit")
+
+    def set_demangler(self, rules: str):
+        """Sets the rules rewriting the hierarchical names of the variables
+        (e.g. ``"verilator"`` or ``"unescape,strip:tb"``), to be called before
+        ``allocate_state``.
+        """
+        status = Status(self.lib.wave_sim_set_demangler(
+            self.handle, rules.encode()))
+        if status != Status.OK:
+            raise WaveError(status, f"invalid demangling rules: {rules}")
+
+    def set_initial_fill(self, level: int):
+        """Sets the level held by the variables until the dump sets them
+        (``LogicLevel.L0`` by default, e.g. ``LogicLevel.X``), to be called
+        before ``allocate_state``.
+        """
+        status = Status(self.lib.wave_sim_set_initial_fill(
+            self.handle, ctypes.c_int8(level)))
+        if status != Status.OK:
+            raise WaveError(status, f"invalid logic level: {level}")
+
     def _get_state_buffer(self) -> np.ndarray:
         p = ctypes.c_void_p()
         size = ctypes.c_uint64(0)
@@ -184,6 +258,37 @@ class StateSim:
             raise WaveError(status, "unable to get state buffer")
         return _raw_numpy_array(p.value, "<i1", (int(size.value),))
 
+    def _get_dirty_bitmap(self) -> np.ndarray:
+        p = ctypes.c_void_p()
+        size = ctypes.c_uint64(0)
+        status = Status(self.lib.wavetk_sim_dirty_bitmap(self.handle, byref(p),
+                                                         byref(size)))
+        if status != Status.OK:
+            raise WaveError(status, "unable to get dirty bitmap")
+        return _raw_numpy_array(p.value, "<u8", (int(size.value),))
+
+    def dirty_bitmap(self) -> np.ndarray:
+        """Boolean mask of the tracked variables (sorted by offset in the
+        state) whose value changed during the last call to ``next_cycle``.
+
+        The mask is padded with False up to a multiple of 64 entries.
+        """
+        words = self._get_dirty_bitmap()
+        return np.unpackbits(words.view(np.uint8), bitorder="little").astype(bool)
+
+    def assigned_bitmap(self) -> np.ndarray:
+        """Boolean mask of the tracked variables (sorted by offset in the
+        state) set at least once by the dump, padded like ``dirty_bitmap``.
+        """
+        p = ctypes.c_void_p()
+        size = ctypes.c_uint64(0)
+        status = Status(self.lib.wavetk_sim_assigned_bitmap(
+            self.handle, byref(p), byref(size)))
+        if status != Status.OK:
+            raise WaveError(status, "unable to get assigned bitmap")
+        words = _raw_numpy_array(p.value, "<u8", (int(size.value),))
+        return np.unpackbits(words.view(np.uint8), bitorder="little").astype(bool)
+
     def allocate_state(self, restrict=None):
         p = None
         n = ctypes.c_size_t(0)
@@ -197,6 +302,32 @@ class StateSim:
             raise WaveError(status, "unable to allocate simulation state")
         self._state_buff = self._get_state_buffer()
 
+    def layout(self) -> List[Tuple[str, int, int]]:
+        """Returns the (name, offset, width) of each tracked variable, sorted
+        by offset in the state buffer.
+        """
+        n = self.lib.wavetk_sim_layout(self.handle, None, None, None, 0)
+        names = (ctypes.c_void_p * n)()
+        offsets = (ctypes.c_uint64 * n)()
+        widths = (ctypes.c_uint64 * n)()
+        self.lib.wavetk_sim_layout(self.handle, names, offsets, widths, n)
+        try:
+            return [(ctypes.string_at(names[i]).decode('utf-8'), offsets[i],
+                     widths[i]) for i in range(n)]
+        finally:
+            self.lib.wavetk_sim_layout_free(names, n)
+
+    def state_dtype(self) -> np.dtype:
+        """Structured dtype over the state buffer, with one field per tracked
+        variable (e.g., ``sim.state.view(sim.state_dtype())``).
+        """
+        layout = self.layout()
+        size = max((o + w for _, o, w in layout), default=0)
+        return np.dtype({'names': [x[0] for x in layout],
+                         'formats': [(np.int8, (x[2],)) for x in layout],
+                         'offsets': [x[1] for x in layout],
+                         'itemsize': size})
+
     def header_info(self) -> HeaderInfo:
         """Query waveform header information
 
@@ -208,11 +339,31 @@ class StateSim:
         try:
             s = self.lib.wave_sim_header_info(self.handle)
             if not s:
-                WaveError(Status.UNKNOWN, "unable to get header info")
-            return HeaderInfo(json.loads(s))
+                raise WaveError(Status.UNKNOWN, "unable to get header info")
+            return HeaderInfo(json.loads(ctypes.string_at(s)))
         finally:
             self.lib.wave_str_destroy(s)
 
+    def aliases(self) -> Dict[str, List[str]]:
+        """Returns, for each VCD id, the hierarchical names of the variables it
+        drives. Ids listed with several names are aliases of the same net.
+        """
+        s = None
+        try:
+            s = self.lib.wave_sim_aliases(self.handle)
+            if not s:
+                raise WaveError(Status.UNKNOWN, "unable to get aliases")
+            return json.loads(ctypes.string_at(s))
+        finally:
+            self.lib.wave_str_destroy(s)
+
+    def metadata(self) -> Dict[str, Any]:
+        """Returns the text of the `$date` and `$version` directives of the
+        header (None when absent) and the list of its top-level `$comment`
+        directives, under the keys `date`, `version` and `comments`.
+        """
+        return self.header_info().metadata
+
     def next_cycle(self) -> Optional[Tuple[int, np.ndarray]]:
         """
         Runs the parser until the end of the next simulation cycle (or
@@ -243,9 +394,175 @@ class StateSim:
         t = _raw_numpy_array(p.value, "<i1", (int(size.value),))
         return cycle.value, t
 
+    def __iter__(self) -> Iterator[Tuple[int, np.ndarray]]:
+        """Iterates over the remaining cycles, as ``for t, state in sim:``.
+
+        .. warning::
+
+            As for :py:meth:`next_cycle`, ``state`` is a read-only view on the
+            internal buffer: it is updated in place when iterating to the next
+            cycle, and invalidated by :py:meth:`allocate_state`. Copy it to
+            keep a value.
+        """
+        while True:
+            s = self.next_cycle()
+            if s is None:
+                return
+            yield s
+
+    def read_block(self, n: int) -> Tuple[np.ndarray, np.ndarray]:
+        """Runs up to ``n`` cycles in a single native call.
+
+        :return: the cycles (int64 array of length ``k``) and a copy of the
+                 state after each of them (int8 matrix of shape
+                 ``(k, state size)``), ``k`` is less than ``n`` only at the
+                 end of the file.
+        """
+        width = len(self._get_state_buffer())
+        cycles = np.empty(n, dtype=np.int64)
+        states = np.empty((n, width), dtype=np.int8)
+        count = ctypes.c_uint64(0)
+        status = Status(self.lib.wavetk_sim_read_block(
+            self.handle, ctypes.c_uint64(n), cycles.ctypes.data,
+            states.ctypes.data, byref(count)))
+        if status != Status.OK:
+            raise WaveError(status, "failed to read a block of cycles")
+        k = int(count.value)
+        return cycles[:k], states[:k]
+
+    def to_dataframe(self, signals: List[str], clock: Optional[str] = None,
+                     window: Optional[Tuple[int, int]] = None,
+                     block_size: int = 4096):
+        """Runs the simulation until the end (or the end of ``window``) and
+        returns the values of ``signals`` as integer columns of a DataFrame
+        (pandas when installed, Polars otherwise), with a ``time`` column.
+
+        Signals are designated by hierarchical name and must be tracked, the
+        leftmost bit of a value is the most significant one. Values with bits
+        other than 0 and 1 are missing (NA).
+
+        :param clock: when given, the signals are sampled on the rising edges
+                      of this 1-bit signal, with the value they held just
+                      before the edge. Otherwise there is one row per cycle.
+        :param window: (start, end) time range of the rows kept, end excluded
+        """
+        layout = {name: (offset, width) for name, offset, width in self.layout()}
+        for s in list(signals) + ([clock] if clock else []):
+            if s not in layout:
+                raise KeyError(f"signal not tracked: {s}")
+        start, end = window if window else (None, None)
+        times = []
+        rows = []
+        previous = None
+        while True:
+            cycles, states = self.read_block(block_size)
+            if not len(cycles):
+                break
+            if clock:
+                before = states[:-1]
+                if previous is not None:
+                    before = np.concatenate((previous[None], before))
+                    at = cycles
+                    after = states
+                else:
+                    at = cycles[1:]
+                    after = states[1:]
+                previous = states[-1].copy()
+                o, _ = layout[clock]
+                edges = (before[:, o] == 0) & (after[:, o] == 1)
+                block_times, block_rows = at[edges], before[edges]
+            else:
+                block_times, block_rows = cycles, states
+            keep = np.ones(len(block_times), dtype=bool)
+            if start is not None:
+                keep &= (block_times >= start) & (block_times < end)
+            times.append(block_times[keep])
+            rows.append(block_rows[keep])
+            if end is not None and cycles[-1] >= end:
+                break
+        width = len(self._get_state_buffer())
+        times = np.concatenate(times) if times else np.empty(0, np.int64)
+        rows = np.concatenate(rows) if rows else np.empty((0, width), np.int8)
+
+        columns = {s: _decode_columns(rows[:, layout[s][0]:sum(layout[s])])
+                   for s in signals}
+        try:
+            import pandas as pd
+        except ImportError:
+            import polars as pl
+            data = {'time': times}
+            for s, (values, valid) in columns.items():
+                data[s] = [int(x) if ok else None for x, ok in zip(values, valid)]
+            return pl.DataFrame(data)
+        data = {'time': times}
+        for s, (values, valid) in columns.items():
+            if values.dtype == object:
+                values = values.copy()
+                values[~valid] = None
+                data[s] = values
+            else:
+                data[s] = pd.arrays.IntegerArray(values, ~valid)
+        return pd.DataFrame(data)
+
     def __del__(self):
         self.lib.wave_sim_destroy(self.handle)
         self.handle = None
 
 
-BIT_REPR = {0: '0', 1: '1', -1: 'U', -2: 'W', -3: 'Z', -4: 'X'}
+def _decode_columns(bits: np.ndarray) -> Tuple[np.ndarray, np.ndarray]:
+    """Decodes each row of a matrix of logic levels as an unsigned integer
+    (leftmost bit first).
+
+    :return: the values (int64 when they fit, Python integers otherwise) and
+             whether each row holds only 0 and 1 (invalid values are 0).
+    """
+    valid = np.all((bits == 0) | (bits == 1), axis=1)
+    n, w = bits.shape
+    if w < 64:
+        weights = np.left_shift(np.int64(1),
+                                np.arange(w - 1, -1, -1, dtype=np.int64))
+        return (bits.astype(np.int64) * valid[:, None]) @ weights, valid
+    values = np.zeros(n, dtype=object)
+    for i in np.flatnonzero(valid):
+        values[i] = int("".join(map(str, bits[i])), 2)
+    return values, valid
+
+
+class Feature(enum.IntFlag):
+    """Optional features of the native library (see `StateSim.lib_features`)
+    """
+    FST = 1 << 0
+    GZIP = 1 << 1
+    ZSTD = 1 << 2
+    XZ = 1 << 3
+    BZIP2 = 1 << 4
+
+
+@enum.unique
+class LogicLevel(enum.IntEnum):
+    """Values of the state buffer (one signed byte per bit)
+    """
+    L0 = 0
+    L1 = 1
+    U = -1
+    W = -2
+    Z = -3
+    X = -4
+    OTHER = -5
+    # Dumping disabled ($dumpoff), distinct from X driven by the design
+    NO_DATA = -6
+    # Weak 0, weak 1 and don't care of IEEE 1164 (written by VHDL simulators)
+    L = -7
+    H = -8
+    DONT_CARE = -9
+
+
+def logic_level_name(level: int, debug=False) -> str:
+    """Readable name of a logic level, as given by the library
+    """
+    return load_wavetk_lib(debug).wavetk_logic_level_name(level).decode()
+
+
+BIT_REPR = {LogicLevel.L0: '0', LogicLevel.L1: '1', LogicLevel.U: 'U',
+            LogicLevel.W: 'W', LogicLevel.Z: 'Z', LogicLevel.X: 'X',
+            LogicLevel.L: 'L', LogicLevel.H: 'H', LogicLevel.DONT_CARE: '-'}
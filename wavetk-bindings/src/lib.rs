@@ -1,30 +1,79 @@
+#![allow(clippy::missing_safety_doc)]
+
+#[cfg(unix)]
+use std::ffi::OsStr;
+#[cfg(windows)]
+use std::ffi::OsString;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
+use std::path::Path;
 use std::ptr::null_mut;
 
 use std::num::ParseIntError;
 use std::slice;
+#[cfg(not(unix))]
+use std::str;
+use std::sync::OnceLock;
+use wavetk::hierarchy::Demangler;
+use wavetk::simulation;
 use wavetk::simulation::StateSimulation;
 use wavetk::vcd::VcdError;
 
-const VERSION_MAJOR: &'static str = env!("CARGO_PKG_VERSION_MAJOR");
-const VERSION_MINOR: &'static str = env!("CARGO_PKG_VERSION_MINOR");
-const VERSION_PATCH: &'static str = env!("CARGO_PKG_VERSION_PATCH");
+const VERSION_MAJOR: &str = env!("CARGO_PKG_VERSION_MAJOR");
+const VERSION_MINOR: &str = env!("CARGO_PKG_VERSION_MINOR");
+const VERSION_PATCH: &str = env!("CARGO_PKG_VERSION_PATCH");
 
 /// FFI error codes, encoded as an i32
 type WaveTkStatus = i32;
 
+/// Status of the calls given an invalid argument (after the codes of `VcdError`)
+const INVALID_ARGUMENT: WaveTkStatus = 14;
+
 fn encode_error(err: VcdError) -> WaveTkStatus {
     match err {
         VcdError::IoError(_) => 1,
-        VcdError::ParseError => 2,
+        VcdError::ParseError | VcdError::Syntax(_) => 2,
         VcdError::MissingData => 3,
         VcdError::PartialHeader => 4,
         VcdError::Utf8Error => 5,
         VcdError::EndOfInput => 6,
+        VcdError::MemoryLimit => 7,
+        VcdError::UnknownVariable => 8,
+        VcdError::InvalidHeader(_) => 9,
+        VcdError::User(_) => 10,
+        VcdError::InvalidChange(_) => 11,
+        VcdError::NonMonotonicTime { .. } => 12,
+        VcdError::TruncatedHeader(_) => 13,
     }
 }
 
+/// Logic levels of the state buffer (one signed byte per bit)
+pub const WAVETK_LEVEL_0: i8 = simulation::LEVEL_0;
+pub const WAVETK_LEVEL_1: i8 = simulation::LEVEL_1;
+pub const WAVETK_LEVEL_U: i8 = simulation::LEVEL_U;
+pub const WAVETK_LEVEL_W: i8 = simulation::LEVEL_W;
+pub const WAVETK_LEVEL_Z: i8 = simulation::LEVEL_Z;
+pub const WAVETK_LEVEL_X: i8 = simulation::LEVEL_X;
+pub const WAVETK_LEVEL_OTHER: i8 = simulation::LEVEL_OTHER;
+pub const WAVETK_LEVEL_NO_DATA: i8 = simulation::LEVEL_NO_DATA;
+pub const WAVETK_LEVEL_L: i8 = simulation::LEVEL_L;
+pub const WAVETK_LEVEL_H: i8 = simulation::LEVEL_H;
+pub const WAVETK_LEVEL_DONT_CARE: i8 = simulation::LEVEL_DONT_CARE;
+
+/// Returns a readable name of a logic level of the state buffer (e.g., "X (unknown)"), the
+/// string is static and must not be released.
+#[no_mangle]
+pub extern "C" fn wavetk_logic_level_name(level: i8) -> *const c_char {
+    // Names of the levels from -10 to 2, the ones out of the encoding are "invalid"
+    static NAMES: OnceLock<Vec<CString>> = OnceLock::new();
+    let names = NAMES.get_or_init(|| {
+        (-10..=2)
+            .map(|l| CString::new(simulation::logic_level_name(l)).unwrap())
+            .collect()
+    });
+    names[(level.clamp(-10, 2) + 10) as usize].as_ptr()
+}
+
 /// Get the (major, minor, patch) triple for this crate version
 fn get_version() -> Result<(u8, u8, u8), ParseIntError> {
     let major = VERSION_MAJOR.parse::<u8>()?;
@@ -39,6 +88,44 @@ pub extern "C" fn wavetk_version() -> u32 {
     (v.0 as u32) << 16 | (v.1 as u32) << 8 | (v.2 as u32)
 }
 
+/// FST files can be read
+pub const WAVETK_FEATURE_FST: u32 = 1 << 0;
+/// Compressed VCD files can be read, for each compression format
+pub const WAVETK_FEATURE_GZIP: u32 = 1 << 1;
+pub const WAVETK_FEATURE_ZSTD: u32 = 1 << 2;
+pub const WAVETK_FEATURE_XZ: u32 = 1 << 3;
+pub const WAVETK_FEATURE_BZIP2: u32 = 1 << 4;
+
+/// Returns the set of optional features compiled in the library, as a bitmask of
+/// `WAVETK_FEATURE_*` flags
+#[no_mangle]
+pub extern "C" fn wavetk_features() -> u32 {
+    let mut features = 0;
+    let flags = [
+        (cfg!(feature = "fst"), WAVETK_FEATURE_FST),
+        (cfg!(feature = "gzip"), WAVETK_FEATURE_GZIP),
+        (cfg!(feature = "zstd"), WAVETK_FEATURE_ZSTD),
+        (cfg!(feature = "xz"), WAVETK_FEATURE_XZ),
+        (cfg!(feature = "bzip2"), WAVETK_FEATURE_BZIP2),
+    ];
+    for (enabled, flag) in flags {
+        if enabled {
+            features |= flag;
+        }
+    }
+    features
+}
+
+unsafe fn create_simulation<P: AsRef<Path>>(filename: P, status: *mut i32) -> *mut StateSimulation {
+    match StateSimulation::new(filename) {
+        Ok(sim) => Box::into_raw(Box::new(sim)),
+        Err(e) => {
+            *status = encode_error(VcdError::IoError(e));
+            null_mut()
+        }
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn wave_sim_create(
     filename: *const c_char,
@@ -50,38 +137,148 @@ pub unsafe extern "C" fn wave_sim_create(
         *status = encode_error(VcdError::Utf8Error);
         return null_mut();
     }
-    match StateSimulation::new(f_name.unwrap()) {
-        Ok(sim) => Box::into_raw(Box::new(sim)),
-        Err(e) => {
-            *status = encode_error(VcdError::IoError(e));
-            null_mut()
+    create_simulation(f_name.unwrap(), status)
+}
+
+/// Same as `wave_sim_create`, but the filename is given as a raw byte buffer of
+/// length `n` (not necessarily NUL-terminated).
+///
+/// On Unix the bytes are passed as-is to the OS, which allows opening paths that
+/// are not valid UTF-8. On other platforms the bytes must be valid UTF-8.
+#[no_mangle]
+pub unsafe extern "C" fn wave_sim_create_bytes(
+    filename: *const u8,
+    n: usize,
+    status: *mut i32,
+) -> *mut StateSimulation {
+    assert!(!filename.is_null());
+    let bytes = slice::from_raw_parts(filename, n);
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        create_simulation(OsStr::from_bytes(bytes), status)
+    }
+    #[cfg(not(unix))]
+    {
+        match str::from_utf8(bytes) {
+            Ok(f_name) => create_simulation(f_name, status),
+            Err(_) => {
+                *status = encode_error(VcdError::Utf8Error);
+                null_mut()
+            }
         }
     }
 }
 
+/// Same as `wave_sim_create`, but the filename is given as a wide-char (UTF-16)
+/// buffer of length `n` (not necessarily NUL-terminated).
+///
+/// On Windows the buffer is passed as-is to the OS, which allows opening any path
+/// (including ill-formed UTF-16). On other platforms it must be valid UTF-16.
 #[no_mangle]
-pub extern "C" fn wave_sim_load_header(ptr: *mut StateSimulation) -> WaveTkStatus {
+pub unsafe extern "C" fn wave_sim_create_wide(
+    filename: *const u16,
+    n: usize,
+    status: *mut i32,
+) -> *mut StateSimulation {
+    assert!(!filename.is_null());
+    let wide = slice::from_raw_parts(filename, n);
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStringExt;
+        create_simulation(OsString::from_wide(wide), status)
+    }
+    #[cfg(not(windows))]
+    {
+        match String::from_utf16(wide) {
+            Ok(f_name) => create_simulation(f_name, status),
+            Err(_) => {
+                *status = encode_error(VcdError::Utf8Error);
+                null_mut()
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wave_sim_load_header(ptr: *mut StateSimulation) -> WaveTkStatus {
     assert!(!ptr.is_null());
-    let sim = unsafe { &mut *ptr };
+    let sim = &mut *ptr;
     match sim.load_header() {
         Ok(_) => 0,
         Err(e) => encode_error(e),
     }
 }
 
+/// Sets the memory ceiling (in bytes) of the simulation, 0 means unlimited.
 #[no_mangle]
-pub extern "C" fn wave_sim_allocate_state(
+pub unsafe extern "C" fn wave_sim_set_memory_limit(
+    ptr: *mut StateSimulation,
+    limit: u64,
+) -> WaveTkStatus {
+    assert!(!ptr.is_null());
+    let sim = &mut *ptr;
+    let limit = if limit == 0 {
+        None
+    } else {
+        Some(limit as usize)
+    };
+    sim.set_memory_limit(limit);
+    0
+}
+
+/// Sets the level of the variables until the dump sets them (e.g., `WAVETK_LEVEL_X`),
+/// taken into account by the next allocate_state. Returns `INVALID_ARGUMENT` for a value
+/// that is not a logic level.
+#[no_mangle]
+pub unsafe extern "C" fn wave_sim_set_initial_fill(
+    ptr: *mut StateSimulation,
+    level: i8,
+) -> WaveTkStatus {
+    assert!(!ptr.is_null());
+    let sim = &mut *ptr;
+    match sim.set_initial_fill(level) {
+        Ok(()) => 0,
+        Err(_) => INVALID_ARGUMENT,
+    }
+}
+
+/// Sets the rules applied to the hierarchical names of the variables (e.g., `verilator`
+/// or `unescape,strip:tb`, see `Demangler::from_str`), as reported by
+/// `wavetk_sim_layout`. To be called before allocate_state.
+#[no_mangle]
+pub unsafe extern "C" fn wave_sim_set_demangler(
+    ptr: *mut StateSimulation,
+    rules: *const c_char,
+) -> WaveTkStatus {
+    assert!(!ptr.is_null() && !rules.is_null());
+    let sim = &mut *ptr;
+    let demangler = match CStr::from_ptr(rules).to_str() {
+        Ok(rules) => rules.parse::<Demangler>(),
+        Err(_) => return encode_error(VcdError::Utf8Error),
+    };
+    match demangler {
+        Ok(d) => {
+            sim.set_demangler(d);
+            0
+        }
+        Err(_) => INVALID_ARGUMENT,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wave_sim_allocate_state(
     ptr: *mut StateSimulation,
     restrict: *const *const c_char,
     n: usize,
 ) -> WaveTkStatus {
     assert!(!ptr.is_null());
-    let sim = unsafe { &mut *ptr };
+    let sim = &mut *ptr;
     if !restrict.is_null() && n > 0 {
-        let names_ptr = unsafe { slice::from_raw_parts(restrict, n as usize) };
+        let names_ptr = slice::from_raw_parts(restrict, n);
         let mut vars: Vec<&str> = Vec::with_capacity(n);
         for name_ptr in names_ptr {
-            let name = unsafe { CStr::from_ptr(*name_ptr).to_str() };
+            let name = CStr::from_ptr(*name_ptr).to_str();
             if name.is_err() {
                 return encode_error(VcdError::Utf8Error);
             }
@@ -100,16 +297,25 @@ pub extern "C" fn wave_sim_allocate_state(
 pub unsafe extern "C" fn wave_sim_header_info(ptr: *const StateSimulation) -> *mut c_char {
     assert!(!ptr.is_null());
     let sim = &*ptr;
-    let header = sim.header_info();
-    if header.is_err() {
-        return null_mut();
+    let mut out = Vec::new();
+    match sim.save_header_info(&mut out) {
+        Ok(()) => CString::new(out).unwrap().into_raw(),
+        Err(_) => null_mut(),
     }
-    let header_str = serde_json::to_string(&header.unwrap());
-    match header_str {
-        Ok(s) => {
-            let c_str = CString::new(s).unwrap();
-            c_str.into_raw()
-        }
+}
+
+/// Returns a JSON object mapping each VCD id to the hierarchical names of the variables it
+/// drives (to be released with `wave_str_destroy`).
+#[no_mangle]
+pub unsafe extern "C" fn wave_sim_aliases(ptr: *const StateSimulation) -> *mut c_char {
+    assert!(!ptr.is_null());
+    let sim = &*ptr;
+    let aliases = match sim.aliases() {
+        Ok(a) => a,
+        Err(_) => return null_mut(),
+    };
+    match serde_json::to_string(&aliases) {
+        Ok(s) => CString::new(s).unwrap().into_raw(),
         Err(_) => null_mut(),
     }
 }
@@ -130,6 +336,82 @@ pub unsafe extern "C" fn wavetk_sim_state_buffer(
     0
 }
 
+/// Retrieve the bitmap of the variables changed by the last call to next_cycle.
+///
+/// Bit `i % 64` of word `i / 64` stands for the i-th tracked variable (sorted by offset in
+/// the state). Like the state buffer, it gets invalidated by calls to allocate_state.
+#[no_mangle]
+pub unsafe extern "C" fn wavetk_sim_dirty_bitmap(
+    ptr: *mut StateSimulation,
+    data: *mut *const u64,
+    size: *mut u64,
+) -> WaveTkStatus {
+    assert!(!ptr.is_null());
+    let sim = &*ptr;
+    *data = sim.dirty_bitmap().as_ptr();
+    *size = sim.dirty_bitmap().len() as u64;
+    0
+}
+
+/// Retrieve the bitmap of the variables set at least once by the dump, laid out like the
+/// dirty bitmap (and invalidated by the same calls).
+#[no_mangle]
+pub unsafe extern "C" fn wavetk_sim_assigned_bitmap(
+    ptr: *mut StateSimulation,
+    data: *mut *const u64,
+    size: *mut u64,
+) -> WaveTkStatus {
+    assert!(!ptr.is_null());
+    let sim = &*ptr;
+    *data = sim.assigned_bitmap().as_ptr();
+    *size = sim.assigned_bitmap().len() as u64;
+    0
+}
+
+/// Describes the state buffer layout with parallel arrays of `n` entries: hierarchical name,
+/// offset and width of each tracked variable, sorted by offset.
+///
+/// Returns the number of tracked variables, only the first `n` are written (call with
+/// `n = 0` to query the size). Names are allocated by the library and must be released
+/// with `wavetk_sim_layout_free`. Arrays may be NULL when not needed.
+#[no_mangle]
+pub unsafe extern "C" fn wavetk_sim_layout(
+    ptr: *const StateSimulation,
+    names_out: *mut *mut c_char,
+    offsets_out: *mut u64,
+    widths_out: *mut u64,
+    n: usize,
+) -> u64 {
+    assert!(!ptr.is_null());
+    let sim = &*ptr;
+    let variables = sim.tracked_variables();
+    for (i, (offset, v)) in variables.iter().take(n).enumerate() {
+        if !names_out.is_null() {
+            let name = CString::new(sim.path_of(v)).unwrap_or_default();
+            *names_out.add(i) = name.into_raw();
+        }
+        if !offsets_out.is_null() {
+            *offsets_out.add(i) = *offset as u64;
+        }
+        if !widths_out.is_null() {
+            *widths_out.add(i) = v.width as u64;
+        }
+    }
+    variables.len() as u64
+}
+
+/// Releases the `n` names written by `wavetk_sim_layout`
+#[no_mangle]
+pub unsafe extern "C" fn wavetk_sim_layout_free(names: *mut *mut c_char, n: usize) {
+    if names.is_null() {
+        return;
+    }
+    for name in slice::from_raw_parts_mut(names, n) {
+        wave_str_destroy(*name);
+        *name = null_mut();
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn wave_sim_next_cycle(
     ptr: *mut StateSimulation,
@@ -143,32 +425,62 @@ pub unsafe extern "C" fn wave_sim_next_cycle(
         return encode_error(VcdError::EndOfInput);
     }
     match sim.next_cycle() {
-        Ok((c, state)) => {
-            *cycle = c;
-            *data = state.as_ptr();
-            *size = state.len() as u64;
+        Ok(step) => {
+            *cycle = step.cycle;
+            *data = step.state.as_ptr();
+            *size = step.state.len() as u64;
             0
         }
         Err(e) => encode_error(e),
     }
 }
 
+/// Runs up to `n` cycles, writing the cycle numbers to `cycles_out` (`n` entries) and the
+/// state after each cycle to consecutive rows of `states_out` (`n` times the size of the
+/// state).
+///
+/// The number of cycles run is written to `count`, it is less than `n` only at the end of
+/// the input (0 when the simulation was already done, no error is returned in that case).
+#[no_mangle]
+pub unsafe extern "C" fn wavetk_sim_read_block(
+    ptr: *mut StateSimulation,
+    n: u64,
+    cycles_out: *mut i64,
+    states_out: *mut i8,
+    count: *mut u64,
+) -> WaveTkStatus {
+    assert!(!ptr.is_null());
+    let sim = &mut *ptr;
+    let width = sim.state().len();
+    let mut k = 0;
+    *count = 0;
+    while k < n as usize && !sim.done() {
+        match sim.next_cycle() {
+            Ok(step) => {
+                *cycles_out.add(k) = step.cycle;
+                let row = slice::from_raw_parts_mut(states_out.add(k * width), width);
+                row.copy_from_slice(step.state);
+            }
+            Err(e) => return encode_error(e),
+        }
+        k += 1;
+        *count = k as u64;
+    }
+    0
+}
+
 #[no_mangle]
-pub extern "C" fn wave_sim_destroy(p: *mut StateSimulation) {
+pub unsafe extern "C" fn wave_sim_destroy(p: *mut StateSimulation) {
     if p.is_null() {
         return;
     }
-    unsafe {
-        Box::from_raw(p);
-    }
+    drop(Box::from_raw(p));
 }
 
 #[no_mangle]
-pub extern "C" fn wave_str_destroy(p: *const c_char) {
+pub unsafe extern "C" fn wave_str_destroy(p: *mut c_char) {
     if p.is_null() {
         return;
     }
-    unsafe {
-        CStr::from_ptr(p);
-    }
+    drop(CString::from_raw(p));
 }
@@ -0,0 +1,90 @@
+//! Minimal command line argument handling.
+use std::str::FromStr;
+
+use wavetk::hierarchy::Demangler;
+
+use crate::Result;
+
+/// Remaining arguments of a command, options are taken out as they are queried
+pub struct Args {
+    args: Vec<String>,
+}
+
+impl Args {
+    pub fn new(args: Vec<String>) -> Self {
+        Args { args }
+    }
+
+    fn position(&self, names: &[&str]) -> Option<usize> {
+        self.args.iter().position(|a| names.contains(&a.as_str()))
+    }
+
+    /// Removes a flag, returns whether it was given
+    pub fn flag(&mut self, names: &[&str]) -> bool {
+        match self.position(names) {
+            Some(i) => {
+                self.args.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes an option given as `--name value` or `--name=value` and returns its value
+    pub fn value(&mut self, names: &[&str]) -> Result<Option<String>> {
+        if let Some(i) = self.position(names) {
+            if i + 1 >= self.args.len() {
+                return Err(format!("missing value for {}", self.args[i]).into());
+            }
+            self.args.remove(i);
+            return Ok(Some(self.args.remove(i)));
+        }
+        for (i, a) in self.args.iter().enumerate() {
+            if let Some((name, value)) = a.split_once('=') {
+                if names.contains(&name) {
+                    let value = value.to_string();
+                    self.args.remove(i);
+                    return Ok(Some(value));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Removes all the occurrences of a repeatable option and returns their values
+    pub fn values(&mut self, names: &[&str]) -> Result<Vec<String>> {
+        let mut values = Vec::new();
+        while let Some(v) = self.value(names)? {
+            values.push(v);
+        }
+        Ok(values)
+    }
+
+    /// Same as `value`, parsed
+    pub fn parsed<T: FromStr>(&mut self, names: &[&str]) -> Result<Option<T>> {
+        match self.value(names)? {
+            Some(v) => match v.parse() {
+                Ok(x) => Ok(Some(x)),
+                Err(_) => Err(format!("invalid value for {}: {:?}", names[0], v).into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the positional arguments, fails if an unknown option remains
+    pub fn finish(self) -> Result<Vec<String>> {
+        if let Some(a) = self.args.iter().find(|a| a.starts_with('-') && a.len() > 1) {
+            return Err(format!("unknown option {}", a).into());
+        }
+        Ok(self.args)
+    }
+}
+
+/// Rules given by `--demangle`, applied to the hierarchical names of the dumps (see
+/// `Demangler::from_str`)
+pub fn load_demangler(args: &mut Args) -> Result<Demangler> {
+    match args.value(&["--demangle"])? {
+        Some(rules) => Ok(rules.parse()?),
+        None => Ok(Demangler::default()),
+    }
+}
@@ -0,0 +1,175 @@
+//! `wavetk convert`: conversion of many dumps between formats, on several threads.
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use wavetk::compression;
+use wavetk::convert::{convert_file, verify_conversion, Format};
+
+use crate::args::Args;
+use crate::top::human_size;
+use crate::Result;
+
+pub const USAGE: &str = "usage: wavetk convert [options] --to <vcd|fst|wdb> <file>...
+
+Converts VCD (possibly compressed) and FST files to VCD, FST or the native format (wdb).
+Each output is named after its input, with the extension of the target format. Files
+are converted concurrently, the progress is reported on stderr as conversions complete.
+
+The exit status is 0 if all the files were converted, 1 otherwise.
+
+options:
+    --to <format>              target format: vcd, fst or wdb
+    -o, --out-dir <dir>        directory of the outputs (default: next to each input)
+    -j, --jobs <N>             number of files converted concurrently (default: number
+                               of CPUs)
+    --verify                   read back each input and its output, and check that the
+                               signals have the same changes (conversions with
+                               discrepancies count as failed)";
+
+/// Number of discrepancies listed per file
+const MAX_DISCREPANCIES: usize = 10;
+
+/// Extensions of compressed files, removed along with the one of the dump
+const COMPRESSED_EXTENSIONS: [&str; 5] = ["gz", "zst", "xz", "bz2", "bz"];
+
+/// Path of the output of `input` (e.g., `dumps/a.vcd.gz` becomes `<dir>/a.fst`)
+fn output_path(input: &Path, out_dir: Option<&Path>, to: Format) -> Result<PathBuf> {
+    let mut name = PathBuf::from(input.file_name().ok_or("invalid input file name")?);
+    if let Some(e) = name.extension().and_then(|e| e.to_str()) {
+        if COMPRESSED_EXTENSIONS.contains(&e) {
+            name.set_extension("");
+        }
+    }
+    name.set_extension(to.extension());
+    let dir = match out_dir {
+        Some(d) => d,
+        None => input.parent().unwrap_or_else(|| Path::new("")),
+    };
+    Ok(dir.join(name))
+}
+
+/// Progress shared by the workers
+struct Progress {
+    done: usize,
+    failed: usize,
+    bytes: u64,
+}
+
+pub fn run(mut args: Args) -> Result<()> {
+    let to: Format = match args.value(&["--to"])? {
+        Some(f) => f.parse()?,
+        None => return Err(format!("no target format given\n\n{}", USAGE).into()),
+    };
+    let out_dir = args.value(&["-o", "--out-dir"])?.map(PathBuf::from);
+    let jobs = match args.parsed(&["-j", "--jobs"])? {
+        Some(0) | None => thread::available_parallelism().map_or(1, |n| n.get()),
+        Some(j) => j,
+    };
+    let verify = args.flag(&["--verify"]);
+    let files = args.finish()?;
+    if files.is_empty() {
+        return Err(format!("no input file\n\n{}", USAGE).into());
+    }
+    // Outputs are named after their input
+    if files.iter().any(compression::is_stdin) {
+        return Err("the standard input (`-`) can not be converted, give a file".into());
+    }
+    if let Some(d) = &out_dir {
+        std::fs::create_dir_all(d)?;
+    }
+    let mut tasks = Vec::with_capacity(files.len());
+    let mut outputs = HashSet::new();
+    for f in &files {
+        let input = PathBuf::from(f);
+        let output = output_path(&input, out_dir.as_deref(), to)?;
+        if !outputs.insert(output.clone()) {
+            return Err(format!("several inputs would be written to {}", output.display()).into());
+        }
+        tasks.push((input, output));
+    }
+
+    let start = Instant::now();
+    let next = AtomicUsize::new(0);
+    let progress = Mutex::new(Progress {
+        done: 0,
+        failed: 0,
+        bytes: 0,
+    });
+    thread::scope(|s| {
+        for _ in 0..jobs.min(tasks.len()) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let (input, output) = match tasks.get(i) {
+                    Some(t) => t,
+                    None => break,
+                };
+                let t = Instant::now();
+                let size = std::fs::metadata(input).map_or(0, |m| m.len());
+                let result = convert_file(input, output, Some(to)).and_then(|()| {
+                    if verify {
+                        verify_conversion(input, output).map(Some)
+                    } else {
+                        Ok(None)
+                    }
+                });
+
+                let mut p = progress.lock().unwrap();
+                p.done += 1;
+                let prefix = format!("[{}/{}]", p.done, tasks.len());
+                match result {
+                    Ok(Some(discrepancies)) if !discrepancies.is_empty() => {
+                        p.failed += 1;
+                        eprintln!(
+                            "{} {} -> {}: {} signal(s) differ",
+                            prefix,
+                            input.display(),
+                            output.display(),
+                            discrepancies.len()
+                        );
+                        for d in discrepancies.iter().take(MAX_DISCREPANCIES) {
+                            eprintln!("    {}", d);
+                        }
+                        if discrepancies.len() > MAX_DISCREPANCIES {
+                            eprintln!("    ...");
+                        }
+                    }
+                    Ok(verified) => {
+                        p.bytes += size;
+                        eprintln!(
+                            "{} {} -> {} ({}, {:.1} s{})",
+                            prefix,
+                            input.display(),
+                            output.display(),
+                            human_size(size),
+                            t.elapsed().as_secs_f64(),
+                            if verified.is_some() { ", verified" } else { "" }
+                        );
+                    }
+                    Err(e) => {
+                        p.failed += 1;
+                        eprintln!("{} {}: error: {}", prefix, input.display(), e);
+                    }
+                }
+            });
+        }
+    });
+
+    let p = progress.into_inner().unwrap();
+    let elapsed = start.elapsed().as_secs_f64();
+    eprintln!(
+        "converted {}/{} files ({}) in {:.1} s, {:.1} MB/s",
+        p.done - p.failed,
+        tasks.len(),
+        human_size(p.bytes),
+        elapsed,
+        p.bytes as f64 / 1e6 / elapsed.max(1e-3)
+    );
+    if p.failed > 0 {
+        return Err(format!("{} conversion(s) failed", p.failed).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,121 @@
+//! `wavetk diff`: structural (and optionally value) differences between two dumps.
+use std::io::Read;
+use std::path::Path;
+
+use wavetk::compression;
+use wavetk::convert::{compare_digests, digest_file_with, digest_vcd, Discrepancy, Format};
+use wavetk::groups::matching_variables_with;
+use wavetk::hierarchy::{diff_hierarchies_with, HierarchyChange};
+use wavetk::session::Session;
+use wavetk::VcdParser;
+
+use crate::args::{load_demangler, Args};
+use crate::group::load_groups;
+use crate::Result;
+
+pub const USAGE: &str = "usage: wavetk diff [options] <old> <new>
+
+Compares the hierarchy of two VCD or FST files (e.g., a golden dump and a regression
+one): signals added, removed, renamed or moved to another scope, scopes moved, and width
+changes. Signals are matched by hierarchical path. A VCD file named `-` is read from the
+standard input.
+
+The exit status is 0 if no difference was found, 1 otherwise.
+
+options:
+    --values                   also compare the changes of the signals found in both
+                               dumps
+    --groups <groups>          file of named signal groups (see `wavetk group`)
+    --group <name>             only compare the signals of a group
+    --demangle <rules>         rules applied to the hierarchical names, comma separated:
+                               `verilator`, `unescape` or `strip:<scope>`";
+
+pub fn run(mut args: Args) -> Result<()> {
+    let values = args.flag(&["--values"]);
+    let groups = load_groups(&mut args)?;
+    let group = args.value(&["--group"])?;
+    let demangler = load_demangler(&mut args)?;
+    let files = args.finish()?;
+    let (old, new) = match files.as_slice() {
+        [a, b] => (a, b),
+        _ => return Err(format!("expected two input files\n\n{}", USAGE).into()),
+    };
+    if compression::is_stdin(old) && compression::is_stdin(new) {
+        return Err("only one dump can be read from the standard input".into());
+    }
+    // Only FST files drop the values of `$dumpoff` sections (see `verify_conversion`)
+    let include_dumpoff = Format::from_path(Path::new(new)) != Format::Fst;
+    let mut session = Session::new();
+    let mut digests = Vec::new();
+    for (name, path) in [("old", old), ("new", new)] {
+        if compression::is_stdin(path) {
+            // The standard input is read once, for the hierarchy and the values
+            let mut data = Vec::new();
+            compression::open_input(path)?.read_to_end(&mut data)?;
+            if values {
+                let mut parser = VcdParser::with_chunk_size(1 << 16, data.as_slice());
+                parser.load_header()?;
+                digests.push(digest_vcd(&mut parser, include_dumpoff, &demangler)?);
+            }
+            session.open_vcd_data(name, data)?;
+        } else {
+            if values {
+                digests.push(digest_file_with(path, include_dumpoff, &demangler)?);
+            }
+            session.open(name, path)?;
+        }
+    }
+    let old_variables = session.variables("old").unwrap_or_default();
+    let new_variables = session.variables("new").unwrap_or_default();
+    let (changes, members) = match (&groups, &group) {
+        (Some(groups), Some(name)) => {
+            let changes = groups.diff_with(name, old_variables, new_variables, &demangler)?;
+            // Paths of the signals of the group, for the comparison of the values
+            let mut members = Vec::new();
+            for s in groups.get(name).unwrap_or_default() {
+                for variables in [old_variables, new_variables] {
+                    let paths = matching_variables_with(variables, s, &demangler)
+                        .into_iter()
+                        .map(|i| demangler.path(&variables[i]));
+                    members.extend(paths);
+                }
+            }
+            (changes, Some(members))
+        }
+        (None, Some(_)) => return Err("--group requires a group file (--groups)".into()),
+        _ => (
+            diff_hierarchies_with(old_variables, new_variables, &demangler),
+            None,
+        ),
+    };
+    for c in &changes {
+        println!("{}", c);
+    }
+    let mut differences = changes.len();
+    if values {
+        // Signals missing from one side, or with another width, are already reported
+        let resized: Vec<&str> = changes
+            .iter()
+            .filter_map(|c| match c {
+                HierarchyChange::WidthChanged { path, .. } => Some(path.as_str()),
+                _ => None,
+            })
+            .collect();
+        for d in compare_digests(&digests[0], &digests[1]) {
+            match &d {
+                Discrepancy::ChangeCount { path, .. } | Discrepancy::Values(path)
+                    if !resized.contains(&path.as_str())
+                        && members.as_ref().is_none_or(|m| m.contains(path)) =>
+                {
+                    println!("{}", d);
+                    differences += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+    if differences > 0 {
+        return Err(format!("{} difference(s) found", differences).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,195 @@
+//! `wavetk follow`: watchdog evaluating trigger conditions on a VCD or FST file being
+//! written.
+use std::fmt::Display;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::process::exit;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use wavetk::compression;
+use wavetk::hierarchy::Demangler;
+use wavetk::session::DumpFormat;
+use wavetk::simulation::{CycleStep, StateSimulation};
+use wavetk::trigger::Trigger;
+#[cfg(feature = "fst")]
+use wavetk::FstSimulation;
+
+use crate::args::{load_demangler, Args};
+use crate::Result;
+
+pub const USAGE: &str = "usage: wavetk follow [options] -e <condition> <file>
+
+Reads a VCD or FST file as it is written by a simulator (like `tail -f`) and prints the time
+at which each condition becomes true. Conditions compare signals to constants, e.g.
+`top.err == 1`, `top.state == 0b1x0 && top.pc >= 0x100` (`&&` binds tighter than `||`).
+
+A file named `-` is read from the standard input (e.g., a pipe from the simulator) until
+it is closed, as a VCD file. An FST file is checked for new blocks every `--poll`, the
+last time written is reported once the simulator flushes it.
+
+The exit status is 0 if a condition fired, 1 otherwise.
+
+options:
+    -e, --expr <condition>     condition to watch (can be repeated)
+    --once                     exit as soon as a condition fires
+    --poll <ms>                delay between two checks of the file size (default: 200)
+    --idle-timeout <s>         stop when the file did not grow for this duration
+    --demangle <rules>         rules applied to the hierarchical names, comma separated:
+                               `verilator`, `unescape` or `strip:<scope>`";
+
+/// A file read as it grows, the end of file is only reported after `idle_timeout`
+struct Follow {
+    file: File,
+    poll: Duration,
+    idle_timeout: Option<Duration>,
+    last_growth: Instant,
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.file.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                self.last_growth = Instant::now();
+                return Ok(n);
+            }
+            if let Some(t) = self.idle_timeout {
+                if self.last_growth.elapsed() >= t {
+                    return Ok(0);
+                }
+            }
+            sleep(self.poll);
+        }
+    }
+}
+
+pub fn run(mut args: Args) -> Result<()> {
+    let exprs = args.values(&["-e", "--expr"])?;
+    let once = args.flag(&["--once"]);
+    let poll = Duration::from_millis(args.parsed(&["--poll"])?.unwrap_or(200));
+    let idle_timeout = args
+        .parsed::<f64>(&["--idle-timeout"])?
+        .map(Duration::from_secs_f64);
+    let demangler = load_demangler(&mut args)?;
+    let files = args.finish()?;
+    let path = match files.as_slice() {
+        [p] => p,
+        _ => return Err(format!("expected a single input file\n\n{}", USAGE).into()),
+    };
+    if exprs.is_empty() {
+        return Err(format!("no condition given\n\n{}", USAGE).into());
+    }
+    let mut triggers = exprs
+        .iter()
+        .map(|e| e.parse())
+        .collect::<std::result::Result<Vec<Trigger>, _>>()?;
+
+    let fired = match DumpFormat::from_path(Path::new(path)) {
+        DumpFormat::Vcd => follow_vcd(path, &mut triggers, once, poll, idle_timeout, demangler)?,
+        #[cfg(feature = "fst")]
+        DumpFormat::Fst => follow_fst(path, &mut triggers, once, poll, idle_timeout, demangler)?,
+        #[cfg(not(feature = "fst"))]
+        DumpFormat::Fst => return Err("FST support not enabled in this build".into()),
+    };
+    if !fired {
+        exit(1);
+    }
+    Ok(())
+}
+
+/// Reports the conditions becoming true in `state`, returns true if one of them fired
+fn check(triggers: &[Trigger], active: &mut [bool], time: impl Display, state: &[i8]) -> bool {
+    // Conditions are reported when they become true, not on each cycle they hold
+    let mut fired = false;
+    for (t, active) in triggers.iter().zip(active.iter_mut()) {
+        let holds = t.eval(state);
+        if holds && !*active {
+            println!("#{}: {}", time, t);
+            fired = true;
+        }
+        *active = holds;
+    }
+    fired
+}
+
+fn follow_vcd(
+    path: &str,
+    triggers: &mut [Trigger],
+    once: bool,
+    poll: Duration,
+    idle_timeout: Option<Duration>,
+    demangler: Demangler,
+) -> Result<bool> {
+    // Reading a pipe already waits for the data written to it
+    let mut sim = if compression::is_stdin(path) {
+        StateSimulation::from_reader(compression::open_input(path)?)
+    } else {
+        StateSimulation::from_reader(Follow {
+            file: File::open(path)?,
+            poll,
+            idle_timeout,
+            last_growth: Instant::now(),
+        })
+    };
+    sim.set_lazy_input(true);
+    sim.set_demangler(demangler);
+    sim.load_header()?;
+    let mut ids = Vec::new();
+    for t in triggers.iter() {
+        ids.extend(t.variable_ids(&sim)?);
+    }
+    let ids: Vec<&str> = ids.iter().map(|s| s.as_str()).collect();
+    sim.track_variables(&ids);
+    sim.allocate_state()?;
+    for t in triggers.iter_mut() {
+        t.bind(&sim)?;
+    }
+
+    let mut active = vec![false; triggers.len()];
+    let mut fired = false;
+    while !sim.done() {
+        let CycleStep {
+            cycle: time, state, ..
+        } = sim.next_cycle()?;
+        if check(triggers, &mut active, time, state) {
+            fired = true;
+            if once {
+                break;
+            }
+        }
+    }
+    Ok(fired)
+}
+
+#[cfg(feature = "fst")]
+fn follow_fst(
+    path: &str,
+    triggers: &mut [Trigger],
+    once: bool,
+    poll: Duration,
+    idle_timeout: Option<Duration>,
+    demangler: Demangler,
+) -> Result<bool> {
+    let mut sim = FstSimulation::from_file(path).map_err(|e| format!("{:?}", e))?;
+    sim.set_demangler(demangler);
+    let mut signals: Vec<&str> = Vec::new();
+    for t in triggers.iter() {
+        signals.extend(t.signals());
+    }
+    sim.track_variables(&signals)
+        .map_err(|e| format!("{:?}", e))?;
+    for t in triggers.iter_mut() {
+        t.bind_with(|p| sim.slot_of(p))?;
+    }
+
+    let mut active = vec![false; triggers.len()];
+    let mut fired = false;
+    sim.follow(poll, idle_timeout, |time, state| {
+        fired |= check(triggers, &mut active, time, state);
+        fired && once
+    });
+    Ok(fired)
+}
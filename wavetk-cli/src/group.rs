@@ -0,0 +1,85 @@
+//! `wavetk group`: edits the file of named signal groups used by the other commands.
+use std::path::Path;
+
+use wavetk::groups::SignalGroups;
+use wavetk::session::Session;
+
+use crate::args::{load_demangler, Args};
+use crate::Result;
+
+pub const USAGE: &str =
+    "usage: wavetk group [options] <groups> [list | set <name> <signals> | remove <name>
+                                           | resolve <name> <file>]
+
+Edits a JSON file of named groups of signals (created if needed). A signal is given by its
+hierarchical path or by the end of it, as for `wavetk render`. Other commands designate
+the signals of a group with `@<name>` when given the file with `--groups <groups>`.
+
+commands:
+    list                       list the groups and their signals (default)
+    set <name> <signals>       define a group, from comma separated signals
+    remove <name>              remove a group
+    resolve <name> <file>      list the variables of a VCD or FST file in a group (`-`
+                               reads a VCD from the standard input)
+
+options:
+    --demangle <rules>         rules applied to the hierarchical names, comma separated:
+                               `verilator`, `unescape` or `strip:<scope>`";
+
+/// Groups of the file given by `--groups`, if any
+pub fn load_groups(args: &mut Args) -> Result<Option<SignalGroups>> {
+    match args.value(&["--groups"])? {
+        Some(path) => Ok(Some(SignalGroups::load(path)?)),
+        None => Ok(None),
+    }
+}
+
+pub fn run(mut args: Args) -> Result<()> {
+    let demangler = load_demangler(&mut args)?;
+    let words = args.finish()?;
+    let words: Vec<&str> = words.iter().map(|s| s.as_str()).collect();
+    let (path, command) = match words.split_first() {
+        Some((p, c)) => (Path::new(p), c),
+        None => return Err(format!("no group file given\n\n{}", USAGE).into()),
+    };
+    let mut groups = if path.exists() {
+        SignalGroups::load(path)?
+    } else {
+        SignalGroups::new()
+    };
+    match command {
+        [] | ["list"] => {
+            for name in groups.names() {
+                println!(
+                    "{}: {}",
+                    name,
+                    groups.get(name).unwrap_or_default().join(",")
+                );
+            }
+        }
+        ["set", name, signals] => {
+            let signals: Vec<&str> = signals.split(',').filter(|s| !s.is_empty()).collect();
+            if signals.is_empty() {
+                return Err("a group needs at least one signal".into());
+            }
+            groups.define(name, &signals);
+            groups.save(path)?;
+        }
+        ["remove", name] => {
+            if !groups.remove(name) {
+                return Err(format!("unknown group {:?}", name).into());
+            }
+            groups.save(path)?;
+        }
+        ["resolve", name, file] => {
+            let mut session = Session::new();
+            session.open("dump", file)?;
+            let variables = session.variables("dump").unwrap_or_default();
+            for i in groups.select_with(name, variables, &demangler)? {
+                println!("{}", demangler.path(&variables[i]));
+            }
+        }
+        _ => return Err(format!("invalid group command\n\n{}", USAGE).into()),
+    }
+    Ok(())
+}
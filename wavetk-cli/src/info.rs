@@ -0,0 +1,106 @@
+//! `wavetk info`: summary of the header and internal structure of a dump.
+use std::path::Path;
+
+use wavetk::compression;
+use wavetk::session::DumpFormat;
+#[cfg(feature = "fst")]
+use wavetk::types::Timescale;
+#[cfg(feature = "fst")]
+use wavetk::FstReader;
+use wavetk::{VcdError, VcdParser};
+
+use crate::args::Args;
+use crate::Result;
+
+pub const USAGE: &str = "usage: wavetk info <file>
+
+Prints the header information of a VCD or FST file. For FST files, also reports how the
+file is organized (value change blocks, aliases, writer memory, dump on/off changes). A
+VCD file named `-` is read from the standard input.
+
+The part of a truncated VCD header read before the end of the file is still reported,
+with the place where it stops.";
+
+fn vcd_info(path: &Path) -> Result<()> {
+    let mut parser = VcdParser::with_chunk_size(1 << 16, compression::open_input(path)?);
+    // A truncated header is reported as far as it goes
+    let truncation = match parser.load_header() {
+        Ok(_) => None,
+        Err(VcdError::TruncatedHeader(t)) => Some(t),
+        Err(e) => return Err(e.into()),
+    };
+    let header = parser.partial_header();
+    println!("format:          VCD");
+    if let Some(d) = &header.metadata.date {
+        println!("date:            {}", d);
+    }
+    if let Some(v) = &header.metadata.version {
+        println!("version:         {}", v);
+    }
+    match header.timescale {
+        Some(t) => println!("timescale:       {}", t),
+        None => println!("timescale:       unknown"),
+    }
+    println!("time zero:       {}", header.time_zero);
+    println!("variables:       {}", header.variables.len());
+    println!("ids:             {}", header.aliases().len());
+    println!("scopes:          {}", header.scopes.scope_count());
+    if let Some(t) = truncation {
+        println!("truncated:       {}", t);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "fst")]
+fn fst_info(path: &Path) -> Result<()> {
+    let name = path.to_str().ok_or("invalid file name")?;
+    let reader = FstReader::from_file(name, false).map_err(|e| format!("{:?}", e))?;
+    println!("format:          FST");
+    println!(
+        "version:         {}",
+        reader.version_string().map_err(|e| format!("{:?}", e))?
+    );
+    println!(
+        "date:            {}",
+        reader.date_string().map_err(|e| format!("{:?}", e))?.trim()
+    );
+    if let Ok(t) = reader.file_type() {
+        println!("file type:       {:?}", t);
+    }
+    println!(
+        "timescale:       {}",
+        Timescale::from_exponent(reader.timescale())
+    );
+    println!("time zero:       {}", reader.time_zero());
+    println!(
+        "time range:      {}..{}",
+        reader.start_time(),
+        reader.end_time()
+    );
+    println!("variables:       {}", reader.var_count());
+    println!("aliases:         {}", reader.alias_count());
+    println!("scopes:          {}", reader.scope_count());
+    println!("blocks:          {}", reader.value_change_section_count());
+    println!("writer memory:   {} bytes", reader.memory_used_by_writer());
+    let activity = reader.dump_activity_changes();
+    println!("dump on/off:     {}", activity.len());
+    for (time, on) in activity {
+        println!("    {:>12} {}", time, if on { "on" } else { "off" });
+    }
+    Ok(())
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let files = args.finish()?;
+    let path = match files.as_slice() {
+        [p] => Path::new(p),
+        _ => return Err(format!("expected a single input file\n\n{}", USAGE).into()),
+    };
+    match DumpFormat::from_path(path) {
+        DumpFormat::Vcd => vcd_info(path),
+        #[cfg(feature = "fst")]
+        DumpFormat::Fst => fst_info(path),
+        #[cfg(not(feature = "fst"))]
+        DumpFormat::Fst => Err("FST support not enabled in this build".into()),
+    }
+}
@@ -0,0 +1,74 @@
+//! Command line front-end of the toolkit.
+use std::process::exit;
+
+mod args;
+mod convert;
+mod diff;
+mod follow;
+mod group;
+mod info;
+mod render;
+mod top;
+
+use args::Args;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const USAGE: &str = "usage: wavetk <command> [options]
+
+commands:
+    convert   convert dumps between VCD, FST and the native format
+    diff      compare the hierarchy of two dumps
+    follow    watch a growing VCD file and report when conditions hold
+    group     edit the file of named signal groups
+    info      print the header and internal statistics of a dump
+    render    draw signals of a dump as an SVG or PNG image
+    top       rank signals and scopes by number of value changes
+
+Run `wavetk <command> --help` for the options of a command.";
+
+fn main() {
+    let mut argv = std::env::args().skip(1);
+    let command = match argv.next() {
+        Some(c) => c,
+        None => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+    let mut args = Args::new(argv.collect());
+    if args.flag(&["-h", "--help"]) {
+        match command.as_str() {
+            "convert" => println!("{}", convert::USAGE),
+            "diff" => println!("{}", diff::USAGE),
+            "follow" => println!("{}", follow::USAGE),
+            "group" => println!("{}", group::USAGE),
+            "info" => println!("{}", info::USAGE),
+            "render" => println!("{}", render::USAGE),
+            "top" => println!("{}", top::USAGE),
+            _ => println!("{}", USAGE),
+        }
+        return;
+    }
+    let result = match command.as_str() {
+        "convert" => convert::run(args),
+        "diff" => diff::run(args),
+        "follow" => follow::run(args),
+        "group" => group::run(args),
+        "info" => info::run(args),
+        "render" => render::run(args),
+        "top" => top::run(args),
+        "-h" | "--help" | "help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        c => {
+            eprintln!("unknown command: {}\n\n{}", c, USAGE);
+            exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        exit(1);
+    }
+}
@@ -0,0 +1,146 @@
+//! `wavetk render`: draws a few signals of a dump as an SVG (or PNG) image.
+use std::path::Path;
+
+use wavetk::compression;
+use wavetk::render::{load_traces_with, load_vcd_traces, timescale_of, SvgRenderer};
+use wavetk::types::Timescale;
+use wavetk::VcdParser;
+
+use crate::args::{load_demangler, Args};
+use crate::group::load_groups;
+use crate::Result;
+
+pub const USAGE: &str = "usage: wavetk render [options] --signals <names> -o <image> <file>
+
+Draws signals of a VCD, FST or native (wdb) file in a time window, as an SVG image, or
+as a PNG image in builds with the `png` feature (the format is given by the extension of
+the output). Times are given with a unit (e.g., `10us`), or in dump units without one. A
+VCD file named `-` is read from the standard input.
+
+options:
+    -s, --signals <names>      comma separated signals, by hierarchical path or by the end
+                               of it (e.g., `top.cpu.pc`, `cpu.pc` or `pc`), or groups
+                               (`@name`, see `--groups`), repeatable
+    --groups <groups>          file of named signal groups (see `wavetk group`)
+    --from <time>              start of the window (default: 0)
+    --to <time>                end of the window (default: last change of the signals)
+    -o, --output <image>       output file, `.svg` or `.png`
+    --width <pixels>           width of the image (default: 1000)
+    --demangle <rules>         rules applied to the hierarchical names, comma separated:
+                               `verilator`, `unescape` or `strip:<scope>`";
+
+fn parse_time(text: &str, timescale: Option<&Timescale>) -> Result<u64> {
+    let time = match timescale {
+        Some(ts) => ts.parse_time(text),
+        None => text.trim().parse().ok(),
+    };
+    time.ok_or_else(|| format!("invalid time: {:?}", text).into())
+}
+
+#[cfg(feature = "png")]
+fn write_png(svg: &str, path: &Path) -> Result<()> {
+    use resvg::{tiny_skia, usvg};
+
+    let mut options = usvg::Options::default();
+    let fonts = options.fontdb_mut();
+    fonts.load_system_fonts();
+    // The default monospace family of fontdb (Courier New) is rarely installed on Linux
+    let monospace = fonts
+        .faces()
+        .find(|f| f.monospaced && !f.families.is_empty())
+        .map(|f| f.families[0].0.clone());
+    if let Some(family) = monospace {
+        fonts.set_monospace_family(family);
+    }
+    let tree = usvg::Tree::from_str(svg, &options)?;
+    let size = tree.size().to_int_size();
+    let mut pixmap =
+        tiny_skia::Pixmap::new(size.width(), size.height()).ok_or("invalid image size")?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    pixmap.save_png(path)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "png"))]
+fn write_png(_svg: &str, _path: &Path) -> Result<()> {
+    Err("PNG output not enabled in this build (`png` feature)".into())
+}
+
+pub fn run(mut args: Args) -> Result<()> {
+    let signals: Vec<String> = args
+        .values(&["-s", "--signals"])?
+        .iter()
+        .flat_map(|s| s.split(','))
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect();
+    let groups = load_groups(&mut args)?;
+    let output = match args.value(&["-o", "--output"])? {
+        Some(o) => o,
+        None => return Err(format!("no output file given\n\n{}", USAGE).into()),
+    };
+    let from = args.value(&["--from"])?;
+    let to = args.value(&["--to"])?;
+    let width = args.parsed(&["--width"])?;
+    let demangler = load_demangler(&mut args)?;
+    let files = args.finish()?;
+    let path = match files.as_slice() {
+        [p] => Path::new(p),
+        _ => return Err(format!("expected a single input file\n\n{}", USAGE).into()),
+    };
+    if signals.is_empty() {
+        return Err(format!("no signal given\n\n{}", USAGE).into());
+    }
+
+    // The standard input can only be read once, for the timescale and the traces
+    let mut stdin = None;
+    let timescale = if compression::is_stdin(path) {
+        let mut parser = VcdParser::with_chunk_size(1 << 16, compression::open_input(path)?);
+        let timescale = parser.load_header()?.timescale;
+        stdin = Some(parser);
+        timescale
+    } else {
+        timescale_of(path)?
+    };
+    let start = match &from {
+        Some(t) => parse_time(t, timescale.as_ref())?,
+        None => 0,
+    };
+    let end = match &to {
+        Some(t) => parse_time(t, timescale.as_ref())?,
+        None => u64::MAX,
+    };
+    let mut signals: Vec<&str> = signals.iter().map(|s| s.as_str()).collect();
+    let expanded;
+    if let Some(groups) = &groups {
+        expanded = groups.expand(&signals)?;
+        signals = expanded.iter().map(|s| s.as_str()).collect();
+    }
+    let traces = match &mut stdin {
+        Some(parser) => load_vcd_traces(parser, &signals, start, end, &demangler)?,
+        None => load_traces_with(path, &signals, start, end, &demangler)?,
+    };
+    let end = match to {
+        Some(_) => end,
+        None => traces
+            .iter()
+            .filter_map(|t| t.changes.last().map(|c| c.0))
+            .max()
+            .unwrap_or(0)
+            .max(start + 1),
+    };
+
+    let mut renderer = SvgRenderer::new();
+    renderer.set_timescale(timescale);
+    if let Some(w) = width {
+        renderer.set_width(w);
+    }
+    let svg = renderer.render(&traces, start, end)?;
+    let output = Path::new(&output);
+    match output.extension().and_then(|e| e.to_str()) {
+        Some("svg") => std::fs::write(output, svg)?,
+        Some("png") => write_png(&svg, output)?,
+        _ => return Err("the output must be a .svg or .png file".into()),
+    }
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! `wavetk top`: signals and scopes contributing the most value changes to a dump.
+use std::path::Path;
+
+#[cfg(all(feature = "fst", not(feature = "rayon")))]
+use wavetk::activity::fst_change_counts;
+#[cfg(feature = "rayon")]
+use wavetk::activity::par_fst_change_counts;
+use wavetk::activity::{vcd_change_counts, ChangeCount};
+use wavetk::compression;
+use wavetk::session::DumpFormat;
+#[cfg(all(feature = "fst", not(feature = "rayon")))]
+use wavetk::FstReader;
+use wavetk::VcdParser;
+
+use crate::args::{load_demangler, Args};
+use crate::Result;
+
+pub const USAGE: &str = "usage: wavetk top [options] <file>
+
+Ranks the scopes (including their sub-scopes) and the signals of a VCD or FST file by
+number of value changes, with the estimated number of bytes they use in the dump. A VCD
+file named `-` is read from the standard input.
+
+options:
+    -n, --count <N>            number of entries listed (default: 20)
+    --sort <changes|bytes>     ranking criterion (default: changes)
+    --scopes                   only list scopes
+    --signals                  only list signals
+    -j, --jobs <N>             threads used for FST files, in builds with the `rayon`
+                               feature (default: number of CPUs)
+    --demangle <rules>         rules applied to the hierarchical names, comma separated:
+                               `verilator`, `unescape` or `strip:<scope>`";
+
+pub(crate) fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "kB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit + 1 < UNITS.len() {
+        size /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Prints the `count` first entries, with their share of the `total` size of the changes
+fn print_table(
+    title: &str,
+    mut entries: Vec<ChangeCount>,
+    by_bytes: bool,
+    count: usize,
+    total: u64,
+) {
+    if by_bytes {
+        entries.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(b.changes.cmp(&a.changes)));
+    } else {
+        entries.sort_by(|a, b| b.changes.cmp(&a.changes).then(b.bytes.cmp(&a.bytes)));
+    }
+    println!("{:<60} {:>14} {:>10} {:>6}", title, "changes", "size", "%");
+    for e in entries.iter().take(count) {
+        let share = if total == 0 {
+            0.0
+        } else {
+            100.0 * e.bytes as f64 / total as f64
+        };
+        println!(
+            "{:<60} {:>14} {:>10} {:>6.1}",
+            e.path,
+            e.changes,
+            human_size(e.bytes),
+            share
+        );
+    }
+}
+
+pub fn run(mut args: Args) -> Result<()> {
+    let count = args.parsed(&["-n", "--count"])?.unwrap_or(20);
+    let by_bytes = match args.value(&["--sort"])?.as_deref() {
+        None | Some("changes") => false,
+        Some("bytes") => true,
+        Some(s) => return Err(format!("invalid sort criterion: {}", s).into()),
+    };
+    let scopes_only = args.flag(&["--scopes"]);
+    let signals_only = args.flag(&["--signals"]);
+    #[cfg_attr(not(feature = "fst"), allow(unused_variables))]
+    let jobs: Option<usize> = args.parsed(&["-j", "--jobs"])?;
+    let demangler = load_demangler(&mut args)?;
+    let files = args.finish()?;
+    let path = match files.as_slice() {
+        [p] => Path::new(p),
+        _ => return Err(format!("expected a single input file\n\n{}", USAGE).into()),
+    };
+
+    let report = match DumpFormat::from_path(path) {
+        DumpFormat::Vcd => {
+            let mut parser = VcdParser::with_chunk_size(1 << 16, compression::open_input(path)?);
+            parser.load_header()?;
+            vcd_change_counts(&mut parser, &demangler)?
+        }
+        #[cfg(all(feature = "fst", not(feature = "rayon")))]
+        DumpFormat::Fst => {
+            if jobs.is_some_and(|j| j != 1) {
+                return Err("multi-threading not enabled in this build".into());
+            }
+            let name = path.to_str().ok_or("invalid file name")?;
+            let mut reader = FstReader::from_file(name, false).map_err(|e| format!("{:?}", e))?;
+            fst_change_counts(&mut reader, &demangler)
+        }
+        #[cfg(feature = "rayon")]
+        DumpFormat::Fst => {
+            let name = path.to_str().ok_or("invalid file name")?;
+            par_fst_change_counts(name, jobs.unwrap_or(0), &demangler)
+                .map_err(|e| format!("{:?}", e))?
+        }
+        #[cfg(not(feature = "fst"))]
+        DumpFormat::Fst => return Err("FST support not enabled in this build".into()),
+    };
+    let total = report.signals.iter().map(|s| s.bytes).sum();
+    if !signals_only {
+        print_table("scope", report.scopes, by_bytes, count, total);
+    }
+    if !scopes_only {
+        if !signals_only {
+            println!();
+        }
+        print_table("signal", report.signals, by_bytes, count, total);
+    }
+    Ok(())
+}
@@ -2,19 +2,16 @@ use wavetk::simulation::StateSimulation;
 
 pub fn main() -> Result<(), Box<dyn std::error::Error>> {
     let input = std::env::args().nth(1).expect("Need 1 argument");
-    let mut s = StateSimulation::new(&input).unwrap();
+    let mut s = StateSimulation::new(&input)?;
     s.load_header()?;
     s.allocate_state()?;
-    let mut w: i64 = 0;
-    let mut i = 0;
-    while !s.done() {
-        let r = s.next_cycle()?;
-        w += r.0;
-        let total: i8 = r.1.iter().sum();
-        w += total as i64;
-        i += 1;
-    }
-    println!("i = {}", i);
-    println!("w = {}", w);
+    let summary = s.run_to_end()?;
+    println!("cycles = {}", summary.cycles);
+    println!("end time = {:?}", summary.end_time.map(|t| t.raw));
+    println!(
+        "commands = {} ({} changes)",
+        summary.commands, summary.changes
+    );
+    println!("throughput = {:.1} MB/s", summary.throughput() / 1e6);
     Ok(())
 }
@@ -0,0 +1,373 @@
+//! Detection of idle (quiet) periods of signals and scopes.
+//!
+//! An idle interval is a period longer than a minimum duration during which a signal does
+//! not change value. A scope is idle while none of the signals declared in it, or in any
+//! of its sub-scopes, changes. The period before the initial values and after the last
+//! change (up to the last timestamp of the dump) are accounted as well.
+//!
+//! Value changes can also be counted without building a simulation state, to find the
+//! signals and scopes contributing the most to the size of a dump.
+use std::borrow::Borrow;
+use std::collections::HashMap;
+#[cfg(feature = "fst")]
+use std::ffi::CStr;
+use std::io::Read;
+#[cfg(feature = "fst")]
+use std::os::raw::c_char;
+
+use serde::Serialize;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+#[cfg(feature = "rayon")]
+use crate::fst::FstError;
+#[cfg(feature = "fst")]
+use crate::fst::FstReader;
+use crate::hierarchy::{rollup_with, Demangler};
+use crate::simulation::StateSimulation;
+use crate::types::{Scope, VariableInfo};
+use crate::vcd::{VcdCommand, VcdError, VcdParser, VcdValue};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct IdleInterval {
+    pub start: i64,
+    pub end: i64,
+}
+
+impl IdleInterval {
+    pub fn duration(&self) -> u64 {
+        (self.end - self.start) as u64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IdleSummary {
+    /// Hierarchical name of the signal or scope
+    pub path: String,
+    /// Number of cycles with a change
+    pub changes: u64,
+    /// Sum of the duration of all the idle intervals
+    pub total_idle: u64,
+    /// Longest idle intervals, longest first
+    pub longest: Vec<IdleInterval>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IdleReport {
+    pub start_time: i64,
+    pub end_time: i64,
+    pub signals: Vec<IdleSummary>,
+    pub scopes: Vec<IdleSummary>,
+}
+
+#[derive(Debug, Clone)]
+struct Activity {
+    summary: IdleSummary,
+    last: Option<i64>,
+}
+
+impl Activity {
+    fn new(path: String) -> Self {
+        Activity {
+            summary: IdleSummary {
+                path,
+                changes: 0,
+                total_idle: 0,
+                longest: Vec::new(),
+            },
+            last: None,
+        }
+    }
+
+    fn gap(&mut self, interval: IdleInterval, min_idle: u64, keep: usize) {
+        let d = interval.duration();
+        if d <= min_idle {
+            return;
+        }
+        self.summary.total_idle += d;
+        let longest = &mut self.summary.longest;
+        let pos = longest.partition_point(|i| i.duration() >= d);
+        if pos < keep {
+            longest.insert(pos, interval);
+            longest.truncate(keep);
+        }
+    }
+
+    fn record(&mut self, time: i64, start: i64, min_idle: u64, keep: usize) {
+        if self.last == Some(time) {
+            return;
+        }
+        let from = self.last.unwrap_or(start);
+        self.gap(
+            IdleInterval {
+                start: from,
+                end: time,
+            },
+            min_idle,
+            keep,
+        );
+        self.last = Some(time);
+        self.summary.changes += 1;
+    }
+}
+
+/// Runs the simulation until the end and reports the idle intervals longer than
+/// `min_idle` of each tracked variable and of each scope, keeping the `keep` longest ones.
+/// Signals and scopes are named by the demangler of the simulation.
+///
+/// The state must have been allocated, only tracked variables are considered.
+pub fn idle_intervals(
+    sim: &mut StateSimulation,
+    min_idle: u64,
+    keep: usize,
+) -> Result<IdleReport, VcdError> {
+    sim.header().ok_or(VcdError::PartialHeader)?;
+    let mut signals: Vec<Activity> = Vec::new();
+    let mut scopes: Vec<Activity> = Vec::new();
+    let mut scope_index: HashMap<String, usize> = HashMap::new();
+    // Scopes (and their parents) of each tracked variable
+    let mut var_scopes: Vec<Vec<usize>> = Vec::new();
+    for (_, v) in sim.tracked_variables() {
+        signals.push(Activity::new(sim.path_of(v)));
+        let mut indices = Vec::new();
+        for depth in 1..=v.scope.len() {
+            let path = sim.demangler().scope_path(&v.scope[..depth]);
+            if path.is_empty() {
+                continue;
+            }
+            let next = scopes.len();
+            let i = *scope_index.entry(path.clone()).or_insert(next);
+            if i == next {
+                scopes.push(Activity::new(path));
+            }
+            indices.push(i);
+        }
+        var_scopes.push(indices);
+    }
+
+    let mut start = None;
+    let mut end = 0;
+    while !sim.done() {
+        let time = sim.next_cycle()?.cycle;
+        if time < 0 {
+            continue;
+        }
+        let t0 = *start.get_or_insert(time);
+        end = time;
+        for i in sim.dirty_variables() {
+            signals[i].record(time, t0, min_idle, keep);
+            for s in &var_scopes[i] {
+                scopes[*s].record(time, t0, min_idle, keep);
+            }
+        }
+    }
+
+    let start = start.unwrap_or(0);
+    let finish = |activities: Vec<Activity>| {
+        activities
+            .into_iter()
+            .map(|mut a| {
+                let from = a.last.unwrap_or(start);
+                a.gap(IdleInterval { start: from, end }, min_idle, keep);
+                a.summary
+            })
+            .collect()
+    };
+    Ok(IdleReport {
+        start_time: start,
+        end_time: end,
+        signals: finish(signals),
+        scopes: finish(scopes),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangeCount {
+    /// Hierarchical name of the signal or scope
+    pub path: String,
+    pub changes: u64,
+    /// Estimated size of the changes in the dump (text of the VCD commands, or uncompressed
+    /// values for an FST)
+    pub bytes: u64,
+}
+
+/// Number of value changes per signal and per scope (including its sub-scopes), in
+/// declaration order. Aliases are counted once, under the first variable declared.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangeReport {
+    pub signals: Vec<ChangeCount>,
+    pub scopes: Vec<ChangeCount>,
+}
+
+/// Per-signal counters, with the scope of each signal
+struct ChangeCounter<K> {
+    index: HashMap<K, usize>,
+    signals: Vec<ChangeCount>,
+    scopes: Vec<Vec<Scope>>,
+    demangler: Demangler,
+}
+
+impl<K: std::hash::Hash + Eq> ChangeCounter<K> {
+    fn new<'a, I>(variables: I, demangler: &Demangler) -> Self
+    where
+        I: Iterator<Item = (K, &'a VariableInfo)>,
+    {
+        let mut counter = ChangeCounter {
+            index: HashMap::new(),
+            signals: Vec::new(),
+            scopes: Vec::new(),
+            demangler: demangler.clone(),
+        };
+        for (key, v) in variables {
+            if counter.index.contains_key(&key) {
+                continue;
+            }
+            counter.index.insert(key, counter.signals.len());
+            counter.signals.push(ChangeCount {
+                path: demangler.path(v),
+                changes: 0,
+                bytes: 0,
+            });
+            counter.scopes.push(v.scope.clone());
+        }
+        counter
+    }
+
+    fn record<Q>(&mut self, key: &Q, bytes: usize)
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq + ?Sized,
+    {
+        self.add(key, 1, bytes as u64);
+    }
+
+    fn add<Q>(&mut self, key: &Q, changes: u64, bytes: u64)
+    where
+        K: Borrow<Q>,
+        Q: std::hash::Hash + Eq + ?Sized,
+    {
+        if let Some(i) = self.index.get(key) {
+            self.signals[*i].changes += changes;
+            self.signals[*i].bytes += bytes;
+        }
+    }
+
+    /// Rolls the signal counts up to their scopes
+    fn report(self) -> ChangeReport {
+        let counts = self
+            .signals
+            .iter()
+            .map(|s| (s.changes, s.bytes))
+            .zip(&self.scopes)
+            .map(|(c, scope)| (scope.as_slice(), c));
+        let scopes = rollup_with(counts, &self.demangler)
+            .into_iter()
+            .map(|s| ChangeCount {
+                path: s.path,
+                changes: s.total.0,
+                bytes: s.total.1,
+            })
+            .collect();
+        ChangeReport {
+            signals: self.signals,
+            scopes,
+        }
+    }
+}
+
+/// Counts the value changes of each variable in the rest of a VCD stream (the header must
+/// have been loaded), signals and scopes are named by `demangler`
+pub fn vcd_change_counts<R: Read>(
+    parser: &mut VcdParser<R>,
+    demangler: &Demangler,
+) -> Result<ChangeReport, VcdError> {
+    let header = parser.header().ok_or(VcdError::PartialHeader)?;
+    let variables = header.variables.iter().map(|v| (v.id.clone(), v));
+    let mut counter = ChangeCounter::new(variables, demangler);
+    while !parser.done() {
+        parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+            if let VcdCommand::ValueChange(v) = cmd {
+                let bytes = match v.value {
+                    // `<bit><id>\n`
+                    VcdValue::Bit(_) | VcdValue::Event => v.var_id.len() + 2,
+                    // `b<value> <id>\n` (`r` for reals, `s` for strings)
+                    VcdValue::Vector(x) | VcdValue::Real(x) | VcdValue::String(x) => {
+                        x.len() + v.var_id.len() + 3
+                    }
+                    // `p<states> <strength0> <strength1> <id>\n`
+                    VcdValue::Port(p) => p.states.len() + v.var_id.len() + 7,
+                };
+                counter.record(v.var_id, bytes);
+            }
+            Ok(false)
+        })?;
+    }
+    Ok(counter.report())
+}
+
+#[cfg(feature = "fst")]
+fn fst_value_size(value: *const u8) -> usize {
+    if value.is_null() {
+        0
+    } else {
+        unsafe { CStr::from_ptr(value as *const c_char) }
+            .to_bytes()
+            .len()
+    }
+}
+
+/// Counts the value changes of each variable of an FST file (only the changes of the
+/// handles tracked by the reader, if any, are counted), named by `demangler`
+#[cfg(feature = "fst")]
+pub fn fst_change_counts(reader: &mut FstReader, demangler: &Demangler) -> ChangeReport {
+    let header = reader.load_header();
+    let variables = header.variables.iter().map(|v| (v.handle, v));
+    let mut counter = ChangeCounter::new(variables, demangler);
+    reader.iter_blocks(|_, handle, value| {
+        counter.record(&handle, fst_value_size(value));
+    });
+    counter.report()
+}
+
+/// Same as `fst_change_counts`, with the handles of the file split across `jobs` threads
+/// (the number of CPUs when 0). Each thread opens its own reader of the file `name`, and
+/// only decodes the blocks of its handles.
+#[cfg(feature = "rayon")]
+pub fn par_fst_change_counts(
+    name: &str,
+    jobs: usize,
+    demangler: &Demangler,
+) -> Result<ChangeReport, FstError> {
+    let header = FstReader::from_file(name, false)?.load_header();
+    let variables = header.variables.iter().map(|v| (v.handle, v));
+    let mut counter = ChangeCounter::new(variables, demangler);
+    let mut handles: Vec<u32> = counter.index.keys().cloned().collect();
+    handles.sort_unstable();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .map_err(|_| FstError::Unsupported)?;
+    let parts = pool.current_num_threads();
+    let chunk = handles.len().div_ceil(parts).max(1);
+    let partial: Vec<HashMap<u32, (u64, u64)>> = pool.install(|| {
+        handles
+            .par_chunks(chunk)
+            .map(|part| {
+                let mut reader = FstReader::from_file(name, false)?;
+                let mut counts: HashMap<u32, (u64, u64)> = HashMap::new();
+                reader.iter_blocks_of(part, |_, handle, value| {
+                    let c = counts.entry(handle).or_default();
+                    c.0 += 1;
+                    c.1 += fst_value_size(value) as u64;
+                });
+                Ok(counts)
+            })
+            .collect::<Result<_, FstError>>()
+    })?;
+    for (handle, (changes, bytes)) in partial.into_iter().flatten() {
+        counter.add(&handle, changes, bytes);
+    }
+    Ok(counter.report())
+}
@@ -0,0 +1,140 @@
+//! Transparent decompression of compressed dumps, detected from their first bytes.
+//!
+//! Each format is enabled by a cargo feature (`gzip`, `zstd`, `xz` and `bzip2`, or all of
+//! them with `compression`). A compressed input whose format is not enabled fails with an
+//! `io::ErrorKind::Unsupported` error, instead of being parsed as garbage.
+//!
+//! Positions reported by parsers (e.g., in checkpoints) are offsets in the decompressed
+//! data, compressed files can not be resumed from them.
+use std::fs::File;
+use std::io;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+/// Number of bytes needed by `Compression::detect`
+pub const MAGIC_SIZE: usize = 6;
+
+impl Compression {
+    /// Guesses the format from the first bytes of a file
+    pub fn detect(magic: &[u8]) -> Self {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else if magic.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Whether this build can decompress the format
+    pub fn supported(self) -> bool {
+        match self {
+            Compression::None => true,
+            Compression::Gzip => cfg!(feature = "gzip"),
+            Compression::Zstd => cfg!(feature = "zstd"),
+            Compression::Xz => cfg!(feature = "xz"),
+            Compression::Bzip2 => cfg!(feature = "bzip2"),
+        }
+    }
+
+    fn feature(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Xz => "xz",
+            Compression::Bzip2 => "bzip2",
+        }
+    }
+}
+
+fn decoder<R: Read + Send + 'static>(
+    compression: Compression,
+    inner: R,
+) -> io::Result<Box<dyn Read + Send>> {
+    Ok(match compression {
+        Compression::None => Box::new(inner),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(inner)),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(inner)?),
+        #[cfg(feature = "xz")]
+        Compression::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(inner)),
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(inner)),
+        #[allow(unreachable_patterns)]
+        c => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "{:?} compressed input, `{}` feature not enabled in this build",
+                    c,
+                    c.feature()
+                ),
+            ))
+        }
+    })
+}
+
+/// Wraps `inner` in a decoder when its content is compressed, returns it as is otherwise
+pub fn decompress<R: Read + Send + 'static>(mut inner: R) -> io::Result<Box<dyn Read + Send>> {
+    let mut magic = Vec::with_capacity(MAGIC_SIZE);
+    (&mut inner)
+        .take(MAGIC_SIZE as u64)
+        .read_to_end(&mut magic)?;
+    let compression = Compression::detect(&magic);
+    decoder(compression, Cursor::new(magic).chain(inner))
+}
+
+/// Opens a file, decompressing it if needed
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Read + Send>> {
+    decompress(File::open(path)?)
+}
+
+/// Whether a path is the special name `-`, designating the standard input
+pub fn is_stdin<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref() == Path::new("-")
+}
+
+/// Opens a dump given by its path, decompressing it if needed (see `open`): the special
+/// name `-` reads from the standard input, which can only be read once.
+pub fn open_input<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Read + Send>> {
+    if is_stdin(&path) {
+        return decompress(io::stdin());
+    }
+    open(path)
+}
+
+/// Opens a file at the given offset of its decompressed content: plain files are seeked,
+/// compressed ones are decompressed up to the offset
+pub fn open_at<P: AsRef<Path>>(path: P, offset: u64) -> io::Result<Box<dyn Read + Send>> {
+    let mut file = File::open(path)?;
+    let mut magic = Vec::with_capacity(MAGIC_SIZE);
+    (&mut file)
+        .take(MAGIC_SIZE as u64)
+        .read_to_end(&mut magic)?;
+    let compression = Compression::detect(&magic);
+    if compression == Compression::None {
+        file.seek(SeekFrom::Start(offset))?;
+        return Ok(Box::new(file));
+    }
+    let mut input = decoder(compression, Cursor::new(magic).chain(file))?;
+    let skipped = io::copy(&mut (&mut input).take(offset), &mut io::sink())?;
+    if skipped < offset {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(input)
+}
@@ -0,0 +1,764 @@
+//! Conversion of dumps between VCD, FST (`fst` feature) and the native format (see
+//! `wdb`).
+//!
+//! VCD inputs may be compressed (see `compression`). The native format can only be
+//! written: it is made for random access, not for being converted back.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::Read;
+#[cfg(feature = "fst")]
+use std::io::Write;
+#[cfg(feature = "fst")]
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::compression;
+#[cfg(feature = "fst")]
+use crate::fst::{FstError, FstReader, FstWriter};
+use crate::hierarchy::Demangler;
+use crate::types::VariableInfo;
+#[cfg(feature = "fst")]
+use crate::types::{Scope, Timescale};
+use crate::vcd::{DumpSection, VcdCommand, VcdError, VcdParser, VcdValue};
+#[cfg(feature = "fst")]
+use crate::vcd_writer::short_id;
+#[cfg(feature = "fst")]
+use crate::wdb::WdbWriter;
+use crate::wdb::{convert_vcd, WaveformDb, WdbError};
+#[cfg(feature = "fst")]
+use crate::VcdWriter;
+
+#[derive(Debug)]
+pub enum ConvertError {
+    IoError(io::Error),
+    Vcd(VcdError),
+    #[cfg(feature = "fst")]
+    Fst(FstError),
+    Wdb(WdbError),
+    /// The conversion is not supported (native format as input, FST without the `fst`
+    /// feature, same input and output formats)
+    UnsupportedConversion(Format, Format),
+}
+
+impl std::fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            ConvertError::IoError(e) => e.fmt(f),
+            ConvertError::Vcd(e) => e.fmt(f),
+            ConvertError::Wdb(e) => e.fmt(f),
+            #[cfg(feature = "fst")]
+            ConvertError::Fst(e) => write!(f, "{:?}", e),
+            ConvertError::UnsupportedConversion(from, to) => write!(
+                f,
+                "conversion from {} to {} not supported",
+                from.name(),
+                to.name()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConvertError::IoError(e) => Some(e),
+            ConvertError::Vcd(e) => Some(e),
+            ConvertError::Wdb(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConvertError {
+    fn from(e: io::Error) -> Self {
+        ConvertError::IoError(e)
+    }
+}
+
+impl From<VcdError> for ConvertError {
+    fn from(e: VcdError) -> Self {
+        ConvertError::Vcd(e)
+    }
+}
+
+#[cfg(feature = "fst")]
+impl From<FstError> for ConvertError {
+    fn from(e: FstError) -> Self {
+        ConvertError::Fst(e)
+    }
+}
+
+impl From<WdbError> for ConvertError {
+    fn from(e: WdbError) -> Self {
+        ConvertError::Wdb(e)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Format {
+    Vcd,
+    Fst,
+    /// Native format (see `wdb`)
+    Wdb,
+}
+
+impl Format {
+    /// Guesses the format from the file extension (`.fst` is FST, `.wdb` the native
+    /// format, anything else VCD)
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("fst") => Format::Fst,
+            Some(e) if e.eq_ignore_ascii_case("wdb") => Format::Wdb,
+            _ => Format::Vcd,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Vcd => "vcd",
+            Format::Fst => "fst",
+            Format::Wdb => "wdb",
+        }
+    }
+
+    /// Extension of the files of this format
+    pub fn extension(self) -> &'static str {
+        self.name()
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vcd" => Ok(Format::Vcd),
+            "fst" => Ok(Format::Fst),
+            "wdb" => Ok(Format::Wdb),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+/// Value of a vector extended (or truncated, keeping the rightmost bits) to `width` bits,
+/// as VCD readers do: with `0` when the leftmost bit is `1`, with the leftmost bit
+/// otherwise
+pub(crate) fn extend_vector(value: &[u8], width: usize, out: &mut Vec<u8>) {
+    out.clear();
+    if value.len() >= width {
+        out.extend_from_slice(&value[value.len() - width..]);
+        return;
+    }
+    let fill = match value.first() {
+        Some(b'1') | None => b'0',
+        Some(c) => *c,
+    };
+    out.resize(width - value.len(), fill);
+    out.extend_from_slice(value);
+}
+
+/// Declares variables along with their scopes in an FST file, returns the handle of each
+/// variable (variables sharing a VCD id become aliases)
+#[cfg(feature = "fst")]
+fn declare_fst_variables(
+    writer: &mut FstWriter,
+    variables: &[VariableInfo],
+) -> Result<std::collections::HashMap<String, (u32, u32, bool)>, FstError> {
+    let mut handles = std::collections::HashMap::new();
+    let mut scopes: Vec<&Scope> = Vec::new();
+    for v in variables {
+        let common = scopes
+            .iter()
+            .zip(&v.scope)
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        while scopes.len() > common {
+            scopes.pop();
+            writer.pop_scope();
+        }
+        for s in &v.scope[common..] {
+            writer.push_scope(s.kind.clone(), &s.name)?;
+            scopes.push(s);
+        }
+        let alias = handles.get(&v.id).map(|(h, _, _)| *h);
+        let h = writer.create_var(v.kind.clone(), v.direction.clone(), v.width, &v.name, alias)?;
+        handles
+            .entry(v.id.clone())
+            .or_insert((h, v.width, v.kind.is_real()));
+    }
+    for _ in scopes {
+        writer.pop_scope();
+    }
+    Ok(handles)
+}
+
+/// Writes the body of a VCD file to an FST file, the header of the parser must have been
+/// loaded.
+///
+/// `$dumpoff`/`$dumpon` sections become dump activity changes (the values they hold are
+/// not written). Timestamps and dump activity changes after the last value change are
+/// dropped, and a dump without any value change gives a file where all the signals are
+/// `x` at time 0 (fstapi writes their initial values).
+#[cfg(feature = "fst")]
+pub fn vcd_to_fst<R: Read>(
+    parser: &mut VcdParser<R>,
+    writer: &mut FstWriter,
+) -> Result<(), ConvertError> {
+    let header = parser.header().ok_or(VcdError::PartialHeader)?;
+    if let Some(t) = header.timescale {
+        writer.set_timescale(t.exponent());
+    }
+    writer.set_time_zero(header.time_zero);
+    let handles = declare_fst_variables(writer, &header.variables)?;
+
+    let mut value = Vec::new();
+    let mut dumpoff = false;
+    let mut error = None;
+    // fstapi writes an unreadable file when times are emitted without any value change:
+    // times and dump activity changes are emitted along with the next value change
+    let mut time = None;
+    let mut last_time = 0;
+    let mut activity = Vec::new();
+    parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+        match cmd {
+            VcdCommand::SetCycle(t) => {
+                time = Some(t);
+                last_time = t;
+            }
+            VcdCommand::BeginDump(DumpSection::Off | DumpSection::PortsOff) => {
+                dumpoff = true;
+                activity.push((last_time, false));
+            }
+            VcdCommand::BeginDump(DumpSection::On | DumpSection::PortsOn) => {
+                activity.push((last_time, true))
+            }
+            VcdCommand::EndDump(_) => dumpoff = false,
+            VcdCommand::ValueChange(_) if dumpoff => {}
+            VcdCommand::ValueChange(c) => {
+                let (handle, width, real) =
+                    *handles.get(c.var_id).ok_or(VcdError::UnknownVariable)?;
+                for (t, active) in activity.drain(..) {
+                    writer.emit_time_change(t);
+                    writer.emit_dump_active(active);
+                }
+                if let Some(t) = time.take() {
+                    writer.emit_time_change(t);
+                }
+                let r = match (c.value, real) {
+                    (VcdValue::Real(x), true) => {
+                        let x = x.parse().map_err(|_| VcdError::ParseError)?;
+                        writer.emit_real_change(handle, x)
+                    }
+                    (VcdValue::Bit(b), false) => {
+                        extend_vector(&[b as u8], width as usize, &mut value);
+                        writer.emit_value_change(handle, &value)
+                    }
+                    (VcdValue::Event, false) => {
+                        extend_vector(b"1", width as usize, &mut value);
+                        writer.emit_value_change(handle, &value)
+                    }
+                    (VcdValue::Vector(x), false) => {
+                        extend_vector(x.as_bytes(), width as usize, &mut value);
+                        writer.emit_value_change(handle, &value)
+                    }
+                    (VcdValue::Port(p), false) => {
+                        extend_vector(p.levels().as_bytes(), width as usize, &mut value);
+                        writer.emit_value_change(handle, &value)
+                    }
+                    _ => return Err(VcdError::ParseError),
+                };
+                if let Err(e) = r {
+                    error = Some(e);
+                    return Ok(true);
+                }
+            }
+            _ => {}
+        }
+        Ok(false)
+    })?;
+    match error {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+/// Variables of an FST file with a VCD id derived from their handle (aliases share it)
+#[cfg(feature = "fst")]
+pub(crate) fn fst_variables(reader: &mut FstReader) -> Vec<VariableInfo> {
+    let mut variables = reader.load_header().variables;
+    for v in &mut variables {
+        v.id = short_id(v.handle as u64 - 1);
+        if v.kind.is_real() {
+            // FST gives the size of a double in bytes
+            v.width = 64;
+        }
+    }
+    variables
+}
+
+/// Writes the content of an FST file as a VCD file
+#[cfg(feature = "fst")]
+pub fn fst_to_vcd<W: Write>(reader: &mut FstReader, out: W) -> Result<W, ConvertError> {
+    let variables = fst_variables(reader);
+    let mut writer = VcdWriter::new(out);
+    let date = reader.date_string()?.trim();
+    if !date.is_empty() {
+        writer.date(date)?;
+    }
+    let version = reader.version_string()?.trim();
+    if !version.is_empty() {
+        writer.version(version)?;
+    }
+    writer.timescale(&Timescale::from_exponent(reader.timescale()))?;
+    if reader.time_zero() != 0 {
+        writer.time_zero(reader.time_zero())?;
+    }
+    writer.variables(&variables)?;
+    writer.enddefinitions()?;
+
+    // Width and whether the variable is a real, for each handle
+    let mut kinds = vec![None; reader.max_handle() as usize + 1];
+    for v in &variables {
+        kinds[v.handle as usize] = Some((v.width, v.kind.is_real()));
+    }
+    let mut activity = reader.dump_activity_changes().into_iter().peekable();
+    let mut time = None;
+    let mut result = Ok(());
+    reader.iter_blocks(|t, handle, value| {
+        if result.is_err() {
+            return;
+        }
+        result = (|| -> Result<(), ConvertError> {
+            if time != Some(t) {
+                write_activity(&mut writer, &mut activity, &mut time, t)?;
+                if time != Some(t) {
+                    writer.timestamp(t)?;
+                    time = Some(t);
+                }
+            }
+            if value.is_null() {
+                return Err(FstError::NullPointer.into());
+            }
+            let value = unsafe { std::ffi::CStr::from_ptr(value as *const std::os::raw::c_char) }
+                .to_str()
+                .map_err(|_| FstError::Utf8Error)?;
+            let id = short_id(handle as u64 - 1);
+            let value = match kinds.get(handle as usize) {
+                Some(Some((_, true))) => VcdValue::Real(value),
+                Some(Some((1, false))) if value.len() == 1 => {
+                    VcdValue::Bit(value.chars().next().unwrap())
+                }
+                Some(Some(_)) => VcdValue::Vector(value),
+                _ => return Err(FstError::UnknownHandle.into()),
+            };
+            writer.change(&id, &value)?;
+            Ok(())
+        })();
+    });
+    result?;
+    write_activity(&mut writer, &mut activity, &mut time, u64::MAX)?;
+    Ok(writer.finish()?)
+}
+
+/// Writes the dump activity changes up to `until` (included) as `$dumpoff`/`$dumpon`
+/// directives, `time` is the last timestamp written
+#[cfg(feature = "fst")]
+fn write_activity<W: Write>(
+    writer: &mut VcdWriter<W>,
+    activity: &mut Peekable<std::vec::IntoIter<(u64, bool)>>,
+    time: &mut Option<u64>,
+    until: u64,
+) -> io::Result<()> {
+    while let Some((at, on)) = activity.next_if(|(at, _)| *at <= until) {
+        if *time != Some(at) {
+            writer.timestamp(at)?;
+            *time = Some(at);
+        }
+        let section = if on {
+            DumpSection::On
+        } else {
+            DumpSection::Off
+        };
+        writer.command(&VcdCommand::BeginDump(section))?;
+        writer.command(&VcdCommand::EndDump(section))?;
+    }
+    Ok(())
+}
+
+/// Writes the content of an FST file in the native format
+#[cfg(feature = "fst")]
+pub fn fst_to_wdb<W: Write>(reader: &mut FstReader, out: W) -> Result<W, ConvertError> {
+    let variables = fst_variables(reader);
+    let mut writer = WdbWriter::new(out, &variables)?;
+    let mut result = Ok(());
+    reader.iter_blocks(|t, handle, value| {
+        if result.is_err() {
+            return;
+        }
+        if value.is_null() {
+            result = Err(FstError::NullPointer.into());
+            return;
+        }
+        let value = unsafe { std::ffi::CStr::from_ptr(value as *const std::os::raw::c_char) };
+        let id = short_id(handle as u64 - 1);
+        result = writer
+            .push_change(t, &id, value.to_bytes())
+            .map_err(ConvertError::from);
+    });
+    result?;
+    Ok(writer.finish()?)
+}
+
+/// Converts the file `input` into `output`, the formats are guessed from the extensions
+/// of the paths (see `Format::from_path`) unless `to` is given.
+///
+/// The output is written next to its final path then renamed, so an interrupted
+/// conversion never leaves a partial output.
+pub fn convert_file<P, Q>(input: P, output: Q, to: Option<Format>) -> Result<(), ConvertError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let (input, output) = (input.as_ref(), output.as_ref());
+    let from = Format::from_path(input);
+    let to = to.unwrap_or_else(|| Format::from_path(output));
+    let mut tmp = output.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = Path::new(&tmp);
+
+    let result = match (from, to) {
+        (Format::Vcd, Format::Wdb) => (|| {
+            let mut parser = VcdParser::with_chunk_size(1 << 16, compression::open_input(input)?);
+            parser.load_header()?;
+            convert_vcd(&mut parser, BufWriter::new(File::create(tmp)?))?
+                .into_inner()
+                .map_err(|e| e.into_error())?
+                .sync_all()?;
+            Ok(())
+        })(),
+        #[cfg(feature = "fst")]
+        (Format::Vcd, Format::Fst) => (|| {
+            let mut parser = VcdParser::with_chunk_size(1 << 16, compression::open_input(input)?);
+            parser.load_header()?;
+            let name = tmp.to_str().ok_or(FstError::Utf8Error)?;
+            // The file is complete once the writer is dropped
+            let mut writer = FstWriter::create(name, true)?;
+            vcd_to_fst(&mut parser, &mut writer)
+        })(),
+        #[cfg(feature = "fst")]
+        (Format::Fst, Format::Vcd) | (Format::Fst, Format::Wdb) => (|| {
+            let name = input.to_str().ok_or(FstError::Utf8Error)?;
+            let mut reader = FstReader::from_file(name, false)?;
+            let out = BufWriter::new(File::create(tmp)?);
+            let out = match to {
+                Format::Vcd => fst_to_vcd(&mut reader, out)?,
+                _ => fst_to_wdb(&mut reader, out)?,
+            };
+            out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            Ok(())
+        })(),
+        _ => return Err(ConvertError::UnsupportedConversion(from, to)),
+    };
+    match result {
+        Ok(()) => Ok(std::fs::rename(tmp, output)?),
+        Err(e) => {
+            let _ = std::fs::remove_file(tmp);
+            Err(e)
+        }
+    }
+}
+
+/// Number of value changes of a signal and hash of their (time, value) pairs, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignalDigest {
+    pub changes: u64,
+    pub hash: u64,
+}
+
+impl Default for SignalDigest {
+    fn default() -> Self {
+        SignalDigest {
+            changes: 0,
+            // FNV-1a offset basis
+            hash: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+}
+
+impl SignalDigest {
+    fn hash_bytes(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.hash = (self.hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    /// Adds a change, the value is normalized first so the digests of a signal match
+    /// across formats: vectors are extended to `width` bits and lowercased, reals are
+    /// hashed as numbers
+    fn push(&mut self, time: u64, value: &str, width: u32, real: bool, buf: &mut Vec<u8>) {
+        self.changes += 1;
+        self.hash_bytes(&time.to_le_bytes());
+        if real {
+            if let Ok(x) = value.parse::<f64>() {
+                self.hash_bytes(&x.to_bits().to_le_bytes());
+                return;
+            }
+        }
+        extend_vector(value.as_bytes(), width as usize, buf);
+        buf.make_ascii_lowercase();
+        self.hash_bytes(buf);
+    }
+}
+
+/// Digest of each signal of a dump, by hierarchical path (see `hierarchy::variable_path`)
+pub type DumpDigest = BTreeMap<String, SignalDigest>;
+
+/// Width and whether the values are reals, for each id
+fn value_kinds(variables: &[VariableInfo]) -> HashMap<&str, (u32, bool)> {
+    variables
+        .iter()
+        .map(|v| (v.id.as_str(), (v.width, v.kind.is_real())))
+        .collect()
+}
+
+/// Digest by path of the signals, from the digests by id
+fn by_path(
+    variables: &[VariableInfo],
+    digests: &HashMap<String, SignalDigest>,
+    demangler: &Demangler,
+) -> DumpDigest {
+    variables
+        .iter()
+        .map(|v| {
+            let d = digests.get(&v.id).copied().unwrap_or_default();
+            (demangler.path(v), d)
+        })
+        .collect()
+}
+
+fn vcd_digest(
+    path: &Path,
+    include_dumpoff: bool,
+    demangler: &Demangler,
+) -> Result<DumpDigest, ConvertError> {
+    let mut parser = VcdParser::with_chunk_size(1 << 16, compression::open_input(path)?);
+    parser.load_header()?;
+    digest_vcd(&mut parser, include_dumpoff, demangler)
+}
+
+/// Computes the digest of each signal of a VCD (see `digest_file_with`) from the body of a
+/// parser whose header is loaded, e.g., for a dump read once from the standard input
+pub fn digest_vcd<R: Read>(
+    parser: &mut VcdParser<R>,
+    include_dumpoff: bool,
+    demangler: &Demangler,
+) -> Result<DumpDigest, ConvertError> {
+    let variables = parser
+        .header()
+        .ok_or(VcdError::PartialHeader)?
+        .variables
+        .clone();
+    let kinds = value_kinds(&variables);
+    let mut digests: HashMap<String, SignalDigest> = HashMap::new();
+    let mut buf = Vec::new();
+    let mut time = 0;
+    let mut dumpoff = false;
+    parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+        match cmd {
+            VcdCommand::SetCycle(t) => time = t,
+            VcdCommand::BeginDump(DumpSection::Off | DumpSection::PortsOff) => {
+                dumpoff = !include_dumpoff
+            }
+            VcdCommand::EndDump(_) => dumpoff = false,
+            VcdCommand::ValueChange(_) if dumpoff => {}
+            VcdCommand::ValueChange(c) => {
+                let (width, real) = *kinds.get(c.var_id).ok_or(VcdError::UnknownVariable)?;
+                let mut bit = [0; 4];
+                let levels;
+                let value = match c.value {
+                    VcdValue::Bit(b) => &*b.encode_utf8(&mut bit),
+                    VcdValue::Event => "1",
+                    VcdValue::Vector(x) | VcdValue::Real(x) | VcdValue::String(x) => x,
+                    VcdValue::Port(p) => {
+                        levels = p.levels();
+                        &levels
+                    }
+                };
+                let digest = match digests.get_mut(c.var_id) {
+                    Some(d) => d,
+                    None => digests.entry(c.var_id.to_string()).or_default(),
+                };
+                digest.push(time, value, width, real, &mut buf);
+            }
+            _ => {}
+        }
+        Ok(false)
+    })?;
+    Ok(by_path(&variables, &digests, demangler))
+}
+
+#[cfg(feature = "fst")]
+fn fst_digest(path: &Path, demangler: &Demangler) -> Result<DumpDigest, ConvertError> {
+    let name = path.to_str().ok_or(FstError::Utf8Error)?;
+    let mut reader = FstReader::from_file(name, false)?;
+    let variables = fst_variables(&mut reader);
+    let mut kinds = vec![(0, false); reader.max_handle() as usize + 1];
+    for v in &variables {
+        kinds[v.handle as usize] = (v.width, v.kind.is_real());
+    }
+    let mut by_handle = vec![SignalDigest::default(); kinds.len()];
+    let mut buf = Vec::new();
+    let mut result = Ok(());
+    reader.iter_blocks(|t, handle, value| {
+        if value.is_null() {
+            result = Err(FstError::NullPointer);
+            return;
+        }
+        let value = unsafe { std::ffi::CStr::from_ptr(value as *const std::os::raw::c_char) };
+        match (value.to_str(), kinds.get(handle as usize)) {
+            (Ok(value), Some((width, real))) => {
+                by_handle[handle as usize].push(t, value, *width, *real, &mut buf)
+            }
+            (Err(_), _) => result = Err(FstError::Utf8Error),
+            (_, None) => result = Err(FstError::UnknownHandle),
+        }
+    });
+    result?;
+    let digests = variables
+        .iter()
+        .map(|v| (v.id.clone(), by_handle[v.handle as usize]))
+        .collect();
+    Ok(by_path(&variables, &digests, demangler))
+}
+
+fn wdb_digest(path: &Path, demangler: &Demangler) -> Result<DumpDigest, ConvertError> {
+    let mut db = WaveformDb::open(path)?;
+    let variables = db.variables().to_vec();
+    let mut digests = HashMap::new();
+    let mut buf = Vec::new();
+    for v in &variables {
+        if digests.contains_key(&v.id) {
+            continue;
+        }
+        let mut digest = SignalDigest::default();
+        for (t, value) in db.all_changes(&v.id)? {
+            digest.push(t, &value, v.width, v.kind.is_real(), &mut buf);
+        }
+        digests.insert(v.id.clone(), digest);
+    }
+    Ok(by_path(&variables, &digests, demangler))
+}
+
+/// Computes the digest of each signal of a dump, the format is guessed from the extension
+/// of the path.
+///
+/// With `include_dumpoff` unset, the values held by the `$dumpoff` sections of a VCD file
+/// are left out (as they are by `vcd_to_fst`).
+pub fn digest_file<P: AsRef<Path>>(
+    path: P,
+    include_dumpoff: bool,
+) -> Result<DumpDigest, ConvertError> {
+    digest_file_with(path, include_dumpoff, &Demangler::default())
+}
+
+/// Same as `digest_file`, with the signals named by a demangler, e.g., to compare the
+/// dumps of two simulators
+pub fn digest_file_with<P: AsRef<Path>>(
+    path: P,
+    include_dumpoff: bool,
+    demangler: &Demangler,
+) -> Result<DumpDigest, ConvertError> {
+    let path = path.as_ref();
+    match Format::from_path(path) {
+        Format::Vcd => vcd_digest(path, include_dumpoff, demangler),
+        #[cfg(feature = "fst")]
+        Format::Fst => fst_digest(path, demangler),
+        #[cfg(not(feature = "fst"))]
+        Format::Fst => Err(ConvertError::UnsupportedConversion(
+            Format::Fst,
+            Format::Fst,
+        )),
+        Format::Wdb => wdb_digest(path, demangler),
+    }
+}
+
+/// Difference between a dump and its conversion found by `verify_conversion`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// Signal of the input absent from the output
+    Missing(String),
+    /// Signal of the output absent from the input
+    Unexpected(String),
+    /// Signal with a different number of changes in the output
+    ChangeCount {
+        path: String,
+        input: u64,
+        output: u64,
+    },
+    /// Signal with the same number of changes, at other times or with other values
+    Values(String),
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Discrepancy::Missing(p) => write!(f, "{}: missing from the output", p),
+            Discrepancy::Unexpected(p) => write!(f, "{}: not in the input", p),
+            Discrepancy::ChangeCount {
+                path,
+                input,
+                output,
+            } => write!(
+                f,
+                "{}: {} changes in the input, {} in the output",
+                path, input, output
+            ),
+            Discrepancy::Values(p) => write!(f, "{}: different times or values", p),
+        }
+    }
+}
+
+/// Reads a dump and its conversion, returns the signals whose changes differ (nothing
+/// when the conversion is faithful).
+///
+/// Signals are matched by hierarchical path, their changes compared through their digests
+/// (see `digest_file`): the number of changes and a hash of the (time, value) pairs.
+pub fn verify_conversion<P, Q>(input: P, output: Q) -> Result<Vec<Discrepancy>, ConvertError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    // Only FST files drop the values of `$dumpoff` sections
+    let include_dumpoff = Format::from_path(output.as_ref()) != Format::Fst;
+    let input = digest_file(input, include_dumpoff)?;
+    let output = digest_file(output, include_dumpoff)?;
+    Ok(compare_digests(&input, &output))
+}
+
+/// Signals whose digests differ between a dump and its conversion (see
+/// `verify_conversion`)
+pub fn compare_digests(input: &DumpDigest, output: &DumpDigest) -> Vec<Discrepancy> {
+    let mut discrepancies = Vec::new();
+    for (path, a) in input {
+        match output.get(path) {
+            None => discrepancies.push(Discrepancy::Missing(path.clone())),
+            Some(b) if a.changes != b.changes => discrepancies.push(Discrepancy::ChangeCount {
+                path: path.clone(),
+                input: a.changes,
+                output: b.changes,
+            }),
+            Some(b) if a.hash != b.hash => discrepancies.push(Discrepancy::Values(path.clone())),
+            Some(_) => {}
+        }
+    }
+    for path in output.keys() {
+        if !input.contains_key(path) {
+            discrepancies.push(Discrepancy::Unexpected(path.clone()));
+        }
+    }
+    discrepancies
+}
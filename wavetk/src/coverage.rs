@@ -0,0 +1,341 @@
+//! Coverage metrics re-mined from waveforms.
+//!
+//! # Toggle coverage report
+//!
+//! `ToggleReport` serializes (e.g., with `serde_json`) to the following schema:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "total": { "bits": 66, "rise": 40, "fall": 38, "covered": 37 },
+//!   "scopes": [
+//!     {
+//!       "path": "top.alu",
+//!       "summary": { "bits": 64, "rise": 39, "fall": 37, "covered": 36 },
+//!       "nets": [
+//!         {
+//!           "name": "result",
+//!           "id": "'",
+//!           "width": 32,
+//!           "rise": [true, false, ...],
+//!           "fall": [true, false, ...]
+//!         }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! - `path` is the dot-separated hierarchical name of the scope (empty for top-level nets),
+//!   `nets` only lists the variables declared directly in that scope.
+//! - `rise[i]` (resp. `fall[i]`) tells whether bit `i` was seen going from 0 to 1 (resp.
+//!   1 to 0) between two consecutive cycles. Bit 0 is the leftmost bit in the dump (i.e.,
+//!   the most significant bit of `[N:0]` vectors). Transitions through X, Z, ... do not
+//!   count.
+//! - in summaries, `bits` is the number of bits, `rise`/`fall` the number of bits with a
+//!   rising/falling transition and `covered` the number of bits with both.
+//! - a net aliased in several scopes (same VCD id) is reported in each of them.
+//!
+//! # Value bins
+//!
+//! `ValueCoverage` counts, for selected buses, how often the bus took a value falling in
+//! user-defined bins (exact values, inclusive ranges and a default bin catching values
+//! no other bin matched). A hit is recorded each cycle the bus changes value, a value
+//! may hit several overlapping bins. Values with X/Z bits (or wider than 64 bits) are
+//! counted apart as `unknown`.
+use serde::Serialize;
+
+use crate::hierarchy::{rollup_with, Aggregate, ScopeTotal};
+use crate::simulation::{decode_unsigned, StateSimulation};
+use crate::vcd::VcdError;
+
+pub const TOGGLE_REPORT_VERSION: u32 = 1;
+
+/// Accumulates rising/falling transitions of each bit of the simulation state
+#[derive(Debug, Clone, Default)]
+pub struct ToggleCoverage {
+    rise: Vec<bool>,
+    fall: Vec<bool>,
+    previous: Vec<i8>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct ToggleSummary {
+    pub bits: usize,
+    pub rise: usize,
+    pub fall: usize,
+    pub covered: usize,
+}
+
+impl ToggleSummary {
+    fn add_bits(&mut self, rise: &[bool], fall: &[bool]) {
+        self.bits += rise.len();
+        self.rise += rise.iter().filter(|x| **x).count();
+        self.fall += fall.iter().filter(|x| **x).count();
+        self.covered += rise.iter().zip(fall).filter(|(r, f)| **r && **f).count();
+    }
+}
+
+impl Aggregate for ToggleSummary {
+    fn add(&mut self, other: &Self) {
+        self.bits += other.bits;
+        self.rise += other.rise;
+        self.fall += other.fall;
+        self.covered += other.covered;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct NetToggles {
+    pub name: String,
+    pub id: String,
+    pub width: u32,
+    pub rise: Vec<bool>,
+    pub fall: Vec<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ScopeToggles {
+    pub path: String,
+    pub summary: ToggleSummary,
+    pub nets: Vec<NetToggles>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ToggleReport {
+    pub version: u32,
+    pub total: ToggleSummary,
+    pub scopes: Vec<ScopeToggles>,
+}
+
+impl ToggleCoverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the transitions between the previously seen state and the given one
+    pub fn update(&mut self, state: &[i8]) {
+        if self.previous.len() != state.len() {
+            // First state seen (or layout changed): nothing to compare with
+            self.rise = vec![false; state.len()];
+            self.fall = vec![false; state.len()];
+            self.previous = state.to_vec();
+            return;
+        }
+        for (i, (p, c)) in self.previous.iter_mut().zip(state).enumerate() {
+            match (*p, *c) {
+                (0, 1) => self.rise[i] = true,
+                (1, 0) => self.fall[i] = true,
+                _ => {}
+            }
+            *p = *c;
+        }
+    }
+
+    /// Transitions seen on the bits of a variable
+    fn bits(&self, offset: usize, width: usize) -> (Vec<bool>, Vec<bool>) {
+        if self.rise.len() >= offset + width {
+            (
+                self.rise[offset..offset + width].to_vec(),
+                self.fall[offset..offset + width].to_vec(),
+            )
+        } else {
+            (vec![false; width], vec![false; width])
+        }
+    }
+
+    /// Summary of each scope with its sub-scopes (the scopes of `ToggleReport` only account
+    /// for the nets declared directly in them), see `hierarchy::rollup`
+    pub fn scope_rollup(
+        &self,
+        sim: &StateSimulation,
+    ) -> Result<Vec<ScopeTotal<ToggleSummary>>, VcdError> {
+        sim.header().ok_or(VcdError::PartialHeader)?;
+        let variables = sim.variables();
+        let totals = variables.iter().filter_map(|v| {
+            let offset = sim.variable_offset(&v.id)?;
+            let (rise, fall) = self.bits(offset, v.width as usize);
+            let mut summary = ToggleSummary::default();
+            summary.add_bits(&rise, &fall);
+            Some((v.scope.as_slice(), summary))
+        });
+        Ok(rollup_with(totals, sim.demangler()))
+    }
+
+    /// Builds the per-scope report, `sim` gives the layout of the states seen by `update`
+    pub fn report(&self, sim: &StateSimulation) -> Result<ToggleReport, VcdError> {
+        sim.header().ok_or(VcdError::PartialHeader)?;
+        let mut total = ToggleSummary::default();
+        let mut scopes: Vec<ScopeToggles> = Vec::new();
+        for v in sim.variables() {
+            let offset = match sim.variable_offset(&v.id) {
+                Some(o) => o,
+                None => continue,
+            };
+            let (rise, fall) = self.bits(offset, v.width as usize);
+            let path = sim.demangler().scope_path(&v.scope);
+            let scope = match scopes.iter_mut().position(|s| s.path == path) {
+                Some(i) => &mut scopes[i],
+                None => {
+                    scopes.push(ScopeToggles {
+                        path,
+                        summary: ToggleSummary::default(),
+                        nets: Vec::new(),
+                    });
+                    scopes.last_mut().unwrap()
+                }
+            };
+            scope.summary.add_bits(&rise, &fall);
+            total.add_bits(&rise, &fall);
+            scope.nets.push(NetToggles {
+                name: v.name.clone(),
+                id: v.id.clone(),
+                width: v.width,
+                rise,
+                fall,
+            });
+        }
+        Ok(ToggleReport {
+            version: TOGGLE_REPORT_VERSION,
+            total,
+            scopes,
+        })
+    }
+}
+
+/// Runs the simulation until the end and returns its toggle coverage report.
+///
+/// The state must have been allocated (variables not tracked are not reported).
+pub fn toggle_coverage(sim: &mut StateSimulation) -> Result<ToggleReport, VcdError> {
+    let mut coverage = ToggleCoverage::new();
+    while !sim.done() {
+        let state = sim.next_cycle()?.state;
+        coverage.update(state);
+    }
+    coverage.report(sim)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Bin {
+    Value(u64),
+    /// Inclusive range of values
+    Range(u64, u64),
+    /// Values not matched by any other bin
+    Default,
+}
+
+impl Bin {
+    fn contains(&self, x: u64) -> bool {
+        match self {
+            Bin::Value(v) => x == *v,
+            Bin::Range(lo, hi) => *lo <= x && x <= *hi,
+            Bin::Default => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BinHits {
+    pub name: String,
+    pub bin: Bin,
+    pub hits: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BusBins {
+    pub var_id: String,
+    pub bins: Vec<BinHits>,
+    pub unknown: u64,
+    #[serde(skip)]
+    previous: Option<Vec<i8>>,
+}
+
+impl BusBins {
+    fn sample(&mut self, bits: &[i8]) {
+        if self.previous.as_deref() == Some(bits) {
+            return;
+        }
+        self.previous = Some(bits.to_vec());
+        let x = match decode_unsigned(bits) {
+            Some(x) => x,
+            None => {
+                self.unknown += 1;
+                return;
+            }
+        };
+        let mut matched = false;
+        for b in self.bins.iter_mut().filter(|b| b.bin.contains(x)) {
+            b.hits += 1;
+            matched = true;
+        }
+        if !matched {
+            for b in self.bins.iter_mut().filter(|b| b.bin == Bin::Default) {
+                b.hits += 1;
+            }
+        }
+    }
+
+    /// Bins that were never hit
+    pub fn holes(&self) -> impl Iterator<Item = &BinHits> {
+        self.bins.iter().filter(|b| b.hits == 0)
+    }
+}
+
+/// Value-space coverage of a set of buses
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ValueCoverage {
+    pub buses: Vec<BusBins>,
+}
+
+impl ValueCoverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines the bins of the variable with the given VCD id, as (name, bin) pairs
+    pub fn add_bins(&mut self, var_id: &str, bins: Vec<(&str, Bin)>) {
+        self.buses.push(BusBins {
+            var_id: var_id.to_string(),
+            bins: bins
+                .into_iter()
+                .map(|(name, bin)| BinHits {
+                    name: name.to_string(),
+                    bin,
+                    hits: 0,
+                })
+                .collect(),
+            unknown: 0,
+            previous: None,
+        });
+    }
+
+    /// Samples the current state of the simulation (typically after each `next_cycle`),
+    /// untracked variables are ignored.
+    pub fn update(&mut self, sim: &StateSimulation) {
+        let state = sim.state();
+        for bus in self.buses.iter_mut() {
+            let offset = match sim.variable_offset(&bus.var_id) {
+                Some(o) => o,
+                None => continue,
+            };
+            let width = sim.variable_width(&bus.var_id).unwrap_or(0);
+            bus.sample(&state[offset..offset + width]);
+        }
+    }
+
+    pub fn bus(&self, var_id: &str) -> Option<&BusBins> {
+        self.buses.iter().find(|b| b.var_id == var_id)
+    }
+}
+
+/// Runs the simulation until the end, sampling the given bins at each cycle
+pub fn value_coverage(
+    sim: &mut StateSimulation,
+    mut coverage: ValueCoverage,
+) -> Result<ValueCoverage, VcdError> {
+    while !sim.done() {
+        sim.next_cycle()?;
+        coverage.update(sim);
+    }
+    Ok(coverage)
+}
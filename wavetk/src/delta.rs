@@ -0,0 +1,171 @@
+//! Compact binary stream of the state changes of a simulation, for incremental consumers
+//! (e.g., a checker running in another process).
+//!
+//! All integers are little-endian. The stream starts with a header:
+//! - the magic `WTKD`, then the format version (u32, currently 1),
+//! - the size of the state (u64).
+//!
+//! Followed by one frame per cycle:
+//! - the cycle (i64), then the number of entries (u32),
+//! - for each entry: the offset in the state (u64), the number of levels (u32), then the
+//!   levels (one i8 each, as in the state).
+//!
+//! An entry holds the whole value of a changed variable. The first frame written holds all
+//! the tracked variables, so that a consumer starts from a complete state.
+use std::io;
+use std::io::{Read, Write};
+
+use crate::simulation::StateSimulation;
+use crate::vcd::VcdError;
+
+pub const DELTA_MAGIC: &[u8; 4] = b"WTKD";
+pub const DELTA_VERSION: u32 = 1;
+/// Largest state accepted by `DeltaReader::new` (in levels, one byte each)
+pub const DELTA_MAX_STATE_SIZE: usize = 1 << 30;
+
+/// Writes the changes of a simulation, one frame per cycle
+pub struct DeltaWriter<W: Write> {
+    out: W,
+    started: bool,
+}
+
+impl<W: Write> DeltaWriter<W> {
+    /// Writes the header of the stream, for a state of the given size
+    pub fn new(mut out: W, state_size: usize) -> io::Result<Self> {
+        out.write_all(DELTA_MAGIC)?;
+        out.write_all(&DELTA_VERSION.to_le_bytes())?;
+        out.write_all(&(state_size as u64).to_le_bytes())?;
+        Ok(DeltaWriter {
+            out,
+            started: false,
+        })
+    }
+
+    /// Writes the frame of the last cycle returned by `next_cycle`: the variables changed
+    /// during that cycle (all the tracked variables for the first frame)
+    pub fn write_cycle(&mut self, sim: &StateSimulation, cycle: i64) -> io::Result<()> {
+        let tracked = sim.tracked_variables();
+        let entries: Vec<usize> = if self.started {
+            sim.dirty_variables().collect()
+        } else {
+            (0..tracked.len()).collect()
+        };
+        self.started = true;
+        self.out.write_all(&cycle.to_le_bytes())?;
+        self.out.write_all(&(entries.len() as u32).to_le_bytes())?;
+        let state = sim.state();
+        for i in entries {
+            let (offset, info) = &tracked[i];
+            let width = sim.variable_width(&info.id).unwrap_or(0);
+            self.out.write_all(&(*offset as u64).to_le_bytes())?;
+            self.out.write_all(&(width as u32).to_le_bytes())?;
+            let levels: Vec<u8> = state[*offset..*offset + width]
+                .iter()
+                .map(|l| *l as u8)
+                .collect();
+            self.out.write_all(&levels)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes and returns the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Runs the simulation until the end, writing a frame for each cycle (the state must have
+/// been allocated)
+pub fn stream_deltas<W: Write>(sim: &mut StateSimulation, out: W) -> Result<W, VcdError> {
+    let mut writer = DeltaWriter::new(out, sim.state().len())?;
+    while !sim.done() {
+        let cycle = sim.next_cycle()?.cycle;
+        writer.write_cycle(sim, cycle)?;
+    }
+    Ok(writer.finish()?)
+}
+
+/// Reads a stream written by `DeltaWriter` and maintains the state it describes
+pub struct DeltaReader<R: Read> {
+    input: R,
+    state: Vec<i8>,
+}
+
+impl<R: Read> DeltaReader<R> {
+    /// Reads the header of the stream, which must describe a state of at most
+    /// `DELTA_MAX_STATE_SIZE` levels
+    pub fn new(input: R) -> io::Result<Self> {
+        Self::with_max_state_size(input, DELTA_MAX_STATE_SIZE)
+    }
+
+    /// Reads the header of the stream, a state larger than `max_state_size` levels fails
+    /// with an `io::ErrorKind::InvalidData` error instead of being allocated
+    pub fn with_max_state_size(mut input: R, max_state_size: usize) -> io::Result<Self> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != DELTA_MAGIC || read_u32(&mut input)? != DELTA_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a delta stream (or unsupported version)",
+            ));
+        }
+        let size = read_u64(&mut input)?;
+        if size > max_state_size as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("state of {} levels beyond the limit", size),
+            ));
+        }
+        let size = size as usize;
+        Ok(DeltaReader {
+            input,
+            state: vec![0; size],
+        })
+    }
+
+    /// State after the last frame read
+    pub fn state(&self) -> &[i8] {
+        &self.state
+    }
+
+    /// Applies the next frame to the state and returns its cycle, None at the end of the
+    /// stream
+    pub fn next_frame(&mut self) -> io::Result<Option<i64>> {
+        let mut cycle = [0; 8];
+        match self.input.read_exact(&mut cycle) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            r => r?,
+        }
+        let count = read_u32(&mut self.input)?;
+        for _ in 0..count {
+            let offset = read_u64(&mut self.input)? as usize;
+            let width = read_u32(&mut self.input)? as usize;
+            let state = &mut self.state;
+            let slot = offset
+                .checked_add(width)
+                .and_then(|end| state.get_mut(offset..end))
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "entry out of the state")
+                })?;
+            let mut levels = vec![0u8; width];
+            self.input.read_exact(&mut levels)?;
+            for (el, l) in slot.iter_mut().zip(levels) {
+                *el = l as i8;
+            }
+        }
+        Ok(Some(i64::from_le_bytes(cycle)))
+    }
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut b = [0; 4];
+    input.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut b = [0; 8];
+    input.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
@@ -0,0 +1,468 @@
+//! Derived (virtual) signals, computed on each cycle of a simulation from the values of
+//! other signals.
+//!
+//! A definition names the signal and gives an expression over the signals of the dump,
+//! e.g. `top.fifo_full = wr_ptr == rd_ptr && !empty`. Signals are designated by their
+//! hierarchical name (see `StateSimulation::path_of`) or by the end of it (`fifo.wr_ptr`,
+//! `wr_ptr`) when it is not ambiguous. Constants are decimal, hexadecimal (`0x1f`) or
+//! binary (`0b101`).
+//!
+//! Operators, from the lowest to the highest precedence, as in Verilog: `||`, `&&`, `|`,
+//! `^`, `&`, `==` `!=`, `<` `<=` `>` `>=`, `+` `-`, and the unary `!` (logical not) and
+//! `~` (bitwise not). Parentheses group sub-expressions.
+//!
+//! Comparisons and logical operators give a single bit, bitwise and arithmetic operators
+//! give a value as wide as their widest operand (arithmetic wraps around). Operands
+//! holding unknown bits (`x`, `z`, ...) make the whole result unknown, except for `&&`
+//! and `||` whose result is already decided by the other operand. Values are computed on
+//! 64 bits, wider signals and reals cannot be used.
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::simulation::{StateSimulation, LEVEL_0, LEVEL_1, LEVEL_H, LEVEL_L, LEVEL_X};
+use crate::types::{Direction, Scope, ScopeKind, VariableInfo, VariableKind};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DerivedError {
+    /// Malformed definition, with the offending part
+    Syntax(String),
+    /// No variable with the given hierarchical name (or end of name)
+    UnknownSignal(String),
+    /// The end of name designates several variables, listed by hierarchical name
+    AmbiguousSignal(String, Vec<String>),
+    /// The variable cannot be used in an expression (real, or wider than 64 bits)
+    UnsupportedSignal(String),
+    /// The name of the derived signal is already taken
+    DuplicateName(String),
+    /// A signal of the expression has no place in the state (e.g., a reconstructed bus once
+    /// the reconstruction is disabled), reported by `allocate_state`
+    UnallocatedSignal(String),
+}
+
+impl fmt::Display for DerivedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DerivedError::Syntax(s) => write!(f, "invalid definition: {:?}", s),
+            DerivedError::UnknownSignal(s) => write!(f, "unknown signal: {}", s),
+            DerivedError::AmbiguousSignal(s, paths) => {
+                write!(f, "ambiguous signal {} ({})", s, paths.join(", "))
+            }
+            DerivedError::UnsupportedSignal(s) => {
+                write!(f, "signal {} is real or wider than 64 bits", s)
+            }
+            DerivedError::DuplicateName(s) => write!(f, "signal {} already exists", s),
+            DerivedError::UnallocatedSignal(s) => write!(f, "signal {} is not in the state", s),
+        }
+    }
+}
+
+impl Error for DerivedError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+}
+
+// By increasing precedence, two-character operators first at each level (`<=` before `<`,
+// `||` before `|`)
+const BINARY_OPERATORS: [&[(&str, BinaryOp)]; 8] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[
+        ("<=", BinaryOp::Le),
+        (">=", BinaryOp::Ge),
+        ("<", BinaryOp::Lt),
+        (">", BinaryOp::Gt),
+    ],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    /// Index in the operands of the derived signal
+    Signal(usize),
+    Constant(u64),
+    Not(Box<Expr>),
+    Invert(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+/// Mask of the `width` low bits
+fn mask(width: u32) -> u64 {
+    if width >= 64 {
+        u64::MAX
+    } else {
+        (1 << width) - 1
+    }
+}
+
+impl Expr {
+    /// Width of the result, given the width of the operands
+    fn width(&self, operands: &[u32]) -> u32 {
+        match self {
+            Expr::Signal(i) => operands[*i],
+            Expr::Constant(c) => (64 - c.leading_zeros()).max(1),
+            Expr::Invert(e) => e.width(operands),
+            Expr::Binary(op, a, b) => match op {
+                BinaryOp::BitOr
+                | BinaryOp::BitXor
+                | BinaryOp::BitAnd
+                | BinaryOp::Add
+                | BinaryOp::Sub => a.width(operands).max(b.width(operands)),
+                _ => 1,
+            },
+            Expr::Not(_) => 1,
+        }
+    }
+
+    /// Value of the expression (None if unknown), given the value and the width of the
+    /// operands
+    fn eval(&self, values: &[Option<u64>], widths: &[u32]) -> Option<u64> {
+        match self {
+            Expr::Signal(i) => values[*i],
+            Expr::Constant(c) => Some(*c),
+            Expr::Not(e) => Some((e.eval(values, widths)? == 0) as u64),
+            Expr::Invert(e) => Some(!e.eval(values, widths)? & mask(e.width(widths))),
+            Expr::Binary(op, a, b) => {
+                let (x, y) = (a.eval(values, widths), b.eval(values, widths));
+                match op {
+                    BinaryOp::Or => match (x, y) {
+                        (Some(x), _) if x != 0 => Some(1),
+                        (_, Some(y)) if y != 0 => Some(1),
+                        (Some(_), Some(_)) => Some(0),
+                        _ => None,
+                    },
+                    BinaryOp::And => match (x, y) {
+                        (Some(0), _) | (_, Some(0)) => Some(0),
+                        (Some(_), Some(_)) => Some(1),
+                        _ => None,
+                    },
+                    _ => {
+                        let (x, y) = (x?, y?);
+                        let m = mask(self.width(widths));
+                        Some(match op {
+                            BinaryOp::BitOr => x | y,
+                            BinaryOp::BitXor => x ^ y,
+                            BinaryOp::BitAnd => x & y,
+                            BinaryOp::Eq => (x == y) as u64,
+                            BinaryOp::Ne => (x != y) as u64,
+                            BinaryOp::Lt => (x < y) as u64,
+                            BinaryOp::Le => (x <= y) as u64,
+                            BinaryOp::Gt => (x > y) as u64,
+                            BinaryOp::Ge => (x >= y) as u64,
+                            BinaryOp::Add => x.wrapping_add(y) & m,
+                            BinaryOp::Sub => x.wrapping_sub(y) & m,
+                            BinaryOp::Or | BinaryOp::And => unreachable!(),
+                        })
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Recursive descent parser of expressions, collecting the names of the signals
+struct Parser<'a> {
+    text: &'a str,
+    rest: &'a str,
+    signals: Vec<String>,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | '$' | '[' | ']' | ':')
+}
+
+impl<'a> Parser<'a> {
+    fn syntax(&self) -> DerivedError {
+        DerivedError::Syntax(self.text.trim().to_string())
+    }
+
+    /// Consumes `token` if the input starts with it
+    fn eat(&mut self, token: &str) -> bool {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(token) {
+            Some(r) => {
+                self.rest = r;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, DerivedError> {
+        if level == BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        'operators: loop {
+            self.rest = self.rest.trim_start();
+            for (token, op) in BINARY_OPERATORS[level] {
+                // `|` and `&` are not the start of `||` and `&&`
+                let doubled =
+                    matches!(*token, "|" | "&") && self.rest.starts_with(&token.repeat(2));
+                if !doubled && self.eat(token) {
+                    let rhs = self.binary(level + 1)?;
+                    lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'operators;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, DerivedError> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("~") {
+            return Ok(Expr::Invert(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let e = self.binary(0)?;
+            if !self.eat(")") {
+                return Err(self.syntax());
+            }
+            return Ok(e);
+        }
+        self.rest = self.rest.trim_start();
+        let end = self
+            .rest
+            .find(|c| !is_name_char(c))
+            .unwrap_or(self.rest.len());
+        let (token, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        if token.starts_with(|c: char| c.is_ascii_digit()) {
+            let digits = |s: &str| s.chars().filter(|c| *c != '_').collect::<String>();
+            let value = if let Some(x) = token.strip_prefix("0x") {
+                u64::from_str_radix(&digits(x), 16)
+            } else if let Some(x) = token.strip_prefix("0b") {
+                u64::from_str_radix(&digits(x), 2)
+            } else {
+                digits(token).parse()
+            };
+            return value.map(Expr::Constant).map_err(|_| self.syntax());
+        }
+        if token.is_empty() {
+            return Err(self.syntax());
+        }
+        let i = match self.signals.iter().position(|s| s == token) {
+            Some(i) => i,
+            None => {
+                self.signals.push(token.to_string());
+                self.signals.len() - 1
+            }
+        };
+        Ok(Expr::Signal(i))
+    }
+}
+
+/// A signal computed from the values of other signals (see the module documentation).
+///
+/// Derived signals are added to a simulation with `StateSimulation::add_derived_signal`
+/// before `allocate_state`, they are then part of the state like the variables of the dump.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivedSignal {
+    text: String,
+    name: String,
+    expr: Expr,
+    /// Names of the signals, as written in the expression
+    signals: Vec<String>,
+    /// VCD id and width of each signal, once resolved
+    operands: Vec<(String, u32)>,
+    /// Offset of each signal in the state, once bound
+    offsets: Vec<usize>,
+    /// Width and value of each signal, reused by each `update`
+    widths: Vec<u32>,
+    values: Vec<Option<u64>>,
+}
+
+impl DerivedSignal {
+    /// Hierarchical name of the derived signal
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Names of the signals used by the expression, as written
+    pub fn signals(&self) -> impl Iterator<Item = &str> {
+        self.signals.iter().map(|s| s.as_str())
+    }
+
+    /// Width of the derived signal (None until resolved)
+    pub fn width(&self) -> Option<u32> {
+        if self.operands.len() != self.signals.len() {
+            return None;
+        }
+        let widths: Vec<u32> = self.operands.iter().map(|o| o.1).collect();
+        Some(self.expr.width(&widths))
+    }
+
+    /// Finds the variables used by the expression among the variables of the simulation,
+    /// returns their VCD ids
+    pub(crate) fn resolve(&mut self, sim: &StateSimulation) -> Result<Vec<String>, DerivedError> {
+        let variables = sim.variables();
+        let mut operands = Vec::with_capacity(self.signals.len());
+        for name in &self.signals {
+            let suffix = format!(".{}", name);
+            let mut found: Vec<(String, &VariableInfo)> = Vec::new();
+            for v in &variables {
+                let path = sim.path_of(v);
+                if path == *name {
+                    found = vec![(path, v)];
+                    break;
+                }
+                if path.ends_with(&suffix) && found.iter().all(|(_, f)| f.id != v.id) {
+                    found.push((path, v));
+                }
+            }
+            let v = match found.as_slice() {
+                [(_, v)] => v,
+                [] => return Err(DerivedError::UnknownSignal(name.clone())),
+                _ => {
+                    let paths = found.into_iter().map(|f| f.0).collect();
+                    return Err(DerivedError::AmbiguousSignal(name.clone(), paths));
+                }
+            };
+            if v.kind.is_real() || v.width > 64 {
+                return Err(DerivedError::UnsupportedSignal(name.clone()));
+            }
+            operands.push((v.id.clone(), v.width));
+        }
+        self.operands = operands;
+        Ok(self.operands.iter().map(|o| o.0.clone()).collect())
+    }
+
+    /// VCD ids of the signals used by the expression, once resolved
+    pub(crate) fn operand_ids(&self) -> impl Iterator<Item = &str> {
+        self.operands.iter().map(|o| o.0.as_str())
+    }
+
+    /// Variable describing the derived signal, with the given VCD id
+    pub(crate) fn info(&self, id: String) -> VariableInfo {
+        let mut names: Vec<&str> = self.name.split('.').collect();
+        let name = names.pop().unwrap_or_default();
+        VariableInfo {
+            id,
+            name: name.to_string(),
+            direction: Direction::Implicit,
+            kind: VariableKind::VcdWire,
+            width: self.width().unwrap_or(1),
+            range: None,
+            handle: 0,
+            scope: names
+                .into_iter()
+                .map(|n| Scope {
+                    kind: ScopeKind::VcdModule,
+                    name: n.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Records the offset of the signals in the state, given by `offset_of` for each VCD id
+    pub(crate) fn bind<F>(&mut self, offset_of: F) -> Result<(), DerivedError>
+    where
+        F: Fn(&str) -> Option<usize>,
+    {
+        let mut offsets = Vec::with_capacity(self.operands.len());
+        for ((id, _), name) in self.operands.iter().zip(&self.signals) {
+            let offset =
+                offset_of(id).ok_or_else(|| DerivedError::UnallocatedSignal(name.clone()))?;
+            offsets.push(offset);
+        }
+        self.offsets = offsets;
+        self.widths = self.operands.iter().map(|o| o.1).collect();
+        self.values = Vec::with_capacity(self.operands.len());
+        Ok(())
+    }
+
+    /// Writes the value of the derived signal for `state` into `out` (as wide as the
+    /// signal), returns whether it changed
+    pub(crate) fn update(&mut self, state: &[i8], out: &mut [i8]) -> bool {
+        self.values.clear();
+        for (offset, width) in self.offsets.iter().zip(&self.widths) {
+            let bits = &state[*offset..*offset + *width as usize];
+            // Weak levels are read as the strong ones (as `to_X01` in VHDL)
+            self.values
+                .push(bits.iter().try_fold(0u64, |acc, b| match *b {
+                    LEVEL_0 | LEVEL_L => Some(acc << 1),
+                    LEVEL_1 | LEVEL_H => Some(acc << 1 | 1),
+                    _ => None,
+                }));
+        }
+        let value = self.expr.eval(&self.values, &self.widths);
+        let mut changed = false;
+        let width = out.len();
+        for (i, el) in out.iter_mut().enumerate() {
+            let level = match value {
+                Some(v) => {
+                    let bit = width - 1 - i;
+                    if bit < 64 && v >> bit & 1 == 1 {
+                        LEVEL_1
+                    } else {
+                        LEVEL_0
+                    }
+                }
+                None => LEVEL_X,
+            };
+            changed |= *el != level;
+            *el = level;
+        }
+        changed
+    }
+}
+
+impl fmt::Display for DerivedSignal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl FromStr for DerivedSignal {
+    type Err = DerivedError;
+
+    /// Parses a definition, `<name> = <expression>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let syntax = || DerivedError::Syntax(s.trim().to_string());
+        let (name, expr) = s.split_once('=').ok_or_else(syntax)?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(is_name_char) || expr.starts_with('=') {
+            return Err(syntax());
+        }
+        let mut parser = Parser {
+            text: expr,
+            rest: expr,
+            signals: Vec::new(),
+        };
+        let expr = parser.binary(0)?;
+        if !parser.rest.trim().is_empty() {
+            return Err(parser.syntax());
+        }
+        Ok(DerivedSignal {
+            text: s.trim().to_string(),
+            name: name.to_string(),
+            expr,
+            signals: parser.signals,
+            operands: Vec::new(),
+            offsets: Vec::new(),
+            widths: Vec::new(),
+            values: Vec::new(),
+        })
+    }
+}
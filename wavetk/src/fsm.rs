@@ -0,0 +1,153 @@
+//! Reconstruction of finite state machines from a state register.
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::simulation::{decode_unsigned, logic_char, CycleStep, StateSimulation};
+use crate::vcd::VcdError;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FsmState {
+    /// Decoded value of the register, None if it holds X/Z bits
+    pub value: Option<u64>,
+    /// Enum name if provided, the decoded value (or the raw bits) otherwise. Only meant for
+    /// display: several states may share a label.
+    pub label: String,
+    pub visits: u64,
+    pub first_time: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FsmTransition {
+    /// Indices in `FsmGraph::states`
+    pub from: usize,
+    pub to: usize,
+    pub count: u64,
+    pub first_time: i64,
+}
+
+/// Observed state-transition graph, states and transitions are ordered by first occurrence
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FsmGraph {
+    pub states: Vec<FsmState>,
+    pub transitions: Vec<FsmTransition>,
+}
+
+impl FsmGraph {
+    /// Index of the state of the register holding `bits`. States are told apart by their
+    /// value, or by their raw bits when they hold X/Z bits, never by their label (names
+    /// may be shared or equal to the label of another encoding).
+    fn state_index(&mut self, bits: &[i8], names: &HashMap<u64, String>, time: i64) -> usize {
+        let value = decode_unsigned(bits);
+        let label = match value {
+            Some(v) => names.get(&v).cloned().unwrap_or_else(|| v.to_string()),
+            None => bits.iter().map(|b| logic_char(*b)).collect(),
+        };
+        let same = |s: &FsmState| match value {
+            Some(_) => s.value == value,
+            None => s.value.is_none() && s.label == label,
+        };
+        match self.states.iter().position(same) {
+            Some(i) => i,
+            None => {
+                self.states.push(FsmState {
+                    value,
+                    label,
+                    visits: 0,
+                    first_time: time,
+                });
+                self.states.len() - 1
+            }
+        }
+    }
+
+    fn record(&mut self, from: Option<usize>, to: usize, time: i64) {
+        self.states[to].visits += 1;
+        let from = match from {
+            Some(f) => f,
+            None => return,
+        };
+        match self
+            .transitions
+            .iter_mut()
+            .find(|t| t.from == from && t.to == to)
+        {
+            Some(t) => t.count += 1,
+            None => self.transitions.push(FsmTransition {
+                from,
+                to,
+                count: 1,
+                first_time: time,
+            }),
+        }
+    }
+
+    /// Exports the graph in Graphviz DOT format, edges are labeled with their count and
+    /// first occurrence time
+    pub fn to_dot(&self, name: &str) -> String {
+        let mut s = String::new();
+        writeln!(s, "digraph \"{}\" {{", dot_escape(name)).unwrap();
+        for (i, st) in self.states.iter().enumerate() {
+            writeln!(
+                s,
+                "    s{} [label=\"{}\\nvisits={}\"];",
+                i,
+                dot_escape(&st.label),
+                st.visits
+            )
+            .unwrap();
+        }
+        for t in &self.transitions {
+            writeln!(
+                s,
+                "    s{} -> s{} [label=\"{} (@{})\"];",
+                t.from, t.to, t.count, t.first_time
+            )
+            .unwrap();
+        }
+        s.push_str("}\n");
+        s
+    }
+}
+
+/// Escapes a string for a quoted DOT identifier
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Runs the simulation until the end and reconstructs the transitions taken by the given
+/// state register (a tracked VCD id), `names` optionally maps encodings to state names.
+///
+/// The section before the first timestamp is ignored, as the register has no meaningful
+/// value yet.
+pub fn extract_fsm(
+    sim: &mut StateSimulation,
+    var_id: &str,
+    names: &HashMap<u64, String>,
+) -> Result<FsmGraph, VcdError> {
+    let offset = sim
+        .variable_offset(var_id)
+        .ok_or(VcdError::UnknownVariable)?;
+    let width = sim.variable_width(var_id).unwrap_or(0);
+    let mut graph = FsmGraph::default();
+    let mut previous: Option<(Vec<i8>, usize)> = None;
+    while !sim.done() {
+        let CycleStep {
+            cycle: time, state, ..
+        } = sim.next_cycle()?;
+        if time < 0 {
+            continue;
+        }
+        let bits = &state[offset..offset + width];
+        if let Some((p, _)) = &previous {
+            if p.as_slice() == bits {
+                continue;
+            }
+        }
+        let to = graph.state_index(bits, names, time);
+        graph.record(previous.map(|(_, i)| i), to, time);
+        previous = Some((bits.to_vec(), to));
+    }
+    Ok(graph)
+}
@@ -1,11 +1,18 @@
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::ffi::{CStr, CString};
 use std::os::raw::{c_char, c_uchar, c_void};
 use std::ptr::null_mut;
 use std::slice;
 use std::str;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
-use crate::types::{Direction, FstHeader, Scope, ScopeKind, VariableInfo, VariableKind};
+use crate::hierarchy::Demangler;
+use crate::simulation::{logic_level, LEVEL_0};
+use crate::types::{
+    Direction, FstHeader, Scope, ScopeKind, Timescale, Timestamp, VariableInfo, VariableKind,
+};
 use fst_sys;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -14,6 +21,10 @@ pub enum FstError {
     InvalidConversion,
     NullPointer,
     Utf8Error,
+    Unsupported,
+    UnknownHandle,
+    /// No variable has the given hierarchical path
+    UnknownVariable,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -23,11 +34,37 @@ pub enum FstFileType {
     VerilogVhdl,
 }
 
+/// Compression algorithm used for value change blocks (`vcd2fst --fastpack` selects
+/// FastLz)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FstPackType {
+    Zlib,
+    FastLz,
+    Lz4,
+}
+
 #[derive(Debug)]
 pub struct FstReader {
     handle: *mut c_void,
+    // Handles decoded by `iter_blocks` (all when empty)
+    tracked: Vec<fst_sys::fstHandle>,
+    // Kept to reopen the file (see `refresh`)
+    path: CString,
+    use_extensions: bool,
+    // Time of the last change given by `iter_new_blocks`
+    followed: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct FstWriter {
+    handle: *mut c_void,
+    // Width of each variable created, indexed by (handle - 1)
+    widths: Vec<u32>,
 }
 
+/// Whether fst-sys compiles fstapi with the parallel writer (it currently never does)
+pub const FST_WRITER_PARALLEL: bool = false;
+
 type FstChangeCallback = extern "C" fn(*mut c_void, u64, fst_sys::fstHandle, *const c_uchar);
 
 fn make_string(ptr: *const c_char, n: usize) -> String {
@@ -40,7 +77,18 @@ fn make_string(ptr: *const c_char, n: usize) -> String {
 
 impl FstReader {
     pub fn from_file(name: &str, use_extensions: bool) -> Result<FstReader, FstError> {
-        let p = unsafe { fst_sys::fstReaderOpen(CString::new(name).unwrap().as_ptr()) };
+        let path = CString::new(name).or(Err(FstError::InvalidConversion))?;
+        Ok(FstReader {
+            handle: Self::open(&path, use_extensions)?,
+            tracked: Vec::new(),
+            path,
+            use_extensions,
+            followed: None,
+        })
+    }
+
+    fn open(path: &CStr, use_extensions: bool) -> Result<*mut c_void, FstError> {
+        let p = unsafe { fst_sys::fstReaderOpen(path.as_ptr()) };
         if p.is_null() {
             return Err(FstError::InvalidFile);
         }
@@ -49,7 +97,91 @@ impl FstReader {
                 fst_sys::fstReaderSetVcdExtensions(p, 1);
             }
         }
-        Ok(FstReader { handle: p })
+        Ok(p)
+    }
+
+    /// Opens the file again, to see the blocks appended since it was opened: simulators
+    /// writing an FST flush value change blocks periodically, the file can be read while
+    /// it grows. Returns whether the end time moved.
+    ///
+    /// The tracked handles are kept, the time range is reset. On error (e.g., the file is
+    /// being written), the reader is left untouched and the call can be retried.
+    pub fn refresh(&mut self) -> Result<bool, FstError> {
+        let end_time = self.end_time();
+        let p = Self::open(&self.path, self.use_extensions)?;
+        unsafe {
+            fst_sys::fstReaderClose(self.handle);
+        }
+        self.handle = p;
+        Ok(self.end_time() != end_time)
+    }
+
+    /// Calls `f` with the value changes of the tracked handles that happened after the
+    /// ones given by the previous call (all of them on the first call), as `iter_blocks`.
+    /// Combined with `refresh`, this reads a dump as it is written.
+    ///
+    /// Overrides the time range. Returns the number of changes given to `f`.
+    pub fn iter_new_blocks<F>(&mut self, mut f: F) -> usize
+    where
+        F: FnMut(u64, fst_sys::fstHandle, *const c_uchar),
+    {
+        let end_time = self.end_time();
+        let after = self.followed;
+        match after {
+            Some(t) if t >= end_time => return 0,
+            // Blocks starting before the range are skipped, a block starts at the end time of
+            // the previous one
+            Some(t) => self.time_range(Some((t, end_time))),
+            None => self.time_range(None),
+        }
+        let mut n = 0;
+        self.iter_blocks(|time, handle, value| {
+            // The values at the start of a block are given even for unchanged variables
+            if after.is_none_or(|t| time > t) {
+                n += 1;
+                f(time, handle, value);
+            }
+        });
+        self.followed = Some(end_time);
+        n
+    }
+
+    /// Reads the file as it is written by a simulator (like `tail -f`): calls `f` with each
+    /// value change of the tracked handles, and checks every `poll` whether new blocks were
+    /// appended. Returns when `f` returns true, or when the file did not grow for
+    /// `idle_timeout`.
+    ///
+    /// As blocks are decoded at once, the changes of the block being decoded when `f` asks
+    /// to stop are not given to `f` anymore but still decompressed.
+    pub fn follow<F>(&mut self, poll: Duration, idle_timeout: Option<Duration>, mut f: F)
+    where
+        F: FnMut(u64, fst_sys::fstHandle, *const c_uchar) -> bool,
+    {
+        let mut stop = false;
+        loop {
+            self.iter_new_blocks(|time, handle, value| {
+                stop = stop || f(time, handle, value);
+            });
+            if stop || !self.wait_for_growth(poll, idle_timeout) {
+                return;
+            }
+        }
+    }
+
+    /// Checks every `poll` whether new blocks were appended to the file, returns false if
+    /// it did not grow for `idle_timeout`
+    fn wait_for_growth(&mut self, poll: Duration, idle_timeout: Option<Duration>) -> bool {
+        let start = Instant::now();
+        loop {
+            if idle_timeout.is_some_and(|t| start.elapsed() >= t) {
+                return false;
+            }
+            sleep(poll);
+            // A failed open is expected while the writer updates the file
+            if let Ok(true) = self.refresh() {
+                return true;
+            }
+        }
     }
 
     fn iter_hier<F>(&mut self, mut callback: F)
@@ -81,7 +213,7 @@ impl FstReader {
         self.iter_hier(|h| match h.htyp as u32 {
             fst_sys::fstHierType_FST_HT_SCOPE => {
                 let x = unsafe { h.u.scope };
-                let kind = ScopeKind::try_from(x.typ as u8).unwrap();
+                let kind = ScopeKind::try_from(x.typ).unwrap();
                 scope.push(Scope {
                     kind,
                     name: make_string(x.name, x.name_length as usize),
@@ -92,8 +224,8 @@ impl FstReader {
             }
             fst_sys::fstHierType_FST_HT_VAR => {
                 let x = unsafe { h.u.var };
-                let kind = VariableKind::try_from(x.typ as u8).unwrap();
-                let direction = Direction::try_from(x.direction as u8).unwrap();
+                let kind = VariableKind::try_from(x.typ).unwrap();
+                let direction = Direction::try_from(x.direction).unwrap();
                 header.variables.push(VariableInfo {
                     id: "".to_string(),
                     name: make_string(x.name, x.name_length as usize),
@@ -111,17 +243,122 @@ impl FstReader {
         header
     }
 
+    /// Restricts `iter_blocks` to the given handles (in addition to the ones already
+    /// tracked), through the facility process mask: the blocks of the other variables are
+    /// never decompressed.
+    pub fn track_handles(&mut self, handles: &[fst_sys::fstHandle]) {
+        for h in handles {
+            if !self.tracked.contains(h) {
+                self.tracked.push(*h);
+            }
+        }
+    }
+
+    /// Replaces the handles tracked by `track_handles`, an empty selection decodes all the
+    /// handles again
+    pub fn set_tracked_handles(&mut self, handles: &[fst_sys::fstHandle]) {
+        self.tracked.clear();
+        self.track_handles(handles);
+    }
+
+    /// Tracks variables given by their hierarchical path (e.g., `top.cpu.pc`), resolved to
+    /// their handles (see `track_handles`), aliases share the same handle. Fails with
+    /// `FstError::UnknownVariable`, without tracking any of them, if a path is not found.
+    pub fn track_variables(&mut self, paths: &[&str]) -> Result<(), FstError> {
+        let header = self.load_header();
+        let demangler = Demangler::default();
+        let by_path: HashMap<String, fst_sys::fstHandle> = header
+            .variables
+            .iter()
+            .map(|v| (demangler.path(v), v.handle))
+            .collect();
+        let handles = paths
+            .iter()
+            .map(|p| by_path.get(*p).copied().ok_or(FstError::UnknownVariable))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.track_handles(&handles);
+        Ok(())
+    }
+
+    /// Handles selected by `track_handles` (all the handles are decoded when empty)
+    pub fn tracked_handles(&self) -> &[fst_sys::fstHandle] {
+        &self.tracked
+    }
+
+    fn apply_process_mask(&mut self) {
+        unsafe {
+            if self.tracked.is_empty() {
+                fst_sys::fstReaderSetFacProcessMaskAll(self.handle);
+            } else {
+                fst_sys::fstReaderClrFacProcessMaskAll(self.handle);
+                for h in &self.tracked {
+                    fst_sys::fstReaderSetFacProcessMask(self.handle, *h);
+                }
+            }
+        }
+    }
+
+    /// Calls `f` with each value change of the tracked handles, as (time, handle, value)
     pub fn iter_blocks<F>(&mut self, mut f: F) -> i32
     where
         F: FnMut(u64, fst_sys::fstHandle, *const c_uchar),
     {
+        self.apply_process_mask();
         unsafe {
-            fst_sys::fstReaderSetFacProcessMaskAll(self.handle);
             let (data, f) = unpack_closure(&mut f);
             fst_sys::fstReaderIterBlocks(self.handle, Some(f), data, null_mut())
         }
     }
 
+    /// Same as `iter_blocks`, for the given handles instead of the tracked ones
+    pub fn iter_blocks_of<F>(&mut self, handles: &[fst_sys::fstHandle], mut f: F) -> i32
+    where
+        F: FnMut(u64, fst_sys::fstHandle, *const c_uchar),
+    {
+        unsafe {
+            fst_sys::fstReaderClrFacProcessMaskAll(self.handle);
+            for h in handles {
+                fst_sys::fstReaderSetFacProcessMask(self.handle, *h);
+            }
+            let (data, f) = unpack_closure(&mut f);
+            let r = fst_sys::fstReaderIterBlocks(self.handle, Some(f), data, null_mut());
+            self.apply_process_mask();
+            r
+        }
+    }
+
+    /// Value changes of a single variable, as (time, value) pairs.
+    ///
+    /// Only the blocks of this variable are decoded (using the facility process mask), so
+    /// this is much cheaper than `iter_blocks` on large dumps.
+    pub fn changes_of(
+        &mut self,
+        handle: fst_sys::fstHandle,
+    ) -> Result<Vec<(u64, String)>, FstError> {
+        if handle == 0 || handle > self.max_handle() {
+            return Err(FstError::UnknownHandle);
+        }
+        let mut changes = Vec::new();
+        let mut error = None;
+        self.iter_blocks_of(&[handle], |time, h, value| {
+            if h != handle || error.is_some() {
+                return;
+            }
+            if value.is_null() {
+                error = Some(FstError::NullPointer);
+                return;
+            }
+            match unsafe { CStr::from_ptr(value as *const c_char) }.to_str() {
+                Ok(v) => changes.push((time, v.to_string())),
+                Err(_) => error = Some(FstError::Utf8Error),
+            }
+        });
+        match error {
+            Some(e) => Err(e),
+            None => Ok(changes),
+        }
+    }
+
     pub fn end_time(&self) -> u64 {
         unsafe { fst_sys::fstReaderGetEndTime(self.handle) }
     }
@@ -158,10 +395,59 @@ impl FstReader {
         unsafe { fst_sys::fstReaderGetTimezero(self.handle) }
     }
 
+    /// Converts a time of the file into a timestamp, the time zero offset is applied to the
+    /// real time only.
+    pub fn timestamp(&self, time: u64) -> Timestamp {
+        let timescale = Timescale::from_exponent(self.timescale());
+        Timestamp {
+            raw: time as i64,
+            seconds: Some(timescale.to_seconds(time as i64 + self.time_zero())),
+        }
+    }
+
     pub fn var_count(&self) -> u64 {
         unsafe { fst_sys::fstReaderGetVarCount(self.handle) }
     }
 
+    /// Number of variables sharing the value changes of another one
+    pub fn alias_count(&self) -> u64 {
+        unsafe { fst_sys::fstReaderGetAliasCount(self.handle) }
+    }
+
+    /// Number of value change blocks, one per flush of the writer
+    pub fn value_change_section_count(&self) -> u64 {
+        unsafe { fst_sys::fstReaderGetValueChangeSectionCount(self.handle) }
+    }
+
+    /// Memory the writer was allowed to use for buffering value changes, in bytes
+    pub fn memory_used_by_writer(&self) -> u64 {
+        unsafe { fst_sys::fstReaderGetMemoryUsedByWriter(self.handle) }
+    }
+
+    /// Times at which dumping was switched off (false) or back on (true), as emitted by
+    /// `FstWriter::emit_dump_active`
+    pub fn dump_activity_changes(&self) -> Vec<(u64, bool)> {
+        let n = unsafe { fst_sys::fstReaderGetNumberDumpActivityChanges(self.handle) };
+        (0..n)
+            .map(|i| unsafe {
+                (
+                    fst_sys::fstReaderGetDumpActivityChangeTime(self.handle, i),
+                    fst_sys::fstReaderGetDumpActivityChangeValue(self.handle, i) != 0,
+                )
+            })
+            .collect()
+    }
+
+    /// Whether doubles are stored with the byte order of this machine
+    pub fn double_endian_match(&self) -> bool {
+        unsafe { fst_sys::fstReaderGetDoubleEndianMatchState(self.handle) != 0 }
+    }
+
+    /// Whether a seek in the file failed while reading it (e.g., truncated file)
+    pub fn fseek_failed(&self) -> bool {
+        unsafe { fst_sys::fstReaderGetFseekFailed(self.handle) != 0 }
+    }
+
     pub fn version_string(&self) -> Result<&str, FstError> {
         let c_str = unsafe {
             let p = fst_sys::fstReaderGetVersionString(self.handle);
@@ -199,6 +485,335 @@ impl Drop for FstReader {
     }
 }
 
+/// State of the tracked variables of an FST file (one logic level of `simulation` per
+/// bit), updated while the file is read as it is written by a simulator: the counterpart
+/// of a `StateSimulation` reading a VCD file being written.
+///
+/// Real and string variables can not be tracked.
+pub struct FstSimulation {
+    reader: FstReader,
+    header: FstHeader,
+    demangler: Demangler,
+    // Offset in the state and width of the tracked variables, indexed by handle
+    slots: Vec<Option<(usize, usize)>>,
+    tracked: Vec<(usize, VariableInfo)>,
+    state: Vec<i8>,
+}
+
+impl FstSimulation {
+    pub fn from_file(name: &str) -> Result<Self, FstError> {
+        let mut reader = FstReader::from_file(name, false)?;
+        let header = reader.load_header();
+        let slots = vec![None; reader.max_handle() as usize + 1];
+        Ok(FstSimulation {
+            reader,
+            header,
+            demangler: Demangler::default(),
+            slots,
+            tracked: Vec::new(),
+            state: Vec::new(),
+        })
+    }
+
+    pub fn header(&self) -> &FstHeader {
+        &self.header
+    }
+
+    /// Sets the rules giving the hierarchical names of the variables (see `path_of`), must
+    /// be called before `track_variables`
+    pub fn set_demangler(&mut self, demangler: Demangler) {
+        self.demangler = demangler;
+    }
+
+    /// Hierarchical name of a variable, demangled by the rules given to `set_demangler`
+    pub fn path_of(&self, v: &VariableInfo) -> String {
+        self.demangler.path(v)
+    }
+
+    /// Adds variables, given by their hierarchical path, at the end of the state. They are
+    /// resolved to their handles, the only ones decoded from the file (see
+    /// `FstReader::track_handles`). Fails, without tracking any of them, with
+    /// `FstError::UnknownVariable` if a path is not found, or `FstError::Unsupported` for
+    /// a real or string variable.
+    pub fn track_variables(&mut self, paths: &[&str]) -> Result<(), FstError> {
+        let mut variables = Vec::with_capacity(paths.len());
+        for p in paths {
+            let v = self
+                .header
+                .variables
+                .iter()
+                .find(|v| self.path_of(v) == *p)
+                .ok_or(FstError::UnknownVariable)?;
+            if v.kind.is_real() || v.kind == VariableKind::GenString {
+                return Err(FstError::Unsupported);
+            }
+            variables.push(v.clone());
+        }
+        for v in variables {
+            let slot = &mut self.slots[v.handle as usize];
+            if slot.is_some() {
+                continue;
+            }
+            let offset = self.state.len();
+            *slot = Some((offset, v.width as usize));
+            self.reader.track_handles(&[v.handle]);
+            self.state.resize(offset + v.width as usize, LEVEL_0);
+            self.tracked.push((offset, v));
+        }
+        Ok(())
+    }
+
+    /// Tracked variables, with their offset in the state
+    pub fn tracked_variables(&self) -> &[(usize, VariableInfo)] {
+        &self.tracked
+    }
+
+    /// Offset in the state and width of the tracked variable with the given hierarchical
+    /// path
+    pub fn slot_of(&self, path: &str) -> Option<(usize, usize)> {
+        self.tracked
+            .iter()
+            .find(|(_, v)| self.path_of(v) == path)
+            .map(|(offset, v)| (*offset, v.width as usize))
+    }
+
+    pub fn state(&self) -> &[i8] {
+        &self.state
+    }
+
+    /// Reads the file as it is written (see `FstReader::follow`): calls `f` with the time
+    /// and the state after the changes of each time, and checks every `poll` whether new
+    /// blocks were appended. Returns when `f` returns true, or when the file did not grow
+    /// for `idle_timeout`.
+    ///
+    /// The last time of the file is given once its blocks are read (a block ends between
+    /// two times).
+    pub fn follow<F>(&mut self, poll: Duration, idle_timeout: Option<Duration>, mut f: F)
+    where
+        F: FnMut(u64, &[i8]) -> bool,
+    {
+        loop {
+            let slots = &self.slots;
+            let state = &mut self.state;
+            let mut current = None;
+            let mut stop = false;
+            self.reader.iter_new_blocks(|time, handle, value| {
+                if stop {
+                    return;
+                }
+                match current {
+                    Some(t) if t != time => {
+                        stop = f(t, state);
+                        if stop {
+                            return;
+                        }
+                    }
+                    _ => {}
+                }
+                current = Some(time);
+                let (offset, width) = match slots.get(handle as usize) {
+                    Some(Some(slot)) if !value.is_null() => *slot,
+                    _ => return,
+                };
+                let value = unsafe { CStr::from_ptr(value as *const c_char) }.to_bytes();
+                for (s, c) in state[offset..offset + width].iter_mut().zip(value) {
+                    *s = logic_level(*c as char);
+                }
+            });
+            if let (false, Some(t)) = (stop, current) {
+                stop = f(t, &self.state);
+            }
+            if stop || !self.reader.wait_for_growth(poll, idle_timeout) {
+                return;
+            }
+        }
+    }
+}
+
+fn make_cstring(s: &str) -> Result<CString, FstError> {
+    CString::new(s).or(Err(FstError::InvalidConversion))
+}
+
+impl FstWriter {
+    /// Creates a new FST file, `compress_hierarchy` enables the compression of the
+    /// hierarchy section (as `vcd2fst --compress`).
+    pub fn create(name: &str, compress_hierarchy: bool) -> Result<FstWriter, FstError> {
+        let name = make_cstring(name)?;
+        let p = unsafe { fst_sys::fstWriterCreate(name.as_ptr(), compress_hierarchy as i32) };
+        if p.is_null() {
+            return Err(FstError::InvalidFile);
+        }
+        Ok(FstWriter {
+            handle: p,
+            widths: Vec::new(),
+        })
+    }
+
+    pub fn set_pack_type(&mut self, pack_type: FstPackType) {
+        let typ = match pack_type {
+            FstPackType::Zlib => fst_sys::fstWriterPackType_FST_WR_PT_ZLIB,
+            FstPackType::FastLz => fst_sys::fstWriterPackType_FST_WR_PT_FASTLZ,
+            FstPackType::Lz4 => fst_sys::fstWriterPackType_FST_WR_PT_LZ4,
+        };
+        unsafe { fst_sys::fstWriterSetPackType(self.handle, typ) }
+    }
+
+    /// When enabled, the whole file is recompressed when the writer is closed, which gives
+    /// smaller files at the cost of a slower close.
+    pub fn set_repack_on_close(&mut self, enable: bool) {
+        unsafe { fst_sys::fstWriterSetRepackOnClose(self.handle, enable as i32) }
+    }
+
+    /// When enabled, value change blocks are compressed in a separate thread (as
+    /// `vcd2fst --parallel`).
+    ///
+    /// Returns `FstError::Unsupported` if the bundled fstapi was built without
+    /// `FST_WRITER_PARALLEL`, in which case the C library would exit the process.
+    pub fn set_parallel_mode(&mut self, enable: bool) -> Result<(), FstError> {
+        if enable && !FST_WRITER_PARALLEL {
+            return Err(FstError::Unsupported);
+        }
+        unsafe { fst_sys::fstWriterSetParallelMode(self.handle, enable as i32) };
+        Ok(())
+    }
+
+    // The exponent of the timescale, time = cycle 10^(timescale)
+    pub fn set_timescale(&mut self, timescale: i8) {
+        unsafe { fst_sys::fstWriterSetTimescale(self.handle, timescale as i32) }
+    }
+
+    pub fn set_time_zero(&mut self, time_zero: i64) {
+        unsafe { fst_sys::fstWriterSetTimezero(self.handle, time_zero) }
+    }
+
+    pub fn set_file_type(&mut self, file_type: FstFileType) {
+        let typ = match file_type {
+            FstFileType::Verilog => fst_sys::fstFileType_FST_FT_VERILOG,
+            FstFileType::Vhdl => fst_sys::fstFileType_FST_FT_VHDL,
+            FstFileType::VerilogVhdl => fst_sys::fstFileType_FST_FT_VERILOG_VHDL,
+        };
+        unsafe { fst_sys::fstWriterSetFileType(self.handle, typ) }
+    }
+
+    pub fn set_date(&mut self, date: &str) -> Result<(), FstError> {
+        let date = make_cstring(date)?;
+        unsafe { fst_sys::fstWriterSetDate(self.handle, date.as_ptr()) };
+        Ok(())
+    }
+
+    pub fn set_version(&mut self, version: &str) -> Result<(), FstError> {
+        let version = make_cstring(version)?;
+        unsafe { fst_sys::fstWriterSetVersion(self.handle, version.as_ptr()) };
+        Ok(())
+    }
+
+    pub fn set_comment(&mut self, comment: &str) -> Result<(), FstError> {
+        let comment = make_cstring(comment)?;
+        unsafe { fst_sys::fstWriterSetComment(self.handle, comment.as_ptr()) };
+        Ok(())
+    }
+
+    pub fn push_scope(&mut self, kind: ScopeKind, name: &str) -> Result<(), FstError> {
+        let name = make_cstring(name)?;
+        unsafe { fst_sys::fstWriterSetScope(self.handle, kind as u32, name.as_ptr(), null_mut()) };
+        Ok(())
+    }
+
+    pub fn pop_scope(&mut self) {
+        unsafe { fst_sys::fstWriterSetUpscope(self.handle) }
+    }
+
+    /// Declares a variable in the current scope and returns its handle.
+    ///
+    /// If `alias` is given, the new variable shares the value changes of the given handle.
+    pub fn create_var(
+        &mut self,
+        kind: VariableKind,
+        direction: Direction,
+        width: u32,
+        name: &str,
+        alias: Option<fst_sys::fstHandle>,
+    ) -> Result<fst_sys::fstHandle, FstError> {
+        let name = make_cstring(name)?;
+        let h = unsafe {
+            fst_sys::fstWriterCreateVar(
+                self.handle,
+                kind as u32,
+                direction as u32,
+                width,
+                name.as_ptr(),
+                alias.unwrap_or(0),
+            )
+        };
+        if alias.is_none() {
+            debug_assert_eq!(h as usize, self.widths.len() + 1);
+            self.widths.push(width);
+        }
+        Ok(h)
+    }
+
+    pub fn emit_time_change(&mut self, time: u64) {
+        unsafe { fst_sys::fstWriterEmitTimeChange(self.handle, time) }
+    }
+
+    /// Emits a new value for the given variable, the value is made of one ASCII character
+    /// per bit ('0', '1', 'x', 'z', ...) and must match the declared width.
+    pub fn emit_value_change(
+        &mut self,
+        handle: fst_sys::fstHandle,
+        value: &[u8],
+    ) -> Result<(), FstError> {
+        match self.widths.get((handle as usize).wrapping_sub(1)) {
+            Some(w) if *w as usize == value.len() => {}
+            _ => return Err(FstError::InvalidConversion),
+        }
+        unsafe {
+            fst_sys::fstWriterEmitValueChange(self.handle, handle, value.as_ptr() as *const c_void)
+        };
+        Ok(())
+    }
+
+    /// Emits a new value for a real variable
+    pub fn emit_real_change(
+        &mut self,
+        handle: fst_sys::fstHandle,
+        value: f64,
+    ) -> Result<(), FstError> {
+        if self.widths.get((handle as usize).wrapping_sub(1)).is_none() {
+            return Err(FstError::UnknownHandle);
+        }
+        unsafe {
+            fst_sys::fstWriterEmitValueChange(
+                self.handle,
+                handle,
+                &value as *const f64 as *const c_void,
+            )
+        };
+        Ok(())
+    }
+
+    pub fn emit_dump_active(&mut self, enable: bool) {
+        unsafe { fst_sys::fstWriterEmitDumpActive(self.handle, enable as i32) }
+    }
+
+    /// Writes the buffered value changes as a new block, at the next time change (readers
+    /// can then see them, see `FstReader::refresh`)
+    pub fn flush(&mut self) {
+        unsafe { fst_sys::fstWriterFlushContext(self.handle) }
+    }
+}
+
+impl Drop for FstWriter {
+    fn drop(&mut self) {
+        if self.handle.is_null() {
+            return;
+        }
+        unsafe {
+            fst_sys::fstWriterClose(self.handle);
+        }
+    }
+}
+
 unsafe fn unpack_closure<F>(closure: &mut F) -> (*mut c_void, FstChangeCallback)
 where
     F: FnMut(u64, fst_sys::fstHandle, *const c_uchar),
@@ -0,0 +1,245 @@
+//! Named groups of signals (e.g., `axi_rd`, `debug_bus`), handled as a unit: tracked by a
+//! simulation, compared between two dumps, exported or rendered.
+//!
+//! A group is a list of signals, each given by its hierarchical path or by the end of it
+//! (e.g., `top.axi.arvalid`, `axi.arvalid` or `arvalid`), matching all the variables whose
+//! path is equal to it or ends with it. Groups are saved as a small JSON file, understood
+//! by the library and the command line tool (where `@axi_rd` stands for the signals of the
+//! group):
+//!
+//! ```json
+//! {
+//!   "groups": {
+//!     "axi_rd": ["axi.arvalid", "axi.arready", "axi.araddr"]
+//!   }
+//! }
+//! ```
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hierarchy::{diff_hierarchies_with, Demangler, HierarchyChange};
+use crate::simulation::StateSimulation;
+use crate::types::VariableInfo;
+
+#[derive(Debug)]
+pub enum GroupError {
+    IoError(io::Error),
+    UnknownGroup(String),
+    /// No variable matches a signal of a group
+    UnknownSignal(String),
+    /// Signals of a group can only be resolved once the header is loaded
+    MissingHeader,
+}
+
+impl std::fmt::Display for GroupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            GroupError::IoError(e) => e.fmt(f),
+            GroupError::UnknownGroup(g) => write!(f, "unknown group {:?}", g),
+            GroupError::UnknownSignal(s) => write!(f, "no signal matches {:?}", s),
+            GroupError::MissingHeader => write!(f, "header not loaded"),
+        }
+    }
+}
+
+impl std::error::Error for GroupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GroupError::IoError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for GroupError {
+    fn from(e: io::Error) -> Self {
+        GroupError::IoError(e)
+    }
+}
+
+/// Variables designated by a signal, by index in `variables`: the variables whose
+/// hierarchical path is equal to it or ends with it
+pub fn matching_variables(variables: &[VariableInfo], signal: &str) -> Vec<usize> {
+    matching_variables_with(variables, signal, &Demangler::default())
+}
+
+/// Same as `matching_variables`, with the paths given by a demangler
+pub fn matching_variables_with(
+    variables: &[VariableInfo],
+    signal: &str,
+    demangler: &Demangler,
+) -> Vec<usize> {
+    let suffix = format!(".{}", signal);
+    variables
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| {
+            let path = demangler.path(v);
+            path == signal || path.ends_with(&suffix)
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Named groups of signals, sorted by name
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignalGroups {
+    groups: BTreeMap<String, Vec<String>>,
+}
+
+impl SignalGroups {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads groups saved by `save`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, GroupError> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(io::BufReader::new(file)).map_err(io::Error::from)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), GroupError> {
+        let mut data = serde_json::to_vec_pretty(self).map_err(io::Error::from)?;
+        data.push(b'\n');
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Defines a group, replacing the one with the same name
+    pub fn define(&mut self, name: &str, signals: &[&str]) {
+        let signals = signals.iter().map(|s| s.to_string()).collect();
+        self.groups.insert(name.to_string(), signals);
+    }
+
+    /// Removes a group, returns whether it existed
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// Signals of a group
+    pub fn get(&self, name: &str) -> Option<&[String]> {
+        self.groups.get(name).map(|s| s.as_slice())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.groups.keys().map(|k| k.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Replaces the references to groups (`@name`) of a list of signals by the signals of
+    /// the groups, other signals are kept as is
+    pub fn expand(&self, signals: &[&str]) -> Result<Vec<String>, GroupError> {
+        let mut expanded = Vec::new();
+        for s in signals {
+            match s.strip_prefix('@') {
+                Some(name) => {
+                    let group = self
+                        .get(name)
+                        .ok_or_else(|| GroupError::UnknownGroup(name.to_string()))?;
+                    expanded.extend(group.iter().cloned());
+                }
+                None => expanded.push(s.to_string()),
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Variables of a group, by index in `variables` (in the order of the signals of the
+    /// group, then of `variables`, without duplicates). Each signal must match at least one
+    /// variable.
+    pub fn select(&self, name: &str, variables: &[VariableInfo]) -> Result<Vec<usize>, GroupError> {
+        self.select_with(name, variables, &Demangler::default())
+    }
+
+    /// Same as `select`, with the paths of the variables given by a demangler
+    pub fn select_with(
+        &self,
+        name: &str,
+        variables: &[VariableInfo],
+        demangler: &Demangler,
+    ) -> Result<Vec<usize>, GroupError> {
+        let group = self
+            .get(name)
+            .ok_or_else(|| GroupError::UnknownGroup(name.to_string()))?;
+        let mut selected = Vec::new();
+        for s in group {
+            let matching = matching_variables_with(variables, s, demangler);
+            if matching.is_empty() {
+                return Err(GroupError::UnknownSignal(s.clone()));
+            }
+            for i in matching {
+                if !selected.contains(&i) {
+                    selected.push(i);
+                }
+            }
+        }
+        Ok(selected)
+    }
+
+    /// Tracks the variables of a group in a simulation (see
+    /// `StateSimulation::track_variables`), returns their ids, e.g., for `mat::export_mat`.
+    /// Signals are matched with the names given by the demangler of the simulation. Must be
+    /// called after `load_header` and before `allocate_state`.
+    pub fn track(&self, name: &str, sim: &mut StateSimulation) -> Result<Vec<String>, GroupError> {
+        let variables = &sim.header().ok_or(GroupError::MissingHeader)?.variables;
+        let mut ids: Vec<String> = Vec::new();
+        for i in self.select_with(name, variables, sim.demangler())? {
+            if !ids.contains(&variables[i].id) {
+                ids.push(variables[i].id.clone());
+            }
+        }
+        let refs: Vec<&str> = ids.iter().map(|s| s.as_str()).collect();
+        sim.track_variables(&refs);
+        Ok(ids)
+    }
+
+    /// Hierarchy differences of the signals of a group between two dumps (see
+    /// `hierarchy::diff_hierarchies`), a signal of the group missing from one of them is
+    /// reported as added or removed
+    pub fn diff(
+        &self,
+        name: &str,
+        old: &[VariableInfo],
+        new: &[VariableInfo],
+    ) -> Result<Vec<HierarchyChange>, GroupError> {
+        self.diff_with(name, old, new, &Demangler::default())
+    }
+
+    /// Same as `diff`, with the signals matched by the names given by a demangler (see
+    /// `hierarchy::diff_hierarchies_with`)
+    pub fn diff_with(
+        &self,
+        name: &str,
+        old: &[VariableInfo],
+        new: &[VariableInfo],
+        demangler: &Demangler,
+    ) -> Result<Vec<HierarchyChange>, GroupError> {
+        let group = self
+            .get(name)
+            .ok_or_else(|| GroupError::UnknownGroup(name.to_string()))?;
+        let members = |variables: &[VariableInfo]| -> Vec<VariableInfo> {
+            let mut indices: Vec<usize> = group
+                .iter()
+                .flat_map(|s| matching_variables_with(variables, s, demangler))
+                .collect();
+            indices.sort_unstable();
+            indices.dedup();
+            indices.into_iter().map(|i| variables[i].clone()).collect()
+        };
+        Ok(diff_hierarchies_with(
+            &members(old),
+            &members(new),
+            demangler,
+        ))
+    }
+}
@@ -0,0 +1,847 @@
+//! Helpers working on the variable hierarchy declared in waveform headers.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Range, Scope, ScopeKind, VariableInfo};
+
+/// Prefix of the ids given to synthetic variables. Real VCD ids never contain spaces, so
+/// synthetic ids cannot collide with them.
+pub const SYNTHETIC_ID_PREFIX: &str = " ";
+
+/// Hierarchical (dot-separated) name of a variable
+pub fn variable_path(v: &VariableInfo) -> String {
+    let mut path: Vec<&str> = v.scope.iter().map(|s| s.name.as_str()).collect();
+    path.push(&v.name);
+    path.join(".")
+}
+
+/// Hierarchical (dot-separated) name of a scope, empty for the top level
+pub fn scope_path(scope: &[Scope]) -> String {
+    scope
+        .iter()
+        .map(|s| s.name.as_str())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Undoes the escaping of names done by simulators:
+/// - escaped identifiers (`\gen[0] `, as written by Icarus Verilog or VCS) lose their
+///   backslash and trailing spaces,
+/// - Verilator's `__DOT__`, `__BRA__` and `__KET__` become `.`, `[` and `]`, and `__0XY`
+///   becomes the character of hexadecimal code `XY`,
+/// - GHDL's generate instances (`gen(3)`) become `gen[3]`.
+pub fn unescape_name(name: &str) -> String {
+    let name = match name.strip_prefix('\\') {
+        Some(n) => n.trim_end(),
+        None => name,
+    };
+    let mut out = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(k) = rest.find("__") {
+        out.push_str(&rest[..k]);
+        rest = &rest[k..];
+        let hex = rest
+            .get(3..5)
+            .filter(|_| rest.as_bytes()[2] == b'0')
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        if let Some(r) = rest.strip_prefix("__DOT__") {
+            out.push('.');
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("__BRA__") {
+            out.push('[');
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("__KET__") {
+            out.push(']');
+            rest = r;
+        } else if let Some(c) = hex.filter(u8::is_ascii) {
+            out.push(c as char);
+            rest = &rest[5..];
+        } else {
+            out.push('_');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    if out.ends_with(')') {
+        if let Some(open) = out.rfind('(') {
+            let index = &out[open + 1..out.len() - 1];
+            if open > 0 && !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()) {
+                out.replace_range(open..open + 1, "[");
+                out.replace_range(out.len() - 1.., "]");
+            }
+        }
+    }
+    out
+}
+
+/// Rules rewriting the hierarchical names of a dump into a form independent of the
+/// simulator that produced it, so that selections written for one simulator apply to the
+/// dumps of another.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Demangler {
+    /// Leading scopes removed from the paths starting with them (dot-separated, e.g.,
+    /// `TOP` or `tb.dut`), only the first matching prefix is removed
+    pub strip_prefixes: Vec<String>,
+    /// Unescapes scope and variable names (see `unescape_name`)
+    pub unescape: bool,
+}
+
+impl Demangler {
+    /// Rules for Verilator dumps, which add a `TOP` scope above the design and mangle
+    /// names
+    pub fn verilator() -> Self {
+        Demangler {
+            strip_prefixes: vec!["TOP".to_string()],
+            unescape: true,
+        }
+    }
+
+    /// Rules undoing the escaping of all the supported simulators, without removing scopes
+    pub fn unescaping() -> Self {
+        Demangler {
+            strip_prefixes: Vec::new(),
+            unescape: true,
+        }
+    }
+
+    /// Hierarchical (dot-separated) name of a variable, after applying the rules
+    pub fn path(&self, v: &VariableInfo) -> String {
+        if *self == Demangler::default() {
+            return variable_path(v);
+        }
+        let names = v.scope.iter().map(|s| &s.name).chain(Some(&v.name));
+        self.strip(self.join(names), false)
+    }
+
+    /// Hierarchical name of a scope, after applying the rules: empty for the top level and
+    /// for the scopes removed by `strip_prefixes` (e.g., `TOP` itself)
+    pub fn scope_path(&self, scope: &[Scope]) -> String {
+        if *self == Demangler::default() {
+            return scope_path(scope);
+        }
+        self.strip(self.join(scope.iter().map(|s| &s.name)), true)
+    }
+
+    fn join<'a, I: Iterator<Item = &'a String>>(&self, names: I) -> String {
+        let names: Vec<String> = if self.unescape {
+            names.map(|n| unescape_name(n)).collect()
+        } else {
+            names.cloned().collect()
+        };
+        // Unescaped names may hold several levels
+        names.join(".")
+    }
+
+    /// Removes the first matching prefix, a scope equal to it becomes the top level
+    fn strip(&self, path: String, is_scope: bool) -> String {
+        for prefix in &self.strip_prefixes {
+            match path.strip_prefix(prefix.as_str()) {
+                Some("") if is_scope => return String::new(),
+                Some(rest) => {
+                    if let Some(p) = rest.strip_prefix('.') {
+                        return p.to_string();
+                    }
+                }
+                None => {}
+            }
+        }
+        path
+    }
+}
+
+impl std::str::FromStr for Demangler {
+    type Err = String;
+
+    /// Parses comma separated rules: `verilator` (see `Demangler::verilator`), `unescape`
+    /// (see `Demangler::unescaping`), `strip:<scope>` for a prefix to remove, or `none`.
+    /// For example, `unescape,strip:tb.dut`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut demangler = Demangler::default();
+        for rule in s.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            match rule {
+                "none" => {}
+                "unescape" => demangler.unescape = true,
+                "verilator" => {
+                    let verilator = Demangler::verilator();
+                    demangler.unescape |= verilator.unescape;
+                    demangler.strip_prefixes.extend(verilator.strip_prefixes);
+                }
+                _ => match rule.strip_prefix("strip:") {
+                    Some(prefix) if !prefix.is_empty() => {
+                        demangler.strip_prefixes.push(prefix.to_string())
+                    }
+                    _ => return Err(format!("unknown demangling rule: {}", rule)),
+                },
+            }
+        }
+        Ok(demangler)
+    }
+}
+
+/// A quantity measured on variables that adds up over the variables of a scope
+pub trait Aggregate {
+    fn add(&mut self, other: &Self);
+}
+
+impl Aggregate for u64 {
+    fn add(&mut self, other: &Self) {
+        *self += other;
+    }
+}
+
+impl<A: Aggregate, B: Aggregate> Aggregate for (A, B) {
+    fn add(&mut self, other: &Self) {
+        self.0.add(&other.0);
+        self.1.add(&other.1);
+    }
+}
+
+/// Total of a quantity over a scope, its sub-scopes included
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScopeTotal<T> {
+    pub path: String,
+    pub total: T,
+}
+
+/// Sums quantities measured on variables (given with their scope) into each scope
+/// containing them, parents included: `top` accounts for the variables of `top.cpu` and
+/// `top.cpu.alu`. Scopes are listed in the order they are first seen, top-level
+/// variables are not accounted.
+pub fn rollup<'a, T, I>(items: I) -> Vec<ScopeTotal<T>>
+where
+    T: Aggregate + Default,
+    I: IntoIterator<Item = (&'a [Scope], T)>,
+{
+    rollup_with(items, &Demangler::default())
+}
+
+/// Same as `rollup`, with the scopes named by a demangler (the scopes it strips are not
+/// accounted)
+pub fn rollup_with<'a, T, I>(items: I, demangler: &Demangler) -> Vec<ScopeTotal<T>>
+where
+    T: Aggregate + Default,
+    I: IntoIterator<Item = (&'a [Scope], T)>,
+{
+    let mut scopes: Vec<ScopeTotal<T>> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (scope, x) in items {
+        for depth in 1..=scope.len() {
+            let path = demangler.scope_path(&scope[..depth]);
+            if path.is_empty() {
+                continue;
+            }
+            let next = scopes.len();
+            let i = *index.entry(path.clone()).or_insert(next);
+            if i == next {
+                scopes.push(ScopeTotal {
+                    path,
+                    total: T::default(),
+                });
+            }
+            scopes[i].total.add(&x);
+        }
+    }
+    scopes
+}
+
+/// Order of the variables (as indices in `variables`) where each scope, sub-scopes
+/// included, forms a contiguous run. Scopes keep the order they are first declared in, as
+/// do the variables and sub-scopes of each scope: the declaration order is kept, unless a
+/// scope is declared several times (its variables are then gathered at its first
+/// declaration).
+pub fn group_by_scope(variables: &[VariableInfo]) -> Vec<usize> {
+    group_by_scope_with(variables, &Demangler::default())
+}
+
+/// `group_by_scope` with scopes named by the given rules: scopes sharing a demangled name
+/// (e.g., `top.cpu` and `cpu` with `strip:top`) are gathered, and the scopes removed by
+/// the rules are not grouped. A scope is placed under the scope of the first variable
+/// declared in it.
+pub fn group_by_scope_with(variables: &[VariableInfo], demangler: &Demangler) -> Vec<usize> {
+    enum Item {
+        Variable(usize),
+        Scope(usize),
+    }
+    // Items of each scope, the top level first
+    let mut scopes: Vec<Vec<Item>> = vec![Vec::new()];
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (i, v) in variables.iter().enumerate() {
+        let mut parent = 0;
+        for depth in 1..=v.scope.len() {
+            let path = demangler.scope_path(&v.scope[..depth]);
+            if path.is_empty() {
+                continue;
+            }
+            let next = scopes.len();
+            let s = *index.entry(path).or_insert(next);
+            if s == parent {
+                continue;
+            }
+            if s == next {
+                scopes.push(Vec::new());
+                scopes[parent].push(Item::Scope(s));
+            }
+            parent = s;
+        }
+        scopes[parent].push(Item::Variable(i));
+    }
+    let mut order = Vec::with_capacity(variables.len());
+    let mut stack = vec![scopes[0].iter()];
+    while let Some(items) = stack.last_mut() {
+        match items.next() {
+            Some(Item::Variable(i)) => order.push(*i),
+            Some(Item::Scope(s)) => stack.push(scopes[*s].iter()),
+            None => {
+                stack.pop();
+            }
+        }
+    }
+    order
+}
+
+/// Scope declared in a header with its sub-scopes and variables (see `VcdHeader::scopes`),
+/// the root of the tree stands for the top level
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScopeNode {
+    /// Empty for the root
+    pub name: String,
+    /// Kind of the first declaration of the scope, None for the root
+    pub kind: Option<ScopeKind>,
+    /// Sub-scopes, in the order they are first declared
+    pub children: Vec<ScopeNode>,
+    /// Variables declared directly in the scope, as indices in the variables of the header
+    pub variables: Vec<usize>,
+}
+
+impl ScopeNode {
+    /// Builds the tree of the scopes holding the given variables (scopes declared without
+    /// variables are not known from them)
+    pub fn from_variables(variables: &[VariableInfo]) -> Self {
+        let mut root = ScopeNode::default();
+        let mut builder = ScopeTreeBuilder::default();
+        for (i, v) in variables.iter().enumerate() {
+            builder.position.clear();
+            for depth in 1..=v.scope.len() {
+                builder.enter(&mut root, &v.scope[..depth]);
+            }
+            builder.add_variable(&mut root, i);
+        }
+        root
+    }
+
+    pub fn child(&self, name: &str) -> Option<&ScopeNode> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    /// Scope at a dot-separated path relative to this one (itself for an empty path)
+    pub fn get(&self, path: &str) -> Option<&ScopeNode> {
+        if path.is_empty() {
+            return Some(self);
+        }
+        path.split('.')
+            .try_fold(self, |node, name| node.child(name))
+    }
+
+    /// Number of scopes below this one, at any depth
+    pub fn scope_count(&self) -> usize {
+        self.children.iter().map(|c| 1 + c.scope_count()).sum()
+    }
+
+    /// Scopes below this one with their dot-separated path relative to it, parents first
+    pub fn descendants(&self) -> Vec<(String, &ScopeNode)> {
+        let mut scopes = Vec::new();
+        let mut stack: Vec<(String, &ScopeNode)> = self
+            .children
+            .iter()
+            .rev()
+            .map(|c| (c.name.clone(), c))
+            .collect();
+        while let Some((path, node)) = stack.pop() {
+            for c in node.children.iter().rev() {
+                stack.push((format!("{}.{}", path, c.name), c));
+            }
+            scopes.push((path, node));
+        }
+        scopes
+    }
+
+    fn node_mut(&mut self, position: &[usize]) -> &mut ScopeNode {
+        position.iter().fold(self, |node, i| &mut node.children[*i])
+    }
+}
+
+/// Adds the scopes and variables of a header to its scope tree as they are declared
+#[derive(Debug, Default)]
+pub(crate) struct ScopeTreeBuilder {
+    /// Index of each scope among the children of its parent, by position of the parent and
+    /// name (names may hold dots, they can not be joined into paths)
+    index: HashMap<(Vec<usize>, String), usize>,
+    /// Indices of the open scopes, from the top level
+    position: Vec<usize>,
+}
+
+impl ScopeTreeBuilder {
+    /// Opens the last of the given scopes (the ones currently open), added to the tree
+    /// when it is declared for the first time
+    pub fn enter(&mut self, root: &mut ScopeNode, scope: &[Scope]) {
+        let parent = root.node_mut(&self.position);
+        let last = &scope[scope.len() - 1];
+        let key = (self.position.clone(), last.name.clone());
+        let i = *self.index.entry(key).or_insert_with(|| {
+            parent.children.push(ScopeNode {
+                name: last.name.clone(),
+                kind: Some(last.kind.clone()),
+                ..Default::default()
+            });
+            parent.children.len() - 1
+        });
+        self.position.push(i);
+    }
+
+    pub fn leave(&mut self) {
+        self.position.pop();
+    }
+
+    pub fn add_variable(&self, root: &mut ScopeNode, variable: usize) {
+        root.node_mut(&self.position).variables.push(variable);
+    }
+}
+
+/// Groups variables by VCD id: several variables may share the same id (e.g., a clock
+/// net seen from several modules), each id maps to the variables it drives, in
+/// declaration order.
+pub fn alias_map(variables: &[VariableInfo]) -> HashMap<&str, Vec<&VariableInfo>> {
+    let mut aliases: HashMap<&str, Vec<&VariableInfo>> = HashMap::with_capacity(variables.len());
+    for v in variables {
+        aliases.entry(v.id.as_str()).or_default().push(v);
+    }
+    aliases
+}
+
+/// A family of 1-bit variables `name[i]` reconstructed as a single bus
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitBlastedBus {
+    /// Synthetic variable describing the whole bus
+    pub info: VariableInfo,
+    /// VCD ids of the members, leftmost (most significant) bit first
+    pub members: Vec<String>,
+}
+
+/// Detects families of 1-bit variables sharing the same scope and name and declared with
+/// a single bit index (e.g., `data [0]`, `data [1]`, ...), as found in netlist dumps.
+///
+/// Only families forming a contiguous range of at least 2 bits, whose members all have
+/// distinct ids not shared with another family, are reported. The synthetic variable
+/// gets a `[msb:lsb]` range and an id made of `SYNTHETIC_ID_PREFIX` followed by its
+/// hierarchical name.
+pub fn find_bit_blasted_buses(variables: &[VariableInfo]) -> Vec<BitBlastedBus> {
+    find_bit_blasted_buses_with(variables, &Demangler::default())
+}
+
+/// Same as `find_bit_blasted_buses`, with the families (and the ids of the synthetic
+/// variables) named by a demangler
+pub fn find_bit_blasted_buses_with(
+    variables: &[VariableInfo],
+    demangler: &Demangler,
+) -> Vec<BitBlastedBus> {
+    let mut families: Vec<(String, Vec<(u64, &VariableInfo)>)> = Vec::new();
+    let mut family_index: HashMap<String, usize> = HashMap::new();
+    for v in variables {
+        let bit = match v.range {
+            Some(Range::Bit(b)) if v.width == 1 => b,
+            _ => continue,
+        };
+        let path = demangler.path(v);
+        let i = *family_index.entry(path.clone()).or_insert_with(|| {
+            families.push((path, Vec::new()));
+            families.len() - 1
+        });
+        families[i].1.push((bit, v));
+    }
+
+    let mut used_ids: HashSet<&str> = HashSet::new();
+    let mut buses = Vec::new();
+    for (path, mut members) in families {
+        if members.len() < 2 {
+            continue;
+        }
+        members.sort_by_key(|m| std::cmp::Reverse(m.0));
+        let contiguous = members.windows(2).all(|w| w[0].0 == w[1].0 + 1);
+        let distinct_ids: HashSet<&str> = members.iter().map(|(_, v)| v.id.as_str()).collect();
+        if !contiguous
+            || distinct_ids.len() != members.len()
+            || distinct_ids.iter().any(|id| used_ids.contains(id))
+        {
+            continue;
+        }
+        used_ids.extend(distinct_ids);
+
+        let (msb, first) = members[0];
+        let lsb = members[members.len() - 1].0;
+        let info = VariableInfo {
+            id: format!("{}{}", SYNTHETIC_ID_PREFIX, path),
+            name: first.name.clone(),
+            direction: first.direction.clone(),
+            kind: first.kind.clone(),
+            width: members.len() as u32,
+            range: Some(Range::Range((msb as i64, lsb as i64))),
+            handle: 0,
+            scope: first.scope.clone(),
+        };
+        buses.push(BitBlastedBus {
+            info,
+            members: members.iter().map(|(_, v)| v.id.clone()).collect(),
+        });
+    }
+    buses
+}
+
+/// Structural difference between two headers found by `diff_hierarchies`, variables are
+/// given by hierarchical path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum HierarchyChange {
+    /// Variable only in the new header
+    Added(String),
+    /// Variable only in the old header
+    Removed(String),
+    /// Variable of the same scope, kind and width under another name
+    Renamed { from: String, to: String },
+    /// Variable of the same name and width in another scope
+    Moved { from: String, to: String },
+    /// Scope found at another place (or under another name), holding the same variables
+    ScopeMoved { from: String, to: String },
+    /// Variable declared with another width (its new path for moved scopes)
+    WidthChanged { path: String, from: u32, to: u32 },
+}
+
+impl fmt::Display for HierarchyChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HierarchyChange::Added(p) => write!(f, "added {}", p),
+            HierarchyChange::Removed(p) => write!(f, "removed {}", p),
+            HierarchyChange::Renamed { from, to } => write!(f, "renamed {} to {}", from, to),
+            HierarchyChange::Moved { from, to } => write!(f, "moved {} to {}", from, to),
+            HierarchyChange::ScopeMoved { from, to } => {
+                write!(f, "moved scope {} to {}", from, to)
+            }
+            HierarchyChange::WidthChanged { path, from, to } => {
+                write!(f, "{}: width changed from {} to {}", path, from, to)
+            }
+        }
+    }
+}
+
+/// Variables by hierarchical path, in declaration order (the first one for paths declared
+/// several times)
+fn paths<'a>(
+    variables: &'a [VariableInfo],
+    demangler: &Demangler,
+) -> Vec<(String, &'a VariableInfo)> {
+    let mut seen = HashSet::new();
+    variables
+        .iter()
+        .map(|v| (demangler.path(v), v))
+        .filter(|(p, _)| seen.insert(p.clone()))
+        .collect()
+}
+
+/// Paths of the scopes declared by the variables, parents included, in the order they are
+/// first seen
+fn scope_paths(variables: &[(String, &VariableInfo)], demangler: &Demangler) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut scopes = Vec::new();
+    for (_, v) in variables {
+        for depth in 1..=v.scope.len() {
+            let path = demangler.scope_path(&v.scope[..depth]);
+            if !path.is_empty() && seen.insert(path.clone()) {
+                scopes.push(path);
+            }
+        }
+    }
+    scopes
+}
+
+/// Whether a path is inside a scope (strictly)
+fn is_under(path: &str, scope: &str) -> bool {
+    path.strip_prefix(scope).is_some_and(|r| r.starts_with('.'))
+}
+
+/// Paths of the variables under each of the given scopes, relative to it, sorted
+fn scope_contents(
+    variables: &[(String, &VariableInfo)],
+    scopes: &HashSet<&str>,
+    demangler: &Demangler,
+) -> HashMap<String, Vec<String>> {
+    let mut contents: HashMap<String, Vec<String>> = HashMap::new();
+    for (path, v) in variables {
+        for depth in 1..=v.scope.len() {
+            let scope = demangler.scope_path(&v.scope[..depth]);
+            if !scopes.contains(scope.as_str()) {
+                continue;
+            }
+            // Stripping a longer prefix from the path than from the scope can leave it out
+            if let Some(relative) = path.strip_prefix(&scope).and_then(|r| r.strip_prefix('.')) {
+                let relative = relative.to_string();
+                contents.entry(scope).or_default().push(relative);
+            }
+        }
+    }
+    for content in contents.values_mut() {
+        content.sort();
+    }
+    contents
+}
+
+type Entry<'a> = &'a (String, &'a VariableInfo);
+
+/// Pairs each removed variable with the first added one of the same key, the paired
+/// variables are taken out of both lists
+fn pair_by_key<K, F>(
+    removed: &mut Vec<Entry>,
+    added: &mut Vec<Entry>,
+    key: F,
+) -> Vec<(String, String)>
+where
+    K: std::hash::Hash + Eq,
+    F: Fn(&VariableInfo) -> K,
+{
+    let mut candidates: HashMap<K, VecDeque<usize>> = HashMap::new();
+    for (i, (_, v)) in added.iter().enumerate() {
+        candidates.entry(key(v)).or_default().push_back(i);
+    }
+    let mut pairs = Vec::new();
+    let mut paired = vec![false; added.len()];
+    removed.retain(
+        |(from, v)| match candidates.get_mut(&key(v)).and_then(|c| c.pop_front()) {
+            Some(i) => {
+                paired[i] = true;
+                pairs.push((from.clone(), added[i].0.clone()));
+                false
+            }
+            None => true,
+        },
+    );
+    let mut paired = paired.into_iter();
+    added.retain(|_| !paired.next().unwrap_or(false));
+    pairs
+}
+
+/// Compares the hierarchy of two headers, variables being matched by hierarchical path.
+///
+/// Variables missing from one side are paired when possible, in this order:
+/// - scopes missing from each side holding the same variables (by path relative to the
+///   scope) are reported as moved, their sub-scopes move along,
+/// - a variable with the same name and width in another scope is reported as moved,
+/// - a variable of the same scope, kind and width is reported as renamed.
+///
+/// Candidates are paired in declaration order. Changes are listed by kind, in the order
+/// above, followed by width changes, removed and added variables.
+pub fn diff_hierarchies(old: &[VariableInfo], new: &[VariableInfo]) -> Vec<HierarchyChange> {
+    diff_hierarchies_with(old, new, &Demangler::default())
+}
+
+/// Same as `diff_hierarchies`, with the variables and scopes matched by their names after
+/// applying a demangler, e.g., to compare the dumps of two simulators
+pub fn diff_hierarchies_with(
+    old: &[VariableInfo],
+    new: &[VariableInfo],
+    demangler: &Demangler,
+) -> Vec<HierarchyChange> {
+    let (old, new) = (paths(old, demangler), paths(new, demangler));
+    let old_by_path: HashMap<&str, &VariableInfo> =
+        old.iter().map(|(p, v)| (p.as_str(), *v)).collect();
+    let new_by_path: HashMap<&str, &VariableInfo> =
+        new.iter().map(|(p, v)| (p.as_str(), *v)).collect();
+    let mut widths = Vec::new();
+    let mut compare_widths = |from: &str, to: &str| {
+        if let (Some(a), Some(b)) = (old_by_path.get(from), new_by_path.get(to)) {
+            if a.width != b.width {
+                widths.push(HierarchyChange::WidthChanged {
+                    path: to.to_string(),
+                    from: a.width,
+                    to: b.width,
+                });
+            }
+        }
+    };
+    for (path, _) in &old {
+        compare_widths(path, path);
+    }
+
+    // Scopes only found on one side, matched by content
+    let (old_scopes, new_scopes) = (scope_paths(&old, demangler), scope_paths(&new, demangler));
+    let old_set: HashSet<&str> = old_scopes.iter().map(String::as_str).collect();
+    let new_set: HashSet<&str> = new_scopes.iter().map(String::as_str).collect();
+    let old_only: HashSet<&str> = old_set.difference(&new_set).copied().collect();
+    let new_only: HashSet<&str> = new_set.difference(&old_set).copied().collect();
+    let mut old_contents = scope_contents(&old, &old_only, demangler);
+    let mut targets: HashMap<Vec<String>, VecDeque<&str>> = HashMap::new();
+    let mut new_contents = scope_contents(&new, &new_only, demangler);
+    for scope in new_scopes.iter().filter(|s| new_only.contains(s.as_str())) {
+        let content = new_contents.remove(scope).unwrap_or_default();
+        targets.entry(content).or_default().push_back(scope);
+    }
+    let mut sources: Vec<&String> = old_scopes
+        .iter()
+        .filter(|s| old_only.contains(s.as_str()))
+        .collect();
+    // Parents first, so that their sub-scopes are not reported
+    sources.sort_by_key(|s| s.matches('.').count());
+    let mut moved_scopes: Vec<(String, String)> = Vec::new();
+    for from in sources {
+        if moved_scopes.iter().any(|(a, _)| is_under(from, a)) {
+            continue;
+        }
+        let content = old_contents.remove(from.as_str()).unwrap_or_default();
+        let to = match targets.get_mut(&content).and_then(|t| {
+            let free = t
+                .iter()
+                .position(|to| !moved_scopes.iter().any(|(_, b)| is_under(to, b)))?;
+            t.remove(free)
+        }) {
+            Some(to) => to,
+            None => continue,
+        };
+        for relative in &content {
+            compare_widths(
+                &format!("{}.{}", from, relative),
+                &format!("{}.{}", to, relative),
+            );
+        }
+        moved_scopes.push((from.clone(), to.to_string()));
+    }
+
+    let mut removed: Vec<Entry> = old
+        .iter()
+        .filter(|(p, _)| !new_by_path.contains_key(p.as_str()))
+        .filter(|(p, _)| !moved_scopes.iter().any(|(a, _)| is_under(p, a)))
+        .collect();
+    let mut added: Vec<Entry> = new
+        .iter()
+        .filter(|(p, _)| !old_by_path.contains_key(p.as_str()))
+        .filter(|(p, _)| !moved_scopes.iter().any(|(_, b)| is_under(p, b)))
+        .collect();
+    let moved = pair_by_key(&mut removed, &mut added, |v| (v.name.clone(), v.width));
+    let renamed = pair_by_key(&mut removed, &mut added, |v| {
+        (
+            demangler.scope_path(&v.scope),
+            v.width,
+            v.kind.clone() as u8,
+        )
+    });
+
+    let mut changes: Vec<HierarchyChange> = moved_scopes
+        .into_iter()
+        .map(|(from, to)| HierarchyChange::ScopeMoved { from, to })
+        .collect();
+    changes.extend(
+        moved
+            .into_iter()
+            .map(|(from, to)| HierarchyChange::Moved { from, to }),
+    );
+    changes.extend(
+        renamed
+            .into_iter()
+            .map(|(from, to)| HierarchyChange::Renamed { from, to }),
+    );
+    changes.extend(widths);
+    changes.extend(
+        removed
+            .iter()
+            .map(|(p, _)| HierarchyChange::Removed(p.clone())),
+    );
+    changes.extend(added.iter().map(|(p, _)| HierarchyChange::Added(p.clone())));
+    changes
+}
+
+/// Name proposed by `NameIndex::complete`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Completion<'a> {
+    /// Hierarchical name of the scope or variable
+    pub path: &'a str,
+    /// Whether it is a scope, which can be completed further after a `.`
+    pub is_scope: bool,
+}
+
+/// Sorted index of the hierarchical names (scopes and variables) of a header, for prefix
+/// searches such as the completion of signal names in interactive tools
+#[derive(Debug, Clone, Default)]
+pub struct NameIndex {
+    /// (path, is_scope) pairs, sorted and without duplicates
+    names: Vec<(String, bool)>,
+}
+
+impl NameIndex {
+    pub fn new(variables: &[VariableInfo]) -> Self {
+        Self::with_demangler(variables, &Demangler::default())
+    }
+
+    /// Index of the names given by a demangler (the scopes it strips are left out)
+    pub fn with_demangler(variables: &[VariableInfo], demangler: &Demangler) -> Self {
+        let mut names = Vec::with_capacity(variables.len());
+        for v in variables {
+            for depth in 1..=v.scope.len() {
+                let path = demangler.scope_path(&v.scope[..depth]);
+                if !path.is_empty() {
+                    names.push((path, true));
+                }
+            }
+            names.push((demangler.path(v), false));
+        }
+        names.sort_unstable();
+        names.dedup();
+        NameIndex { names }
+    }
+
+    /// Number of names (scopes and variables)
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// All the names starting with `prefix`, at any depth, in sorted order
+    pub fn starting_with<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = Completion<'a>> {
+        let start = self.names.partition_point(|(p, _)| p.as_str() < prefix);
+        self.names[start..]
+            .iter()
+            .take_while(move |(p, _)| p.starts_with(prefix))
+            .map(|(path, is_scope)| Completion {
+                path,
+                is_scope: *is_scope,
+            })
+    }
+
+    /// Candidates for the last component of `prefix`, in sorted order: the names of the
+    /// scope written before its last `.` which start with the text after it. For example,
+    /// `top.cpu.al` gives `top.cpu.alu` and `top.cpu.alu_out`, but not `top.cpu.alu.a`.
+    pub fn complete(&self, prefix: &str) -> Vec<Completion<'_>> {
+        let mut candidates = Vec::new();
+        let mut i = self.names.partition_point(|(p, _)| p.as_str() < prefix);
+        while let Some((path, is_scope)) = self.names.get(i) {
+            if !path.starts_with(prefix) {
+                break;
+            }
+            match path[prefix.len()..].find('.') {
+                None => {
+                    candidates.push(Completion {
+                        path,
+                        is_scope: *is_scope,
+                    });
+                    i += 1;
+                }
+                // Name deeper in the hierarchy, the names under the same scope are
+                // contiguous and skipped at once
+                Some(k) => {
+                    let scope = &path[..=prefix.len() + k];
+                    i += self.names[i..].partition_point(|(p, _)| p.starts_with(scope));
+                }
+            }
+        }
+        candidates
+    }
+}
@@ -1,9 +1,33 @@
+pub mod activity;
+pub mod compression;
+pub mod convert;
+pub mod coverage;
+pub mod delta;
+pub mod derived;
+pub mod fsm;
+#[cfg(feature = "fst")]
 pub mod fst;
+pub mod groups;
+pub mod hierarchy;
+pub mod mat;
+pub mod pattern;
+pub mod render;
+pub mod sampling;
+pub mod session;
 pub mod simulation;
+pub mod transform;
+pub mod trigger;
 pub mod types;
 pub mod vcd;
+#[cfg(feature = "vcd-interop")]
+pub mod vcd_interop;
+pub mod vcd_writer;
+pub mod wdb;
 
-pub use fst::{FstError, FstReader};
+#[cfg(feature = "fst")]
+pub use fst::{FstError, FstReader, FstSimulation, FstWriter};
 pub use vcd::{VcdError, VcdParser};
+pub use vcd_writer::VcdWriter;
+pub use wdb::{WaveformDb, WdbError, WdbWriter};
 
 mod utils;
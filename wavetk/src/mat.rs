@@ -0,0 +1,176 @@
+//! Export of signals to MATLAB (level 5 MAT-file format, uncompressed).
+//!
+//! Each signal is saved as a structure named after its hierarchical name (e.g., `top.cpu.pc`
+//! becomes `top_cpu_pc`, see `StateSimulation::path_of`), with two column vectors of
+//! doubles:
+//! - `time`: time of each change, in dump units (initial values are at time 0),
+//! - `value`: value after each change, as an unsigned integer (`NaN` when it holds bits other
+//!   than 0 and 1, values wider than 53 bits are rounded).
+//!
+//! The file can be loaded with `load('dump.mat')` in MATLAB or Octave.
+use std::collections::HashSet;
+use std::io;
+use std::io::Write;
+
+use crate::simulation::{decode_unsigned, StateSimulation};
+use crate::vcd::VcdError;
+
+const MI_INT8: u32 = 1;
+const MI_INT32: u32 = 5;
+const MI_UINT32: u32 = 6;
+const MI_DOUBLE: u32 = 9;
+const MI_MATRIX: u32 = 14;
+
+const MX_STRUCT_CLASS: u32 = 2;
+const MX_DOUBLE_CLASS: u32 = 6;
+
+/// Maximum length of MATLAB names
+const NAME_LENGTH: usize = 63;
+
+/// Appends a data element (tag, then data padded to 8 bytes)
+fn element(out: &mut Vec<u8>, data_type: u32, data: &[u8]) {
+    out.extend_from_slice(&data_type.to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    out.resize(out.len() + (8 - data.len() % 8) % 8, 0);
+}
+
+/// Appends the array flags, dimensions and name subelements of a matrix
+fn matrix_header(out: &mut Vec<u8>, class: u32, dims: [u32; 2], name: &str) {
+    let mut flags = class.to_le_bytes().to_vec();
+    flags.extend_from_slice(&[0; 4]);
+    element(out, MI_UINT32, &flags);
+    let dims: Vec<u8> = dims.iter().flat_map(|d| d.to_le_bytes()).collect();
+    element(out, MI_INT32, &dims);
+    element(out, MI_INT8, name.as_bytes());
+}
+
+/// Wraps the subelements of a matrix in a `miMATRIX` element
+fn matrix(out: &mut Vec<u8>, content: &[u8]) {
+    element(out, MI_MATRIX, content);
+}
+
+fn double_column(name: &str, values: &[f64]) -> Vec<u8> {
+    let mut content = Vec::new();
+    matrix_header(
+        &mut content,
+        MX_DOUBLE_CLASS,
+        [values.len() as u32, 1],
+        name,
+    );
+    let data: Vec<u8> = values.iter().flat_map(|x| x.to_le_bytes()).collect();
+    element(&mut content, MI_DOUBLE, &data);
+    let mut out = Vec::new();
+    matrix(&mut out, &content);
+    out
+}
+
+/// A 1x1 structure whose fields are double column vectors
+fn structure(name: &str, fields: &[(&str, &[f64])]) -> Vec<u8> {
+    const FIELD_LENGTH: usize = 32;
+    let mut content = Vec::new();
+    matrix_header(&mut content, MX_STRUCT_CLASS, [1, 1], name);
+    // Field name length, as a small data element
+    content.extend_from_slice(&((4 << 16) | MI_INT32).to_le_bytes());
+    content.extend_from_slice(&(FIELD_LENGTH as u32).to_le_bytes());
+    let mut names = Vec::new();
+    for (field, _) in fields {
+        let mut n = field.as_bytes().to_vec();
+        n.resize(FIELD_LENGTH, 0);
+        names.extend_from_slice(&n);
+    }
+    element(&mut content, MI_INT8, &names);
+    for (_, values) in fields {
+        content.extend_from_slice(&double_column("", values));
+    }
+    let mut out = Vec::new();
+    matrix(&mut out, &content);
+    out
+}
+
+/// Valid MATLAB name for a hierarchical name: letters, digits and underscores, starting
+/// with a letter
+pub fn matlab_name(path: &str) -> String {
+    let mut name: String = path
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name.insert(0, 's');
+    }
+    name.truncate(NAME_LENGTH);
+    name
+}
+
+/// Writes the header of a MAT-file
+fn file_header<W: Write>(out: &mut W) -> io::Result<()> {
+    let mut text = b"MATLAB 5.0 MAT-file, Created by: wavetk".to_vec();
+    text.resize(116, b' ');
+    out.write_all(&text)?;
+    // No subsystem data, version 0x0100, little-endian indicator
+    out.write_all(&[0; 8])?;
+    out.write_all(&0x0100u16.to_le_bytes())?;
+    out.write_all(b"IM")
+}
+
+/// Runs the simulation until the end and writes the changes of the variables with the given
+/// VCD ids to `out`, as a MAT-file (see the module documentation).
+///
+/// The state must have been allocated, with the given variables tracked.
+pub fn export_mat<W: Write>(
+    sim: &mut StateSimulation,
+    ids: &[&str],
+    mut out: W,
+) -> Result<W, VcdError> {
+    // Index in `tracked_variables` of each exported variable
+    let mut indices = Vec::new();
+    for id in ids {
+        let i = sim
+            .tracked_variables()
+            .iter()
+            .position(|(_, v)| v.id == *id)
+            .ok_or(VcdError::UnknownVariable)?;
+        indices.push(i);
+    }
+    let mut series: Vec<(Vec<f64>, Vec<f64>)> = vec![(Vec::new(), Vec::new()); ids.len()];
+    while !sim.done() {
+        let time = sim.next_cycle()?.cycle.max(0) as f64;
+        for (k, i) in indices.iter().enumerate() {
+            if !sim.is_dirty(*i) {
+                continue;
+            }
+            let (offset, info) = &sim.tracked_variables()[*i];
+            let bits = &sim.state()[*offset..*offset + info.width as usize];
+            let value = match decode_unsigned(bits) {
+                Some(x) => x as f64,
+                // Too wide for a u64: accumulate as a double
+                None if bits.iter().all(|b| *b == 0 || *b == 1) => {
+                    bits.iter().fold(0.0, |acc, b| 2.0 * acc + *b as f64)
+                }
+                None => f64::NAN,
+            };
+            series[k].0.push(time);
+            series[k].1.push(value);
+        }
+    }
+
+    file_header(&mut out)?;
+    let mut names = HashSet::new();
+    for (i, (times, values)) in indices.iter().zip(&series) {
+        let base = matlab_name(&sim.path_of(&sim.tracked_variables()[*i].1));
+        let mut name = base.clone();
+        let mut n = 1;
+        while !names.insert(name.clone()) {
+            n += 1;
+            let suffix = format!("_{}", n);
+            name = format!(
+                "{}{}",
+                &base[..base.len().min(NAME_LENGTH - suffix.len())],
+                suffix
+            );
+        }
+        out.write_all(&structure(&name, &[("time", times), ("value", values)]))?;
+    }
+    out.flush()?;
+    Ok(out)
+}
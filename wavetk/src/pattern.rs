@@ -0,0 +1,191 @@
+//! Bit patterns with don't-care positions, matched against the state of a variable.
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::simulation::{CycleStep, StateSimulation};
+use crate::vcd::VcdError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternBit {
+    Zero,
+    One,
+    DontCare,
+}
+
+/// How X/Z (and any other non 0/1 level) in the waveform are matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum XzMatch {
+    /// Unknown bits match anything
+    Any,
+    /// Unknown bits only match don't-care positions of a pattern
+    #[default]
+    DontCare,
+    /// Unknown bits always fail the match, even on don't-care positions
+    Never,
+}
+
+impl XzMatch {
+    fn is_known(level: i8) -> bool {
+        level == 0 || level == 1
+    }
+
+    /// Whether a waveform level matches a pattern bit
+    pub fn bit_matches(self, p: PatternBit, level: i8) -> bool {
+        if !Self::is_known(level) {
+            return match self {
+                XzMatch::Any => true,
+                XzMatch::DontCare => p == PatternBit::DontCare,
+                XzMatch::Never => false,
+            };
+        }
+        match p {
+            PatternBit::DontCare => true,
+            PatternBit::Zero => level == 0,
+            PatternBit::One => level == 1,
+        }
+    }
+
+    /// Whether two waveform levels are considered equal (e.g., when comparing two dumps),
+    /// with `DontCare` an unknown level is only equal to the same unknown level.
+    pub fn levels_equal(self, a: i8, b: i8) -> bool {
+        match self {
+            XzMatch::Any => a == b || !Self::is_known(a) || !Self::is_known(b),
+            XzMatch::DontCare => a == b,
+            XzMatch::Never => a == b && Self::is_known(a),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatternError {
+    Empty,
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for PatternError {}
+
+/// A value pattern, leftmost bit first.
+///
+/// Patterns are written in binary (`1xx0`, optionally prefixed by `0b`) or in hexadecimal
+/// with a `0x` prefix (`0xDEAD_BEEF`). Don't-care positions are written `x`, `?` or `-`
+/// (a whole nibble in hexadecimal), `_` can be used as a separator.
+///
+/// A pattern is zero-extended on the left to the width of the variable it is compared to,
+/// bits of the pattern beyond the width of the variable must be 0 or don't-care to match.
+/// Unknown bits of the waveform are handled according to `xz_match` (see `set_xz_match`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    bits: Vec<PatternBit>,
+    xz_match: XzMatch,
+}
+
+impl Pattern {
+    pub fn bits(&self) -> &[PatternBit] {
+        &self.bits
+    }
+
+    /// Selects how X/Z bits of the waveform are matched (`XzMatch::DontCare` by default)
+    pub fn set_xz_match(&mut self, mode: XzMatch) {
+        self.xz_match = mode;
+    }
+
+    pub fn xz_match(&self) -> XzMatch {
+        self.xz_match
+    }
+
+    /// Whether the given state slice (leftmost bit first) matches the pattern
+    pub fn matches(&self, value: &[i8]) -> bool {
+        let n = self.bits.len().max(value.len());
+        (0..n).all(|i| {
+            // Aligned on the right, missing bits on either side are zeros
+            let p = match (i + self.bits.len()).checked_sub(n) {
+                Some(k) => self.bits[k],
+                None => PatternBit::Zero,
+            };
+            let v = match (i + value.len()).checked_sub(n) {
+                Some(k) => value[k],
+                None => 0,
+            };
+            self.xz_match.bit_matches(p, v)
+        })
+    }
+}
+
+fn is_dont_care(c: char) -> bool {
+    matches!(c, 'x' | 'X' | '?' | '-')
+}
+
+impl FromStr for Pattern {
+    type Err = PatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bits = Vec::with_capacity(s.len());
+        if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            for c in hex.chars().filter(|c| *c != '_') {
+                if is_dont_care(c) {
+                    bits.extend_from_slice(&[PatternBit::DontCare; 4]);
+                    continue;
+                }
+                let d = c.to_digit(16).ok_or(PatternError::InvalidCharacter(c))?;
+                bits.extend((0..4).rev().map(|k| {
+                    if d & (1 << k) != 0 {
+                        PatternBit::One
+                    } else {
+                        PatternBit::Zero
+                    }
+                }));
+            }
+        } else {
+            let bin = s.strip_prefix("0b").unwrap_or(s);
+            for c in bin.chars().filter(|c| *c != '_') {
+                bits.push(match c {
+                    '0' => PatternBit::Zero,
+                    '1' => PatternBit::One,
+                    c if is_dont_care(c) => PatternBit::DontCare,
+                    c => return Err(PatternError::InvalidCharacter(c)),
+                });
+            }
+        }
+        if bits.is_empty() {
+            return Err(PatternError::Empty);
+        }
+        Ok(Pattern {
+            bits,
+            xz_match: XzMatch::default(),
+        })
+    }
+}
+
+/// Advances the simulation up to the first cycle where the variable matches the pattern.
+///
+/// Returns the time of that cycle (the simulation state then holds the matching value), or
+/// None if the end of the waveform is reached without a match.
+pub fn find_first(
+    sim: &mut StateSimulation,
+    var_id: &str,
+    pattern: &Pattern,
+) -> Result<Option<i64>, VcdError> {
+    let offset = sim
+        .variable_offset(var_id)
+        .ok_or(VcdError::UnknownVariable)?;
+    let width = sim.variable_width(var_id).unwrap_or(0);
+    while !sim.done() {
+        let CycleStep {
+            cycle: time, state, ..
+        } = sim.next_cycle()?;
+        if time < 0 {
+            continue;
+        }
+        if pattern.matches(&state[offset..offset + width]) {
+            return Ok(Some(time));
+        }
+    }
+    Ok(None)
+}
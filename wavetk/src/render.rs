@@ -0,0 +1,752 @@
+//! Drawing of a few signals of a dump as an SVG image, e.g., to include a waveform snippet
+//! in a bug report or in documentation.
+//!
+//! Single-bit signals are drawn as a line, high for `1` and low for `0`. High impedance is
+//! drawn in the middle of the row and the other levels (`x`, `u`, ...) as a red band. Wider
+//! signals are drawn as a bus labeled with the value of each segment: in hexadecimal, with
+//! an `x` (`z`) digit for groups of bits holding unknown (high impedance) bits. Reals are
+//! labeled with their value.
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write as _;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use crate::compression;
+use crate::convert::{extend_vector, ConvertError, Format};
+#[cfg(feature = "fst")]
+use crate::fst::{FstError, FstReader};
+use crate::groups::matching_variables_with;
+use crate::hierarchy::Demangler;
+use crate::types::{TimeUnit, Timescale, VariableInfo};
+use crate::vcd::{VcdCommand, VcdError, VcdParser, VcdValue};
+use crate::wdb::{WaveformDb, WdbError};
+
+/// Height of the time axis, in pixels
+const AXIS_HEIGHT: u32 = 24;
+/// Estimated width of a character of the font, in pixels
+const CHAR_WIDTH: f64 = 7.2;
+const FONT: &str = "font-family=\"monospace\" font-size=\"12\"";
+/// Fill and stroke colors of the values
+const KNOWN_COLORS: (&str, &str) = ("#dafbe1", "#1a7f37");
+const UNKNOWN_COLORS: (&str, &str) = ("#ffebe9", "#cf222e");
+const HIGH_IMPEDANCE_COLORS: (&str, &str) = ("#fff8c5", "#b08800");
+
+#[derive(Debug)]
+pub enum RenderError {
+    /// The dump could not be read
+    Load(ConvertError),
+    /// No variable matches a requested signal
+    UnknownSignal(String),
+    /// The time window is empty
+    EmptyWindow,
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Load(e) => e.fmt(f),
+            RenderError::UnknownSignal(s) => write!(f, "no signal matches {:?}", s),
+            RenderError::EmptyWindow => write!(f, "empty time window"),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RenderError::Load(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConvertError> for RenderError {
+    fn from(e: ConvertError) -> Self {
+        RenderError::Load(e)
+    }
+}
+
+impl From<io::Error> for RenderError {
+    fn from(e: io::Error) -> Self {
+        RenderError::Load(e.into())
+    }
+}
+
+impl From<VcdError> for RenderError {
+    fn from(e: VcdError) -> Self {
+        RenderError::Load(e.into())
+    }
+}
+
+#[cfg(feature = "fst")]
+impl From<FstError> for RenderError {
+    fn from(e: FstError) -> Self {
+        RenderError::Load(e.into())
+    }
+}
+
+impl From<WdbError> for RenderError {
+    fn from(e: WdbError) -> Self {
+        RenderError::Load(e.into())
+    }
+}
+
+/// Changes of a signal to draw
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    /// Label of the row
+    pub name: String,
+    pub width: u32,
+    pub real: bool,
+    /// Changes as (time, value), with values as written in the dump, one per time
+    pub changes: Vec<(u64, String)>,
+}
+
+impl Trace {
+    fn new(v: &VariableInfo, demangler: &Demangler) -> Self {
+        Trace {
+            name: demangler.path(v),
+            width: v.width,
+            real: v.kind.is_real(),
+            changes: Vec::new(),
+        }
+    }
+
+    /// Adds a change, replacing the previous one at the same time
+    fn push(&mut self, time: u64, value: String) {
+        match self.changes.last_mut() {
+            Some(last) if last.0 == time => last.1 = value,
+            _ => self.changes.push((time, value)),
+        }
+    }
+
+    /// Keeps the changes of `[start, end]`, plus the value held at `start`
+    fn clip(&mut self, start: u64, end: u64) {
+        let first = self
+            .changes
+            .iter()
+            .rposition(|(t, _)| *t <= start)
+            .unwrap_or(0);
+        self.changes.drain(..first);
+        self.changes.retain(|(t, _)| *t <= end);
+    }
+
+    /// Reduces the changes of `[start, end]` to at most `n` buckets of equal duration, for
+    /// plotting a signal with far more changes than pixels: each bucket keeps the first, last,
+    /// smallest and largest values held in it, which is enough to draw its changes at the
+    /// resolution of a bucket. Buckets without any change are left out (the last value of the
+    /// previous bucket is held), except the first one holding the value at `start`. That held
+    /// value counts as the first value of the next bucket when its first change comes after
+    /// its start.
+    pub fn resample(&self, start: u64, end: u64, n: usize) -> Vec<Bucket> {
+        let mut buckets: Vec<Bucket> = Vec::new();
+        if n == 0 || start > end {
+            return buckets;
+        }
+        let len = (end - start) as u128 + 1;
+        let bound = |i: u128| start + (i * len).div_ceil(n as u128) as u64;
+        let from = self
+            .changes
+            .iter()
+            .rposition(|(t, _)| *t <= start)
+            .unwrap_or(0);
+        for (t, value) in &self.changes[from..] {
+            if *t > end {
+                break;
+            }
+            let i = ((*t).max(start) - start) as u128 * n as u128 / len;
+            let number = numeric_value(value, self.real);
+            let changed = *t >= start;
+            match buckets.last_mut() {
+                Some(b) if b.start == bound(i) => b.add(value, number, changed),
+                previous => {
+                    // The last value of the previous bucket is held until the first change
+                    let (first, first_number) = match previous {
+                        Some(b) if *t > bound(i) => {
+                            (b.last.clone(), numeric_value(&b.last, self.real))
+                        }
+                        _ => (value.clone(), number),
+                    };
+                    let mut bucket = Bucket {
+                        start: bound(i),
+                        end: bound(i + 1),
+                        last: first.clone(),
+                        first,
+                        min: first_number,
+                        max: first_number,
+                        changes: 0,
+                        unknown: first_number.is_none(),
+                    };
+                    bucket.add(value, number, changed);
+                    buckets.push(bucket);
+                }
+            }
+        }
+        buckets
+    }
+}
+
+/// Values of a trace held during a time bucket (see `Trace::resample`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket {
+    /// Time window `[start, end)` of the bucket
+    pub start: u64,
+    pub end: u64,
+    /// Values held first and last in the bucket
+    pub first: String,
+    pub last: String,
+    /// Extremes of the numeric values held in the bucket (vectors read as unsigned
+    /// integers), `None` when none of them is a number
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Number of changes in the bucket
+    pub changes: usize,
+    /// Whether a value which is not a number (e.g., holding `x` or `z` bits) is held
+    pub unknown: bool,
+}
+
+impl Bucket {
+    fn add(&mut self, value: &str, number: Option<f64>, changed: bool) {
+        self.last = value.to_string();
+        if let Some(x) = number {
+            self.min = Some(self.min.map_or(x, |m| m.min(x)));
+            self.max = Some(self.max.map_or(x, |m| m.max(x)));
+        }
+        self.changes += changed as usize;
+        self.unknown |= number.is_none();
+    }
+}
+
+/// Value of a change as a number: reals as is, vectors of `0`/`1` bits as unsigned integers
+fn numeric_value(value: &str, real: bool) -> Option<f64> {
+    if real {
+        return value.parse().ok();
+    }
+    let bits = value.as_bytes();
+    if bits.is_empty() || !bits.iter().all(|c| *c == b'0' || *c == b'1') {
+        return None;
+    }
+    Some(bits.iter().fold(0.0, |x, c| 2.0 * x + (c - b'0') as f64))
+}
+
+/// Variables selected by `signals`, by index in `variables`. A signal designates the
+/// variables whose hierarchical path is equal to it or ends with it (e.g., `pc` or `cpu.pc`
+/// for `top.cpu.pc`, see `groups::matching_variables`).
+fn select(
+    variables: &[VariableInfo],
+    signals: &[&str],
+    demangler: &Demangler,
+) -> Result<Vec<usize>, RenderError> {
+    let mut selected = Vec::new();
+    for s in signals {
+        let matching = matching_variables_with(variables, s, demangler);
+        if matching.is_empty() {
+            return Err(RenderError::UnknownSignal(s.to_string()));
+        }
+        selected.extend(matching);
+    }
+    Ok(selected)
+}
+
+fn vcd_traces(
+    path: &Path,
+    signals: &[&str],
+    start: u64,
+    end: u64,
+    demangler: &Demangler,
+) -> Result<Vec<Trace>, RenderError> {
+    let mut parser = VcdParser::with_chunk_size(1 << 16, compression::open_input(path)?);
+    parser.load_header()?;
+    load_vcd_traces(&mut parser, signals, start, end, demangler)
+}
+
+/// Loads the changes of the given signals in the time window `[start, end]` (see
+/// `load_traces_with`) from the body of a VCD, whose header is already loaded. Dumps read
+/// once (e.g., from the standard input) give their timescale and their traces from the
+/// same parser.
+pub fn load_vcd_traces<R: Read>(
+    parser: &mut VcdParser<R>,
+    signals: &[&str],
+    start: u64,
+    end: u64,
+    demangler: &Demangler,
+) -> Result<Vec<Trace>, RenderError> {
+    if start > end {
+        return Err(RenderError::EmptyWindow);
+    }
+    let variables = parser
+        .header()
+        .ok_or(VcdError::PartialHeader)?
+        .variables
+        .clone();
+    let selected = select(&variables, signals, demangler)?;
+    let mut traces: Vec<Trace> = selected
+        .iter()
+        .map(|i| Trace::new(&variables[*i], demangler))
+        .collect();
+    let mut by_id: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (k, i) in selected.iter().enumerate() {
+        by_id.entry(variables[*i].id.as_str()).or_default().push(k);
+    }
+    let mut time = 0;
+    let mut past_end = false;
+    while !parser.done() && !past_end {
+        parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+            match cmd {
+                VcdCommand::SetCycle(t) => {
+                    time = t;
+                    past_end = t > end;
+                    return Ok(past_end);
+                }
+                VcdCommand::ValueChange(c) => {
+                    if let Some(ks) = by_id.get(c.var_id) {
+                        let value = match c.value {
+                            VcdValue::Bit(b) => b.to_string(),
+                            VcdValue::Vector(x) | VcdValue::Real(x) | VcdValue::String(x) => {
+                                x.to_string()
+                            }
+                            VcdValue::Port(p) => p.levels(),
+                            VcdValue::Event => "1".to_string(),
+                        };
+                        for k in ks {
+                            traces[*k].push(time, value.clone());
+                        }
+                    }
+                }
+                _ => {}
+            }
+            Ok(false)
+        })?;
+    }
+    for t in &mut traces {
+        t.clip(start, end);
+    }
+    Ok(traces)
+}
+
+#[cfg(feature = "fst")]
+fn fst_traces(
+    path: &Path,
+    signals: &[&str],
+    start: u64,
+    end: u64,
+    demangler: &Demangler,
+) -> Result<Vec<Trace>, RenderError> {
+    let name = path.to_str().ok_or(FstError::Utf8Error)?;
+    let mut reader = FstReader::from_file(name, false)?;
+    let variables = crate::convert::fst_variables(&mut reader);
+    let selected = select(&variables, signals, demangler)?;
+    let mut traces: Vec<Trace> = selected
+        .iter()
+        .map(|i| Trace::new(&variables[*i], demangler))
+        .collect();
+    let mut by_handle: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (k, i) in selected.iter().enumerate() {
+        by_handle.entry(variables[*i].handle).or_default().push(k);
+    }
+    let handles: Vec<u32> = by_handle.keys().copied().collect();
+    let mut error = None;
+    reader.time_range(Some((0, end)));
+    reader.iter_blocks_of(&handles, |time, handle, value| {
+        if value.is_null() {
+            error = Some(FstError::NullPointer);
+            return;
+        }
+        let value = unsafe { std::ffi::CStr::from_ptr(value as *const std::os::raw::c_char) };
+        match (value.to_str(), by_handle.get(&handle)) {
+            (Ok(value), Some(ks)) => {
+                for k in ks {
+                    traces[*k].push(time, value.to_string());
+                }
+            }
+            (Err(_), _) => error = Some(FstError::Utf8Error),
+            (_, None) => {}
+        }
+    });
+    if let Some(e) = error {
+        return Err(e.into());
+    }
+    for t in &mut traces {
+        t.clip(start, end);
+    }
+    Ok(traces)
+}
+
+fn wdb_traces(
+    path: &Path,
+    signals: &[&str],
+    start: u64,
+    end: u64,
+    demangler: &Demangler,
+) -> Result<Vec<Trace>, RenderError> {
+    let mut db = WaveformDb::open(path)?;
+    let variables = db.variables().to_vec();
+    let mut traces = Vec::new();
+    for i in select(&variables, signals, demangler)? {
+        let mut trace = Trace::new(&variables[i], demangler);
+        for (t, v) in db.changes(&variables[i].id, start, end)? {
+            trace.push(t, v.to_string());
+        }
+        traces.push(trace);
+    }
+    Ok(traces)
+}
+
+/// Loads the changes of the given signals in the time window `[start, end]` (see
+/// `Trace::clip`), the format is guessed from the extension of the path.
+///
+/// A signal is given by its hierarchical path or by the end of it (e.g., `cpu.pc` for
+/// `top.cpu.pc`), all the variables matching it are loaded, in the order of the header.
+pub fn load_traces<P: AsRef<Path>>(
+    path: P,
+    signals: &[&str],
+    start: u64,
+    end: u64,
+) -> Result<Vec<Trace>, RenderError> {
+    load_traces_with(path, signals, start, end, &Demangler::default())
+}
+
+/// Same as `load_traces`, with the signals matched and labeled by the names given by a
+/// demangler
+pub fn load_traces_with<P: AsRef<Path>>(
+    path: P,
+    signals: &[&str],
+    start: u64,
+    end: u64,
+    demangler: &Demangler,
+) -> Result<Vec<Trace>, RenderError> {
+    let path = path.as_ref();
+    if start > end {
+        return Err(RenderError::EmptyWindow);
+    }
+    match Format::from_path(path) {
+        Format::Vcd => vcd_traces(path, signals, start, end, demangler),
+        #[cfg(feature = "fst")]
+        Format::Fst => fst_traces(path, signals, start, end, demangler),
+        #[cfg(not(feature = "fst"))]
+        Format::Fst => Err(ConvertError::UnsupportedConversion(Format::Fst, Format::Fst).into()),
+        Format::Wdb => wdb_traces(path, signals, start, end, demangler),
+    }
+}
+
+/// Timescale of a dump, read from its header (the native format does not record it)
+pub fn timescale_of<P: AsRef<Path>>(path: P) -> Result<Option<Timescale>, RenderError> {
+    let path = path.as_ref();
+    match Format::from_path(path) {
+        Format::Vcd => {
+            let mut parser = VcdParser::with_chunk_size(1 << 16, compression::open_input(path)?);
+            Ok(parser.load_header()?.timescale)
+        }
+        #[cfg(feature = "fst")]
+        Format::Fst => {
+            let name = path.to_str().ok_or(FstError::Utf8Error)?;
+            let reader = FstReader::from_file(name, false)?;
+            Ok(Some(Timescale::from_exponent(reader.timescale())))
+        }
+        #[cfg(not(feature = "fst"))]
+        Format::Fst => Err(ConvertError::UnsupportedConversion(Format::Fst, Format::Fst).into()),
+        Format::Wdb => Ok(None),
+    }
+}
+
+/// Text of a bus segment: hexadecimal digits for vectors, the value for reals (and for
+/// values that are not made of bits, e.g., FST strings)
+pub fn bus_label(value: &str, width: u32, real: bool) -> String {
+    let is_bit = |c: u8| b"01xXzZuUwWhHlL-".contains(&c);
+    if real || !value.bytes().all(is_bit) {
+        return value.to_string();
+    }
+    let mut bits = Vec::new();
+    extend_vector(value.as_bytes(), width as usize, &mut bits);
+    let mut digits = Vec::with_capacity(bits.len().div_ceil(4));
+    let mut end = bits.len();
+    while end > 0 {
+        let nibble = &bits[end.saturating_sub(4)..end];
+        let digit = if nibble.iter().all(|c| *c == b'0' || *c == b'1') {
+            let x = nibble.iter().fold(0, |x, c| 2 * x + (c - b'0') as u32);
+            std::char::from_digit(x, 16).unwrap()
+        } else if nibble.iter().all(|c| matches!(c, b'z' | b'Z')) {
+            'z'
+        } else {
+            'x'
+        };
+        digits.push(digit);
+        end = end.saturating_sub(4);
+    }
+    digits.iter().rev().collect()
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Draws traces as an SVG image
+#[derive(Debug, Clone)]
+pub struct SvgRenderer {
+    width: u32,
+    row_height: u32,
+    timescale: Option<Timescale>,
+}
+
+impl Default for SvgRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SvgRenderer {
+    pub fn new() -> Self {
+        SvgRenderer {
+            width: 1000,
+            row_height: 30,
+            timescale: None,
+        }
+    }
+
+    /// Width of the image in pixels, including the column of names (1000 by default)
+    pub fn set_width(&mut self, width: u32) {
+        self.width = width;
+    }
+
+    /// Height of the row of each trace in pixels (30 by default)
+    pub fn set_row_height(&mut self, height: u32) {
+        self.row_height = height;
+    }
+
+    /// Timescale of the dump, the time axis is labeled in dump units without it
+    pub fn set_timescale(&mut self, timescale: Option<Timescale>) {
+        self.timescale = timescale;
+    }
+
+    /// Size of the image drawn for `n` traces, in pixels
+    pub fn size(&self, n: usize) -> (u32, u32) {
+        (self.width, AXIS_HEIGHT + n as u32 * self.row_height + 4)
+    }
+
+    /// Label of a time of the axis, `step` being the interval between two ticks and `end`
+    /// the last time of the axis
+    fn time_label(&self, time: u64, step: u64, end: u64) -> String {
+        let ts = match self.timescale {
+            Some(ts) => ts,
+            None => return time.to_string(),
+        };
+        let exponent = |t: u64| ts.exponent() as i32 + ((t as f64).log10() + 1e-9).floor() as i32;
+        // Largest unit not above the end of the axis, with as many decimals as needed to
+        // tell the ticks apart
+        let end_exponent = exponent(end.max(1));
+        let unit = [
+            TimeUnit::S,
+            TimeUnit::Ms,
+            TimeUnit::Us,
+            TimeUnit::Ns,
+            TimeUnit::Ps,
+            TimeUnit::Fs,
+        ]
+        .iter()
+        .copied()
+        .find(|u| u.exponent() as i32 <= end_exponent)
+        .unwrap_or(TimeUnit::Fs);
+        let decimals = (unit.exponent() as i32 - exponent(step)).max(0) as usize;
+        let value = ts.to_seconds(time as i64) / 10f64.powi(unit.exponent() as i32);
+        format!("{:.*} {}", decimals, value, unit.symbol())
+    }
+
+    /// Draws the changes of the traces in the time window `[start, end]`
+    pub fn render(&self, traces: &[Trace], start: u64, end: u64) -> Result<String, RenderError> {
+        if end <= start {
+            return Err(RenderError::EmptyWindow);
+        }
+        let (width, height) = self.size(traces.len());
+        let longest = traces.iter().map(|t| t.name.chars().count()).max();
+        let x0 = (longest.unwrap_or(0) as f64 * CHAR_WIDTH + 16.0).round();
+        let x1 = width as f64 - 30.0;
+        if x1 <= x0 {
+            return Err(RenderError::EmptyWindow);
+        }
+        let scale = (x1 - x0) / (end - start) as f64;
+        let x_of = |t: u64| x0 + (t.clamp(start, end) - start) as f64 * scale;
+
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+             viewBox=\"0 0 {w} {h}\">",
+            w = width,
+            h = height
+        );
+        let _ = writeln!(
+            svg,
+            "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>",
+            width, height
+        );
+
+        // Ticks at multiples of 1, 2 or 5 times a power of ten, about every 100 pixels
+        let target = ((end - start) as f64 * 100.0 / (x1 - x0)).max(1.0);
+        let power = 10f64.powi(target.log10().floor() as i32);
+        let step = [1.0, 2.0, 5.0, 10.0]
+            .iter()
+            .map(|m| m * power)
+            .find(|s| *s >= target)
+            .unwrap_or(10.0 * power) as u64;
+        let mut tick = start.div_ceil(step) * step;
+        while tick <= end {
+            let x = x_of(tick);
+            let _ = writeln!(
+                svg,
+                "<line x1=\"{x:.1}\" y1=\"{}\" x2=\"{x:.1}\" y2=\"{}\" stroke=\"#ddd\"/>\n\
+                 <text x=\"{x:.1}\" y=\"14\" {} text-anchor=\"middle\">{}</text>",
+                AXIS_HEIGHT - 4,
+                height,
+                FONT,
+                self.time_label(tick, step, end),
+                x = x
+            );
+            tick += step;
+        }
+
+        for (row, trace) in traces.iter().enumerate() {
+            let top = (AXIS_HEIGHT + row as u32 * self.row_height) as f64 + 5.0;
+            let bottom = top + self.row_height as f64 - 10.0;
+            let middle = (top + bottom) / 2.0;
+            let _ = writeln!(
+                svg,
+                "<text x=\"4\" y=\"{:.1}\" {}>{}</text>",
+                middle + 4.0,
+                FONT,
+                escape(&trace.name)
+            );
+            let levels = (top, middle, bottom);
+            for (i, (t, value)) in trace.changes.iter().enumerate() {
+                let next = trace.changes.get(i + 1).map_or(end, |c| c.0);
+                let (a, b) = (x_of(*t), x_of(next));
+                if b <= a {
+                    continue;
+                }
+                if trace.width == 1 && !trace.real {
+                    let edge = i > 0 && *t > start && trace.changes[i - 1].1 != *value;
+                    draw_bit(&mut svg, value, (a, b), levels, edge);
+                } else {
+                    let label = bus_label(value, trace.width, trace.real);
+                    let colors = if trace.real {
+                        KNOWN_COLORS
+                    } else if label.chars().all(|c| c == 'z') {
+                        HIGH_IMPEDANCE_COLORS
+                    } else if label.contains('x') {
+                        UNKNOWN_COLORS
+                    } else {
+                        KNOWN_COLORS
+                    };
+                    draw_bus(&mut svg, &label, colors, (a, b), levels);
+                }
+            }
+        }
+        svg.push_str("</svg>\n");
+        Ok(svg)
+    }
+}
+
+/// Draws a segment of a single-bit signal, with an edge at its start if `edge` is set
+fn draw_bit(
+    svg: &mut String,
+    value: &str,
+    (a, b): (f64, f64),
+    (top, middle, bottom): (f64, f64, f64),
+    edge: bool,
+) {
+    let (y, color) = match value {
+        "1" => (top, KNOWN_COLORS.1),
+        "0" => (bottom, KNOWN_COLORS.1),
+        "z" | "Z" => (middle, HIGH_IMPEDANCE_COLORS.1),
+        _ => {
+            let _ = writeln!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\" \
+                 stroke=\"{}\"/>",
+                a,
+                top,
+                b - a,
+                bottom - top,
+                UNKNOWN_COLORS.0,
+                UNKNOWN_COLORS.1
+            );
+            return;
+        }
+    };
+    if edge {
+        let _ = writeln!(
+            svg,
+            "<line x1=\"{a:.1}\" y1=\"{:.1}\" x2=\"{a:.1}\" y2=\"{:.1}\" stroke=\"{}\"/>",
+            top,
+            bottom,
+            color,
+            a = a
+        );
+    }
+    let _ = writeln!(
+        svg,
+        "<line x1=\"{:.1}\" y1=\"{y:.1}\" x2=\"{:.1}\" y2=\"{y:.1}\" stroke=\"{}\" \
+         stroke-width=\"1.5\"/>",
+        a,
+        b,
+        color,
+        y = y
+    );
+}
+
+/// Draws a segment of a bus, with its label when there is room for it
+fn draw_bus(
+    svg: &mut String,
+    label: &str,
+    (fill, stroke): (&str, &str),
+    (a, b): (f64, f64),
+    (top, middle, bottom): (f64, f64, f64),
+) {
+    let s = ((b - a) / 2.0).min(3.0);
+    let _ = writeln!(
+        svg,
+        "<polygon points=\"{:.1},{m:.1} {:.1},{t:.1} {:.1},{t:.1} {:.1},{m:.1} {:.1},{b:.1} \
+         {:.1},{b:.1}\" fill=\"{}\" stroke=\"{}\"/>",
+        a,
+        a + s,
+        b - s,
+        b,
+        b - s,
+        a + s,
+        fill,
+        stroke,
+        m = middle,
+        t = top,
+        b = bottom
+    );
+    // Labels too long for the segment are cut, and left out below 3 characters
+    let room = ((b - a - 2.0 * s - 4.0) / CHAR_WIDTH).floor().max(0.0) as usize;
+    let text = if label.chars().count() <= room {
+        label.to_string()
+    } else if room >= 3 {
+        let mut t: String = label.chars().take(room - 1).collect();
+        t.push('…');
+        t
+    } else {
+        return;
+    };
+    let _ = writeln!(
+        svg,
+        "<text x=\"{:.1}\" y=\"{:.1}\" {} text-anchor=\"middle\">{}</text>",
+        (a + b) / 2.0,
+        middle + 4.0,
+        FONT,
+        escape(&text)
+    );
+}
@@ -0,0 +1,260 @@
+//! Sampling of signal groups on the edges of their own clock (multi-clock designs), and
+//! clocked views of a simulation (see `StateSimulation::sample_on`).
+use serde::Serialize;
+
+use crate::simulation::StateSimulation;
+use crate::types::Timestamp;
+use crate::vcd::VcdError;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+}
+
+impl Edge {
+    fn matches(self, previous: i8, current: i8) -> bool {
+        match self {
+            Edge::Rising => previous == 0 && current == 1,
+            Edge::Falling => previous == 1 && current == 0,
+            Edge::Both => (previous == 0 && current == 1) || (previous == 1 && current == 0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Sample {
+    /// Time of the clock edge
+    pub time: i64,
+    /// Value of each signal of the domain (in `ClockDomain::signals` order)
+    pub values: Vec<Vec<i8>>,
+}
+
+/// A clock and the signals sampled on its edges
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClockDomain {
+    pub name: String,
+    /// VCD id of the clock
+    pub clock: String,
+    pub edge: Edge,
+    /// VCD ids of the sampled signals
+    pub signals: Vec<String>,
+    pub samples: Vec<Sample>,
+    #[serde(skip)]
+    clock_level: Option<i8>,
+    #[serde(skip)]
+    last: Vec<Vec<i8>>,
+}
+
+/// Samples several clock domains while the simulation runs.
+///
+/// Signals are sampled with the value they held just before the edge (as a flip-flop
+/// would), changes occurring at the same time as the edge are seen by the next one.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MultiClockSampler {
+    pub domains: Vec<ClockDomain>,
+}
+
+impl MultiClockSampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a domain sampling `signals` (VCD ids) on the given edges of `clock`
+    pub fn add_domain(&mut self, name: &str, clock: &str, edge: Edge, signals: &[&str]) {
+        self.domains.push(ClockDomain {
+            name: name.to_string(),
+            clock: clock.to_string(),
+            edge,
+            signals: signals.iter().map(|s| s.to_string()).collect(),
+            samples: Vec::new(),
+            clock_level: None,
+            last: Vec::new(),
+        });
+    }
+
+    pub fn domain(&self, name: &str) -> Option<&ClockDomain> {
+        self.domains.iter().find(|d| d.name == name)
+    }
+
+    /// Checks that all the clocks and signals are tracked by the simulation
+    pub fn check(&self, sim: &StateSimulation) -> Result<(), VcdError> {
+        for d in &self.domains {
+            for id in std::iter::once(&d.clock).chain(&d.signals) {
+                sim.variable_offset(id).ok_or(VcdError::UnknownVariable)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Samples the current state of the simulation (to be called after each `next_cycle`),
+    /// untracked variables are ignored.
+    pub fn update(&mut self, sim: &StateSimulation, time: i64) {
+        let state = sim.state();
+        let value = |id: &str| {
+            let offset = sim.variable_offset(id)?;
+            let width = sim.variable_width(id)?;
+            Some(&state[offset..offset + width])
+        };
+        for d in self.domains.iter_mut() {
+            let clock = match value(&d.clock) {
+                Some(c) => c[0],
+                None => continue,
+            };
+            if let Some(previous) = d.clock_level {
+                if d.edge.matches(previous, clock) && d.last.len() == d.signals.len() {
+                    d.samples.push(Sample {
+                        time,
+                        values: d.last.clone(),
+                    });
+                }
+            }
+            d.clock_level = Some(clock);
+            d.last = d
+                .signals
+                .iter()
+                .filter_map(|id| value(id).map(|v| v.to_vec()))
+                .collect();
+        }
+    }
+}
+
+/// Runs the simulation until the end and returns the samples of each domain
+pub fn sample_domains(
+    sim: &mut StateSimulation,
+    mut sampler: MultiClockSampler,
+) -> Result<MultiClockSampler, VcdError> {
+    sampler.check(sim)?;
+    while !sim.done() {
+        let time = sim.next_cycle()?.cycle;
+        sampler.update(sim, time);
+    }
+    Ok(sampler)
+}
+
+/// Delay between a clock edge and the sampling of the state
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Offset {
+    Steps(u64),
+    Ns(f64),
+}
+
+/// State of a simulation just after a clock edge
+#[derive(Debug)]
+pub struct ClockedStep<'a> {
+    /// Time of the edge
+    pub edge: i64,
+    pub time: Timestamp,
+    /// Number of edges seen before this one
+    pub index: u64,
+    /// State at the time of the edge plus the offset
+    pub state: &'a [i8],
+}
+
+/// Clocked view of a simulation, built by `StateSimulation::sample_on`: each call to
+/// `next` advances the simulation to the next selected edge of the clock, and returns the
+/// state just after it (including the changes occurring at the time of the edge).
+///
+/// The values of the tracked variables are read with `sim()`, e.g., to feed a
+/// `MultiClockSampler` or an exporter.
+pub struct ClockedView<'a> {
+    sim: &'a mut StateSimulation,
+    clock: String,
+    edge: Edge,
+    offset: Offset,
+    /// Id of the clock and offset in dump units, resolved by the first `next`
+    resolved: Option<(String, u64)>,
+    level: Option<i8>,
+    edges: u64,
+}
+
+impl StateSimulation {
+    /// Clocked view of the simulation, sampled on the given edges of `clock` (a
+    /// hierarchical path, see `path_of`, or a VCD id). The clock must be tracked, it is
+    /// resolved by the first call to `ClockedView::next`.
+    pub fn sample_on(&mut self, clock: &str, edge: Edge) -> ClockedView<'_> {
+        ClockedView {
+            sim: self,
+            clock: clock.to_string(),
+            edge,
+            offset: Offset::Steps(0),
+            resolved: None,
+            level: None,
+            edges: 0,
+        }
+    }
+}
+
+impl<'a> ClockedView<'a> {
+    /// Samples the state `steps` dump units after each edge
+    pub fn offset(mut self, steps: u64) -> Self {
+        self.offset = Offset::Steps(steps);
+        self
+    }
+
+    /// Samples the state `ns` nanoseconds after each edge, rounded to dump units (taken as
+    /// nanoseconds when the timescale is unknown). The offset should be shorter than the
+    /// clock period, edges falling within it are not reported.
+    pub fn offset_ns(mut self, ns: f64) -> Self {
+        self.offset = Offset::Ns(ns);
+        self
+    }
+
+    pub fn sim(&self) -> &StateSimulation {
+        self.sim
+    }
+
+    fn resolve(&self) -> Result<(String, u64), VcdError> {
+        let header = self.sim.header().ok_or(VcdError::PartialHeader)?;
+        let id = header
+            .variables
+            .iter()
+            .find(|v| self.sim.path_of(v) == self.clock)
+            .map(|v| v.id.clone())
+            .unwrap_or_else(|| self.clock.clone());
+        self.sim
+            .variable_offset(&id)
+            .ok_or(VcdError::UnknownVariable)?;
+        let steps = match self.offset {
+            Offset::Steps(s) => s,
+            Offset::Ns(ns) => {
+                let step = self.sim.timescale().map_or(1e-9, |t| t.to_seconds(1));
+                (ns * 1e-9 / step).round().max(0.0) as u64
+            }
+        };
+        Ok((id, steps))
+    }
+
+    /// Advances to the next selected edge, returns None at the end of the dump
+    // Not an `Iterator`: the returned state borrows the simulation
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<ClockedStep<'_>>, VcdError> {
+        if self.resolved.is_none() {
+            self.resolved = Some(self.resolve()?);
+        }
+        let (clock, offset) = self.resolved.as_ref().unwrap();
+        let level = |sim: &StateSimulation| sim.value(clock).map_or(0, |v| v[0]);
+        while !self.sim.done() {
+            let edge = self.sim.next_cycle()?.cycle;
+            let current = level(self.sim);
+            let previous = self.level.replace(current);
+            if !previous.is_some_and(|p| self.edge.matches(p, current)) {
+                continue;
+            }
+            let target = edge + *offset as i64;
+            while *offset > 0 && !self.sim.done() && self.sim.pending_cycle() <= target {
+                self.sim.next_cycle()?;
+            }
+            self.level = Some(level(self.sim));
+            self.edges += 1;
+            return Ok(Some(ClockedStep {
+                edge,
+                time: self.sim.timestamp(edge),
+                index: self.edges - 1,
+                state: self.sim.state(),
+            }));
+        }
+        Ok(None)
+    }
+}
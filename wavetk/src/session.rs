@@ -0,0 +1,268 @@
+//! A set of dumps (VCD or FST) opened together and queried with namespaced paths.
+//!
+//! Each dump is registered under a name, variables are then designated by
+//! `<dump>:<hierarchical path>` (e.g., `golden:top.cpu.pc`).
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::compression;
+#[cfg(feature = "fst")]
+use crate::fst::{FstError, FstReader};
+use crate::hierarchy::Demangler;
+use crate::types::VariableInfo;
+use crate::vcd::{VcdError, VcdParser};
+
+#[derive(Debug)]
+pub enum SessionError {
+    IoError(io::Error),
+    Vcd(VcdError),
+    #[cfg(feature = "fst")]
+    Fst(FstError),
+    /// The format is not supported by this build (FST without the `fst` feature)
+    UnsupportedFormat,
+    DuplicateName,
+    UnknownDump,
+    UnknownVariable,
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            SessionError::IoError(e) => e.fmt(f),
+            SessionError::Vcd(e) => e.fmt(f),
+            x => write!(f, "{:?}", x),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SessionError::IoError(e) => Some(e),
+            SessionError::Vcd(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SessionError {
+    fn from(e: io::Error) -> Self {
+        SessionError::IoError(e)
+    }
+}
+
+impl From<VcdError> for SessionError {
+    fn from(e: VcdError) -> Self {
+        SessionError::Vcd(e)
+    }
+}
+
+#[cfg(feature = "fst")]
+impl From<FstError> for SessionError {
+    fn from(e: FstError) -> Self {
+        SessionError::Fst(e)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DumpFormat {
+    Vcd,
+    Fst,
+}
+
+impl DumpFormat {
+    /// Guesses the format from the file extension (`.fst` is FST, anything else VCD)
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("fst") => DumpFormat::Fst,
+            _ => DumpFormat::Vcd,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Source {
+    Vcd(PathBuf),
+    /// VCD read from the standard input, kept in memory as it can only be read once
+    VcdData(Vec<u8>),
+    #[cfg(feature = "fst")]
+    Fst(FstReader),
+}
+
+#[derive(Debug)]
+struct Dump {
+    name: String,
+    source: Source,
+    variables: Vec<VariableInfo>,
+    demangler: Demangler,
+}
+
+impl Dump {
+    fn find(&self, path: &str) -> Option<&VariableInfo> {
+        self.variables
+            .iter()
+            .find(|v| self.demangler.path(v) == path)
+    }
+}
+
+/// Several dumps answering hierarchy and value queries through a single interface.
+///
+/// Headers are loaded when a dump is opened, value queries only decode the changes of the
+/// requested variable (see `VcdParser::changes_of` and `FstReader::changes_of`), a VCD is
+/// re-read for each query. A VCD read from the standard input (`-`) is kept in memory.
+#[derive(Debug, Default)]
+pub struct Session {
+    dumps: Vec<Dump>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a dump (format guessed from the extension) and registers it under `name`
+    pub fn open<P: AsRef<Path>>(&mut self, name: &str, path: P) -> Result<(), SessionError> {
+        let path = path.as_ref();
+        self.open_as(name, path, DumpFormat::from_path(path))
+    }
+
+    pub fn open_as<P: AsRef<Path>>(
+        &mut self,
+        name: &str,
+        path: P,
+        format: DumpFormat,
+    ) -> Result<(), SessionError> {
+        if self.dumps.iter().any(|d| d.name == name) {
+            return Err(SessionError::DuplicateName);
+        }
+        let path = path.as_ref();
+        let (source, variables) = match format {
+            DumpFormat::Vcd if compression::is_stdin(path) => {
+                let mut data = Vec::new();
+                compression::open_input(path)?.read_to_end(&mut data)?;
+                return self.open_vcd_data(name, data);
+            }
+            DumpFormat::Vcd => {
+                let mut parser = VcdParser::with_chunk_size(4096, compression::open(path)?);
+                let variables = parser.load_header()?.variables.clone();
+                (Source::Vcd(path.to_path_buf()), variables)
+            }
+            #[cfg(feature = "fst")]
+            DumpFormat::Fst => {
+                let name = path.to_str().ok_or(FstError::Utf8Error)?;
+                let mut reader = FstReader::from_file(name, false)?;
+                let variables = reader.load_header().variables;
+                (Source::Fst(reader), variables)
+            }
+            #[cfg(not(feature = "fst"))]
+            DumpFormat::Fst => return Err(SessionError::UnsupportedFormat),
+        };
+        self.dumps.push(Dump {
+            name: name.to_string(),
+            source,
+            variables,
+            demangler: Demangler::default(),
+        });
+        Ok(())
+    }
+
+    /// Registers a VCD held in memory (e.g., read from the standard input) under `name`
+    pub fn open_vcd_data(&mut self, name: &str, data: Vec<u8>) -> Result<(), SessionError> {
+        if self.dumps.iter().any(|d| d.name == name) {
+            return Err(SessionError::DuplicateName);
+        }
+        let mut parser = VcdParser::with_chunk_size(4096, data.as_slice());
+        let variables = parser.load_header()?.variables.clone();
+        self.dumps.push(Dump {
+            name: name.to_string(),
+            source: Source::VcdData(data),
+            variables,
+            demangler: Demangler::default(),
+        });
+        Ok(())
+    }
+
+    /// Names of the dumps, in opening order
+    pub fn dumps(&self) -> impl Iterator<Item = &str> {
+        self.dumps.iter().map(|d| d.name.as_str())
+    }
+
+    pub fn variables(&self, dump: &str) -> Option<&[VariableInfo]> {
+        self.dump(dump).map(|d| d.variables.as_slice())
+    }
+
+    /// Sets the rules applied to the hierarchical names of the variables of a dump, so that
+    /// the same paths designate the same signals in the dumps of different simulators
+    pub fn set_demangler(&mut self, dump: &str, demangler: Demangler) -> Result<(), SessionError> {
+        let dump = self
+            .dumps
+            .iter_mut()
+            .find(|d| d.name == dump)
+            .ok_or(SessionError::UnknownDump)?;
+        dump.demangler = demangler;
+        Ok(())
+    }
+
+    /// Namespaced paths (`<dump>:<path>`) of all the variables of all the dumps
+    pub fn paths(&self) -> Vec<String> {
+        self.dumps
+            .iter()
+            .flat_map(|d| {
+                d.variables
+                    .iter()
+                    .map(move |v| format!("{}:{}", d.name, d.demangler.path(v)))
+            })
+            .collect()
+    }
+
+    /// Looks up a variable from its namespaced path
+    pub fn find(&self, path: &str) -> Result<&VariableInfo, SessionError> {
+        let (dump, path) = self.split(path)?;
+        dump.find(path).ok_or(SessionError::UnknownVariable)
+    }
+
+    /// Value changes of a variable, as (time, value) pairs
+    pub fn changes(&mut self, path: &str) -> Result<Vec<(u64, String)>, SessionError> {
+        let (name, path) = path.split_once(':').ok_or(SessionError::UnknownDump)?;
+        let dump = self
+            .dumps
+            .iter_mut()
+            .find(|d| d.name == name)
+            .ok_or(SessionError::UnknownDump)?;
+        let var = dump
+            .find(path)
+            .ok_or(SessionError::UnknownVariable)?
+            .clone();
+        match &mut dump.source {
+            Source::Vcd(p) => {
+                let mut parser = VcdParser::with_chunk_size(4096, compression::open(p)?);
+                parser.load_header()?;
+                Ok(parser.changes_of(&var.id)?)
+            }
+            Source::VcdData(data) => {
+                let mut parser = VcdParser::with_chunk_size(4096, data.as_slice());
+                parser.load_header()?;
+                Ok(parser.changes_of(&var.id)?)
+            }
+            #[cfg(feature = "fst")]
+            Source::Fst(reader) => Ok(reader.changes_of(var.handle)?),
+        }
+    }
+
+    /// Value of a variable at the given time (None before its first change)
+    pub fn value_at(&mut self, path: &str, time: u64) -> Result<Option<String>, SessionError> {
+        let changes = self.changes(path)?;
+        let n = changes.partition_point(|(t, _)| *t <= time);
+        Ok(n.checked_sub(1).map(|i| changes[i].1.clone()))
+    }
+
+    fn dump(&self, name: &str) -> Option<&Dump> {
+        self.dumps.iter().find(|d| d.name == name)
+    }
+
+    fn split<'a>(&self, path: &'a str) -> Result<(&Dump, &'a str), SessionError> {
+        let (name, path) = path.split_once(':').ok_or(SessionError::UnknownDump)?;
+        Ok((self.dump(name).ok_or(SessionError::UnknownDump)?, path))
+    }
+}
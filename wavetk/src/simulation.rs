@@ -1,56 +1,1099 @@
-use std::collections::{HashMap, HashSet};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::File;
 use std::io;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use crate::types::{VariableInfo, VariableKind};
-use crate::vcd::{VcdCommand, VcdError, VcdParser, VcdValue};
+use serde::{Deserialize, Serialize};
 
-fn logic_level(c: char) -> i8 {
+use crate::compression;
+use crate::derived::{DerivedError, DerivedSignal};
+use crate::hierarchy::{
+    find_bit_blasted_buses_with, group_by_scope_with, rollup_with, Aggregate, BitBlastedBus,
+    Demangler, ScopeTotal,
+};
+use crate::types::{BitVector, Range, Timescale, Timestamp, VariableInfo, VariableKind};
+use crate::vcd::{
+    BodyTruncation, ChangeProblem, DumpSection, InvalidChange, ReorderBuffer, TimestampPolicy,
+    VcdCommand, VcdError, VcdHeader, VcdMetadata, VcdParser, VcdValue,
+};
+use crate::vcd_writer::{short_id, VcdWriter};
+
+/// Encoding of the logic levels in the state
+pub const LEVEL_0: i8 = 0;
+pub const LEVEL_1: i8 = 1;
+/// Uninitialized
+pub const LEVEL_U: i8 = -1;
+/// Weak unknown
+pub const LEVEL_W: i8 = -2;
+/// High impedance
+pub const LEVEL_Z: i8 = -3;
+/// Unknown
+pub const LEVEL_X: i8 = -4;
+/// Any other character found in a value (only given by `logic_level`, such characters are
+/// rejected in value changes)
+pub const LEVEL_OTHER: i8 = -5;
+/// No data, dumping is disabled (`$dumpoff`). Never found in a value, unlike X which is
+/// driven by the design.
+pub const LEVEL_NO_DATA: i8 = -6;
+/// Weak 0 (`L` of IEEE 1164, written by VHDL simulators such as GHDL)
+pub const LEVEL_L: i8 = -7;
+/// Weak 1 (`H` of IEEE 1164)
+pub const LEVEL_H: i8 = -8;
+/// Don't care (`-` of IEEE 1164)
+pub const LEVEL_DONT_CARE: i8 = -9;
+
+pub(crate) fn logic_level(c: char) -> i8 {
     match c as u8 {
-        b'0' => 0,
-        b'1' => 1,
-        b'U' | b'u' => -1,
-        b'W' | b'w' => -2,
-        b'Z' | b'z' => -3,
-        b'X' | b'x' => -4,
-        _ => -5,
+        b'0' => LEVEL_0,
+        b'1' => LEVEL_1,
+        b'U' | b'u' => LEVEL_U,
+        b'W' | b'w' => LEVEL_W,
+        b'Z' | b'z' => LEVEL_Z,
+        b'X' | b'x' => LEVEL_X,
+        b'L' | b'l' => LEVEL_L,
+        b'H' | b'h' => LEVEL_H,
+        b'-' => LEVEL_DONT_CARE,
+        _ => LEVEL_OTHER,
+    }
+}
+
+/// Logic level of a character of a value, characters that are not one of the IEEE 1164
+/// values (or `Z` and `X` in lower case) are rejected
+fn checked_level(c: char) -> Result<i8, ChangeProblem> {
+    match logic_level(c) {
+        LEVEL_OTHER => Err(ChangeProblem::InvalidCharacter(c)),
+        level => Ok(level),
+    }
+}
+
+/// Character representation of a logic level (inverse of `logic_level`)
+pub fn logic_char(level: i8) -> char {
+    match level {
+        LEVEL_0 => '0',
+        LEVEL_1 => '1',
+        LEVEL_U => 'U',
+        LEVEL_W => 'W',
+        LEVEL_Z => 'Z',
+        LEVEL_X => 'X',
+        LEVEL_L => 'L',
+        LEVEL_H => 'H',
+        LEVEL_DONT_CARE => '-',
+        _ => '?',
+    }
+}
+
+/// Readable name of a logic level, for debugging
+pub fn logic_level_name(level: i8) -> &'static str {
+    match level {
+        LEVEL_0 => "0",
+        LEVEL_1 => "1",
+        LEVEL_U => "U (uninitialized)",
+        LEVEL_W => "W (weak unknown)",
+        LEVEL_Z => "Z (high impedance)",
+        LEVEL_X => "X (unknown)",
+        LEVEL_OTHER => "other",
+        LEVEL_NO_DATA => "no data (dumping disabled)",
+        LEVEL_L => "L (weak 0)",
+        LEVEL_H => "H (weak 1)",
+        LEVEL_DONT_CARE => "- (don't care)",
+        _ => "invalid",
+    }
+}
+
+/// Decodes the value of a variable from its slice of the state (leftmost bit first).
+///
+/// Returns None if any bit is not 0 or 1, or if the value doesn't fit in a u64.
+pub fn decode_unsigned(bits: &[i8]) -> Option<u64> {
+    let first = bits.iter().position(|b| *b != 0).unwrap_or(bits.len());
+    if bits.len() - first > 64 {
+        return None;
+    }
+    let mut x = 0u64;
+    for b in &bits[first..] {
+        match b {
+            0 | 1 => x = (x << 1) | (*b as u64),
+            _ => return None,
+        }
+    }
+    Some(x)
+}
+
+/// Decodes a two's complement value from its slice of the state (leftmost bit first), the
+/// leftmost bit being the sign.
+///
+/// Returns None if any bit is not 0 or 1, or if the value doesn't fit in an i64.
+pub fn decode_signed(bits: &[i8]) -> Option<i64> {
+    let sign = match bits.first() {
+        Some(b) => *b,
+        None => return Some(0),
+    };
+    // Leading copies of the sign bit don't change the value
+    let first = bits
+        .iter()
+        .position(|b| *b != sign)
+        .unwrap_or(bits.len())
+        .saturating_sub(1);
+    let bits = &bits[first..];
+    if bits.len() > 64 {
+        return None;
+    }
+    let x = decode_unsigned(bits)?;
+    if sign == 1 && bits.len() < 64 {
+        Some((x | (!0 << bits.len())) as i64)
+    } else {
+        Some(x as i64)
+    }
+}
+
+/// Decodes a Gray-coded value from its slice of the state (leftmost bit first), e.g., a
+/// FIFO pointer crossing clock domains.
+///
+/// Returns None under the same conditions as `decode_unsigned`.
+pub fn decode_gray(bits: &[i8]) -> Option<u64> {
+    let mut x = decode_unsigned(bits)?;
+    let mut shift = 1;
+    while shift < 64 {
+        x ^= x >> shift;
+        shift <<= 1;
+    }
+    Some(x)
+}
+
+/// Decodes a one-hot value from its slice of the state (leftmost bit first): returns the
+/// index of the bit set, counted from the rightmost bit.
+///
+/// Returns None if any bit is not 0 or 1, or if not exactly one bit is set.
+pub fn decode_one_hot(bits: &[i8]) -> Option<usize> {
+    let mut index = None;
+    for (i, b) in bits.iter().rev().enumerate() {
+        match (b, index) {
+            (0, _) => {}
+            (1, None) => index = Some(i),
+            _ => return None,
+        }
+    }
+    index
+}
+
+/// How the values set before the first timestamp (e.g., a `$dumpvars` block preceding
+/// `#0`) are reported
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum InitialCycle {
+    /// As a cycle numbered -1, followed by the first timestamp
+    #[default]
+    Separate,
+    /// As a cycle 0 (merged with the changes of `#0` if present), no cycle -1 is returned
+    Merge,
+    /// As a `Step::Initial` from `next_step` (`next_cycle` still reports it as cycle -1)
+    Event,
+}
+
+/// Position of a state returned by `next_step`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Step {
+    /// Values set before the first timestamp
+    Initial,
+    Cycle(i64),
+}
+
+/// Summary of a run returned by `StateSimulation::run_to_end`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct RunSummary {
+    /// Number of cycles returned, the values set before the first timestamp included
+    pub cycles: u64,
+    /// Time of the last cycle, None if no cycle was left
+    pub end_time: Option<Timestamp>,
+    /// Number of commands of the body parsed (timestamps, value changes, dump sections...)
+    pub commands: u64,
+    /// Number of value changes parsed
+    pub changes: u64,
+    /// Number of bytes of the (decompressed) input consumed
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// Where the input was cut, if it ends in the middle of a command (see
+    /// `StateSimulation::set_allow_truncation`)
+    pub truncation: Option<BodyTruncation>,
+}
+
+impl RunSummary {
+    /// Bytes of input parsed per second
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+}
+
+/// Function called with each cycle (see `StateSimulation::on_cycle`)
+pub type CycleHook = Box<dyn FnMut(&CycleStep) + Send>;
+
+/// A cycle returned by `StateSimulation::next_cycle`
+#[derive(Debug, Clone, Copy)]
+pub struct CycleStep<'a> {
+    /// Cycle number (timestamp of the dump), -1 for the values set before the first
+    /// timestamp (see `InitialCycle`)
+    pub cycle: i64,
+    /// Cycle converted to real time, when the timescale is known
+    pub time: Timestamp,
+    /// State after the changes of the cycle
+    pub state: &'a [i8],
+    /// Variables changed during the cycle (see `StateSimulation::dirty_bitmap`)
+    pub dirty: &'a [u64],
+    /// Whether dumping is disabled (`$dumpoff` not followed by `$dumpon`) at the end of
+    /// the cycle
+    pub blackout: bool,
+}
+
+impl<'a> CycleStep<'a> {
+    /// Indices in `StateSimulation::tracked_variables` of the variables changed during the
+    /// cycle
+    pub fn changed(&self) -> impl Iterator<Item = usize> + 'a {
+        self.dirty.iter().enumerate().flat_map(|(k, w)| {
+            let w = *w;
+            (0..64)
+                .filter(move |b| w & (1 << b) != 0)
+                .map(move |b| k * 64 + b)
+        })
+    }
+}
+
+/// Number of distinct logic levels of the state (0, 1, U, W, Z, X, other, no data, L, H
+/// and -)
+pub const LOGIC_LEVELS: usize = 11;
+
+/// Index of a logic level in `[0, LOGIC_LEVELS)`: 0 and 1, then U, W, Z, X, other, no data,
+/// L, H and -
+fn level_index(level: i8) -> usize {
+    if level >= 0 {
+        level.min(1) as usize
+    } else {
+        (1 - level.max(LEVEL_DONT_CARE)) as usize
+    }
+}
+
+/// Summary of the activity of a variable (see `StateSimulation::set_stats`)
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct VariableStats {
+    /// Number of cycles in which the variable changed, its first assignment included
+    pub changes: u64,
+    pub first_change: Option<i64>,
+    pub last_change: Option<i64>,
+    /// Time spent in each logic level, summed over the bits of the variable (see
+    /// `time_in`)
+    pub level_time: [u64; LOGIC_LEVELS],
+}
+
+impl VariableStats {
+    /// Time spent in the given logic level, summed over the bits of the variable (e.g., for
+    /// a 4-bit bus, 10 time units with 2 bits at X count as 20 for X)
+    pub fn time_in(&self, level: i8) -> u64 {
+        self.level_time[level_index(level)]
+    }
+}
+
+impl Aggregate for VariableStats {
+    /// Sums the changes and times, keeps the earliest first change and latest last change
+    fn add(&mut self, other: &Self) {
+        self.changes += other.changes;
+        self.first_change = match (self.first_change, other.first_change) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.last_change = self.last_change.max(other.last_change);
+        for (t, o) in self.level_time.iter_mut().zip(&other.level_time) {
+            *t += o;
+        }
+    }
+}
+
+/// Statistics of a variable accumulated during the simulation, as saved by checkpoints
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatsAccumulator {
+    stats: VariableStats,
+    // Number of bits of the current value in each level, and time it was set
+    bits: [u64; LOGIC_LEVELS],
+    since: i64,
+}
+
+impl StatsAccumulator {
+    fn update(&mut self, cycle: i64, value: &[i8]) {
+        let mut level_time = self.stats.level_time;
+        self.accumulate(&mut level_time, cycle);
+        self.stats.level_time = level_time;
+        self.bits = [0; LOGIC_LEVELS];
+        for l in value {
+            self.bits[level_index(*l)] += 1;
+        }
+        self.since = cycle.max(0);
+        self.stats.changes += 1;
+        self.stats.first_change.get_or_insert(cycle);
+        self.stats.last_change = Some(cycle);
+    }
+
+    /// Adds the time spent by the current value up to `cycle`
+    fn accumulate(&self, level_time: &mut [u64; LOGIC_LEVELS], cycle: i64) {
+        let elapsed = (cycle.max(0) - self.since).max(0) as u64;
+        for (t, n) in level_time.iter_mut().zip(&self.bits) {
+            *t += elapsed * n;
+        }
+    }
+}
+
+/// Position of the value of a VCD id in the state
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    offset: usize,
+    width: usize,
+    /// Index of the variable in `allocated`
+    index: usize,
+}
+
+/// Values of the string variables, each distinct text is stored once
+#[derive(Debug, Default)]
+struct StringTable {
+    strings: Vec<String>,
+    index: HashMap<String, usize>,
+    /// Size of the texts, counted twice as they are also the keys of `index`
+    bytes: usize,
+}
+
+impl StringTable {
+    /// Index of a text in the table, added if not there yet. Fails with
+    /// `VcdError::MemoryLimit` if the table would grow beyond `limit` bytes.
+    fn intern(&mut self, text: &str, limit: Option<usize>) -> Result<usize, VcdError> {
+        if let Some(i) = self.index.get(text) {
+            return Ok(*i);
+        }
+        let bytes = self.bytes + 2 * text.len();
+        if limit.is_some_and(|l| bytes > l) {
+            return Err(VcdError::MemoryLimit);
+        }
+        self.bytes = bytes;
+        self.strings.push(text.to_string());
+        self.index.insert(text.to_string(), self.strings.len() - 1);
+        Ok(self.strings.len() - 1)
+    }
+
+    fn clear(&mut self) {
+        self.strings.clear();
+        self.index.clear();
+        self.bytes = 0;
+    }
+}
+
+/// Layout of `StateSimulation::save_header_info`
+#[derive(Serialize)]
+struct HeaderInfoJson<'a, V> {
+    variables: V,
+    metadata: &'a VcdMetadata,
+}
+
+/// Serializes the (key, value) pairs of an iterator as a map, without collecting them
+struct SerializeMapOnce<I>(RefCell<Option<I>>);
+
+impl<K, V, I> Serialize for SerializeMapOnce<I>
+where
+    K: Serialize,
+    V: Serialize,
+    I: Iterator<Item = (K, V)>,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let pairs = self.0.borrow_mut().take();
+        serializer.collect_map(pairs.into_iter().flatten())
+    }
+}
+
+/// Occurrences of the tracked event variables during a cycle, kept in declaration order
+#[derive(Debug, Default)]
+struct EventCounts {
+    counts: Vec<(String, u32)>,
+    index: HashMap<String, usize>,
+}
+
+impl EventCounts {
+    fn insert(&mut self, id: &str) {
+        if !self.index.contains_key(id) {
+            self.index.insert(id.to_string(), self.counts.len());
+            self.counts.push((id.to_string(), 0));
+        }
+    }
+
+    fn contains_key(&self, id: &str) -> bool {
+        self.index.contains_key(id)
+    }
+
+    fn get(&self, id: &str) -> Option<u32> {
+        self.index.get(id).map(|i| self.counts[*i].1)
+    }
+
+    fn get_mut(&mut self, id: &str) -> Option<&mut u32> {
+        let i = *self.index.get(id)?;
+        Some(&mut self.counts[i].1)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.counts.iter().map(|(id, n)| (id.as_str(), *n))
+    }
+
+    fn reset(&mut self) {
+        self.counts.iter_mut().for_each(|(_, n)| *n = 0);
+    }
+
+    fn clear(&mut self) {
+        self.counts.clear();
+        self.index.clear();
+    }
+}
+
+/// Copies a part of a checkpoint over the one allocated for the resumed simulation. An
+/// empty part is skipped (checkpoints written before it was saved), any other length
+/// mismatch is a `VcdError::ParseError`.
+fn restore_vec<T: Clone>(allocated: &mut [T], saved: &[T]) -> Result<(), VcdError> {
+    if saved.is_empty() {
+        return Ok(());
+    }
+    if saved.len() != allocated.len() {
+        return Err(VcdError::ParseError);
+    }
+    allocated.clone_from_slice(saved);
+    Ok(())
+}
+
+/// Number encoded by a VCD id, when made of at most 4 printable ASCII characters (the
+/// inverse of the numbering used by `VcdWriter`, and by most simulators).
+fn id_code(id: &str) -> Option<usize> {
+    const BASE: usize = (b'~' - b'!') as usize + 1;
+    let bytes = id.as_bytes();
+    if bytes.is_empty() || bytes.len() > 4 {
+        return None;
+    }
+    let mut code = 0;
+    for (i, c) in bytes.iter().rev().enumerate() {
+        if !(b'!'..=b'~').contains(c) {
+            return None;
+        }
+        let digit = (c - b'!') as usize;
+        code = if i == 0 {
+            digit
+        } else {
+            digit + BASE * (code + 1)
+        };
+    }
+    Some(code)
+}
+
+/// Slots of the tracked ids, in a flat vector indexed by the dense index of each id.
+///
+/// All the ids are numbered once, by `reset`. Ids are usually numbered densely by
+/// simulators, their index is then their number (see `id_code`). The other ids are kept
+/// sorted, and indexed after them by their rank.
+///
+/// The ids declared by the header (tracked or not) are also recorded, to tell the changes
+/// of untracked variables from the ones of undeclared ids.
+#[derive(Debug, Default)]
+struct DispatchTable {
+    /// Number of ids indexed by their number
+    n_dense: usize,
+    /// Other ids, sorted
+    sparse: Vec<Box<str>>,
+    slots: Vec<Option<Slot>>,
+    declared: Vec<bool>,
+}
+
+impl DispatchTable {
+    /// Empties the table and numbers the given ids, the only ones it can then hold
+    fn reset<'a>(&mut self, ids: impl Iterator<Item = &'a str> + Clone) {
+        self.n_dense = 2 * ids.clone().count() + 64;
+        self.sparse.clear();
+        let n_dense = self.n_dense;
+        self.sparse.extend(
+            ids.filter(|id| id_code(id).is_none_or(|c| c >= n_dense))
+                .map(Box::from),
+        );
+        self.sparse.sort_unstable();
+        self.sparse.dedup();
+        let len = self.n_dense + self.sparse.len();
+        self.slots.clear();
+        self.slots.resize(len, None);
+        self.declared.clear();
+        self.declared.resize(len, false);
+    }
+
+    /// Index of an id in `slots`
+    #[inline]
+    fn index(&self, id: &str) -> Option<usize> {
+        match id_code(id) {
+            Some(c) if c < self.n_dense => Some(c),
+            _ => {
+                let k = self.sparse.binary_search_by(|s| (**s).cmp(id)).ok()?;
+                Some(self.n_dense + k)
+            }
+        }
+    }
+
+    fn declare(&mut self, id: &str) {
+        if let Some(i) = self.index(id) {
+            self.declared[i] = true;
+        }
+    }
+
+    fn is_declared(&self, id: &str) -> bool {
+        self.index(id).is_some_and(|i| self.declared[i])
+    }
+
+    fn insert(&mut self, id: &str, slot: Slot) {
+        let i = self.index(id).expect("id numbered by reset");
+        self.slots[i] = Some(slot);
+    }
+
+    #[inline]
+    fn get(&self, id: &str) -> Option<&Slot> {
+        self.slots[self.index(id)?].as_ref()
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        self.get(id).is_some()
+    }
+}
+
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+/// Serializable snapshot of a simulation between two cycles, from which a new process can
+/// resume the simulation of the same file (see `StateSimulation::checkpoint` and
+/// `StateSimulation::resume`).
+///
+/// The memory limit is not saved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub version: u32,
+    pub header: VcdHeader,
+    /// Bytes of the VCD file consumed at the checkpoint
+    pub offset: u64,
+    pub tracked: Vec<String>,
+    pub reconstruct_buses: bool,
+    pub initial_cycle: InitialCycle,
+    pub utf8: bool,
+    pub state: Vec<i8>,
+    pub previous_cycle: i64,
+    pub current_cycle: i64,
+    pub initial_snapshot: Option<bool>,
+    /// Cycle of the last change of each tracked variable
+    #[serde(default)]
+    pub last_change: Vec<Option<i64>>,
+    /// Whether dumping was disabled by `$dumpoff`
+    #[serde(default)]
+    pub blackout: bool,
+    #[serde(default)]
+    pub blackouts: Vec<(i64, Option<i64>)>,
+    #[serde(default)]
+    pub time_range: Option<(i64, i64)>,
+    /// Definitions of the derived signals
+    #[serde(default)]
+    pub derived: Vec<String>,
+    /// Variables set at least once (see `StateSimulation::assigned_bitmap`)
+    #[serde(default)]
+    pub assigned: Vec<u64>,
+    /// Current value of the string variables set so far, as (id, value) pairs
+    #[serde(default)]
+    pub strings: Vec<(String, String)>,
+    /// Whether undecodable bytes are replaced (see `VcdParser::set_lossy`)
+    #[serde(default)]
+    pub lossy: bool,
+    /// Whether the input may end in the middle of a command (see
+    /// `VcdParser::set_allow_truncation`)
+    #[serde(default)]
+    pub allow_truncation: bool,
+    /// Level of the variables not set yet (see `StateSimulation::set_initial_fill`)
+    #[serde(default)]
+    pub initial_fill: i8,
+    #[serde(default)]
+    pub history_depth: usize,
+    /// History of each tracked variable (see `StateSimulation::set_history_depth`)
+    #[serde(default)]
+    pub history: Vec<VecDeque<(i64, Vec<i8>)>>,
+    #[serde(default)]
+    pub stats_enabled: bool,
+    /// Statistics of each tracked variable (see `StateSimulation::set_stats`)
+    #[serde(default)]
+    pub stats: Vec<StatsAccumulator>,
+    /// Occurrences of each tracked event in the last cycle, as (id, count) pairs
+    #[serde(default)]
+    pub event_counts: Vec<(String, u32)>,
+    /// Value changes parsed so far and change budget (see `VcdParser::set_change_budget`)
+    #[serde(default)]
+    pub change_count: u64,
+    #[serde(default)]
+    pub change_budget: Option<u64>,
+    /// Handling of decreasing timestamps (see `VcdParser::set_timestamp_policy`), with the
+    /// highest timestamp seen and the cycles held back by `TimestampPolicy::Reorder`
+    #[serde(default)]
+    pub timestamp_policy: TimestampPolicy,
+    #[serde(default)]
+    pub last_time: Option<u64>,
+    #[serde(default)]
+    pub reorder: ReorderBuffer,
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint as JSON
+    pub fn save<W: io::Write>(&self, out: W) -> Result<(), VcdError> {
+        serde_json::to_writer(out, self).map_err(|e| VcdError::IoError(e.into()))
+    }
+
+    pub fn load<R: Read>(input: R) -> Result<Self, VcdError> {
+        let checkpoint: Checkpoint =
+            serde_json::from_reader(input).map_err(|e| VcdError::IoError(e.into()))?;
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(VcdError::ParseError);
+        }
+        Ok(checkpoint)
     }
 }
 
 /// The StateSimulation recreates the complete state of a circuit over the time
 pub struct StateSimulation {
-    parser: VcdParser<File>,
+    parser: VcdParser<Box<dyn Read + Send>>,
+    /// File the simulation was opened from, and position of the end of its header (for
+    /// `reset`)
+    source: Option<PathBuf>,
+    header_end: Option<u64>,
     state: Vec<i8>,
-    var_offset: HashMap<String, usize>,
-    var_width: HashMap<String, usize>,
+    slots: DispatchTable,
     tracked_var: HashSet<String>,
+    allocated: Vec<(usize, VariableInfo)>,
+    dirty: Vec<u64>,
+    /// Variables set by the dump at least once, as a bitmap like `dirty`
+    assigned: Vec<u64>,
+    /// Level of the variables not set yet
+    initial_fill: i8,
+    history_depth: usize,
+    history: Vec<VecDeque<(i64, Vec<i8>)>>,
+    last_change: Vec<Option<i64>>,
+    stats_enabled: bool,
+    stats: Vec<StatsAccumulator>,
+    blackout: bool,
+    blackouts: Vec<(i64, Option<i64>)>,
+    /// Inside the values listed by a `$dumpoff` (up to its `$end`)
+    dumpoff_section: bool,
+    initial_cycle: InitialCycle,
+    cycle_hooks: Vec<CycleHook>,
+    /// Commands of the body parsed so far
+    command_count: u64,
+    initial_snapshot: Option<bool>,
+    time_range: Option<(i64, i64)>,
+    demangler: Demangler,
+    reconstruct_buses: bool,
+    buses: Vec<BitBlastedBus>,
+    bus_members: HashMap<String, usize>,
+    memory_limit: Option<usize>,
+    /// Part of the state holding the variables of each scope (derived signals excluded)
+    scope_ranges: HashMap<String, std::ops::Range<usize>>,
+    /// Derived signals, with the variable describing each of them
+    derived: Vec<(DerivedSignal, VariableInfo)>,
+    /// String variables are not part of the state: the current value of each tracked one
+    /// (by id) is an index in `strings`
+    strings: StringTable,
+    string_values: HashMap<String, Option<usize>>,
+    /// Event variables have no value either: occurrences of each tracked one (by id) in the
+    /// last cycle
+    event_counts: EventCounts,
     previous_cycle: i64,
     current_cycle: i64,
 }
 
 impl StateSimulation {
-    pub fn new(filename: &str) -> io::Result<Self> {
+    /// Opens the given VCD file, the special name `-` reads from the standard input.
+    ///
+    /// Compressed inputs are decompressed (see the `compression` module).
+    pub fn new<P: AsRef<Path>>(filename: P) -> io::Result<Self> {
+        let filename = filename.as_ref();
+        let mut sim = Self::from_reader(compression::open_input(filename)?);
+        if !compression::is_stdin(filename) {
+            sim.source = Some(filename.to_path_buf());
+        }
+        Ok(sim)
+    }
+
+    /// Opens a VCD file (see `new`) and only parses its header (see
+    /// `VcdParser::parse_header_only`): the variables and scopes are known, the body is not
+    /// read and the simulation has no cycle. `reset` reopens the file after the header to
+    /// simulate the body anyway.
+    pub fn header_only<P: AsRef<Path>>(filename: P) -> Result<Self, VcdError> {
+        let mut sim = Self::new(filename)?;
+        sim.load_header()?;
+        sim.parser.skip_body();
+        Ok(sim)
+    }
+
+    /// Builds a simulation over any VCD stream (e.g., a pipe or an in-memory buffer)
+    pub fn from_reader<R: Read + Send + 'static>(inner: R) -> Self {
         const N_VAR: usize = 2048;
-        let f = File::open(filename)?;
-        Ok(StateSimulation {
-            parser: VcdParser::with_chunk_size(4096, f),
+        let inner: Box<dyn Read + Send> = Box::new(inner);
+        StateSimulation {
+            parser: VcdParser::with_chunk_size(4096, inner),
+            source: None,
+            header_end: None,
             state: Vec::with_capacity(N_VAR),
-            var_offset: HashMap::with_capacity(N_VAR),
-            var_width: HashMap::with_capacity(N_VAR),
+            slots: DispatchTable::default(),
             tracked_var: HashSet::new(),
+            allocated: Vec::new(),
+            dirty: Vec::new(),
+            assigned: Vec::new(),
+            initial_fill: LEVEL_0,
+            history_depth: 0,
+            history: Vec::new(),
+            last_change: Vec::new(),
+            stats_enabled: false,
+            stats: Vec::new(),
+            blackout: false,
+            blackouts: Vec::new(),
+            dumpoff_section: false,
+            initial_cycle: InitialCycle::default(),
+            cycle_hooks: Vec::new(),
+            command_count: 0,
+            initial_snapshot: None,
+            time_range: None,
+            demangler: Demangler::default(),
+            reconstruct_buses: false,
+            buses: Vec::new(),
+            bus_members: HashMap::new(),
+            memory_limit: None,
+            scope_ranges: HashMap::new(),
+            derived: Vec::new(),
+            strings: StringTable::default(),
+            string_values: HashMap::new(),
+            event_counts: EventCounts::default(),
             previous_cycle: -1,
             current_cycle: -1,
-        })
+        }
     }
 
     pub fn state(&self) -> &[i8] {
         &self.state
     }
 
+    /// Captures the simulation state, the state must have been allocated.
+    ///
+    /// Must be taken between two calls to `next_cycle` (not from a callback).
+    pub fn checkpoint(&self) -> Result<Checkpoint, VcdError> {
+        let header = self.parser.header().ok_or(VcdError::PartialHeader)?;
+        let mut tracked: Vec<String> = self.tracked_var.iter().cloned().collect();
+        tracked.sort();
+        let (last_time, reorder) = self.parser.timestamp_state();
+        let mut event_counts: Vec<(String, u32)> = self
+            .event_counts
+            .iter()
+            .map(|(id, n)| (id.to_string(), n))
+            .collect();
+        event_counts.sort();
+        Ok(Checkpoint {
+            version: CHECKPOINT_VERSION,
+            header: header.clone(),
+            offset: self.parser.position(),
+            tracked,
+            reconstruct_buses: self.reconstruct_buses,
+            initial_cycle: self.initial_cycle,
+            utf8: self.parser.utf8(),
+            lossy: self.parser.lossy(),
+            allow_truncation: self.parser.allow_truncation(),
+            state: self.state.clone(),
+            previous_cycle: self.previous_cycle,
+            current_cycle: self.current_cycle,
+            initial_snapshot: self.initial_snapshot,
+            last_change: self.last_change.clone(),
+            blackout: self.blackout,
+            blackouts: self.blackouts.clone(),
+            time_range: self.time_range,
+            derived: self.derived.iter().map(|d| d.0.to_string()).collect(),
+            assigned: self.assigned.clone(),
+            strings: self
+                .string_values
+                .keys()
+                .filter_map(|id| Some((id.clone(), self.string_value(id)?.to_string())))
+                .collect(),
+            initial_fill: self.initial_fill,
+            history_depth: self.history_depth,
+            history: self.history.clone(),
+            stats_enabled: self.stats_enabled,
+            stats: self.stats.clone(),
+            event_counts,
+            change_count: self.parser.change_count(),
+            change_budget: self.parser.change_budget(),
+            timestamp_policy: self.parser.timestamp_policy(),
+            last_time,
+            reorder,
+        })
+    }
+
+    /// Reopens the VCD file a checkpoint was taken from and restores the simulation, the
+    /// next call to `next_cycle` continues where the checkpointed simulation stopped.
+    pub fn resume<P: AsRef<Path>>(filename: P, checkpoint: &Checkpoint) -> Result<Self, VcdError> {
+        let inner = compression::open_at(filename, checkpoint.offset)?;
+        let mut sim = Self::from_reader(io::empty());
+        sim.parser = VcdParser::with_header(4096, inner, checkpoint.header.clone());
+        sim.parser.set_utf8(checkpoint.utf8);
+        sim.parser.set_lossy(checkpoint.lossy);
+        sim.parser.set_allow_truncation(checkpoint.allow_truncation);
+        sim.parser
+            .restore_change_count(checkpoint.change_count, checkpoint.change_budget);
+        sim.parser.set_timestamp_policy(checkpoint.timestamp_policy);
+        sim.parser
+            .restore_timestamp_state(checkpoint.last_time, checkpoint.reorder.clone());
+        sim.tracked_var = checkpoint.tracked.iter().cloned().collect();
+        sim.reconstruct_buses = checkpoint.reconstruct_buses;
+        sim.initial_cycle = checkpoint.initial_cycle;
+        sim.set_initial_fill(checkpoint.initial_fill)
+            .map_err(|_| VcdError::ParseError)?;
+        sim.history_depth = checkpoint.history_depth;
+        sim.stats_enabled = checkpoint.stats_enabled;
+        for text in &checkpoint.derived {
+            let signal = text.parse().map_err(|_| VcdError::ParseError)?;
+            sim.add_derived_signal(signal)
+                .map_err(|_| VcdError::ParseError)?;
+        }
+        sim.allocate_state()?;
+        if sim.state.len() != checkpoint.state.len() {
+            return Err(VcdError::ParseError);
+        }
+        sim.state.copy_from_slice(&checkpoint.state);
+        sim.previous_cycle = checkpoint.previous_cycle;
+        sim.current_cycle = checkpoint.current_cycle;
+        sim.initial_snapshot = checkpoint.initial_snapshot;
+        sim.blackout = checkpoint.blackout;
+        sim.blackouts = checkpoint.blackouts.clone();
+        sim.time_range = checkpoint.time_range;
+        restore_vec(&mut sim.last_change, &checkpoint.last_change)?;
+        restore_vec(&mut sim.assigned, &checkpoint.assigned)?;
+        let budget = sim.string_budget();
+        for (id, value) in &checkpoint.strings {
+            if let Some(v) = sim.string_values.get_mut(id) {
+                *v = Some(sim.strings.intern(value, budget)?);
+            }
+        }
+        sim.update_parser_limit();
+        restore_vec(&mut sim.history, &checkpoint.history)?;
+        restore_vec(&mut sim.stats, &checkpoint.stats)?;
+        for (id, n) in &checkpoint.event_counts {
+            if let Some(count) = sim.event_counts.get_mut(id) {
+                *count = *n;
+            }
+        }
+        Ok(sim)
+    }
+
+    /// Sets a ceiling (in bytes) on the memory used by the simulation.
+    ///
+    /// The limit covers the state buffer, the texts of the string variables and the parser
+    /// input buffer (header metadata is not accounted). When `allocate_state`, a new string
+    /// value or the parser would go beyond it, `VcdError::MemoryLimit` is returned instead of
+    /// allocating.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+        self.update_parser_limit();
+    }
+
+    /// Gives the parser what the state and the string table leave of the memory limit
+    fn update_parser_limit(&mut self) {
+        let used = self.state.len() + self.strings.bytes;
+        self.parser
+            .set_memory_limit(self.memory_limit.map(|l| l.saturating_sub(used)));
+    }
+
+    /// What the state and the parser input buffer leave of the memory limit to the texts of
+    /// the string variables
+    fn string_budget(&self) -> Option<usize> {
+        let used = self.state.len() + self.parser.memory_usage();
+        self.memory_limit.map(|l| l.saturating_sub(used))
+    }
+
+    /// Accepts UTF-8 identifiers and names (see `VcdParser::set_utf8`), must be called
+    /// before `load_header`
+    pub fn set_utf8(&mut self, enable: bool) {
+        self.parser.set_utf8(enable);
+    }
+
+    /// Replaces the bytes that can not be decoded instead of failing (see
+    /// `VcdParser::set_lossy`), must be called before `load_header`
+    pub fn set_lossy(&mut self, enable: bool) {
+        self.parser.set_lossy(enable);
+    }
+
+    /// Ends the simulation at the last complete command of an input cut in the middle of
+    /// one, instead of failing (see `VcdParser::set_allow_truncation`)
+    pub fn set_allow_truncation(&mut self, enable: bool) {
+        self.parser.set_allow_truncation(enable);
+    }
+
+    /// Sets the handling of timestamps lower than a previous one (see
+    /// `VcdParser::set_timestamp_policy`)
+    pub fn set_timestamp_policy(&mut self, policy: TimestampPolicy) {
+        self.parser.set_timestamp_policy(policy);
+    }
+
+    /// Reads the input only when needed (see `VcdParser::set_lazy_input`), for inputs
+    /// blocking until more data is available
+    pub fn set_lazy_input(&mut self, enable: bool) {
+        self.parser.set_lazy_input(enable);
+    }
+
+    /// Validates the header strictly (see `VcdParser::set_strict`), must be called before
+    /// `load_header`
+    pub fn set_strict(&mut self, strict: bool) {
+        self.parser.set_strict(strict);
+    }
+
+    /// Accepts headers without `$enddefinitions` (see
+    /// `VcdParser::set_allow_missing_enddefinitions`), must be called before `load_header`
+    pub fn set_allow_missing_enddefinitions(&mut self, enable: bool) {
+        self.parser.set_allow_missing_enddefinitions(enable);
+    }
+
+    /// When enabled, `allocate_state` detects families of bit-blasted nets (`data [0]`,
+    /// `data [1]`, ...) and lays them out contiguously, so that they appear as a single
+    /// synthetic variable (see `hierarchy::find_bit_blasted_buses`) in `header_info`,
+    /// `tracked_variables` and `variables`. Members remain accessible with their own id.
+    pub fn set_bus_reconstruction(&mut self, enable: bool) {
+        self.reconstruct_buses = enable;
+    }
+
+    /// Keeps the last `depth` values (with the cycle they were set) of each tracked
+    /// variable, 0 (the default) disables the history. Takes effect at the next
+    /// `allocate_state`.
+    pub fn set_history_depth(&mut self, depth: usize) {
+        self.history_depth = depth;
+    }
+
+    /// Accumulates statistics on the activity of each tracked variable (see `stats`), takes
+    /// effect at the next `allocate_state`
+    pub fn set_stats(&mut self, enable: bool) {
+        self.stats_enabled = enable;
+    }
+
+    /// Restricts the simulation to the cycles from `start` to `end` (included), must be called
+    /// before the first `next_cycle`.
+    ///
+    /// The changes before `start` are applied without returning their cycles: the first
+    /// cycle returned is the first timestamp at or after `start`, its state holds all the
+    /// previous changes and every variable changed since the beginning of the dump is marked
+    /// dirty. The simulation is done once the next timestamp is after `end`.
+    pub fn set_time_range(&mut self, start: u64, end: u64) {
+        let cycle = |t: u64| t.min(i64::MAX as u64) as i64;
+        self.time_range = Some((cycle(start), cycle(end)));
+    }
+
+    /// Registers a function called with each cycle returned by `next_cycle` (and by the
+    /// functions running the simulation with it, e.g., `write_tracked`), after the state,
+    /// derived signals and statistics are updated. Hooks are called in the order they were
+    /// registered, they are kept by `reset`.
+    pub fn on_cycle<F: FnMut(&CycleStep) + Send + 'static>(&mut self, hook: F) {
+        self.cycle_hooks.push(Box::new(hook));
+    }
+
+    /// Removes the functions registered with `on_cycle`
+    pub fn clear_cycle_hooks(&mut self) {
+        self.cycle_hooks.clear();
+    }
+
+    /// Selects how the values set before the first timestamp are reported, must be called
+    /// before the first `next_cycle`
+    pub fn set_initial_cycle(&mut self, mode: InitialCycle) {
+        self.initial_cycle = mode;
+    }
+
+    /// Sets the level held by the variables until the dump sets them (`LEVEL_0` by
+    /// default), e.g. `LEVEL_X` to tell them from variables set to 0. Takes effect at the
+    /// next `allocate_state` (or `reset`). Fails with a `VcdError::User` error if `level` is
+    /// not a logic level a value can hold (`LEVEL_OTHER` and `LEVEL_NO_DATA` are not).
+    pub fn set_initial_fill(&mut self, level: i8) -> Result<(), VcdError> {
+        match level {
+            LEVEL_0 | LEVEL_1 | LEVEL_U | LEVEL_W | LEVEL_Z | LEVEL_X | LEVEL_L | LEVEL_H
+            | LEVEL_DONT_CARE => {}
+            _ => return Err(VcdError::user(format!("invalid logic level {}", level))),
+        }
+        self.initial_fill = level;
+        Ok(())
+    }
+
+    pub fn initial_fill(&self) -> i8 {
+        self.initial_fill
+    }
+
+    /// Whether the dump sets values before its first timestamp (e.g., a `$dumpvars` block
+    /// preceding `#0`, or no timestamp at all), None until the first `next_cycle`.
+    ///
+    /// Without an initial snapshot, the state holds the initial fill (see
+    /// `set_initial_fill`) for the variables not yet set.
+    pub fn initial_snapshot(&self) -> Option<bool> {
+        self.initial_snapshot
+    }
+
+    /// Intervals during which dumping was disabled, as (start, end) cycles: from a
+    /// `$dumpoff` to the next `$dumpon` (None while dumping is still disabled). Tracked
+    /// variables hold `LEVEL_NO_DATA` in between.
+    pub fn blackouts(&self) -> &[(i64, Option<i64>)] {
+        &self.blackouts
+    }
+
+    /// Rules applied to the hierarchical names of the variables (see `path_of`), e.g., to
+    /// use the same signal names with the dumps of different simulators. Reconstructed buses
+    /// are named by the rules set before `allocate_state`.
+    pub fn set_demangler(&mut self, demangler: Demangler) {
+        self.demangler = demangler;
+    }
+
+    /// Rules given to `set_demangler`
+    pub fn demangler(&self) -> &Demangler {
+        &self.demangler
+    }
+
+    /// Hierarchical name of a variable, demangled by the rules given to `set_demangler`
+    pub fn path_of(&self, v: &VariableInfo) -> String {
+        self.demangler.path(v)
+    }
+
+    /// Buses reconstructed by the last `allocate_state`
+    pub fn buses(&self) -> &[BitBlastedBus] {
+        &self.buses
+    }
+
     pub fn track_variables(&mut self, vars: &[&str]) {
         self.tracked_var.extend(vars.iter().map(|s| s.to_string()));
     }
 
+    /// Adds a signal computed on each cycle from the values of other signals (see the
+    /// `derived` module), placed after the variables of the dump in the state. It appears
+    /// in `variables`, `tracked_variables` and `header_info` like them, and is written by
+    /// `write_tracked_to`.
+    ///
+    /// Must be called after `load_header` and before `allocate_state`, the signals used by
+    /// the expression (possibly earlier derived signals) are tracked along with it.
+    pub fn add_derived_signal(&mut self, mut signal: DerivedSignal) -> Result<(), DerivedError> {
+        let variables = self.variables();
+        if variables.iter().any(|v| self.path_of(v) == signal.name()) {
+            return Err(DerivedError::DuplicateName(signal.name().to_string()));
+        }
+        // The id must be written to VCD files, unlike the ones of reconstructed buses
+        let header = self.header().into_iter().flat_map(|h| &h.variables);
+        let used: HashSet<&str> = header
+            .chain(self.derived.iter().map(|d| &d.1))
+            .map(|v| v.id.as_str())
+            .collect();
+        let id = (used.len() as u64..)
+            .map(short_id)
+            .find(|id| !used.contains(id.as_str()))
+            .unwrap();
+        signal.resolve(self)?;
+        let info = signal.info(id);
+        self.derived.push((signal, info));
+        Ok(())
+    }
+
+    /// Derived signals added with `add_derived_signal`
+    pub fn derived_signals(&self) -> impl Iterator<Item = &DerivedSignal> {
+        self.derived.iter().map(|d| &d.0)
+    }
+
     pub fn allocate_state(&mut self) -> Result<(), VcdError> {
         let mut offset = 0usize;
         let variables = &self
@@ -59,103 +1102,912 @@ impl StateSimulation {
             .ok_or(VcdError::PartialHeader)?
             .variables;
 
-        self.var_offset.clear();
-        self.var_width.clear();
-        for v in variables {
-            if self.var_offset.get(&v.id).is_some() {
+        let operands: HashSet<&str> = self
+            .derived
+            .iter()
+            .flat_map(|d| d.0.operand_ids())
+            .collect();
+        let tracked_var = &self.tracked_var;
+        let is_tracked =
+            |id: &str| tracked_var.is_empty() || tracked_var.contains(id) || operands.contains(id);
+        self.allocated.clear();
+        self.string_values.clear();
+        self.strings.clear();
+        self.event_counts.clear();
+        self.bus_members.clear();
+        self.buses = if self.reconstruct_buses {
+            find_bit_blasted_buses_with(variables, &self.demangler)
+        } else {
+            Vec::new()
+        };
+        for (i, bus) in self.buses.iter().enumerate() {
+            for m in &bus.members {
+                self.bus_members.insert(m.clone(), i);
+            }
+        }
+        let ids = variables
+            .iter()
+            .chain(self.buses.iter().map(|b| &b.info))
+            .chain(self.derived.iter().map(|d| &d.1))
+            .map(|v| v.id.as_str());
+        self.slots.reset(ids);
+        let order = group_by_scope_with(variables, &self.demangler);
+        for v in order.into_iter().map(|i| &variables[i]) {
+            self.slots.declare(&v.id);
+            if let Some(slot) = self.slots.get(&v.id) {
                 // It seems legal that several variables map to the same ID. For example the
                 // clock is defined in many component but they all map to the same ID.
                 //
                 // FIXME: maybe the header should be checked for correctness upon load?
-                assert_eq!(self.var_width.get(&v.id).cloned(), Some(v.width as usize));
+                assert_eq!(slot.width, v.width as usize);
                 continue;
             }
             if v.kind == VariableKind::VcdReal {
                 continue;
             }
-            if !self.tracked_var.is_empty() && !self.tracked_var.contains(&v.id) {
+            if v.kind == VariableKind::GenString {
+                if is_tracked(&v.id) {
+                    self.string_values.insert(v.id.clone(), None);
+                }
                 continue;
             }
-            self.var_offset.insert(v.id.clone(), offset);
-            self.var_width.insert(v.id.clone(), v.width as usize);
+            if v.kind == VariableKind::VcdEvent {
+                if is_tracked(&v.id) {
+                    self.event_counts.insert(&v.id);
+                }
+                continue;
+            }
+            if let Some(i) = self.bus_members.get(&v.id) {
+                let bus = &self.buses[*i];
+                let tracked = is_tracked(&bus.info.id) || bus.members.iter().any(|m| is_tracked(m));
+                if !tracked {
+                    continue;
+                }
+                let index = self.allocated.len();
+                for (i, m) in bus.members.iter().enumerate() {
+                    let slot = Slot {
+                        offset: offset + i,
+                        width: 1,
+                        index,
+                    };
+                    self.slots.insert(m, slot);
+                }
+                let slot = Slot {
+                    offset,
+                    width: bus.members.len(),
+                    index,
+                };
+                self.slots.insert(&bus.info.id, slot);
+                self.allocated.push((offset, bus.info.clone()));
+                offset += bus.members.len();
+                continue;
+            }
+            if !is_tracked(&v.id) {
+                continue;
+            }
+            let slot = Slot {
+                offset,
+                width: v.width as usize,
+                index: self.allocated.len(),
+            };
+            self.slots.insert(&v.id, slot);
+            self.allocated.push((offset, v.clone()));
             offset += v.width as usize;
         }
-        self.state.resize(offset, 0);
+        self.scope_ranges.clear();
+        for (offset, info) in &self.allocated {
+            let end = offset + self.slots.get(&info.id).map_or(0, |s| s.width);
+            for depth in 1..=info.scope.len() {
+                // Scopes removed by the demangler (e.g., `strip:<scope>`) have no name
+                let path = self.demangler.scope_path(&info.scope[..depth]);
+                if path.is_empty() {
+                    continue;
+                }
+                let range = self.scope_ranges.entry(path).or_insert(*offset..end);
+                range.end = end;
+            }
+        }
+        let slots = &mut self.slots;
+        for (signal, info) in self.derived.iter_mut() {
+            signal
+                .bind(|id| slots.get(id).map(|s| s.offset))
+                .map_err(VcdError::user)?;
+            slots.declare(&info.id);
+            let slot = Slot {
+                offset,
+                width: info.width as usize,
+                index: self.allocated.len(),
+            };
+            slots.insert(&info.id, slot);
+            self.allocated.push((offset, info.clone()));
+            offset += info.width as usize;
+        }
+        if let Some(limit) = self.memory_limit {
+            let used = offset + self.strings.bytes;
+            if used + self.parser.memory_usage() > limit {
+                return Err(VcdError::MemoryLimit);
+            }
+            self.parser.set_memory_limit(Some(limit - used));
+        }
+        self.state.resize(offset, self.initial_fill);
+        self.dirty.clear();
+        self.dirty.resize(self.allocated.len().div_ceil(64), 0);
+        self.assigned.clear();
+        self.assigned.resize(self.dirty.len(), 0);
+        self.last_change.clear();
+        self.last_change.resize(self.allocated.len(), None);
+        self.stats.clear();
+        if self.stats_enabled {
+            self.stats
+                .resize(self.allocated.len(), StatsAccumulator::default());
+        }
+        self.history.clear();
+        if self.history_depth > 0 {
+            self.history.resize(self.allocated.len(), VecDeque::new());
+        }
         Ok(())
     }
 
-    pub fn header_info(&self) -> Result<HashMap<&str, (Option<usize>, VariableInfo)>, VcdError> {
+    /// Variables that were given a slot in the state by `allocate_state`, as
+    /// (offset, info) pairs sorted by offset.
+    ///
+    /// When several variables share the same VCD id, only the first one declared
+    /// in the header is listed.
+    pub fn tracked_variables(&self) -> &[(usize, VariableInfo)] {
+        &self.allocated
+    }
+
+    /// Part of the state holding the tracked variables of a scope (given by its
+    /// hierarchical name, e.g. `top.cpu`, demangled like the paths of `path_of`) and of its
+    /// sub-scopes, None if it has none.
+    ///
+    /// `allocate_state` lays out the variables scope by scope, so that the variables of
+    /// each scope are contiguous in the state (see `hierarchy::group_by_scope_with`).
+    /// Variables sharing their id with a variable allocated in another scope, and derived
+    /// signals, are not part of the range.
+    pub fn scope_range(&self, scope: &str) -> Option<std::ops::Range<usize>> {
+        self.scope_ranges.get(scope).cloned()
+    }
+
+    /// Values of the tracked variables of a scope and of its sub-scopes, as a contiguous
+    /// part of the state (see `scope_range`)
+    pub fn state_of_scope(&self, scope: &str) -> Option<&[i8]> {
+        Some(&self.state[self.scope_range(scope)?])
+    }
+
+    /// Offset in the state of the variable with the given VCD id (None if not tracked)
+    pub fn variable_offset(&self, var_id: &str) -> Option<usize> {
+        self.slots.get(var_id).map(|s| s.offset)
+    }
+
+    /// Width in the state of the variable with the given VCD id (None if not tracked)
+    pub fn variable_width(&self, var_id: &str) -> Option<usize> {
+        self.slots.get(var_id).map(|s| s.width)
+    }
+
+    /// Current value of the variable with the given VCD id (None if not tracked)
+    pub fn value(&self, var_id: &str) -> Option<&[i8]> {
+        let offset = self.variable_offset(var_id)?;
+        let width = self.variable_width(var_id)?;
+        Some(&self.state[offset..offset + width])
+    }
+
+    /// Current value of the string variable with the given VCD id (None if not tracked or
+    /// not set yet).
+    ///
+    /// String variables are not part of the state, their values are kept in a table of
+    /// the distinct texts seen (see `string_table`).
+    pub fn string_value(&self, var_id: &str) -> Option<&str> {
+        let i = self.string_index(var_id)?;
+        Some(&self.strings.strings[i])
+    }
+
+    /// Index in `string_table` of the current value of a string variable
+    pub fn string_index(&self, var_id: &str) -> Option<usize> {
+        *self.string_values.get(var_id)?
+    }
+
+    /// Distinct values taken by the string variables so far, in the order they were seen
+    pub fn string_table(&self) -> &[String] {
+        &self.strings.strings
+    }
+
+    /// Number of times the event variable with the given VCD id was triggered during the last
+    /// cycle (None if not tracked).
+    ///
+    /// Event variables are not part of the state, their `1` changes are counted instead of
+    /// being applied as levels.
+    pub fn event_count(&self, var_id: &str) -> Option<u32> {
+        self.event_counts.get(var_id)
+    }
+
+    /// Ids of the event variables triggered during the last cycle, in declaration order
+    pub fn triggered_events(&self) -> impl Iterator<Item = &str> {
+        self.event_counts
+            .iter()
+            .filter(|(_, n)| *n > 0)
+            .map(|(id, _)| id)
+    }
+
+    /// Current value of the variable with the given VCD id as a `BitVector` (None if not
+    /// tracked)
+    pub fn bit_vector(&self, var_id: &str) -> Option<BitVector> {
+        self.value(var_id).map(BitVector::from)
+    }
+
+    /// Current value of a variable as a signed integer, in two's complement over the bits
+    /// of its declared range (or its whole width without range). Vectors dumped with fewer
+    /// bits than declared are sign-extended after being left-extended as in the state.
+    ///
+    /// None if the variable is not tracked, holds unknown bits or doesn't fit in an i64.
+    pub fn value_i64(&self, var_id: &str) -> Option<i64> {
+        let bits = self.value(var_id)?;
+        let (_, info) = &self.allocated[self.slots.get(var_id)?.index];
+        let span = match &info.range {
+            Some(Range::Range((msb, lsb))) if info.id == var_id => (msb - lsb).unsigned_abs() + 1,
+            _ => bits.len() as u64,
+        };
+        let skip = bits.len().saturating_sub(span as usize);
+        decode_signed(&bits[skip..])
+    }
+
+    /// Bitmap of the variables whose value changed during the last `next_cycle`.
+    ///
+    /// Bit `i % 64` of word `i / 64` is set when the i-th entry of `tracked_variables`
+    /// changed. A value change command that rewrites the current value does not mark the
+    /// variable, except for the initial values (up to the first timestamp included) where
+    /// every variable initialized by the dump is marked.
+    pub fn dirty_bitmap(&self) -> &[u64] {
+        &self.dirty
+    }
+
+    /// Whether the i-th entry of `tracked_variables` changed during the last `next_cycle`
+    pub fn is_dirty(&self, index: usize) -> bool {
+        self.dirty
+            .get(index / 64)
+            .is_some_and(|w| w & (1 << (index % 64)) != 0)
+    }
+
+    /// Bitmap of the variables set at least once by the dump (up to the last
+    /// `next_cycle`), indexed like `dirty_bitmap`. Derived signals count as set once
+    /// computed.
+    pub fn assigned_bitmap(&self) -> &[u64] {
+        &self.assigned
+    }
+
+    /// Whether the variable with the given VCD id has been set by the dump (None if not
+    /// tracked), variables never set hold the initial fill (see `set_initial_fill`)
+    pub fn is_assigned(&self, var_id: &str) -> Option<bool> {
+        let i = self.slots.get(var_id)?.index;
+        Some(self.assigned[i / 64] & (1 << (i % 64)) != 0)
+    }
+
+    /// Indices in `tracked_variables` of the variables changed during the last `next_cycle`
+    pub fn dirty_variables(&self) -> impl Iterator<Item = usize> + '_ {
+        self.step().changed()
+    }
+
+    /// Recorded values of a variable as (cycle, value) pairs, oldest first (see
+    /// `set_history_depth`). None if the variable is not tracked or history is disabled,
+    /// members of reconstructed buses only have the history of the bus.
+    pub fn history(&self, var_id: &str) -> Option<&VecDeque<(i64, Vec<i8>)>> {
+        let i = self.slots.get(var_id)?.index;
+        if self.allocated[i].1.id != var_id {
+            return None;
+        }
+        self.history.get(i)
+    }
+
+    /// Value held by a variable `n` changes ago (0 is the current value), with the cycle it
+    /// was set.
+    pub fn value_ago(&self, var_id: &str, n: usize) -> Option<(i64, &[i8])> {
+        let h = self.history(var_id)?;
+        let (t, v) = h.get(h.len().checked_sub(n + 1)?)?;
+        Some((*t, v))
+    }
+
+    /// Cycle of the last change of a variable (see `dirty_bitmap` for what counts as a
+    /// change), as returned by `next_cycle`. None if the variable is not tracked or has not
+    /// been set yet, members of reconstructed buses report the last change of the bus.
+    pub fn last_change(&self, var_id: &str) -> Option<i64> {
+        self.last_change[self.slots.get(var_id)?.index]
+    }
+
+    /// Statistics of a variable up to the last cycle returned by `next_cycle`, the time
+    /// spent in the current value is counted up to that cycle (time before 0 is not
+    /// counted). None if the variable is not tracked or statistics are disabled (see
+    /// `set_stats`), members of reconstructed buses report the statistics of the bus.
+    pub fn stats(&self, var_id: &str) -> Option<VariableStats> {
+        let s = self.stats.get(self.slots.get(var_id)?.index)?;
+        let mut stats = s.stats.clone();
+        if stats.changes > 0 {
+            s.accumulate(&mut stats.level_time, self.previous_cycle);
+        }
+        Some(stats)
+    }
+
+    /// Statistics of each scope, its sub-scopes included (see `stats` and
+    /// `hierarchy::rollup`), empty if statistics are disabled
+    pub fn scope_stats(&self) -> Vec<ScopeTotal<VariableStats>> {
+        rollup_with(
+            self.allocated
+                .iter()
+                .filter_map(|(_, v)| Some((v.scope.as_slice(), self.stats(&v.id)?))),
+            &self.demangler,
+        )
+    }
+
+    /// Timescale declared in the header, if any
+    pub fn timescale(&self) -> Option<Timescale> {
+        self.parser.header().and_then(|h| h.timescale)
+    }
+
+    /// Converts a cycle number (in dump units) into a timestamp, the time zero offset is
+    /// applied to the real time only
+    pub fn timestamp(&self, cycle: i64) -> Timestamp {
+        Timestamp {
+            raw: cycle,
+            seconds: self
+                .timescale()
+                .map(|t| t.to_seconds(cycle + self.time_zero())),
+        }
+    }
+
+    /// Time origin of the dump, given by `$timezero` (0 if absent or before `load_header`)
+    pub fn time_zero(&self) -> i64 {
+        self.parser.header().map_or(0, |h| h.time_zero)
+    }
+
+    pub fn header(&self) -> Option<&VcdHeader> {
+        self.parser.header()
+    }
+
+    /// VCD id, offset in the state (None if not tracked) and description of the variables
+    /// of the header, then of the synthetic buses and of the derived signals, borrowed from
+    /// the simulation. Ids shared by several variables (see `aliases`) are given once per
+    /// variable. Use `variable_offset` to look up a single id.
+    pub fn header_info(
+        &self,
+    ) -> Result<impl Iterator<Item = (&str, (Option<usize>, &VariableInfo))> + '_, VcdError> {
         let variables = &self
             .parser
             .header()
             .ok_or(VcdError::PartialHeader)?
             .variables;
-        let mut w: HashMap<&str, (Option<usize>, VariableInfo)> =
-            HashMap::with_capacity(variables.len());
-        for v in variables {
-            w.insert(&v.id, (self.var_offset.get(&v.id).cloned(), v.clone()));
+        let synthetic = self.buses.iter().map(|b| &b.info);
+        Ok(variables
+            .iter()
+            .chain(synthetic)
+            .chain(self.derived.iter().map(|d| &d.1))
+            .map(move |v| (v.id.as_str(), (self.variable_offset(&v.id), v))))
+    }
+
+    /// Writes the variables given by `header_info` and the metadata of the header as JSON:
+    /// `{"variables": {<id>: [<offset>, <variable>], ...}, "metadata": {"date": ...,
+    /// "version": ..., "comments": [...]}}`. Variables sharing an id are all written, the
+    /// last one is kept by most readers.
+    pub fn save_header_info<W: io::Write>(&self, out: W) -> Result<(), VcdError> {
+        let header = self.parser.header().ok_or(VcdError::PartialHeader)?;
+        let variables = RefCell::new(Some(self.header_info()?));
+        let json = HeaderInfoJson {
+            variables: SerializeMapOnce(variables),
+            metadata: &header.metadata,
+        };
+        serde_json::to_writer(out, &json).map_err(|e| VcdError::IoError(e.into()))
+    }
+
+    /// Hierarchical names of the variables driven by each VCD id, ids shared by several
+    /// variables are aliases of the same net and hold the same value in the state.
+    pub fn aliases(&self) -> Result<HashMap<&str, Vec<String>>, VcdError> {
+        let header = self.parser.header().ok_or(VcdError::PartialHeader)?;
+        Ok(header
+            .aliases()
+            .into_iter()
+            .map(|(id, vars)| {
+                let paths = vars.into_iter().map(|v| self.demangler.path(v)).collect();
+                (id, paths)
+            })
+            .collect())
+    }
+
+    /// Variables of the header, in declaration order, where the members of reconstructed
+    /// buses are replaced by the synthetic bus variable, followed by the derived signals.
+    pub fn variables(&self) -> Vec<&VariableInfo> {
+        let header = match self.parser.header() {
+            Some(h) => h,
+            None => return Vec::new(),
+        };
+        let mut emitted = vec![false; self.buses.len()];
+        let mut variables = Vec::with_capacity(header.variables.len());
+        for v in &header.variables {
+            match self.bus_members.get(&v.id) {
+                Some(i) if self.bus_is_member(*i, v) => {
+                    if !emitted[*i] {
+                        emitted[*i] = true;
+                        variables.push(&self.buses[*i].info);
+                    }
+                }
+                _ => variables.push(v),
+            }
         }
-        Ok(w)
+        variables.extend(self.derived.iter().map(|d| &d.1));
+        variables
+    }
+
+    fn bus_is_member(&self, bus: usize, v: &VariableInfo) -> bool {
+        // Aliases of a member declared elsewhere in the hierarchy stay visible
+        let info = &self.buses[bus].info;
+        v.scope == info.scope && v.name == info.name
     }
 
     pub fn load_header(&mut self) -> Result<(), VcdError> {
         self.parser.load_header()?;
+        self.header_end = Some(self.parser.position());
+        Ok(())
+    }
+
+    /// Restarts the simulation from the beginning of the dump, keeping the header, the
+    /// tracked variables, the state layout and the options: the file is reopened right
+    /// after its header, and the state, history and statistics are cleared.
+    ///
+    /// Only available for simulations opened from a file with `new` (not from the standard
+    /// input, a reader or a checkpoint), fails with an `io::ErrorKind::Unsupported` error
+    /// otherwise.
+    pub fn reset(&mut self) -> Result<(), VcdError> {
+        let (source, offset) = match (&self.source, self.header_end) {
+            (Some(s), Some(o)) => (s, o),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "only a simulation opened from a file with a loaded header can be reset",
+                )
+                .into())
+            }
+        };
+        let header = self.parser.header().ok_or(VcdError::PartialHeader)?.clone();
+        let options = self.parser.options();
+        let hook = self.parser.take_directive_hook();
+        self.parser = VcdParser::with_header(4096, compression::open_at(source, offset)?, header);
+        self.parser.apply_options(&options);
+        if let Some(hook) = hook {
+            self.parser.set_directive_hook(hook);
+        }
+
+        self.state.fill(self.initial_fill);
+        self.strings.clear();
+        for v in self.string_values.values_mut() {
+            *v = None;
+        }
+        self.event_counts.reset();
+        self.dirty.fill(0);
+        self.assigned.fill(0);
+        self.last_change.fill(None);
+        for s in self.stats.iter_mut() {
+            *s = StatsAccumulator::default();
+        }
+        for h in self.history.iter_mut() {
+            h.clear();
+        }
+        self.blackout = false;
+        self.blackouts.clear();
+        self.dumpoff_section = false;
+        self.initial_snapshot = None;
+        self.previous_cycle = -1;
+        self.current_cycle = -1;
+        self.command_count = 0;
         Ok(())
     }
 
     pub fn done(&self) -> bool {
         self.parser.done()
+            || self
+                .time_range
+                .is_some_and(|(_, end)| self.current_cycle > end)
+    }
+
+    /// Timestamp of the next cycle (its changes are not applied yet)
+    pub(crate) fn pending_cycle(&self) -> i64 {
+        self.current_cycle
+    }
+
+    /// Last cycle returned by `next_cycle`
+    fn step(&self) -> CycleStep<'_> {
+        CycleStep {
+            cycle: self.previous_cycle,
+            time: self.timestamp(self.previous_cycle),
+            state: &self.state,
+            dirty: &self.dirty,
+            blackout: self.blackout,
+        }
+    }
+
+    /// Advances the simulation by one timestamp and returns the state at the end of the
+    /// cycle.
+    ///
+    /// Values set before the first timestamp are reported according to
+    /// `set_initial_cycle` (by default, as a cycle -1).
+    pub fn next_cycle(&mut self) -> Result<CycleStep<'_>, VcdError> {
+        for w in self.dirty.iter_mut() {
+            *w = 0;
+        }
+        self.event_counts.reset();
+        let initial = self.current_cycle < 0;
+        let changes = self.process_cycle()?;
+        if initial {
+            self.initial_snapshot = Some(changes > 0);
+            if self.initial_cycle == InitialCycle::Merge {
+                // Initial values belong to time 0, they are merged with the first timestamp
+                // only when it is #0 (or when there is nothing to report before it)
+                if (self.current_cycle == 0 || changes == 0) && !self.done() {
+                    self.process_cycle()?;
+                } else {
+                    self.previous_cycle = 0;
+                }
+            }
+        }
+        // Fast-forward to the start of the time range (dirty bits accumulate)
+        if let Some((start, _)) = self.time_range {
+            while self.previous_cycle < start && !self.parser.done() {
+                self.process_cycle()?;
+            }
+        }
+        self.update_derived();
+        for i in self.dirty_variables().collect::<Vec<_>>() {
+            self.last_change[i] = Some(self.previous_cycle);
+            if let Some(s) = self.stats.get_mut(i) {
+                let (offset, info) = &self.allocated[i];
+                let width = self.slots.get(&info.id).unwrap().width;
+                s.update(self.previous_cycle, &self.state[*offset..*offset + width]);
+            }
+        }
+        if !self.history.is_empty() {
+            self.record_history();
+        }
+        if !self.cycle_hooks.is_empty() {
+            let mut hooks = std::mem::take(&mut self.cycle_hooks);
+            let step = self.step();
+            for hook in hooks.iter_mut() {
+                hook(&step);
+            }
+            self.cycle_hooks = hooks;
+        }
+        Ok(self.step())
+    }
+
+    /// Runs the simulation until the end, calling the functions registered with `on_cycle`
+    /// on each cycle, and returns a summary of the run (from the current cycle)
+    pub fn run_to_end(&mut self) -> Result<RunSummary, VcdError> {
+        let start = Instant::now();
+        let commands = self.command_count;
+        let changes = self.parser.change_count();
+        let bytes = self.parser.position();
+        let mut cycles = 0;
+        let mut end_time = None;
+        while !self.done() {
+            end_time = Some(self.next_cycle()?.time);
+            cycles += 1;
+        }
+        Ok(RunSummary {
+            cycles,
+            end_time,
+            commands: self.command_count - commands,
+            changes: self.parser.change_count() - changes,
+            bytes: self.parser.position() - bytes,
+            elapsed: start.elapsed(),
+            truncation: self.parser.truncation(),
+        })
+    }
+
+    /// Number of commands of the body parsed so far (timestamps, value changes, dump
+    /// sections...)
+    pub fn command_count(&self) -> u64 {
+        self.command_count
+    }
+
+    /// Same as `next_cycle`, but the values set before the first timestamp are reported as
+    /// `Step::Initial` when `InitialCycle::Event` is selected.
+    pub fn next_step(&mut self) -> Result<(Step, &[i8]), VcdError> {
+        let initial = self.current_cycle < 0;
+        let cycle = self.next_cycle()?.cycle;
+        let step = if initial && self.initial_cycle == InitialCycle::Event {
+            Step::Initial
+        } else {
+            Step::Cycle(cycle)
+        };
+        Ok((step, &self.state))
+    }
+
+    /// Runs the simulation until the end and writes a VCD file holding only the tracked
+    /// variables (see `write_tracked_to`)
+    pub fn write_tracked<P: AsRef<Path>>(&mut self, path: P) -> Result<(), VcdError> {
+        let out = BufWriter::new(File::create(path)?);
+        self.write_tracked_to(out)?
+            .into_inner()
+            .map_err(|e| e.into_error())?;
+        Ok(())
+    }
+
+    /// Runs the simulation until the end and writes a VCD holding only the tracked
+    /// variables (with their aliases, reals are not supported) to `out`.
+    ///
+    /// When the simulation already started, the current state is written as the initial
+    /// values (a `$dumpvars` block) at the time of the last cycle. Members of reconstructed
+    /// buses are written as in the original file.
+    pub fn write_tracked_to<W: Write>(&mut self, out: W) -> Result<W, VcdError> {
+        let header = self.parser.header().ok_or(VcdError::PartialHeader)?;
+        let variables: Vec<&VariableInfo> = header
+            .variables
+            .iter()
+            .chain(self.derived.iter().map(|d| &d.1))
+            .filter(|v| self.slots.contains(&v.id) || self.event_counts.contains_key(&v.id))
+            .collect();
+        // Ids (with their offset and width) to write when an entry of `allocated` changes
+        let mut slots: Vec<Vec<(String, usize, usize)>> = vec![Vec::new(); self.allocated.len()];
+        let mut written = HashSet::new();
+        for v in &variables {
+            if let Some(slot) = self.slots.get(&v.id) {
+                if written.insert(&v.id) {
+                    slots[slot.index].push((v.id.clone(), slot.offset, slot.width));
+                }
+            }
+        }
+
+        let mut writer = VcdWriter::new(out);
+        if let Some(d) = &header.metadata.date {
+            writer.date(d)?;
+        }
+        if let Some(v) = &header.metadata.version {
+            writer.version(v)?;
+        }
+        for c in &header.metadata.comments {
+            writer.comment(c)?;
+        }
+        if let Some(t) = header.timescale {
+            writer.timescale(&t)?;
+        }
+        if header.time_zero != 0 {
+            writer.time_zero(header.time_zero)?;
+        }
+        writer.variables(variables)?;
+        writer.enddefinitions()?;
+        if self.current_cycle >= 0 {
+            if self.previous_cycle >= 0 {
+                writer.timestamp(self.previous_cycle as u64)?;
+            }
+            writer.dumpvars()?;
+            for (id, offset, width) in slots.iter().flatten() {
+                writer.change_levels(id, &self.state[*offset..*offset + width])?;
+            }
+            writer.end()?;
+        }
+        while !self.done() {
+            let cycle = self.next_cycle()?.cycle;
+            if cycle >= 0 {
+                writer.timestamp(cycle as u64)?;
+            } else {
+                writer.dumpvars()?;
+            }
+            for i in self.dirty_variables() {
+                for (id, offset, width) in &slots[i] {
+                    writer.change_levels(id, &self.state[*offset..*offset + width])?;
+                }
+            }
+            for (id, n) in self.event_counts.iter() {
+                for _ in 0..n {
+                    writer.change(id, &VcdValue::Event)?;
+                }
+            }
+            if cycle < 0 {
+                writer.end()?;
+            }
+        }
+        Ok(writer.finish()?)
+    }
+
+    /// Computes the derived signals, they are marked as changed when their value changes
+    /// or has never been reported
+    fn update_derived(&mut self) {
+        for (signal, info) in self.derived.iter_mut() {
+            let slot = match self.slots.get(&info.id) {
+                Some(s) => *s,
+                None => continue,
+            };
+            // Derived signals only use the values placed before them in the state
+            let (before, after) = self.state.split_at_mut(slot.offset);
+            let changed = signal.update(before, &mut after[..slot.width]);
+            let i = slot.index;
+            self.assigned[i / 64] |= 1 << (i % 64);
+            if changed || self.last_change[i].is_none() {
+                self.dirty[i / 64] |= 1 << (i % 64);
+            }
+        }
     }
 
-    pub fn next_cycle(&mut self) -> Result<(i64, &[i8]), VcdError> {
+    /// Applies the changes up to the next timestamp, returns the number of value changes
+    fn process_cycle(&mut self) -> Result<usize, VcdError> {
+        let string_budget = self.string_budget();
+        let string_bytes = self.strings.bytes;
         let state = &mut self.state;
-        let var_offset = &self.var_offset;
-        let var_width = &self.var_width;
-        let tracked_var = &self.tracked_var;
+        let slots = &self.slots;
+        let dirty = &mut self.dirty;
+        let assigned = &mut self.assigned;
+        let strings = &mut self.strings;
+        let string_values = &mut self.string_values;
+        let event_counts = &mut self.event_counts;
+        let allocated = &self.allocated;
+        let blackout = &mut self.blackout;
+        let blackouts = &mut self.blackouts;
+        let dumpoff_section = &mut self.dumpoff_section;
+        let current = self.current_cycle;
+        let first = self.previous_cycle < 0;
         let mut cycle = 0;
-        let callback = |cmd: VcdCommand| {
+        let mut changes = 0;
+        let command_count = &mut self.command_count;
+        let callback = |cmd: VcdCommand| -> Result<bool, VcdError> {
+            *command_count += 1;
             match cmd {
                 VcdCommand::SetCycle(c) => {
                     cycle = c as i64;
-                    return true;
+                    return Ok(true);
                 }
                 VcdCommand::ValueChange(v) => {
-                    if !tracked_var.is_empty() && !tracked_var.contains(v.var_id) {
-                        return false;
+                    changes += 1;
+                    if *dumpoff_section {
+                        // X values listed by `$dumpoff`, variables are already set to
+                        // LEVEL_NO_DATA
+                        return Ok(false);
+                    }
+                    let invalid = |reason| {
+                        let value = match &v.value {
+                            VcdValue::Bit(c) => c.to_string(),
+                            VcdValue::Vector(x) => x.to_string(),
+                            VcdValue::Real(x) | VcdValue::String(x) => x.to_string(),
+                            VcdValue::Port(p) => p.states.to_string(),
+                            VcdValue::Event => "1".to_string(),
+                        };
+                        // The path is filled once the parser is no longer borrowed
+                        VcdError::InvalidChange(InvalidChange {
+                            path: String::new(),
+                            id: v.var_id.to_string(),
+                            time: current,
+                            value,
+                            reason,
+                        })
+                    };
+                    if let VcdValue::String(x) = v.value {
+                        match string_values.get_mut(v.var_id) {
+                            Some(value) => *value = Some(strings.intern(x, string_budget)?),
+                            None if slots.is_declared(v.var_id) => {}
+                            None => return Err(invalid(ChangeProblem::UnknownId)),
+                        }
+                        return Ok(false);
+                    }
+                    if let VcdValue::Event = v.value {
+                        match event_counts.get_mut(v.var_id) {
+                            Some(n) => *n += 1,
+                            None if slots.is_declared(v.var_id) => {}
+                            None => return Err(invalid(ChangeProblem::UnknownId)),
+                        }
+                        return Ok(false);
                     }
-                    let base = var_offset
-                        .get(v.var_id)
-                        .cloned()
-                        .expect(&format!("missing key {}", v.var_id));
-                    match v.value {
-                        VcdValue::Bit(c) => state[base] = logic_level(c),
+                    let slot = match slots.get(v.var_id) {
+                        Some(s) => s,
+                        // Not tracked (or not representable in the state, e.g., reals)
+                        None if slots.is_declared(v.var_id) => return Ok(false),
+                        None => return Err(invalid(ChangeProblem::UnknownId)),
+                    };
+                    let mut changed = false;
+                    let mut set = |el: &mut i8, x: i8| {
+                        changed |= *el != x;
+                        *el = x;
+                    };
+                    // Port states of extended VCD files are applied as their logic levels
+                    let levels;
+                    let value = match v.value {
+                        VcdValue::Port(p) => {
+                            levels = p.levels();
+                            VcdValue::Vector(&levels)
+                        }
+                        value => value,
+                    };
+                    match value {
+                        VcdValue::Bit(c) => {
+                            let level = checked_level(c).map_err(invalid)?;
+                            set(&mut state[slot.offset], level)
+                        }
                         VcdValue::Vector(x) => {
-                            let w = var_width.get(v.var_id).cloned().unwrap();
-                            assert!(x.len() <= w, "unsupported vector format");
+                            let (base, w) = (slot.offset, slot.width);
+                            if x.is_empty() || x.len() > w {
+                                return Err(invalid(ChangeProblem::WidthMismatch { width: w }));
+                            }
                             let fill_size = w - x.len();
 
                             // According to the standard, section 18.2.2, vectors
                             // should be left-extented with the leftmost value.
-                            let v = logic_level(x.chars().next().unwrap());
+                            let fill = checked_level(x.chars().next().unwrap()).map_err(invalid)?;
                             for el in state[base..base + fill_size].iter_mut() {
-                                *el = v;
+                                set(el, fill);
                             }
 
-                            for (el, c) in state[base + fill_size..base + w].iter_mut().zip(x.chars()) {
-                                *el = logic_level(c);
+                            for (el, c) in
+                                state[base + fill_size..base + w].iter_mut().zip(x.chars())
+                            {
+                                set(el, checked_level(c).map_err(invalid)?);
                             }
                         }
-                        VcdValue::Real(_) => {}
+                        VcdValue::Real(_)
+                        | VcdValue::Port(_)
+                        | VcdValue::String(_)
+                        | VcdValue::Event => {}
                     };
+                    let i = slot.index;
+                    assigned[i / 64] |= 1 << (i % 64);
+                    if changed || first {
+                        dirty[i / 64] |= 1 << (i % 64);
+                    }
                 }
-                VcdCommand::Directive(_) | VcdCommand::VcdEnd => {}
+                VcdCommand::BeginDump(DumpSection::Off | DumpSection::PortsOff) => {
+                    *dumpoff_section = true;
+                    if !*blackout {
+                        *blackout = true;
+                        blackouts.push((current, None));
+                        for (i, (offset, info)) in allocated.iter().enumerate() {
+                            let width = slots.get(&info.id).map_or(0, |s| s.width);
+                            let value = &mut state[*offset..*offset + width];
+                            if value.iter().any(|b| *b != LEVEL_NO_DATA) {
+                                value.fill(LEVEL_NO_DATA);
+                                dirty[i / 64] |= 1 << (i % 64);
+                            }
+                        }
+                    }
+                }
+                VcdCommand::BeginDump(DumpSection::On | DumpSection::PortsOn) => {
+                    if *blackout {
+                        *blackout = false;
+                        if let Some(b) = blackouts.last_mut() {
+                            b.1 = Some(current);
+                        }
+                    }
+                }
+                VcdCommand::EndDump(_) => *dumpoff_section = false,
+                VcdCommand::BeginDump(_)
+                | VcdCommand::VcdEnd
+                | VcdCommand::VcdClose(_)
+                | VcdCommand::Directive(_) => {}
             }
-            false
+            Ok(false)
         };
-        self.parser.process_vcd_commands(callback)?;
+        if let Err(mut e) = self.parser.process_vcd_commands(callback) {
+            if let VcdError::InvalidChange(c) = &mut e {
+                if let Some(v) = self
+                    .parser
+                    .header()
+                    .and_then(|h| h.variables.iter().find(|v| v.id == c.id))
+                {
+                    c.path = self.demangler.path(v);
+                }
+            }
+            return Err(e);
+        }
+        if self.strings.bytes != string_bytes {
+            self.update_parser_limit();
+        }
 
         self.previous_cycle = self.current_cycle;
         self.current_cycle = cycle;
-        Ok((self.previous_cycle, &self.state))
+        Ok(changes)
+    }
+
+    fn record_history(&mut self) {
+        let depth = self.history_depth;
+        for i in self.dirty_variables().collect::<Vec<_>>() {
+            let (offset, info) = &self.allocated[i];
+            let value = self.state[*offset..*offset + info.width as usize].to_vec();
+            let h = &mut self.history[i];
+            if h.len() == depth {
+                h.pop_front();
+            }
+            h.push_back((self.previous_cycle, value));
+        }
     }
 }
@@ -0,0 +1,154 @@
+//! Rewriting of VCD files, command by command.
+//!
+//! A `VcdTransform` copies a parsed VCD to a `VcdWriter`, hooks decide for each command of
+//! the body whether it is dropped, and may replace values or rename variable ids. For
+//! instance, masking a bus:
+//!
+//! ```no_run
+//! # use std::fs::File;
+//! # use wavetk::transform::VcdTransform;
+//! # use wavetk::{VcdParser, VcdWriter};
+//! # fn main() -> Result<(), wavetk::VcdError> {
+//! let mut parser = VcdParser::with_chunk_size(1 << 16, File::open("in.vcd")?);
+//! let writer = VcdWriter::new(File::create("out.vcd")?);
+//! let mut transform = VcdTransform::new();
+//! transform.modify_values(|id, _| match id {
+//!     "#k" => Some("x".to_string()),
+//!     _ => None,
+//! });
+//! transform.run(&mut parser, writer)?;
+//! # Ok(())
+//! # }
+//! ```
+use std::io;
+use std::io::{Read, Write};
+
+use crate::types::VariableInfo;
+use crate::vcd::{VcdCommand, VcdError, VcdParser, VcdValue};
+use crate::vcd_writer::VcdWriter;
+
+type DropHook<'a> = Box<dyn FnMut(&VcdCommand) -> bool + 'a>;
+type ModifyHook<'a> = Box<dyn FnMut(&str, &VcdValue) -> Option<String> + 'a>;
+type RemapHook<'a> = Box<dyn FnMut(&str) -> Option<String> + 'a>;
+
+/// Copies the commands of a VCD through hooks, without hooks the copy is identical to the
+/// input up to whitespaces and the text of directives the parser does not keep (`$date`,
+/// `$version`, `$comment`).
+///
+/// Hooks see the ids of the input file, they are called in this order:
+/// - `drop_commands`: commands for which it returns true are not written,
+/// - `modify_values`: replaces the value of a change (bits of a scalar or vector, number
+///   of a real), keeping its kind,
+/// - `remap_ids`: renames a variable, in the header and in value changes.
+///
+/// The output is checked by the writer, a replacement value larger than its variable, or
+/// ids remapped to the same id with different widths, fail with an
+/// `io::ErrorKind::InvalidInput` error.
+#[derive(Default)]
+pub struct VcdTransform<'a> {
+    drop: Option<DropHook<'a>>,
+    modify: Option<ModifyHook<'a>>,
+    remap: Option<RemapHook<'a>>,
+}
+
+impl<'a> VcdTransform<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops the commands for which `f` returns true
+    pub fn drop_commands<F>(&mut self, f: F)
+    where
+        F: FnMut(&VcdCommand) -> bool + 'a,
+    {
+        self.drop = Some(Box::new(f));
+    }
+
+    /// Replaces the value of the changes for which `f` returns a new value, `f` is given the
+    /// id of the variable and its value
+    pub fn modify_values<F>(&mut self, f: F)
+    where
+        F: FnMut(&str, &VcdValue) -> Option<String> + 'a,
+    {
+        self.modify = Some(Box::new(f));
+    }
+
+    /// Renames the ids for which `f` returns a new id
+    pub fn remap_ids<F>(&mut self, f: F)
+    where
+        F: FnMut(&str) -> Option<String> + 'a,
+    {
+        self.remap = Some(Box::new(f));
+    }
+
+    fn remap(&mut self, id: &str) -> Option<String> {
+        self.remap.as_mut().and_then(|f| f(id))
+    }
+
+    fn write_header<W: Write>(
+        &mut self,
+        variables: &[VariableInfo],
+        writer: &mut VcdWriter<W>,
+    ) -> io::Result<()> {
+        let variables: Vec<VariableInfo> = variables
+            .iter()
+            .map(|v| match self.remap(&v.id) {
+                Some(id) => VariableInfo { id, ..v.clone() },
+                None => v.clone(),
+            })
+            .collect();
+        writer.variables(&variables)?;
+        writer.enddefinitions()
+    }
+
+    fn write_command<W: Write>(
+        &mut self,
+        cmd: VcdCommand,
+        writer: &mut VcdWriter<W>,
+    ) -> io::Result<()> {
+        if self.drop.as_mut().is_some_and(|f| f(&cmd)) {
+            return Ok(());
+        }
+        let change = match cmd {
+            VcdCommand::ValueChange(c) => c,
+            cmd => return writer.command(&cmd),
+        };
+        let new_value = self
+            .modify
+            .as_mut()
+            .and_then(|f| f(change.var_id, &change.value));
+        let value = match (new_value.as_deref(), change.value) {
+            (None, value) => value,
+            (Some(x), VcdValue::Real(_)) => VcdValue::Real(x),
+            (Some(x), VcdValue::Bit(_)) if x.len() == 1 => VcdValue::Bit(x.as_bytes()[0] as char),
+            (Some(x), _) => VcdValue::Vector(x),
+        };
+        let new_id = self.remap(change.var_id);
+        writer.change(new_id.as_deref().unwrap_or(change.var_id), &value)
+    }
+
+    /// Copies the file read by `parser` (whose header may already be loaded) to `writer`,
+    /// and returns the underlying output once flushed
+    pub fn run<R: Read, W: Write>(
+        &mut self,
+        parser: &mut VcdParser<R>,
+        mut writer: VcdWriter<W>,
+    ) -> Result<W, VcdError> {
+        let header = match parser.header() {
+            Some(h) => h.clone(),
+            None => parser.load_header()?.clone(),
+        };
+        if let Some(t) = header.timescale {
+            writer.timescale(&t)?;
+        }
+        if header.time_zero != 0 {
+            writer.time_zero(header.time_zero)?;
+        }
+        self.write_header(&header.variables, &mut writer)?;
+        parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+            self.write_command(cmd, &mut writer)?;
+            Ok(false)
+        })?;
+        Ok(writer.finish()?)
+    }
+}
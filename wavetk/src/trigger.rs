@@ -0,0 +1,206 @@
+//! Trigger conditions on signal values, evaluated on the state of a simulation.
+//!
+//! A condition compares signals (designated by their hierarchical name, see
+//! `StateSimulation::path_of`) to constants, e.g.
+//! `top.err == 1 || top.cpu.state == 0b1x0 && top.cpu.pc >= 0x100`. `&&` binds tighter
+//! than `||`, there are no parentheses.
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::pattern::Pattern;
+use crate::simulation::StateSimulation;
+use crate::types::BitVector;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TriggerError {
+    /// Malformed condition, with the offending part
+    Syntax(String),
+    /// No tracked variable with the given hierarchical name
+    UnknownSignal(String),
+}
+
+impl fmt::Display for TriggerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TriggerError::Syntax(s) => write!(f, "invalid condition: {:?}", s),
+            TriggerError::UnknownSignal(s) => write!(f, "unknown signal: {}", s),
+        }
+    }
+}
+
+impl Error for TriggerError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// Two-character operators first, so that `<=` is not read as `<`
+const OPERATORS: [(&str, Operator); 6] = [
+    ("==", Operator::Eq),
+    ("!=", Operator::Ne),
+    ("<=", Operator::Le),
+    (">=", Operator::Ge),
+    ("<", Operator::Lt),
+    (">", Operator::Gt),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Comparison {
+    path: String,
+    op: Operator,
+    pattern: Pattern,
+    // Numeric value of the constant, for ordering operators
+    value: Option<u64>,
+    // Position of the signal in the state, once bound
+    slot: Option<(usize, usize)>,
+}
+
+impl Comparison {
+    fn parse(s: &str) -> Result<Self, TriggerError> {
+        let syntax = || TriggerError::Syntax(s.trim().to_string());
+        let (i, name, op) = OPERATORS
+            .iter()
+            .filter_map(|(name, op)| s.find(name).map(|i| (i, *name, *op)))
+            .min_by_key(|(i, name, _)| (*i, usize::MAX - name.len()))
+            .ok_or_else(syntax)?;
+        let path = s[..i].trim();
+        let constant = s[i + name.len()..].trim();
+        if path.is_empty() || path.contains(char::is_whitespace) {
+            return Err(syntax());
+        }
+        let (pattern, value) = if constant.starts_with("0x")
+            || constant.starts_with("0X")
+            || constant.starts_with("0b")
+        {
+            let pattern: Pattern = constant.parse().map_err(|_| syntax())?;
+            let digits: String = constant[2..].chars().filter(|c| *c != '_').collect();
+            let radix = if constant.starts_with("0b") { 2 } else { 16 };
+            (pattern, u64::from_str_radix(&digits, radix).ok())
+        } else {
+            let value: u64 = constant.parse().map_err(|_| syntax())?;
+            let pattern = format!("{:b}", value).parse().map_err(|_| syntax())?;
+            (pattern, Some(value))
+        };
+        if value.is_none() && !matches!(op, Operator::Eq | Operator::Ne) {
+            return Err(syntax());
+        }
+        Ok(Comparison {
+            path: path.to_string(),
+            op,
+            pattern,
+            value,
+            slot: None,
+        })
+    }
+
+    fn eval(&self, state: &[i8]) -> bool {
+        let (offset, width) = match self.slot {
+            Some(s) => s,
+            None => return false,
+        };
+        let bits = &state[offset..offset + width];
+        let ordering = || Some(BitVector::from(bits).to_u64()?.cmp(&self.value?));
+        match self.op {
+            Operator::Eq => self.pattern.matches(bits),
+            Operator::Ne => !self.pattern.matches(bits),
+            Operator::Lt => ordering().is_some_and(|o| o.is_lt()),
+            Operator::Le => ordering().is_some_and(|o| o.is_le()),
+            Operator::Gt => ordering().is_some_and(|o| o.is_gt()),
+            Operator::Ge => ordering().is_some_and(|o| o.is_ge()),
+        }
+    }
+}
+
+/// A condition on signal values, in disjunctive normal form.
+///
+/// Comparisons with `==` and `!=` accept don't-care bits in binary and hexadecimal
+/// constants (see `Pattern`), ordering comparisons are false when the signal holds unknown
+/// bits. The trigger must be bound to a simulation (see `bind`) before being evaluated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trigger {
+    text: String,
+    terms: Vec<Vec<Comparison>>,
+}
+
+impl Trigger {
+    /// Hierarchical names of the signals used by the condition
+    pub fn signals(&self) -> impl Iterator<Item = &str> {
+        self.terms.iter().flatten().map(|c| c.path.as_str())
+    }
+
+    /// VCD ids of the signals used by the condition, to be given to
+    /// `StateSimulation::track_variables` (the header must have been loaded)
+    pub fn variable_ids(&self, sim: &StateSimulation) -> Result<Vec<String>, TriggerError> {
+        let variables = sim.variables();
+        self.signals()
+            .map(|path| {
+                variables
+                    .iter()
+                    .find(|v| sim.path_of(v) == path)
+                    .map(|v| v.id.clone())
+                    .ok_or_else(|| TriggerError::UnknownSignal(path.to_string()))
+            })
+            .collect()
+    }
+
+    /// Resolves the position of the signals in the state of the simulation, which must have
+    /// been allocated
+    pub fn bind(&mut self, sim: &StateSimulation) -> Result<(), TriggerError> {
+        self.bind_with(|path| {
+            let (offset, v) = sim
+                .tracked_variables()
+                .iter()
+                .find(|(_, v)| sim.path_of(v) == path)?;
+            Some((*offset, v.width as usize))
+        })
+    }
+
+    /// Resolves the position of the signals in a state, given by `slot_of` as (offset,
+    /// width) from their hierarchical name (e.g., `FstSimulation::slot_of`)
+    pub fn bind_with<F>(&mut self, slot_of: F) -> Result<(), TriggerError>
+    where
+        F: Fn(&str) -> Option<(usize, usize)>,
+    {
+        for c in self.terms.iter_mut().flatten() {
+            let slot =
+                slot_of(&c.path).ok_or_else(|| TriggerError::UnknownSignal(c.path.clone()))?;
+            c.slot = Some(slot);
+        }
+        Ok(())
+    }
+
+    /// Whether the condition holds for the given state
+    pub fn eval(&self, state: &[i8]) -> bool {
+        self.terms
+            .iter()
+            .any(|term| term.iter().all(|c| c.eval(state)))
+    }
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl FromStr for Trigger {
+    type Err = TriggerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let terms = s
+            .split("||")
+            .map(|term| term.split("&&").map(Comparison::parse).collect())
+            .collect::<Result<_, _>>()?;
+        Ok(Trigger {
+            text: s.trim().to_string(),
+            terms,
+        })
+    }
+}
@@ -1,12 +1,36 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitXor, Not};
 
-#[derive(Clone, Debug, Serialize, PartialEq)]
+use crate::vcd::VcdValue;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub enum Range {
     Bit(u64),
     Range((i64, i64)),
 }
 
+impl Range {
+    /// Number of bits covered by the range
+    pub fn width(&self) -> u64 {
+        match self {
+            Range::Bit(_) => 1,
+            Range::Range((msb, lsb)) => (msb - lsb).unsigned_abs() + 1,
+        }
+    }
+}
+
+impl fmt::Display for Range {
+    /// Formats the range as in a VCD declaration (`[7:0]`, `[3]`)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Range::Bit(b) => write!(f, "[{}]", b),
+            Range::Range((msb, lsb)) => write!(f, "[{}:{}]", msb, lsb),
+        }
+    }
+}
+
 /// For enums represented by an integer type, this macro implements the
 /// TryFrom trait. The conversion is done by a direct std::mem::transmute
 /// (unsafe), but the value is checked to be less than Type::End before
@@ -31,7 +55,7 @@ macro_rules! enum_direct_conversion {
     };
 }
 
-#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[repr(u8)]
 pub enum ScopeKind {
     VcdModule = 0,
@@ -64,7 +88,28 @@ pub enum ScopeKind {
 
 enum_direct_conversion!(ScopeKind, u8);
 
-#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+impl ScopeKind {
+    /// Keyword of the kind in a VCD `$scope` declaration (`module` for kinds without VCD
+    /// equivalent, e.g., VHDL ones)
+    pub fn vcd_keyword(&self) -> &'static str {
+        match self {
+            ScopeKind::VcdTask => "task",
+            ScopeKind::VcdFunction => "function",
+            ScopeKind::VcdBegin => "begin",
+            ScopeKind::VcdFork => "fork",
+            ScopeKind::VcdGenerate => "generate",
+            ScopeKind::VcdStruct => "struct",
+            ScopeKind::VcdUnion => "union",
+            ScopeKind::VcdClass => "class",
+            ScopeKind::VcdInterface => "interface",
+            ScopeKind::VcdPackage => "package",
+            ScopeKind::VcdProgram => "program",
+            _ => "module",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[repr(u8)]
 pub enum VariableKind {
     VcdEvent = 0,
@@ -122,6 +167,8 @@ impl From<&str> for VariableKind {
             "wand" => VariableKind::VcdTriand,
             "wire" => VariableKind::VcdWire,
             "wor" => VariableKind::VcdWor,
+            "port" => VariableKind::VcdPort,
+            "string" => VariableKind::GenString,
             _ => VariableKind::End,
         }
     }
@@ -129,7 +176,46 @@ impl From<&str> for VariableKind {
 
 enum_direct_conversion!(VariableKind, u8);
 
-#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+impl VariableKind {
+    /// Whether the values of the variable are floating-point numbers
+    pub fn is_real(&self) -> bool {
+        matches!(
+            self,
+            VariableKind::VcdReal
+                | VariableKind::VcdRealParameter
+                | VariableKind::VcdRealtime
+                | VariableKind::SvShortreal
+        )
+    }
+
+    /// Keyword of the kind in a VCD `$var` declaration (`wire` for kinds without VCD
+    /// equivalent)
+    pub fn vcd_keyword(&self) -> &'static str {
+        match self {
+            VariableKind::VcdEvent => "event",
+            VariableKind::VcdInteger => "integer",
+            VariableKind::VcdParameter => "parameter",
+            VariableKind::VcdReal | VariableKind::VcdRealParameter => "real",
+            VariableKind::VcdReg => "reg",
+            VariableKind::VcdSupply0 => "supply0",
+            VariableKind::VcdSupply1 => "supply1",
+            VariableKind::VcdTime => "time",
+            VariableKind::VcdTri => "tri",
+            VariableKind::VcdTriand => "triand",
+            VariableKind::VcdTrior => "trior",
+            VariableKind::VcdTrireg => "trireg",
+            VariableKind::VcdTri0 => "tri0",
+            VariableKind::VcdTri1 => "tri1",
+            VariableKind::VcdWand => "wand",
+            VariableKind::VcdWor => "wor",
+            VariableKind::VcdRealtime => "realtime",
+            VariableKind::GenString => "string",
+            _ => "wire",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Direction {
     Implicit = 0,
@@ -147,7 +233,7 @@ enum_direct_conversion!(Direction, u8);
 type VarHandle = u32;
 
 /// Analogous to VariableInfo (for VCD), the two representation will be merged soon
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VariableInfo {
     pub id: String,
     pub name: String,
@@ -159,7 +245,7 @@ pub struct VariableInfo {
     pub scope: Vec<Scope>,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Scope {
     pub kind: ScopeKind,
     pub name: String,
@@ -187,3 +273,492 @@ impl Scope {
 pub struct FstHeader {
     pub variables: Vec<VariableInfo>,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeUnit {
+    S,
+    Ms,
+    Us,
+    Ns,
+    Ps,
+    Fs,
+}
+
+impl TimeUnit {
+    /// Power of ten of the unit, in seconds
+    pub fn exponent(self) -> i8 {
+        match self {
+            TimeUnit::S => 0,
+            TimeUnit::Ms => -3,
+            TimeUnit::Us => -6,
+            TimeUnit::Ns => -9,
+            TimeUnit::Ps => -12,
+            TimeUnit::Fs => -15,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            TimeUnit::S => "s",
+            TimeUnit::Ms => "ms",
+            TimeUnit::Us => "us",
+            TimeUnit::Ns => "ns",
+            TimeUnit::Ps => "ps",
+            TimeUnit::Fs => "fs",
+        }
+    }
+
+    pub fn from_symbol(s: &str) -> Option<Self> {
+        match s {
+            "s" => Some(TimeUnit::S),
+            "ms" => Some(TimeUnit::Ms),
+            "us" => Some(TimeUnit::Us),
+            "ns" => Some(TimeUnit::Ns),
+            "ps" => Some(TimeUnit::Ps),
+            "fs" => Some(TimeUnit::Fs),
+            _ => None,
+        }
+    }
+}
+
+/// Duration of one time step of a dump, e.g., `10 ns`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timescale {
+    /// 1, 10 or 100
+    pub magnitude: u32,
+    pub unit: TimeUnit,
+}
+
+impl Timescale {
+    /// Parses the text of a VCD `$timescale` directive (`1 ns`, `100ps`, ...)
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let split = text
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len());
+        let magnitude: u32 = text[..split].parse().ok()?;
+        if ![1, 10, 100].contains(&magnitude) {
+            return None;
+        }
+        let unit = TimeUnit::from_symbol(text[split..].trim())?;
+        Some(Timescale { magnitude, unit })
+    }
+
+    /// Builds the timescale of an FST file from its exponent (e.g., -9 for 1 ns)
+    pub fn from_exponent(exponent: i8) -> Self {
+        let units = [
+            TimeUnit::S,
+            TimeUnit::Ms,
+            TimeUnit::Us,
+            TimeUnit::Ns,
+            TimeUnit::Ps,
+            TimeUnit::Fs,
+        ];
+        let e = (exponent as i32).clamp(-15, 2);
+        let k = (2 - e).div_euclid(3).clamp(0, 5) as usize;
+        let unit = units[k];
+        let magnitude = 10u32.pow((e - unit.exponent() as i32).clamp(0, 2) as u32);
+        Timescale { magnitude, unit }
+    }
+
+    /// Power of ten of one time step, in seconds
+    pub fn exponent(&self) -> i8 {
+        self.unit.exponent() + (self.magnitude as f64).log10() as i8
+    }
+
+    /// Converts a number of time steps into seconds
+    pub fn to_seconds(&self, time: i64) -> f64 {
+        time as f64 * self.magnitude as f64 * 10f64.powi(self.unit.exponent() as i32)
+    }
+
+    /// Parses a time given with a unit (e.g., `10us` or `2.5 ns`) into a number of time
+    /// steps, rounded to the nearest one. A number without unit is a number of time steps.
+    pub fn parse_time(&self, text: &str) -> Option<u64> {
+        let text = text.trim();
+        let split = text
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(text.len());
+        let value: f64 = text[..split].parse().ok()?;
+        let steps = match text[split..].trim() {
+            "" => value,
+            unit => {
+                let unit = TimeUnit::from_symbol(unit)?;
+                value * 10f64.powi(unit.exponent() as i32 - self.exponent() as i32)
+            }
+        };
+        if !steps.is_finite() || steps > u64::MAX as f64 {
+            return None;
+        }
+        Some(steps.round() as u64)
+    }
+}
+
+impl fmt::Display for Timescale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.magnitude, self.unit.symbol())
+    }
+}
+
+/// A time both in dump units and in seconds (when the timescale is known)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Timestamp {
+    pub raw: i64,
+    pub seconds: Option<f64>,
+}
+
+impl Timestamp {
+    pub fn new(raw: i64, timescale: Option<&Timescale>) -> Self {
+        Timestamp {
+            raw,
+            seconds: timescale.map(|t| t.to_seconds(raw)),
+        }
+    }
+
+    pub fn ns(&self) -> Option<f64> {
+        self.seconds.map(|s| s * 1e9)
+    }
+}
+
+/// A vector of four-state bits: each bit is 0, 1 or unknown (the other logic levels of the
+/// simulation state, `x`, `z`, `u`, ..., are all unknown).
+///
+/// Bits are indexed from the rightmost one (bit 0). Bitwise operations propagate unknown
+/// bits as in Verilog: `0 & x` is 0 and `1 | x` is 1, other operations involving an
+/// unknown bit give an unknown bit. Operands of different widths are zero-extended to the
+/// widest one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct BitVector {
+    width: usize,
+    // Known bits set to 1, 64 bits per word starting from bit 0
+    value: Vec<u64>,
+    // Unknown bits (cleared in `value`)
+    unknown: Vec<u64>,
+}
+
+impl BitVector {
+    pub fn zeros(width: usize) -> Self {
+        BitVector {
+            width,
+            value: vec![0; width.div_ceil(64)],
+            unknown: vec![0; width.div_ceil(64)],
+        }
+    }
+
+    /// A vector whose bits are all unknown
+    pub fn unknown(width: usize) -> Self {
+        let mut v = Self::zeros(width);
+        v.unknown.iter_mut().for_each(|w| *w = !0);
+        v.clear_padding();
+        v
+    }
+
+    /// The `width` lower bits of `x`
+    pub fn from_u64(x: u64, width: usize) -> Self {
+        let mut v = Self::zeros(width);
+        if let Some(w) = v.value.first_mut() {
+            *w = x;
+        }
+        v.clear_padding();
+        v
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Value of the i-th bit, None if unknown.
+    ///
+    /// Panics if `i` is not less than the width.
+    pub fn bit(&self, i: usize) -> Option<bool> {
+        assert!(
+            i < self.width,
+            "bit {} out of a vector of width {}",
+            i,
+            self.width
+        );
+        if self.unknown[i / 64] & (1 << (i % 64)) != 0 {
+            None
+        } else {
+            Some(self.value[i / 64] & (1 << (i % 64)) != 0)
+        }
+    }
+
+    /// Sets the i-th bit (None for unknown).
+    ///
+    /// Panics if `i` is not less than the width.
+    pub fn set_bit(&mut self, i: usize, bit: Option<bool>) {
+        assert!(
+            i < self.width,
+            "bit {} out of a vector of width {}",
+            i,
+            self.width
+        );
+        let mask = 1 << (i % 64);
+        self.value[i / 64] &= !mask;
+        self.unknown[i / 64] &= !mask;
+        match bit {
+            Some(true) => self.value[i / 64] |= mask,
+            Some(false) => {}
+            None => self.unknown[i / 64] |= mask,
+        }
+    }
+
+    /// Whether all the bits are known
+    pub fn is_known(&self) -> bool {
+        self.unknown.iter().all(|w| *w == 0)
+    }
+
+    /// Unsigned value of the vector, None if a bit is unknown or if it doesn't fit in a u64
+    pub fn to_u64(&self) -> Option<u64> {
+        if !self.is_known() || self.value.iter().skip(1).any(|w| *w != 0) {
+            return None;
+        }
+        Some(self.value.first().cloned().unwrap_or(0))
+    }
+
+    /// Bits `msb` down to `lsb` (included), as Verilog's `v[msb:lsb]`.
+    ///
+    /// Panics if `lsb > msb` or if `msb` is not less than the width.
+    pub fn slice(&self, msb: usize, lsb: usize) -> Self {
+        assert!(
+            lsb <= msb && msb < self.width,
+            "invalid slice [{}:{}]",
+            msb,
+            lsb
+        );
+        let mut v = Self::zeros(msb - lsb + 1);
+        for i in lsb..=msb {
+            v.set_bit(i - lsb, self.bit(i));
+        }
+        v
+    }
+
+    /// Concatenation with `self` as upper bits, as Verilog's `{self, low}`
+    pub fn concat(&self, low: &BitVector) -> Self {
+        let mut v = low.resize(self.width + low.width);
+        for i in 0..self.width {
+            v.set_bit(low.width + i, self.bit(i));
+        }
+        v
+    }
+
+    /// Truncates or zero-extends the vector to the given width
+    pub fn resize(&self, width: usize) -> Self {
+        let mut v = Self::zeros(width);
+        for (k, w) in v.value.iter_mut().enumerate() {
+            *w = self.value.get(k).cloned().unwrap_or(0);
+        }
+        for (k, w) in v.unknown.iter_mut().enumerate() {
+            *w = self.unknown.get(k).cloned().unwrap_or(0);
+        }
+        v.clear_padding();
+        v
+    }
+
+    /// Logic levels of the bits, leftmost bit first as in the simulation state (unknown
+    /// bits are X)
+    pub fn to_levels(&self) -> Vec<i8> {
+        (0..self.width)
+            .rev()
+            .map(|i| match self.bit(i) {
+                Some(b) => b as i8,
+                None => -4,
+            })
+            .collect()
+    }
+
+    fn clear_padding(&mut self) {
+        if self.width % 64 != 0 {
+            let mask = (1u64 << (self.width % 64)) - 1;
+            if let Some(w) = self.value.last_mut() {
+                *w &= mask;
+            }
+            if let Some(w) = self.unknown.last_mut() {
+                *w &= mask;
+            }
+        }
+    }
+
+    /// Applies `f` on the (value, unknown) words of both operands, zero-extended to the
+    /// widest one
+    fn zip_words<F>(&self, other: &BitVector, f: F) -> Self
+    where
+        F: Fn((u64, u64), (u64, u64)) -> (u64, u64),
+    {
+        let mut v = Self::zeros(self.width.max(other.width));
+        let word = |x: &BitVector, k: usize| {
+            (
+                x.value.get(k).cloned().unwrap_or(0),
+                x.unknown.get(k).cloned().unwrap_or(0),
+            )
+        };
+        for k in 0..v.value.len() {
+            let (value, unknown) = f(word(self, k), word(other, k));
+            v.value[k] = value & !unknown;
+            v.unknown[k] = unknown;
+        }
+        v.clear_padding();
+        v
+    }
+}
+
+impl BitAnd for &BitVector {
+    type Output = BitVector;
+
+    fn bitand(self, other: &BitVector) -> BitVector {
+        self.zip_words(other, |(av, au), (bv, bu)| {
+            let zero = (!av & !au) | (!bv & !bu);
+            let one = av & bv;
+            (one, !(zero | one))
+        })
+    }
+}
+
+impl BitOr for &BitVector {
+    type Output = BitVector;
+
+    fn bitor(self, other: &BitVector) -> BitVector {
+        self.zip_words(other, |(av, au), (bv, bu)| {
+            let zero = !av & !au & !bv & !bu;
+            let one = av | bv;
+            (one, !(zero | one))
+        })
+    }
+}
+
+impl BitXor for &BitVector {
+    type Output = BitVector;
+
+    fn bitxor(self, other: &BitVector) -> BitVector {
+        self.zip_words(other, |(av, au), (bv, bu)| (av ^ bv, au | bu))
+    }
+}
+
+impl Not for &BitVector {
+    type Output = BitVector;
+
+    fn not(self) -> BitVector {
+        let mut v = self.clone();
+        for (w, u) in v.value.iter_mut().zip(&v.unknown) {
+            *w = !*w & !u;
+        }
+        v.clear_padding();
+        v
+    }
+}
+
+impl From<&[i8]> for BitVector {
+    /// Converts a slice of the simulation state (leftmost bit first)
+    fn from(levels: &[i8]) -> Self {
+        let mut v = BitVector::zeros(levels.len());
+        for (i, l) in levels.iter().rev().enumerate() {
+            match l {
+                0 => {}
+                1 => v.set_bit(i, Some(true)),
+                _ => v.set_bit(i, None),
+            }
+        }
+        v
+    }
+}
+
+impl TryFrom<&VcdValue<'_>> for BitVector {
+    type Error = ();
+
+    /// Converts a scalar or vector value, with the number of bits dumped (reals are not
+    /// converted)
+    fn try_from(value: &VcdValue) -> Result<Self, Self::Error> {
+        let bits = |s: &str| {
+            let mut v = BitVector::zeros(s.len());
+            for (i, c) in s.chars().rev().enumerate() {
+                match c {
+                    '0' => {}
+                    '1' => v.set_bit(i, Some(true)),
+                    _ => v.set_bit(i, None),
+                }
+            }
+            v
+        };
+        match value {
+            VcdValue::Bit(c) => Ok(bits(c.encode_utf8(&mut [0; 4]))),
+            VcdValue::Vector(s) => Ok(bits(s)),
+            VcdValue::Real(_) | VcdValue::String(_) => Err(()),
+            VcdValue::Port(p) => Ok(bits(&p.levels())),
+            VcdValue::Event => Ok(bits("1")),
+        }
+    }
+}
+
+impl fmt::Display for BitVector {
+    /// Formats the bits as a binary string, leftmost bit first (`x` for unknown bits)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for i in (0..self.width).rev() {
+            let c = match self.bit(i) {
+                Some(true) => '1',
+                Some(false) => '0',
+                None => 'x',
+            };
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "bitvec")]
+impl<T: bitvec::store::BitStore, O: bitvec::order::BitOrder> From<&bitvec::slice::BitSlice<T, O>>
+    for BitVector
+{
+    /// Converts a slice whose first bit is bit 0
+    fn from(bits: &bitvec::slice::BitSlice<T, O>) -> Self {
+        let mut v = BitVector::zeros(bits.len());
+        for (i, b) in bits.iter().by_vals().enumerate() {
+            v.set_bit(i, Some(b));
+        }
+        v
+    }
+}
+
+#[cfg(feature = "bitvec")]
+impl TryFrom<&BitVector> for bitvec::vec::BitVec {
+    type Error = ();
+
+    /// Converts a vector without unknown bits, bit 0 first
+    fn try_from(v: &BitVector) -> Result<Self, Self::Error> {
+        (0..v.width()).map(|i| v.bit(i).ok_or(())).collect()
+    }
+}
+
+#[cfg(feature = "num-bigint")]
+impl BitVector {
+    /// The `width` lower bits of `x`
+    pub fn from_biguint(x: &num_bigint::BigUint, width: usize) -> Self {
+        let mut v = Self::zeros(width);
+        for (w, d) in v.value.iter_mut().zip(x.iter_u64_digits()) {
+            *w = d;
+        }
+        v.clear_padding();
+        v
+    }
+}
+
+#[cfg(feature = "num-bigint")]
+impl From<&num_bigint::BigUint> for BitVector {
+    /// Converts a number to a vector of its significant bits (at least one)
+    fn from(x: &num_bigint::BigUint) -> Self {
+        BitVector::from_biguint(x, (x.bits() as usize).max(1))
+    }
+}
+
+#[cfg(feature = "num-bigint")]
+impl TryFrom<&BitVector> for num_bigint::BigUint {
+    type Error = ();
+
+    /// Unsigned value of a vector without unknown bits
+    fn try_from(v: &BitVector) -> Result<Self, Self::Error> {
+        if !v.is_known() {
+            return Err(());
+        }
+        let bytes: Vec<u8> = v.value.iter().flat_map(|w| w.to_le_bytes()).collect();
+        Ok(num_bigint::BigUint::from_bytes_le(&bytes))
+    }
+}
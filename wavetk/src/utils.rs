@@ -1,6 +1,24 @@
 use std::io;
 use std::io::Read;
 
+/// Payload of the error given by a `Buffer` growing past its limit (see `Buffer::set_limit`),
+/// to tell it from the `io::ErrorKind::OutOfMemory` errors of the input
+#[derive(Debug)]
+pub(crate) struct LimitExceeded;
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "buffer memory limit exceeded")
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Whether an error was given by a `Buffer` growing past its limit
+pub(crate) fn is_limit_exceeded(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<LimitExceeded>())
+}
+
 /// A very simple buffer around any type implementing the Read Trait.
 ///
 /// This buffer is designed to support a producer/consumer workflow for streaming
@@ -13,6 +31,15 @@ pub(crate) struct Buffer<R> {
     offset: usize,
     size: usize,
     data: Vec<u8>,
+    limit: Option<usize>,
+    // Total number of bytes read from `inner`
+    read: u64,
+    // Number of bytes added with `push` (not part of the input)
+    pushed: u64,
+    // Number of line feeds in the bytes dropped from the buffer, and position of the line
+    // following the last one
+    lines: u64,
+    line_start: u64,
 }
 
 impl<R: Read> Buffer<R> {
@@ -22,7 +49,73 @@ impl<R: Read> Buffer<R> {
             offset: 0,
             size: 0,
             data: Vec::with_capacity(capacity),
+            limit: None,
+            read: 0,
+            pushed: 0,
+            lines: 0,
+            line_start: 0,
+        }
+    }
+
+    /// Sets the maximum number of bytes the buffer is allowed to hold, growing past this
+    /// limit makes `refill` and `push` fail with an `io::ErrorKind::OutOfMemory` error
+    /// (see `is_limit_exceeded`).
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Position in the input of the first byte not consumed yet. Bytes added by `push`
+    /// (e.g., the separator ending the last command) are not part of the input: they
+    /// are only counted once consumed, and never beyond the end of the input.
+    pub fn position(&self) -> u64 {
+        (self.read + self.pushed)
+            .saturating_sub(self.size as u64)
+            .min(self.read)
+    }
+
+    /// Line and column (both counted from 1, the column in bytes) of `position`.
+    ///
+    /// Consumed bytes are only scanned here and when they are dropped from the buffer, so
+    /// that consuming stays cheap.
+    pub fn line_column(&self) -> (u64, u64) {
+        let (lines, line_start) = self.count_lines();
+        let position = self.position();
+        (lines + 1, position - line_start.min(position) + 1)
+    }
+
+    /// Line feeds consumed so far, and position of the line following the last one
+    fn count_lines(&self) -> (u64, u64) {
+        let consumed = &self.data[..self.offset];
+        match consumed.iter().rposition(|c| *c == b'\n') {
+            Some(last) => {
+                let n = consumed.iter().filter(|c| **c == b'\n').count() as u64;
+                let start = self
+                    .position()
+                    .saturating_sub((self.offset - last - 1) as u64);
+                (self.lines + n, start)
+            }
+            None => (self.lines, self.line_start),
+        }
+    }
+
+    /// Drops the consumed bytes from the buffer
+    fn drop_consumed(&mut self) {
+        let (lines, line_start) = self.count_lines();
+        self.lines = lines;
+        self.line_start = line_start;
+        if self.size > 0 {
+            self.data.drain(0..self.offset);
         }
+        self.offset = 0;
+    }
+
+    /// Number of bytes currently allocated for the buffer
+    pub fn allocated(&self) -> usize {
+        self.data.capacity()
     }
 
     fn capacity(&self) -> usize {
@@ -33,23 +126,38 @@ impl<R: Read> Buffer<R> {
         self.capacity() - (self.size + self.offset)
     }
 
-    pub fn push(&mut self, elt: u8) {
+    /// Grows the buffer to `len` bytes, within the limit
+    fn grow(&mut self, len: usize) -> io::Result<()> {
+        if let Some(limit) = self.limit {
+            if len > limit {
+                return Err(io::Error::new(io::ErrorKind::OutOfMemory, LimitExceeded));
+            }
+            // Avoid the amortized growth of Vec, which may allocate up to twice the limit
+            self.data.reserve_exact(len - self.data.len());
+        }
+        self.data.resize(len, 0);
+        Ok(())
+    }
+
+    /// Appends a byte after the data read, growing the buffer if it is full
+    pub fn push(&mut self, elt: u8) -> io::Result<()> {
         if self.available() == 0 {
-            self.data.push(elt);
+            let len = self.data.len() + 1;
+            self.grow(len)?;
+            self.data[len - 1] = elt;
         } else {
             self.data[self.offset + self.size] = elt;
-            self.size += 1;
         }
+        self.size += 1;
+        self.pushed += 1;
+        Ok(())
     }
 
     pub fn consume(&mut self, size: usize) {
-        if size >= self.size {
-            self.offset = 0;
-            self.size = 0;
-        } else {
-            self.offset += size;
-            self.size -= size;
-        }
+        // The consumed bytes are kept until the next `refill` or `shift`, for `line_column`
+        let size = size.min(self.size);
+        self.offset += size;
+        self.size -= size;
     }
 
     pub fn trim(&mut self) -> usize {
@@ -63,25 +171,62 @@ impl<R: Read> Buffer<R> {
     }
 
     pub fn shift(&mut self) {
-        self.data.drain(0..self.offset);
-        self.offset = 0;
+        self.drop_consumed();
     }
 
     pub fn refill(&mut self, size: usize) -> io::Result<usize> {
+        if self.size == 0 && self.offset > 0 {
+            self.drop_consumed();
+        }
         let end = self.offset + self.size;
         if self.available() < size {
-            self.data.resize(end + size, 0);
+            self.grow(end + size)?;
         }
         let n = self.inner.read(&mut self.data[end..end + size])?;
         self.size += n;
+        self.read += n as u64;
         Ok(n)
     }
 
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
     pub fn data(&self) -> &[u8] {
         &self.data[self.offset..self.offset + self.size]
     }
 
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data[self.offset..self.offset + self.size]
+    }
+
     pub fn len(&self) -> usize {
         self.size
     }
 }
+
+/// Appends an unsigned LEB128 encoding of `x` to the given vector
+pub(crate) fn write_varint(out: &mut Vec<u8>, mut x: u64) {
+    loop {
+        let b = (x & 0x7f) as u8;
+        x >>= 7;
+        if x == 0 {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+/// Decodes an unsigned LEB128 integer at the start of `data`, returns the value and the
+/// number of bytes consumed (None if `data` is truncated or the value overflows)
+pub(crate) fn read_varint(data: &[u8]) -> Option<(u64, usize)> {
+    let mut x = 0u64;
+    for (i, b) in data.iter().enumerate().take(10) {
+        x |= ((b & 0x7f) as u64) << (7 * i);
+        if b & 0x80 == 0 {
+            return Some((x, i + 1));
+        }
+    }
+    None
+}
@@ -1,9 +1,9 @@
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io;
 use std::io::Read;
 use std::str;
 use std::str::FromStr;
 
-#[cfg(test)]
 use nom::error::ErrorKind;
 use nom::{
     branch::alt,
@@ -17,9 +17,11 @@ use nom::{
     sequence::{delimited, preceded, separated_pair, terminated, tuple},
     IResult,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
 
-use crate::types::{Direction, Range, Scope, VariableInfo, VariableKind};
+use crate::hierarchy;
+use crate::types::{Direction, Range, Scope, Timescale, VariableInfo, VariableKind};
 use crate::utils;
 
 #[derive(Debug)]
@@ -30,21 +32,272 @@ pub enum VcdError {
     PartialHeader,
     Utf8Error,
     EndOfInput,
+    MemoryLimit,
+    UnknownVariable,
+    /// The header does not comply with the standard (only reported in strict mode)
+    InvalidHeader(HeaderViolation),
+    /// A value change cannot be applied to the state of a simulation
+    InvalidChange(InvalidChange),
+    /// The input ends before `$enddefinitions`, what was parsed is given by
+    /// `VcdParser::partial_header`
+    TruncatedHeader(HeaderTruncation),
+    /// A command of the input can not be parsed, with its location (`ParseError` is only
+    /// given when no location is known)
+    Syntax(SyntaxError),
+    /// A timestamp is lower than a previous one (see `TimestampPolicy`), `position` is the
+    /// byte offset of the input right after it
+    NonMonotonicTime {
+        time: u64,
+        previous: u64,
+        position: u64,
+    },
+    /// Error raised by user code, e.g., a callback given to `process_vcd_commands`
+    User(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl VcdError {
+    /// Wraps an error of user code
+    pub fn user<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> Self {
+        VcdError::User(e.into())
+    }
 }
 
 impl std::fmt::Display for VcdError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
             VcdError::IoError(e) => e.fmt(f),
+            VcdError::InvalidHeader(v) => write!(f, "invalid header: {}", v),
+            VcdError::InvalidChange(c) => c.fmt(f),
+            VcdError::TruncatedHeader(t) => t.fmt(f),
+            VcdError::Syntax(e) => e.fmt(f),
+            VcdError::NonMonotonicTime {
+                time,
+                previous,
+                position,
+            } => write!(
+                f,
+                "timestamp #{} after #{} (byte {} of the input)",
+                time, previous, position
+            ),
+            VcdError::User(e) => e.fmt(f),
             x => write!(f, "{:?}", x),
         }
     }
 }
 
+/// Problems detected in a header by the strict mode (see `VcdParser::set_strict`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderViolation {
+    /// An identifier code holds characters outside of the printable ASCII range (33-126)
+    InvalidId {
+        id: String,
+        name: String,
+    },
+    NonPositiveWidth {
+        name: String,
+        width: i64,
+    },
+    /// `$upscope` without a matching `$scope`
+    UnbalancedUpscope,
+    /// Scopes still open at `$enddefinitions`
+    UnclosedScopes(Vec<String>),
+}
+
+impl std::fmt::Display for HeaderViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            HeaderViolation::InvalidId { id, name } => write!(
+                f,
+                "identifier {:?} of variable {} is not made of printable ASCII characters",
+                id, name
+            ),
+            HeaderViolation::NonPositiveWidth { name, width } => {
+                write!(f, "variable {} has a non positive width {}", name, width)
+            }
+            HeaderViolation::UnbalancedUpscope => write!(f, "$upscope without matching $scope"),
+            HeaderViolation::UnclosedScopes(s) => {
+                write!(f, "scopes not closed at $enddefinitions: {}", s.join("."))
+            }
+        }
+    }
+}
+
+/// Kind of command expected where a syntax error is found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Expected {
+    /// Command of the header (`$scope`, `$var`... up to its `$end`)
+    HeaderCommand,
+    /// Command of the body starting with a `$` (e.g., `$dumpvars`)
+    BodyCommand,
+    /// `#` followed by a time
+    Timestamp,
+    /// Bit value followed by an identifier (e.g., `1!`)
+    ScalarChange,
+    /// `b<bits> <id>`
+    VectorChange,
+    /// `r<number> <id>`
+    RealChange,
+    /// `p<states> <strength0> <strength1> <id>`
+    PortChange,
+    /// `s<text> <id>`
+    StringChange,
+}
+
+impl Expected {
+    /// Command expected in the body from the first character of the input
+    fn body_command(first: u8) -> Self {
+        match first {
+            b'$' => Expected::BodyCommand,
+            b'#' => Expected::Timestamp,
+            b'b' | b'B' => Expected::VectorChange,
+            b'r' | b'R' => Expected::RealChange,
+            b'p' => Expected::PortChange,
+            b's' => Expected::StringChange,
+            _ => Expected::ScalarChange,
+        }
+    }
+}
+
+impl std::fmt::Display for Expected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.write_str(match self {
+            Expected::HeaderCommand => "a header command",
+            Expected::BodyCommand => "a command",
+            Expected::Timestamp => "a timestamp",
+            Expected::ScalarChange => "a scalar value change",
+            Expected::VectorChange => "a vector value change",
+            Expected::RealChange => "a real value change",
+            Expected::PortChange => "a port value change",
+            Expected::StringChange => "a string value change",
+        })
+    }
+}
+
+/// Location of a command that can not be parsed (see `VcdError::Syntax`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyntaxError {
+    /// Byte offset of the start of the command in the input
+    pub position: u64,
+    /// Line and column (in bytes) of `position`, both counted from 1
+    pub line: u64,
+    pub column: u64,
+    pub expected: Expected,
+    /// Input found at `position`, up to the end of the line (at most 64 bytes)
+    pub excerpt: String,
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "syntax error at line {}, column {} (byte {}): expected {}, found {:?}",
+            self.line, self.column, self.position, self.expected, self.excerpt
+        )
+    }
+}
+
+/// Where the input of a truncated header ends (see `VcdError::TruncatedHeader`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HeaderTruncation {
+    /// Byte offset of the input after the last complete command of the header
+    pub position: u64,
+    /// Keyword of the command cut by the end of the input (e.g., `var`), None when the
+    /// input ends between two commands
+    pub command: Option<String>,
+    /// Scopes still open, outermost first
+    pub open_scopes: Vec<String>,
+    /// Number of variables declared before the truncation
+    pub variables: usize,
+}
+
+impl std::fmt::Display for HeaderTruncation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "header truncated at byte {}", self.position)?;
+        if let Some(c) = &self.command {
+            write!(f, " in ${}", c)?;
+        }
+        write!(f, " ({} variables declared", self.variables)?;
+        if !self.open_scopes.is_empty() {
+            write!(f, ", inside {}", self.open_scopes.join("."))?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Where the body of a dump cut in the middle of a command ends (see
+/// `VcdParser::set_allow_truncation`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BodyTruncation {
+    /// Byte offset of the input after the last complete command
+    pub position: u64,
+    /// Number of bytes of the incomplete command, dropped
+    pub discarded: u64,
+}
+
+impl std::fmt::Display for BodyTruncation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "body truncated at byte {} ({} bytes discarded)",
+            self.position, self.discarded
+        )
+    }
+}
+
+/// Value change rejected by a simulation, with the context needed to locate it in the file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidChange {
+    /// Hierarchical name of the variable (empty when the id is not declared)
+    pub path: String,
+    pub id: String,
+    /// Time of the change, negative before the first timestamp
+    pub time: i64,
+    /// Value as written in the file (without the `b` or `r` prefix)
+    pub value: String,
+    pub reason: ChangeProblem,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeProblem {
+    /// The id is not declared in the header
+    UnknownId,
+    /// The value has more bits than the variable
+    WidthMismatch { width: usize },
+    /// The value holds a character that is not a logic level
+    InvalidCharacter(char),
+}
+
+impl std::fmt::Display for InvalidChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "invalid change {:?} of ", self.value)?;
+        if self.path.is_empty() {
+            write!(f, "id {:?}", self.id)?;
+        } else {
+            write!(f, "{} (id {:?})", self.path, self.id)?;
+        }
+        if self.time < 0 {
+            write!(f, " before the first timestamp: ")?;
+        } else {
+            write!(f, " at time {}: ", self.time)?;
+        }
+        match &self.reason {
+            ChangeProblem::UnknownId => write!(f, "id not declared in the header"),
+            ChangeProblem::WidthMismatch { width } => write!(
+                f,
+                "{} bits given for a variable of width {}",
+                self.value.len(),
+                width
+            ),
+            ChangeProblem::InvalidCharacter(c) => write!(f, "{:?} is not a logic level", c),
+        }
+    }
+}
+
 impl std::error::Error for VcdError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             VcdError::IoError(e) => Some(e),
+            VcdError::User(e) => Some(e.as_ref()),
             _ => None,
         }
     }
@@ -52,11 +305,17 @@ impl std::error::Error for VcdError {
 
 impl From<io::Error> for VcdError {
     fn from(e: io::Error) -> Self {
-        VcdError::IoError(e)
+        match e.kind() {
+            // Only the accounting of the parser reports the limit, not the input
+            io::ErrorKind::OutOfMemory if utils::is_limit_exceeded(&e) => VcdError::MemoryLimit,
+            // Non-blocking input with no data available yet (e.g., `PushInput`)
+            io::ErrorKind::WouldBlock => VcdError::MissingData,
+            _ => VcdError::IoError(e),
+        }
     }
 }
 
-impl<'a, E: ParseError<&'a str>> From<nom::Err<E>> for VcdError {
+impl<'a, E: ParseError<&'a [u8]>> From<nom::Err<E>> for VcdError {
     fn from(e: nom::Err<E>) -> Self {
         match e {
             nom::Err::Incomplete(_) => VcdError::MissingData,
@@ -71,11 +330,116 @@ pub struct VcdChange<'a> {
     pub value: VcdValue<'a>,
 }
 
-#[derive(Debug, Serialize, PartialEq)]
+/// Value changes of one `#time` section, decoded in a single pass by
+/// `VcdParser::decode_section`. Ids and values are copied into one text buffer, which is
+/// kept (with its capacity) from one section to the next, so that decoding does not
+/// allocate for each change.
+#[derive(Debug, Clone, Default)]
+pub struct ChangeBatch {
+    time: Option<u64>,
+    text: String,
+    changes: Vec<BatchEntry>,
+}
+
+#[derive(Debug, Clone)]
+struct BatchEntry {
+    id: std::ops::Range<usize>,
+    value: std::ops::Range<usize>,
+    kind: BatchValue,
+}
+
+/// Kind of a value change of a batch, its text being kept in the buffer of the batch
+#[derive(Debug, Clone, Copy)]
+enum BatchValue {
+    Bit(char),
+    Vector,
+    Real,
+    String,
+    Port(u8, u8),
+    Event,
+}
+
+impl ChangeBatch {
+    /// Time of the section, `None` for the changes written before the first timestamp
+    pub fn time(&self) -> Option<u64> {
+        self.time
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Removes the changes, keeping the allocated buffers
+    pub fn clear(&mut self) {
+        self.time = None;
+        self.text.clear();
+        self.changes.clear();
+    }
+
+    pub fn get(&self, index: usize) -> Option<VcdChange<'_>> {
+        let entry = self.changes.get(index)?;
+        let text = &self.text[entry.value.clone()];
+        let value = match entry.kind {
+            BatchValue::Bit(c) => VcdValue::Bit(c),
+            BatchValue::Vector => VcdValue::Vector(text),
+            BatchValue::Real => VcdValue::Real(text),
+            BatchValue::String => VcdValue::String(text),
+            BatchValue::Port(strength0, strength1) => VcdValue::Port(PortValue {
+                states: text,
+                strength0,
+                strength1,
+            }),
+            BatchValue::Event => VcdValue::Event,
+        };
+        Some(VcdChange {
+            var_id: &self.text[entry.id.clone()],
+            value,
+        })
+    }
+
+    /// The changes, in the order of the file
+    pub fn iter(&self) -> impl Iterator<Item = VcdChange<'_>> {
+        (0..self.changes.len()).filter_map(move |i| self.get(i))
+    }
+
+    fn push(&mut self, change: &VcdChange) {
+        let (text, kind) = match change.value {
+            VcdValue::Bit(c) => ("", BatchValue::Bit(c)),
+            VcdValue::Vector(x) => (x, BatchValue::Vector),
+            VcdValue::Real(x) => (x, BatchValue::Real),
+            VcdValue::String(x) => (x, BatchValue::String),
+            VcdValue::Port(p) => (p.states, BatchValue::Port(p.strength0, p.strength1)),
+            VcdValue::Event => ("", BatchValue::Event),
+        };
+        let start = self.text.len();
+        self.text.push_str(change.var_id);
+        let middle = self.text.len();
+        self.text.push_str(text);
+        self.changes.push(BatchEntry {
+            id: start..middle,
+            value: middle..self.text.len(),
+            kind,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
 pub enum VcdValue<'a> {
     Bit(char),
     Vector(&'a str),
     Real(&'a str),
+    /// Value of a port in an extended VCD (`$dumpports`)
+    Port(PortValue<'a>),
+    /// Value of a string variable (`s<text> <id>`, written by GTKWave and SystemVerilog
+    /// simulators), the text can not hold whitespace
+    String(&'a str),
+    /// Occurrence of an event variable (a `1<id>` change of a `$var event`), events have
+    /// no value kept between cycles
+    Event,
 }
 
 impl<'a> VcdValue<'a> {
@@ -84,28 +448,201 @@ impl<'a> VcdValue<'a> {
             VcdValue::Bit(_) => 1,
             VcdValue::Vector(v) => v.len(),
             VcdValue::Real(_) => 1,
+            VcdValue::Port(p) => p.states.len(),
+            VcdValue::String(_) | VcdValue::Event => 1,
+        }
+    }
+}
+
+/// Port value change of an extended VCD (`p<states> <strength0> <strength1> <id>`, IEEE
+/// 1364 §18.4.3.1)
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub struct PortValue<'a> {
+    /// One state character per bit, leftmost bit first: driving direction and level of the
+    /// port (e.g., `D`/`U` driven low/high by the test fixture, `L`/`H` by the design,
+    /// `0`/`1` by both)
+    pub states: &'a str,
+    /// Strengths of the drivers of the 0 and 1 levels, from 0 (highz) to 7 (supply)
+    pub strength0: u8,
+    pub strength1: u8,
+}
+
+impl<'a> PortValue<'a> {
+    /// The states as VCD logic levels (see `port_level`)
+    pub fn levels(&self) -> String {
+        self.states.chars().map(port_level).collect()
+    }
+}
+
+/// Logic level (`0`, `1`, `x` or `z`) of an extended VCD port state, whatever the side
+/// driving it
+pub fn port_level(state: char) -> char {
+    match state {
+        'D' | 'd' | 'L' | 'l' | '0' => '0',
+        'U' | 'u' | 'H' | 'h' | '1' => '1',
+        'Z' | 'T' | 'F' | 'f' => 'z',
+        _ => 'x',
+    }
+}
+
+/// Simulation keywords of the body, opening a section of value changes closed by `$end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DumpSection {
+    /// `$dumpvars`, initial values
+    Vars,
+    /// `$dumpall`, current values of all the variables
+    All,
+    /// `$dumpoff`, dumping is suspended, the variables are listed as unknown
+    Off,
+    /// `$dumpon`, dumping resumes with the current values of the variables
+    On,
+    /// `$dumpports`, `$dumpportsall`, `$dumpportsoff` and `$dumpportson` of an extended VCD,
+    /// holding port values
+    Ports,
+    PortsAll,
+    PortsOff,
+    PortsOn,
+}
+
+impl DumpSection {
+    pub fn keyword(self) -> &'static str {
+        match self {
+            DumpSection::Vars => "dumpvars",
+            DumpSection::All => "dumpall",
+            DumpSection::Off => "dumpoff",
+            DumpSection::On => "dumpon",
+            DumpSection::Ports => "dumpports",
+            DumpSection::PortsAll => "dumpportsall",
+            DumpSection::PortsOff => "dumpportsoff",
+            DumpSection::PortsOn => "dumpportson",
+        }
+    }
+
+    pub fn from_keyword(keyword: &str) -> Option<Self> {
+        match keyword {
+            "dumpvars" => Some(DumpSection::Vars),
+            "dumpall" => Some(DumpSection::All),
+            "dumpoff" => Some(DumpSection::Off),
+            "dumpon" => Some(DumpSection::On),
+            "dumpports" => Some(DumpSection::Ports),
+            "dumpportsall" => Some(DumpSection::PortsAll),
+            "dumpportsoff" => Some(DumpSection::PortsOff),
+            "dumpportson" => Some(DumpSection::PortsOn),
+            _ => None,
+        }
+    }
+
+    /// Section of a standard VCD with the same meaning (e.g., `Vars` for `Ports`)
+    pub fn vcd_equivalent(self) -> Self {
+        match self {
+            DumpSection::Ports => DumpSection::Vars,
+            DumpSection::PortsAll => DumpSection::All,
+            DumpSection::PortsOff => DumpSection::Off,
+            DumpSection::PortsOn => DumpSection::On,
+            d => d,
         }
     }
 }
 
 #[derive(Debug, Serialize)]
 pub enum VcdCommand<'a> {
+    /// Directive of the body other than the simulation keywords (e.g., `$comment`)
     Directive(&'a str),
+    /// Start of a `$dumpvars`, `$dumpall`, `$dumpoff` or `$dumpon` section (or of their
+    /// extended VCD counterparts, e.g., `$dumpports`)
+    BeginDump(DumpSection),
+    /// `$end` of a section opened by `BeginDump`
+    EndDump(DumpSection),
+    /// `$end` closing no section
     VcdEnd,
     SetCycle(u64),
     ValueChange(VcdChange<'a>),
+    /// Final time of the dump given by `$vcdclose` (written at the end of extended VCD
+    /// files, and by ModelSim and Questa)
+    VcdClose(u64),
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct VcdHeader {
     pub variables: Vec<VariableInfo>,
+    /// Parsed `$timescale` (None if absent or not understood)
+    #[serde(default)]
+    pub timescale: Option<Timescale>,
+    /// Time origin given by `$timezero`, in dump units (0 if absent): real times are
+    /// computed from the times of the dump plus this offset
+    #[serde(default)]
+    pub time_zero: i64,
+    #[serde(default)]
+    pub metadata: VcdMetadata,
+    /// Tree of the declared scopes, with the variables of each one
+    #[serde(default)]
+    pub scopes: hierarchy::ScopeNode,
+}
+
+/// Text of the `$date`, `$version` and top-level `$comment` directives of a header
+/// (leading and trailing whitespace removed)
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VcdMetadata {
+    pub date: Option<String>,
+    pub version: Option<String>,
+    /// Comments found outside of any scope, in order
+    pub comments: Vec<String>,
+}
+
+impl VcdHeader {
+    /// Variables driven by each VCD id (see `hierarchy::alias_map`)
+    pub fn aliases(&self) -> HashMap<&str, Vec<&VariableInfo>> {
+        hierarchy::alias_map(&self.variables)
+    }
+
+    /// Scope at a dot-separated path (e.g., `top.cpu`) in the scope tree
+    pub fn scope(&self, path: &str) -> Option<&hierarchy::ScopeNode> {
+        self.scopes.get(path)
+    }
+
+    /// Variables declared directly in a scope of the scope tree
+    pub fn variables_of<'a>(
+        &'a self,
+        scope: &'a hierarchy::ScopeNode,
+    ) -> impl Iterator<Item = &'a VariableInfo> {
+        scope.variables.iter().map(move |i| &self.variables[*i])
+    }
 }
 
+/// Receives the keyword and text of the directives that are neither standard nor known
+/// vendor extensions (see `VcdParser::set_directive_hook`)
+pub type DirectiveHook = Box<dyn FnMut(&str, &str) + Send>;
+
+/// Directives of the standard carrying text, and vendor extensions, that are recognized
+/// (and not reported to the directive hook)
+const KNOWN_DIRECTIVES: [&str; 7] = [
+    "comment",
+    "date",
+    "version",
+    // GTKWave attributes, ModelSim/Questa time origin and final time
+    "attrbegin",
+    "attrend",
+    "timezero",
+    "vcdclose",
+];
+
 pub struct VcdHeaderParser {
     pub header: VcdHeader,
     header_valid: bool,
     scope: Vec<Scope>,
+    scope_tree: hierarchy::ScopeTreeBuilder,
     verbose: bool,
+    strict: bool,
+    /// Whether the body may start without `$enddefinitions`
+    implicit_end: bool,
+    violation: Option<HeaderViolation>,
+    directive_hook: Option<DirectiveHook>,
+}
+
+impl Default for VcdHeaderParser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VcdHeaderParser {
@@ -113,41 +650,169 @@ impl VcdHeaderParser {
         VcdHeaderParser {
             header: VcdHeader {
                 variables: Vec::with_capacity(1024),
+                timescale: None,
+                time_zero: 0,
+                metadata: VcdMetadata::default(),
+                scopes: hierarchy::ScopeNode::default(),
             },
             header_valid: false,
             scope: Vec::with_capacity(16),
+            scope_tree: hierarchy::ScopeTreeBuilder::default(),
             verbose: false,
+            strict: false,
+            implicit_end: false,
+            violation: None,
+            directive_hook: None,
+        }
+    }
+
+    /// Reports a directive to the hook if it is not known
+    fn unknown_directive(&mut self, keyword: &str, text: &str) {
+        if KNOWN_DIRECTIVES.contains(&keyword) {
+            return;
+        }
+        if self.verbose {
+            eprintln!("warning: ignoring directive {}", keyword);
+        }
+        if let Some(hook) = self.directive_hook.as_mut() {
+            hook(keyword, text);
+        }
+    }
+
+    /// In strict mode, identifiers, widths and scope nesting are checked, the first
+    /// violation stops the parsing (see `take_violation`)
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Ends the header without `$enddefinitions` when the body starts (with a timestamp, a
+    /// value change or a `$dump` section) or at the end of the input, as written by some
+    /// tools
+    pub fn set_allow_missing_enddefinitions(&mut self, enable: bool) {
+        self.implicit_end = enable;
+    }
+
+    /// Ends the header, on `$enddefinitions` or where the body starts (see
+    /// `set_allow_missing_enddefinitions`)
+    fn end_definitions<'a, E: ParseError<&'a [u8]>>(
+        &mut self,
+        input: &'a [u8],
+    ) -> IResult<&'a [u8], bool, E> {
+        if self.strict && !self.scope.is_empty() {
+            let names = self.scope.iter().map(|s| s.name.clone()).collect();
+            return self.violation(input, HeaderViolation::UnclosedScopes(names));
+        }
+        self.header_valid = true;
+        Ok((input, true))
+    }
+
+    /// Describes where the header stops when the input ends before `$enddefinitions`
+    fn truncation<R: Read>(&self, buffer: &VcdStreamParser<R>) -> HeaderTruncation {
+        let rest = buffer.buff.data();
+        let start = rest.iter().position(|c| !c.is_ascii_whitespace());
+        let command = start
+            .and_then(|k| rest[k..].strip_prefix(b"$"))
+            .map(|r| {
+                r.iter()
+                    .take_while(|c| c.is_ascii_alphanumeric())
+                    .copied()
+                    .collect()
+            })
+            .map(|k| String::from_utf8(k).unwrap_or_default());
+        HeaderTruncation {
+            position: buffer.buff.position(),
+            command,
+            open_scopes: self.scope.iter().map(|s| s.name.clone()).collect(),
+            variables: self.header.variables.len(),
         }
     }
 
-    fn next_header_command<'a, E: ParseError<&'a str>>(
+    /// Violation that made the last parsing fail in strict mode
+    pub fn take_violation(&mut self) -> Option<HeaderViolation> {
+        self.violation.take()
+    }
+
+    fn violation<'a, T, E: ParseError<&'a [u8]>>(
+        &mut self,
+        input: &'a [u8],
+        violation: HeaderViolation,
+    ) -> IResult<&'a [u8], T, E> {
+        self.violation = Some(violation);
+        Err(nom::Err::Failure(E::from_error_kind(
+            input,
+            nom::error::ErrorKind::Verify,
+        )))
+    }
+
+    fn next_header_command<'a, E: ParseError<&'a [u8]>>(
         &mut self,
-        input: &'a str,
-    ) -> IResult<&'a str, bool, E> {
-        let (remaining, cmd) = terminated(preceded(char('$'), alphanumeric1), multispace0)(input)?;
+        input: &'a [u8],
+    ) -> IResult<&'a [u8], bool, E> {
+        let (input, _) = multispace0(input)?;
+        if self.implicit_end && input.first().is_some_and(|c| *c != b'$') {
+            if self.verbose {
+                eprintln!("warning: header ended without $enddefinitions");
+            }
+            return self.end_definitions(input);
+        }
+        let (remaining, cmd) =
+            terminated(preceded(char('$'), utf8(alphanumeric1)), multispace0)(input)?;
         match cmd {
             "enddefinitions" => {
                 let (remaining, _) = vcd_end(remaining)?;
-                self.header_valid = true;
-                Ok((remaining, true))
+                self.end_definitions(remaining)
+            }
+            x if self.implicit_end && x.starts_with("dump") => {
+                if self.verbose {
+                    eprintln!("warning: header ended without $enddefinitions");
+                }
+                self.end_definitions(input)
             }
             "scope" => {
                 let (remaining, (kind, name)) =
                     terminated(tuple((vcd_word, vcd_word)), vcd_end)(remaining)?;
                 self.scope.push(Scope::from_str(kind, name));
+                self.scope_tree.enter(&mut self.header.scopes, &self.scope);
                 Ok((remaining, false))
             }
             "upscope" => {
                 let (remaining, _) = vcd_end(remaining)?;
+                if self.strict && self.scope.is_empty() {
+                    return self.violation(input, HeaderViolation::UnbalancedUpscope);
+                }
                 self.scope.pop();
+                self.scope_tree.leave();
                 Ok((remaining, false))
             }
             "var" => {
-                let (remaining, (var_type, width, var_id, var_name, range)) =
+                // Ports of extended VCD files give their range instead of their width
+                let size = alt((
+                    map(var_width, |w| (w, None)),
+                    map(var_range, |r| (r.width() as i64, Some(r))),
+                ));
+                let (remaining, (var_type, (width, port_range), var_id, var_name, range)) =
                     terminated(
-                        tuple((vcd_word, var_width, vcd_word, var_name, opt(var_range))),
+                        tuple((vcd_word, size, vcd_word, var_name, opt(var_range))),
                         vcd_end,
                     )(remaining)?;
+                let range = range.or(port_range);
+                let index = self.header.variables.len();
+                if self.strict {
+                    if var_id.is_empty() || !var_id.bytes().all(|c| (33..=126).contains(&c)) {
+                        let violation = HeaderViolation::InvalidId {
+                            id: var_id.to_string(),
+                            name: var_name.to_string(),
+                        };
+                        return self.violation(input, violation);
+                    }
+                    if width <= 0 {
+                        let violation = HeaderViolation::NonPositiveWidth {
+                            name: var_name.to_string(),
+                            width,
+                        };
+                        return self.violation(input, violation);
+                    }
+                }
                 self.header.variables.push(VariableInfo {
                     id: String::from(var_id),
                     kind: VariableKind::from(var_type),
@@ -158,13 +823,43 @@ impl VcdHeaderParser {
                     scope: self.scope.clone(),
                     direction: Direction::Implicit,
                 });
+                self.scope_tree.add_variable(&mut self.header.scopes, index);
                 Ok((remaining, false))
             }
-            x => {
-                if self.verbose {
-                    eprintln!("warning: ignoring directive {}", x);
+            "timescale" => {
+                let (remaining, text) = directive_text(remaining)?;
+                let text = String::from_utf8_lossy(text);
+                self.header.timescale = Timescale::parse(&text);
+                if self.header.timescale.is_none() && self.verbose {
+                    eprintln!("warning: invalid timescale {}", text);
+                }
+                Ok((remaining, false))
+            }
+            "date" | "version" | "comment" => {
+                let (remaining, text) = directive_text(remaining)?;
+                let text = String::from_utf8_lossy(text);
+                let metadata = &mut self.header.metadata;
+                match cmd {
+                    "date" => metadata.date = Some(text.to_string()),
+                    "version" => metadata.version = Some(text.to_string()),
+                    _ if self.scope.is_empty() => metadata.comments.push(text.to_string()),
+                    _ => {}
                 }
-                let (remaining, _) = skip_until_vcd_end(remaining)?;
+                Ok((remaining, false))
+            }
+            "timezero" => {
+                let (remaining, text) = directive_text(remaining)?;
+                let text = String::from_utf8_lossy(text);
+                match text.parse() {
+                    Ok(t) => self.header.time_zero = t,
+                    Err(_) if self.verbose => eprintln!("warning: invalid timezero {}", text),
+                    Err(_) => {}
+                }
+                Ok((remaining, false))
+            }
+            x => {
+                let (remaining, text) = directive_text(remaining)?;
+                self.unknown_directive(x, &String::from_utf8_lossy(text));
                 Ok((remaining, false))
             }
         }
@@ -183,14 +878,123 @@ impl VcdHeaderParser {
     }
 
     pub fn run<'a, E: ParseError<&'a str>>(&mut self, input: &'a str) -> IResult<&'a str, (), E> {
-        let mut w = input;
-        loop {
-            let (remaining, done) = self.next_header_command(w)?;
-            if done || remaining.is_empty() {
-                return Ok((remaining, ()));
+        on_text(input, |input| {
+            let mut w = input;
+            loop {
+                let (remaining, done) = self.next_header_command(w)?;
+                if done || remaining.is_empty() {
+                    return Ok((remaining, ()));
+                }
+                w = remaining;
+            }
+        })
+    }
+}
+
+/// Directives whose content is free text, where non-ASCII bytes are tolerated
+const TEXT_DIRECTIVES: [&[u8]; 3] = [b"comment", b"date", b"version"];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum TextState {
+    /// Between two commands
+    Normal,
+    Keyword,
+    /// In a command, up to its `$end`
+    Command,
+    Text,
+    /// After `$enddefinitions`
+    Body,
+}
+
+/// Follows the input across refills to know which bytes belong to free text directives
+/// (see `TEXT_DIRECTIVES`), and where the header ends.
+///
+/// In the header of an ASCII input, non-ASCII bytes found in text directives are replaced by
+/// `?`, anywhere else they are an error unless the input is read in lossy mode. The body is
+/// not checked: its tokens are decoded as they are parsed, and the text of its directives
+/// only when it is reported.
+#[derive(Debug)]
+struct TextFilter {
+    state: TextState,
+    /// The body may start without `$enddefinitions` (see
+    /// `VcdHeaderParser::set_allow_missing_enddefinitions`)
+    implicit_end: bool,
+    keyword: Vec<u8>,
+    // Number of bytes of "$end" matched so far in a text directive
+    end_match: usize,
+}
+
+impl TextFilter {
+    fn new() -> Self {
+        TextFilter {
+            state: TextState::Normal,
+            implicit_end: false,
+            keyword: Vec::with_capacity(16),
+            end_match: 0,
+        }
+    }
+
+    /// Filters new input bytes, returns how many of them belong to the header, or None if a
+    /// non-ASCII byte is found outside text (they are replaced too when `lossy` is set).
+    /// Without `ascii`, only follows the input.
+    fn filter(&mut self, data: &mut [u8], ascii: bool, lossy: bool) -> Option<usize> {
+        const END: &[u8] = b"$end";
+        for (i, c) in data.iter_mut().enumerate() {
+            // Same condition as the end of a header without `$enddefinitions`
+            let body_start = self.state == TextState::Normal
+                && self.implicit_end
+                && *c != b'$'
+                && !c.is_ascii_whitespace();
+            if body_start {
+                self.state = TextState::Body;
+            }
+            if self.state == TextState::Body {
+                return Some(i);
+            }
+            if *c >= 128 && ascii {
+                match self.state {
+                    TextState::Text => *c = b'?',
+                    _ if lossy => *c = b'?',
+                    _ => return None,
+                }
+            }
+            match self.state {
+                TextState::Normal | TextState::Command => {
+                    if *c == b'$' {
+                        self.state = TextState::Keyword;
+                        self.keyword.clear();
+                    }
+                }
+                TextState::Keyword => {
+                    if c.is_ascii_alphanumeric() {
+                        self.keyword.push(*c);
+                    } else if self.keyword == b"enddefinitions"
+                        || (self.implicit_end && self.keyword.starts_with(b"dump"))
+                    {
+                        self.state = TextState::Body;
+                    } else if TEXT_DIRECTIVES.contains(&self.keyword.as_slice()) {
+                        self.state = TextState::Text;
+                        self.end_match = 0;
+                    } else if self.keyword == b"end" {
+                        self.state = TextState::Normal;
+                    } else {
+                        self.state = TextState::Command;
+                    }
+                }
+                TextState::Text => {
+                    if *c == END[self.end_match] {
+                        self.end_match += 1;
+                    } else {
+                        self.end_match = (*c == b'$') as usize;
+                    }
+                    if self.end_match == END.len() {
+                        self.state = TextState::Normal;
+                    }
+                }
+                TextState::Body => {}
             }
-            w = remaining;
         }
+        Some(data.len())
     }
 }
 
@@ -199,6 +1003,16 @@ struct VcdStreamParser<R> {
     buff: utils::Buffer<R>,
     chunk_size: usize,
     end_of_input: bool,
+    text_filter: TextFilter,
+    // Validate the input as UTF-8 instead of ASCII
+    utf8: bool,
+    // Trailing bytes of the header forming an incomplete UTF-8 character, checked with the
+    // next refill
+    incomplete: usize,
+    // Replace the bytes that can not be decoded by `?` instead of failing
+    lossy: bool,
+    // Only read from the input when no command can be parsed from the buffer
+    lazy: bool,
 }
 
 impl<R: Read> VcdStreamParser<R> {
@@ -207,11 +1021,16 @@ impl<R: Read> VcdStreamParser<R> {
             buff: utils::Buffer::with_capacity(2 * chunk_size, inner),
             chunk_size,
             end_of_input: false,
+            text_filter: TextFilter::new(),
+            utf8: false,
+            incomplete: 0,
+            lossy: false,
+            lazy: false,
         }
     }
 
     pub fn done(&self) -> bool {
-        self.end_of_input && self.buff.data().len() == 0
+        self.end_of_input && self.buff.data().is_empty()
     }
 
     pub fn trim_refill(&mut self) -> Result<usize, VcdError> {
@@ -224,6 +1043,67 @@ impl<R: Read> VcdStreamParser<R> {
         }
     }
 
+    /// Gives the location of a parsing error at the current position (other errors are
+    /// returned as is)
+    ///
+    /// The input is read ahead up to the end of the line for the excerpt.
+    fn locate(&mut self, e: VcdError, header: bool) -> VcdError {
+        if !matches!(e, VcdError::ParseError) {
+            return e;
+        }
+        const EXCERPT: usize = 64;
+        while !self.end_of_input
+            && self.buff.len() < EXCERPT
+            && !self.buff.data().contains(&b'\n')
+            && self.refill(false).is_ok()
+        {}
+        let rest = self.buff.data();
+        let line = &rest[..rest.len().min(EXCERPT)];
+        let line = line.split(|c| *c == b'\n').next().unwrap_or_default();
+        let (line_number, column) = self.buff.line_column();
+        VcdError::Syntax(SyntaxError {
+            position: self.buff.position(),
+            line: line_number,
+            column,
+            expected: match (header, rest.first()) {
+                (true, _) => Expected::HeaderCommand,
+                (false, None) => Expected::BodyCommand,
+                (false, Some(c)) => Expected::body_command(*c),
+            },
+            excerpt: String::from_utf8_lossy(line).trim_end().to_string(),
+        })
+    }
+
+    /// Whether a parsing error is caused by the end of the input cutting the last command:
+    /// the command needs more data, or no other line follows it
+    fn cut_by_end(&self, e: &VcdError) -> bool {
+        let rest = self.buff.data();
+        let end = rest.iter().rposition(|c| !c.is_ascii_whitespace());
+        self.end_of_input
+            && match e {
+                VcdError::MissingData => true,
+                VcdError::ParseError => !rest[..end.unwrap_or(0)].contains(&b'\n'),
+                _ => false,
+            }
+    }
+
+    /// Drops the rest of the input, which ends the body
+    fn discard_rest(&mut self) -> BodyTruncation {
+        let position = self.buff.position();
+        self.buff.consume(self.buff.len());
+        self.incomplete = 0;
+        BodyTruncation {
+            position,
+            discarded: self.buff.position() - position,
+        }
+    }
+
+    /// Ends the input at the current position, without reading further
+    fn close(&mut self) {
+        self.discard_rest();
+        self.end_of_input = true;
+    }
+
     /// Refills a chunk of data
     ///
     /// Returns the number of bytes read, returns 0 at the end of file
@@ -235,14 +1115,42 @@ impl<R: Read> VcdStreamParser<R> {
                 self.buff.refill(self.chunk_size).map_err(VcdError::from)
             }
         }?;
-        if self.buff.data().iter().rev().take(n).any(|c| *c >= 128) {
-            return Err(VcdError::Utf8Error);
+        let data = self.buff.data_mut();
+        let new = data.len().saturating_sub(n);
+        let header = self
+            .text_filter
+            .filter(&mut data[new..], !self.utf8, self.lossy)
+            .ok_or(VcdError::Utf8Error)?;
+        // The body is only checked to replace what can not be decoded in lossy mode
+        let end = if self.lossy { data.len() } else { new + header };
+        if self.utf8 {
+            let mut start = new.saturating_sub(self.incomplete);
+            self.incomplete = loop {
+                match str::from_utf8(&data[start..end]) {
+                    Ok(_) => break 0,
+                    Err(e) if e.error_len().is_none() && n > 0 => {
+                        break end - start - e.valid_up_to()
+                    }
+                    // Invalid sequence, or sequence cut by the end of the input
+                    Err(e) if self.lossy => {
+                        let invalid = start + e.valid_up_to();
+                        start = e.error_len().map_or(end, |k| invalid + k);
+                        data[invalid..start].fill(b'?');
+                    }
+                    Err(_) => return Err(VcdError::Utf8Error),
+                }
+            };
+        } else {
+            data[new + header..end]
+                .iter_mut()
+                .filter(|c| **c >= 128)
+                .for_each(|c| *c = b'?');
         }
         // eprintln!("info: refilling {} bytes", n);
         if n == 0 {
             self.end_of_input = true;
             if !trim {
-                self.buff.push(b'\n');
+                self.buff.push(b'\n')?;
             }
         }
         Ok(n)
@@ -250,20 +1158,17 @@ impl<R: Read> VcdStreamParser<R> {
 
     pub fn run_parser<T, F>(&mut self, mut f: F) -> Result<T, VcdError>
     where
-        F: FnMut(&str) -> Result<(usize, T), VcdError>,
+        F: FnMut(&[u8]) -> Result<(usize, T), VcdError>,
     {
         loop {
-            let s = unsafe {
-                // NOTE: we check on refill that any incoming data is made of **only** ASCII
-                // characters, thus the unchecked conversion is safe.
-                str::from_utf8_unchecked(self.buff.data())
-            };
-            // println!("info: buff({:3} unused) = {:?}", self.buff.unused(), s);
-            match f(s) {
+            let view = self.buff.len();
+            match f(self.buff.data()) {
                 Ok((n_remaining, v)) => {
-                    let consumed = self.buff.len() - n_remaining;
+                    let consumed = view - n_remaining;
                     self.buff.consume(consumed);
-                    if self.buff.len() == 0 {
+                    if self.lazy {
+                        // Refilled by the caller, when the next command is requested
+                    } else if self.buff.len() == 0 {
                         // We need to trim leading whitespaces between VCD commands
                         self.refill(true)?;
                     } else if !self.end_of_input && (self.buff.len() <= 256) {
@@ -286,88 +1191,1037 @@ impl<R: Read> VcdStreamParser<R> {
     }
 }
 
-pub struct VcdParser<R> {
-    buffer: VcdStreamParser<R>,
-    header_parser: VcdHeaderParser,
+/// Handling of timestamps lower than a previous one, found in some broken dumps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimestampPolicy {
+    /// Fail with `VcdError::NonMonotonicTime` (the default)
+    #[default]
+    Error,
+    /// Give the timestamps as they are found
+    Keep,
+    /// Skip the timestamps lower than the highest one seen, their changes are merged into
+    /// the current cycle
+    Clamp,
+    /// Hold back the given number of cycles to sort them by time (changes of cycles with
+    /// the same time are merged). Timestamps lower than a cycle already given out are still
+    /// an error.
+    Reorder(usize),
 }
 
-impl<R: Read> VcdParser<R> {
-    pub fn with_chunk_size(chunk_size: usize, inner: R) -> Self {
-        VcdParser {
-            buffer: VcdStreamParser::with_chunk_size(chunk_size, inner),
-            header_parser: VcdHeaderParser::new(),
-        }
-    }
+/// Short text of an owned command (id or value), kept inline up to 16 bytes: most ids and
+/// values of a dump are copied without allocating
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct CommandText(SmallVec<[u8; 16]>);
 
-    pub fn load_header(&mut self) -> Result<&VcdHeader, VcdError> {
-        type E<'a> = (&'a str, nom::error::ErrorKind);
-        let buffer = &mut self.buffer;
-        let header_parser = &mut self.header_parser;
-        loop {
-            let done = buffer.run_parser(|i| {
-                header_parser
-                    .next_header_command::<E>(i)
-                    .map_err(VcdError::from)
-                    .map(|(s, v)| (s.len(), v))
-            })?;
-            if done {
-                return Ok(&self.header_parser.header);
-            }
-        }
+impl CommandText {
+    pub fn as_str(&self) -> &str {
+        // Only built from text
+        str::from_utf8(&self.0).unwrap_or_default()
     }
+}
 
-    pub fn header(&self) -> Option<&VcdHeader> {
-        self.header_parser.header()
+impl From<&str> for CommandText {
+    fn from(text: &str) -> Self {
+        CommandText(SmallVec::from_slice(text.as_bytes()))
     }
+}
 
-    pub fn done(&self) -> bool {
-        self.buffer.done()
-    }
+impl std::ops::Deref for CommandText {
+    type Target = str;
 
-    pub fn process_vcd_commands<F>(&mut self, mut callback: F) -> Result<(), VcdError>
-    where
-        F: FnMut(VcdCommand) -> bool,
-    {
-        let mut should_stop = false;
-        if self.buffer.buff.len() == 0 {
-            let n = self.buffer.refill(true)?;
-            if n == 0 {
-                return Ok(());
-            }
-        }
-        while !should_stop && !self.buffer.done() {
-            self.buffer.run_parser(|i| {
-                let (s, cmd) = vcd_command::<(&str, nom::error::ErrorKind)>(i)?;
-                if callback(cmd) {
-                    should_stop = true;
-                }
-                Ok((s.len(), ()))
-            })?;
-        }
-        Ok(())
+    fn deref(&self) -> &str {
+        self.as_str()
     }
 }
 
-/// Parse whitespaces between VCD commands, this parser is **complete** (i.e., it succeeds on empty
-/// input)
-fn fill_ws1<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, &'a str, E> {
-    nom::character::complete::multispace1(input)
+impl std::fmt::Debug for CommandText {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{:?}", self.as_str())
+    }
 }
 
-fn number<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, i64, E> {
-    let (input, c) = opt(char('-'))(input)?;
-    let sign = if c.is_some() { -1 } else { 1 };
-    map_res(digit1, |r| i64::from_str(r))(input).map(|(r, x)| (r, sign * x))
+impl std::fmt::Display for CommandText {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.write_str(self.as_str())
+    }
 }
 
-fn var_width<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, i64, E> {
-    terminated(number, multispace0)(input)
+impl Serialize for CommandText {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
 }
 
-fn var_range<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Range, E> {
+impl<'de> Deserialize<'de> for CommandText {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(CommandText::from(
+            String::deserialize(deserializer)?.as_str(),
+        ))
+    }
+}
+
+/// Owned copy of a body command, which does not borrow the buffer of the parser, so that it
+/// can be queued or sent to another thread: commands given by `VcdParser::commands` and
+/// `process_owned_commands`, or held back by `TimestampPolicy::Reorder`. Value changes hold
+/// the id of their variable, then their value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OwnedVcdCommand {
+    Directive(String),
+    BeginDump(DumpSection),
+    EndDump(DumpSection),
+    VcdEnd,
+    SetCycle(u64),
+    Bit(CommandText, char),
+    Vector(CommandText, CommandText),
+    Real(CommandText, CommandText),
+    Port(CommandText, CommandText, u8, u8),
+    String(CommandText, CommandText),
+    Event(CommandText),
+    VcdClose(u64),
+}
+
+impl<'a> VcdCommand<'a> {
+    /// Copy of the command not borrowing the input
+    pub fn to_owned(&self) -> OwnedVcdCommand {
+        OwnedVcdCommand::new(self)
+    }
+}
+
+/// Command given out by the parser: borrowed from its buffer, or already owned (when held back
+/// by `TimestampPolicy::Reorder`)
+enum ParsedCommand<'a> {
+    Borrowed(VcdCommand<'a>),
+    Owned(OwnedVcdCommand),
+}
+
+impl OwnedVcdCommand {
+    fn new(cmd: &VcdCommand) -> Self {
+        match cmd {
+            VcdCommand::Directive(d) => OwnedVcdCommand::Directive(d.to_string()),
+            VcdCommand::BeginDump(d) => OwnedVcdCommand::BeginDump(*d),
+            VcdCommand::EndDump(d) => OwnedVcdCommand::EndDump(*d),
+            VcdCommand::VcdEnd => OwnedVcdCommand::VcdEnd,
+            VcdCommand::SetCycle(t) => OwnedVcdCommand::SetCycle(*t),
+            VcdCommand::VcdClose(t) => OwnedVcdCommand::VcdClose(*t),
+            VcdCommand::ValueChange(c) => {
+                let id = CommandText::from(c.var_id);
+                match c.value {
+                    VcdValue::Bit(b) => OwnedVcdCommand::Bit(id, b),
+                    VcdValue::Vector(x) => OwnedVcdCommand::Vector(id, x.into()),
+                    VcdValue::Real(x) => OwnedVcdCommand::Real(id, x.into()),
+                    VcdValue::Port(p) => {
+                        OwnedVcdCommand::Port(id, p.states.into(), p.strength0, p.strength1)
+                    }
+                    VcdValue::String(x) => OwnedVcdCommand::String(id, x.into()),
+                    VcdValue::Event => OwnedVcdCommand::Event(id),
+                }
+            }
+        }
+    }
+
+    /// The command, borrowing its text from `self`
+    pub fn command(&self) -> VcdCommand<'_> {
+        let change = |var_id, value| VcdCommand::ValueChange(VcdChange { var_id, value });
+        match self {
+            OwnedVcdCommand::Directive(d) => VcdCommand::Directive(d),
+            OwnedVcdCommand::BeginDump(d) => VcdCommand::BeginDump(*d),
+            OwnedVcdCommand::EndDump(d) => VcdCommand::EndDump(*d),
+            OwnedVcdCommand::VcdEnd => VcdCommand::VcdEnd,
+            OwnedVcdCommand::SetCycle(t) => VcdCommand::SetCycle(*t),
+            OwnedVcdCommand::Bit(id, b) => change(id, VcdValue::Bit(*b)),
+            OwnedVcdCommand::Vector(id, x) => change(id, VcdValue::Vector(x)),
+            OwnedVcdCommand::Real(id, x) => change(id, VcdValue::Real(x)),
+            OwnedVcdCommand::Port(id, states, strength0, strength1) => {
+                let port = PortValue {
+                    states,
+                    strength0: *strength0,
+                    strength1: *strength1,
+                };
+                change(id, VcdValue::Port(port))
+            }
+            OwnedVcdCommand::String(id, x) => change(id, VcdValue::String(x)),
+            OwnedVcdCommand::Event(id) => change(id, VcdValue::Event),
+            OwnedVcdCommand::VcdClose(t) => VcdCommand::VcdClose(*t),
+        }
+    }
+}
+
+/// Cycles held back by `TimestampPolicy::Reorder`, as saved by checkpoints
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReorderBuffer {
+    window: usize,
+    /// Cycles not given out yet, sorted by time
+    cycles: VecDeque<(u64, Vec<OwnedVcdCommand>)>,
+    /// Commands ready to be given to the callback of `process_vcd_commands`
+    ready: VecDeque<OwnedVcdCommand>,
+    /// Time of the cycle receiving the changes
+    current: Option<u64>,
+    /// Time of the last cycle given out
+    released: Option<u64>,
+}
+
+impl ReorderBuffer {
+    /// Holds a command back, fails with the time of the last cycle given out when the
+    /// command is a timestamp lower than it
+    fn push(&mut self, cmd: &VcdCommand) -> Result<(), u64> {
+        let time = match cmd {
+            VcdCommand::SetCycle(t) => *t,
+            _ => {
+                let cycle = self
+                    .current
+                    .and_then(|t| self.cycles.binary_search_by_key(&t, |c| c.0).ok());
+                match cycle {
+                    Some(i) => self.cycles[i].1.push(OwnedVcdCommand::new(cmd)),
+                    None => self.ready.push_back(OwnedVcdCommand::new(cmd)),
+                }
+                return Ok(());
+            }
+        };
+        match self.released {
+            Some(r) if time < r => return Err(r),
+            _ => {}
+        }
+        if let Err(i) = self.cycles.binary_search_by_key(&time, |c| c.0) {
+            self.cycles.insert(i, (time, Vec::new()));
+        }
+        self.current = Some(time);
+        while self.cycles.len() > self.window {
+            self.release();
+        }
+        Ok(())
+    }
+
+    /// Gives out the earliest cycle
+    fn release(&mut self) {
+        if let Some((t, commands)) = self.cycles.pop_front() {
+            self.ready.push_back(OwnedVcdCommand::SetCycle(t));
+            self.ready.extend(commands);
+            self.released = Some(t);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.cycles.is_empty() && self.ready.is_empty()
+    }
+}
+
+/// Options of a `VcdParser` set by its `set_*` methods (the directive hook excepted), to
+/// configure another parser the same way (see `VcdParser::options` and `apply_options`)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParserOptions {
+    pub utf8: bool,
+    pub lossy: bool,
+    pub lazy_input: bool,
+    pub strict: bool,
+    pub allow_missing_enddefinitions: bool,
+    pub allow_truncation: bool,
+    pub timestamp_policy: TimestampPolicy,
+    pub change_budget: Option<u64>,
+    pub memory_limit: Option<usize>,
+}
+
+pub struct VcdParser<R> {
+    buffer: VcdStreamParser<R>,
+    header_parser: VcdHeaderParser,
+    close_time: Option<u64>,
+    timestamp_policy: TimestampPolicy,
+    /// Highest timestamp seen
+    last_time: Option<u64>,
+    reorder: ReorderBuffer,
+    /// Dump section waiting for its `$end`
+    dump_section: Option<DumpSection>,
+    /// Value changes given to `process_vcd_commands` so far, and how many may be given
+    change_count: u64,
+    change_budget: Option<u64>,
+    /// Ids of the event variables, built from the header on the first call to
+    /// `process_vcd_commands`
+    event_ids: Option<HashSet<String>>,
+    /// Whether a body cut in the middle of a command ends the input (see
+    /// `set_allow_truncation`), and where it was cut
+    allow_truncation: bool,
+    truncation: Option<BodyTruncation>,
+    /// Timestamp read by `decode_section`, opening the next section
+    next_section: Option<u64>,
+}
+
+/// Iterator over the commands of the body, given by `VcdParser::commands`.
+///
+/// Each call to `next` parses a single command, nothing is read ahead: the iterator can be
+/// dropped at any point (e.g., after `take_while`), the parser goes on with the next
+/// command. The iterator ends after the first error.
+pub struct VcdCommands<'p, R> {
+    parser: &'p mut VcdParser<R>,
+    failed: bool,
+}
+
+impl<R: Read> Iterator for VcdCommands<'_, R> {
+    type Item = Result<OwnedVcdCommand, VcdError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Commands dropped by the parser (e.g., clamped timestamps) give nothing
+        while !self.failed && !self.parser.done() {
+            let mut next = None;
+            let result = self
+                .parser
+                .process_owned_commands(|cmd| -> Result<bool, VcdError> {
+                    next = Some(cmd);
+                    Ok(true)
+                });
+            match (result, next) {
+                (_, Some(cmd)) => return Some(Ok(cmd)),
+                (Err(e), None) => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+                (Ok(()), None) => {}
+            }
+        }
+        None
+    }
+}
+
+/// Input of a parser in push mode: data is given by the caller as it arrives (e.g., chunks
+/// of a browser stream) instead of being read from a blocking source.
+///
+/// Reading returns an `io::ErrorKind::WouldBlock` error when all the data given so far was
+/// read, until `finish` marks the end of the input.
+#[derive(Debug, Default)]
+pub struct PushInput {
+    data: VecDeque<u8>,
+    finished: bool,
+}
+
+impl PushInput {
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.data.extend(bytes);
+    }
+
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Number of bytes given and not read yet
+    pub fn pending(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl Read for PushInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.data.is_empty() && !self.finished {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.data.read(buf)
+    }
+}
+
+impl VcdParser<PushInput> {
+    /// Parser fed with `feed` instead of reading an input.
+    ///
+    /// Parsing methods (`load_header`, `process_vcd_commands`) consume the commands fully
+    /// received, and fail with `VcdError::MissingData` when they need more data, they can
+    /// be called again once more data is fed. Call `finish` after the last chunk.
+    pub fn push_mode(chunk_size: usize) -> Self {
+        let mut parser = Self::with_chunk_size(chunk_size, PushInput::default());
+        parser.set_lazy_input(true);
+        parser
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.buff.get_mut().feed(bytes);
+    }
+
+    /// Marks the end of the input
+    pub fn finish(&mut self) {
+        self.buffer.buff.get_mut().finish();
+    }
+}
+
+impl<R: Read> VcdParser<R> {
+    pub fn with_chunk_size(chunk_size: usize, inner: R) -> Self {
+        VcdParser {
+            buffer: VcdStreamParser::with_chunk_size(chunk_size, inner),
+            header_parser: VcdHeaderParser::new(),
+            close_time: None,
+            timestamp_policy: TimestampPolicy::Error,
+            last_time: None,
+            reorder: ReorderBuffer::default(),
+            dump_section: None,
+            change_count: 0,
+            change_budget: None,
+            event_ids: None,
+            allow_truncation: false,
+            truncation: None,
+            next_section: None,
+        }
+    }
+
+    /// Builds a parser over an input positioned right after the header (or anywhere in the
+    /// value change section, between two commands), using an already parsed header
+    pub fn with_header(chunk_size: usize, inner: R, header: VcdHeader) -> Self {
+        let mut parser = Self::with_chunk_size(chunk_size, inner);
+        parser.header_parser.header = header;
+        parser.header_parser.header_valid = true;
+        parser.buffer.text_filter.state = TextState::Body;
+        parser
+    }
+
+    pub fn options(&self) -> ParserOptions {
+        ParserOptions {
+            utf8: self.buffer.utf8,
+            lossy: self.buffer.lossy,
+            lazy_input: self.buffer.lazy,
+            strict: self.header_parser.strict,
+            allow_missing_enddefinitions: self.header_parser.implicit_end,
+            allow_truncation: self.allow_truncation,
+            timestamp_policy: self.timestamp_policy,
+            change_budget: self.change_budget,
+            memory_limit: self.buffer.buff.limit(),
+        }
+    }
+
+    /// Sets all the options given by `options` (e.g., of another parser)
+    pub fn apply_options(&mut self, options: &ParserOptions) {
+        self.set_utf8(options.utf8);
+        self.set_lossy(options.lossy);
+        self.set_lazy_input(options.lazy_input);
+        self.set_strict(options.strict);
+        self.set_allow_missing_enddefinitions(options.allow_missing_enddefinitions);
+        self.set_allow_truncation(options.allow_truncation);
+        self.set_timestamp_policy(options.timestamp_policy);
+        self.set_change_budget(options.change_budget);
+        self.set_memory_limit(options.memory_limit);
+    }
+
+    /// Removes the hook set by `set_directive_hook`, e.g., to move it to another parser
+    pub fn take_directive_hook(&mut self) -> Option<DirectiveHook> {
+        self.header_parser.directive_hook.take()
+    }
+
+    /// Number of bytes of the input consumed so far, at a command boundary this is where
+    /// parsing can be resumed (see `with_header`)
+    pub fn position(&self) -> u64 {
+        self.buffer.buff.position()
+    }
+
+    pub fn utf8(&self) -> bool {
+        self.buffer.utf8
+    }
+
+    pub fn load_header(&mut self) -> Result<&VcdHeader, VcdError> {
+        let buffer = &mut self.buffer;
+        let header_parser = &mut self.header_parser;
+        loop {
+            if buffer.lazy && buffer.buff.len() == 0 {
+                buffer.refill(true)?;
+            }
+            let done = buffer
+                .run_parser(|i| {
+                    header_parser
+                        .next_header_command::<ByteError>(i)
+                        .map_err(VcdError::from)
+                        .map(|(s, v)| (s.len(), v))
+                })
+                .or_else(|e| match (header_parser.take_violation(), e) {
+                    (Some(v), _) => Err(VcdError::InvalidHeader(v)),
+                    (None, VcdError::MissingData)
+                        if buffer.end_of_input
+                            && header_parser.implicit_end
+                            && buffer.buff.data().iter().all(|c| c.is_ascii_whitespace()) =>
+                    {
+                        header_parser
+                            .end_definitions::<ByteError>(&[])
+                            .map_err(VcdError::from)?;
+                        buffer.buff.trim();
+                        Ok(true)
+                    }
+                    (None, VcdError::MissingData) if buffer.end_of_input => {
+                        Err(VcdError::TruncatedHeader(header_parser.truncation(buffer)))
+                    }
+                    (None, e) => Err(buffer.locate(e, true)),
+                })?;
+            if done {
+                // The header may end before `$enddefinitions`
+                buffer.text_filter.state = TextState::Body;
+                return Ok(&self.header_parser.header);
+            }
+        }
+    }
+
+    /// Parses the header and stops right after `$enddefinitions`, for tools only needing
+    /// the signals of dumps (e.g., to index many files). The body is neither parsed nor read
+    /// further than the chunk holding the end of the header (see `skip_body`), which also
+    /// works for inputs that can not be seeked (pipes, decompressed streams).
+    pub fn parse_header_only(&mut self) -> Result<&VcdHeader, VcdError> {
+        self.load_header()?;
+        self.skip_body();
+        Ok(&self.header_parser.header)
+    }
+
+    /// Ends the input without reading the rest of it: the data already read is dropped and
+    /// the parser is done, `process_vcd_commands` gives no more commands
+    pub fn skip_body(&mut self) {
+        self.buffer.close();
+    }
+
+    pub fn header(&self) -> Option<&VcdHeader> {
+        self.header_parser.header()
+    }
+
+    /// Header parsed so far, even if `$enddefinitions` was not reached, e.g., the variables
+    /// and metadata found before a `VcdError::TruncatedHeader`
+    pub fn partial_header(&self) -> &VcdHeader {
+        &self.header_parser.header
+    }
+
+    /// Calls `hook` with the keyword and text of the directives that are neither standard
+    /// nor known vendor extensions (e.g., `$attrbegin` from GTKWave, `$timezero` from
+    /// ModelSim), in the header and in the body. They are skipped up to
+    /// their `$end`, and given as `VcdCommand::Directive` to `process_vcd_commands` in the
+    /// body.
+    pub fn set_directive_hook(&mut self, hook: DirectiveHook) {
+        self.header_parser.directive_hook = Some(hook);
+    }
+
+    /// Final time given by a `$vcdclose` directive (see `VcdCommand::VcdClose`), once
+    /// parsed
+    pub fn close_time(&self) -> Option<u64> {
+        self.close_time
+    }
+
+    /// Checks the header against the standard: identifiers made of printable ASCII
+    /// characters (33 to 126), positive widths and balanced scopes. Violations are
+    /// reported by `load_header` as `VcdError::InvalidHeader`.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.header_parser.set_strict(strict);
+    }
+
+    /// Accepts headers without `$enddefinitions` (see
+    /// `VcdHeaderParser::set_allow_missing_enddefinitions`): `load_header` then stops before
+    /// the first command of the body, or at the end of the input, instead of failing with
+    /// `VcdError::TruncatedHeader` or a syntax error.
+    pub fn set_allow_missing_enddefinitions(&mut self, enable: bool) {
+        self.header_parser.set_allow_missing_enddefinitions(enable);
+        self.buffer.text_filter.implicit_end = enable;
+    }
+
+    /// Accepts UTF-8 input (e.g., in scope or variable names) instead of ASCII only, must be
+    /// called before `load_header`.
+    ///
+    /// Text directives are then kept as is, instead of having non-ASCII bytes replaced, but
+    /// any invalid UTF-8 sequence in the header is an error (the body is always decoded
+    /// token by token).
+    pub fn set_utf8(&mut self, enable: bool) {
+        self.buffer.utf8 = enable;
+    }
+
+    /// Replaces the bytes that can not be decoded by `?` instead of failing with
+    /// `VcdError::Utf8Error`, e.g., for Latin-1 text written by some vendor tools. Must be
+    /// called before `load_header`.
+    ///
+    /// In ASCII mode, non-ASCII bytes found out of the text directives of the header are
+    /// replaced (they already are in text directives), in the body too. In UTF-8 mode (see
+    /// `set_utf8`), valid UTF-8 is kept as is and each invalid sequence is replaced.
+    pub fn set_lossy(&mut self, enable: bool) {
+        self.buffer.lossy = enable;
+    }
+
+    pub fn lossy(&self) -> bool {
+        self.buffer.lossy
+    }
+
+    /// Only reads from the input when the buffered data holds no complete command, instead
+    /// of reading ahead.
+    ///
+    /// Meant for inputs blocking until more data is available (e.g., a file followed while
+    /// a simulator writes it), so that the commands already received are processed without
+    /// waiting for the next ones.
+    pub fn set_lazy_input(&mut self, enable: bool) {
+        self.buffer.lazy = enable;
+    }
+
+    /// Limits the size of the internal input buffer (in bytes), parsing a command that
+    /// does not fit in this budget fails with `VcdError::MemoryLimit`.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.buffer.buff.set_limit(limit);
+    }
+
+    /// Number of bytes currently allocated for the input buffer
+    pub fn memory_usage(&self) -> usize {
+        self.buffer.buff.allocated()
+    }
+
+    /// Sets the handling of timestamps lower than a previous one (by default, an error)
+    pub fn set_timestamp_policy(&mut self, policy: TimestampPolicy) {
+        self.timestamp_policy = policy;
+        if let TimestampPolicy::Reorder(window) = policy {
+            self.reorder.window = window;
+        }
+    }
+
+    pub fn timestamp_policy(&self) -> TimestampPolicy {
+        self.timestamp_policy
+    }
+
+    /// Highest timestamp seen and cycles held back, for checkpoints (see
+    /// `StateSimulation::checkpoint`)
+    pub(crate) fn timestamp_state(&self) -> (Option<u64>, ReorderBuffer) {
+        (self.last_time, self.reorder.clone())
+    }
+
+    pub(crate) fn restore_timestamp_state(
+        &mut self,
+        last_time: Option<u64>,
+        reorder: ReorderBuffer,
+    ) {
+        self.last_time = last_time;
+        self.reorder = reorder;
+    }
+
+    /// Limits the body to its first `budget` value changes (None, the default, removes
+    /// the limit): once they are given by `process_vcd_commands`, the parser is done and
+    /// the rest of the input is not read. Meant to sample the start of huge dumps.
+    pub fn set_change_budget(&mut self, budget: Option<u64>) {
+        self.change_budget = budget;
+    }
+
+    pub fn change_budget(&self) -> Option<u64> {
+        self.change_budget
+    }
+
+    /// Continues the count of value changes of a checkpointed parser (see
+    /// `StateSimulation::resume`)
+    pub(crate) fn restore_change_count(&mut self, count: u64, budget: Option<u64>) {
+        self.change_count = count;
+        self.change_budget = budget;
+    }
+
+    /// Ends the body at the last complete command when the input stops in the middle of
+    /// one (e.g., the dump of a crashed simulation), instead of failing with
+    /// `VcdError::MissingData`. A last line that can not be parsed is also taken as cut.
+    /// Where the input was cut is then given by `truncation`.
+    pub fn set_allow_truncation(&mut self, enable: bool) {
+        self.allow_truncation = enable;
+    }
+
+    pub fn allow_truncation(&self) -> bool {
+        self.allow_truncation
+    }
+
+    /// Where the body was cut, when truncation is allowed (see `set_allow_truncation`) and
+    /// the input ended in the middle of a command
+    pub fn truncation(&self) -> Option<BodyTruncation> {
+        self.truncation
+    }
+
+    /// Number of value changes given by `process_vcd_commands` so far
+    pub fn change_count(&self) -> u64 {
+        self.change_count
+    }
+
+    /// Whether the change budget (see `set_change_budget`) has been spent
+    pub fn budget_exhausted(&self) -> bool {
+        self.change_budget
+            .is_some_and(|budget| self.change_count >= budget)
+    }
+
+    pub fn done(&self) -> bool {
+        (self.buffer.done() && self.reorder.is_empty() && self.next_section.is_none())
+            || self.budget_exhausted()
+    }
+
+    /// Gives the commands of the body to `callback` (see `process_vcd_commands`) until `n`
+    /// more value changes have been given or the end of the input, returns the number of
+    /// value changes given
+    pub fn process_first_n_changes<F, E>(&mut self, n: u64, mut callback: F) -> Result<u64, E>
+    where
+        F: FnMut(VcdCommand) -> Result<bool, E>,
+        E: From<VcdError>,
+    {
+        let (start, budget) = (self.change_count, self.change_budget);
+        self.change_budget = Some(start.saturating_add(n));
+        let mut stopped = false;
+        let mut result = Ok(());
+        while !self.done() && !stopped && result.is_ok() {
+            result = self.process_vcd_commands(|cmd| {
+                stopped = callback(cmd)?;
+                Ok(stopped)
+            });
+        }
+        self.change_budget = budget;
+        result.map(|()| self.change_count - start)
+    }
+
+    /// Reads the rest of the stream and returns the value changes of a single variable, as
+    /// (time, value) pairs. Values are kept as written in the file (vectors are not
+    /// extended), no simulation state is built.
+    pub fn changes_of(&mut self, var_id: &str) -> Result<Vec<(u64, String)>, VcdError> {
+        let header = self.header().ok_or(VcdError::PartialHeader)?;
+        if !header.variables.iter().any(|v| v.id == var_id) {
+            return Err(VcdError::UnknownVariable);
+        }
+        let mut changes = Vec::new();
+        let mut time = 0;
+        while !self.done() {
+            self.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+                match cmd {
+                    VcdCommand::SetCycle(c) => time = c,
+                    VcdCommand::ValueChange(v) if v.var_id == var_id => {
+                        let value = match v.value {
+                            VcdValue::Bit(c) => c.to_string(),
+                            VcdValue::Vector(x) | VcdValue::Real(x) | VcdValue::String(x) => {
+                                x.to_string()
+                            }
+                            VcdValue::Port(p) => p.states.to_string(),
+                            VcdValue::Event => "1".to_string(),
+                        };
+                        changes.push((time, value));
+                    }
+                    _ => {}
+                }
+                Ok(false)
+            })?;
+        }
+        Ok(changes)
+    }
+
+    /// Iterator over the rest of the body (see `VcdCommands`), an alternative to the callback
+    /// of `process_vcd_commands`. Commands are owned, the borrowed form being given by
+    /// `OwnedVcdCommand::command`.
+    pub fn commands(&mut self) -> VcdCommands<'_, R> {
+        VcdCommands {
+            parser: self,
+            failed: false,
+        }
+    }
+
+    /// Decodes the value changes of the next `#time` section into `batch`, cleared first.
+    /// Reusing the same batch for all the sections amortizes the cost of each change (no
+    /// allocation once the buffers are large enough), for bulk extraction of the changes.
+    /// Returns false, with an empty batch, once the body has no section left.
+    ///
+    /// Runs of value changes are decoded straight from the input buffer, other commands
+    /// go through `process_vcd_commands`. The timestamp opening the next section is read
+    /// ahead: it is given by the next call, or by `process_vcd_commands` and `commands`.
+    pub fn decode_section(&mut self, batch: &mut ChangeBatch) -> Result<bool, VcdError> {
+        batch.clear();
+        batch.time = self.next_section.take();
+        // Reordered commands are only given through the reorder buffer
+        let direct = !matches!(self.timestamp_policy, TimestampPolicy::Reorder(_));
+        while !self.done() {
+            if direct {
+                self.decode_changes(batch)?;
+                if self.done() {
+                    break;
+                }
+            }
+            let mut next = None;
+            self.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+                match cmd {
+                    VcdCommand::SetCycle(t) if batch.time.is_none() && batch.is_empty() => {
+                        batch.time = Some(t)
+                    }
+                    VcdCommand::SetCycle(t) => {
+                        next = Some(t);
+                        return Ok(true);
+                    }
+                    VcdCommand::ValueChange(change) => batch.push(&change),
+                    _ => {}
+                }
+                // Back to `decode_changes` after each command
+                Ok(direct)
+            })?;
+            if next.is_some() {
+                self.next_section = next;
+                break;
+            }
+        }
+        Ok(batch.time.is_some() || !batch.is_empty())
+    }
+
+    /// Decodes the value changes found at the start of the buffer into `batch`, in a
+    /// single run of the parser, up to another command, a change cut by the end of the
+    /// buffer or the change budget. Returns the number of changes decoded.
+    fn decode_changes(&mut self, batch: &mut ChangeBatch) -> Result<usize, VcdError> {
+        self.load_event_ids();
+        if self.buffer.buff.len() == 0 && self.buffer.refill(true)? == 0 {
+            return Ok(0);
+        }
+        let event_ids = &self.event_ids;
+        let budget = self
+            .change_budget
+            .map_or(usize::MAX, |b| b.saturating_sub(self.change_count) as usize);
+        let result = self.buffer.run_parser(|i| {
+            let mut rest = i;
+            let mut n = 0;
+            while n < budget {
+                let parsed = match scalar_or_vector_change(rest) {
+                    Some(parsed) => Ok(parsed),
+                    None => vcd_change::<ByteError>(rest),
+                };
+                match parsed {
+                    Ok((r, mut change)) => {
+                        if change.value == VcdValue::Bit('1')
+                            && event_ids
+                                .as_ref()
+                                .is_some_and(|e| e.contains(change.var_id))
+                        {
+                            change.value = VcdValue::Event;
+                        }
+                        batch.push(&change);
+                        rest = r;
+                        n += 1;
+                    }
+                    // Refilled by `run_parser`
+                    Err(nom::Err::Incomplete(_)) if n == 0 => return Err(VcdError::MissingData),
+                    // Left to `process_vcd_commands`
+                    Err(_) => break,
+                }
+            }
+            Ok((rest.len(), n))
+        });
+        let n = match result {
+            Ok(n) => n,
+            // The end of the input is handled by `process_vcd_commands`
+            Err(VcdError::MissingData) => 0,
+            Err(e) => return Err(e),
+        };
+        self.change_count += n as u64;
+        Ok(n)
+    }
+
+    /// Ids of the event variables, whose changes are given as `VcdValue::Event`
+    fn load_event_ids(&mut self) {
+        if self.event_ids.is_none() {
+            if let Some(header) = self.header() {
+                let events = header
+                    .variables
+                    .iter()
+                    .filter(|v| v.kind == VariableKind::VcdEvent);
+                self.event_ids = Some(events.map(|v| v.id.clone()).collect());
+            }
+        }
+    }
+
+    /// Parses the next commands of the body and gives them to `callback`, until the end of
+    /// the input or of the current chunk. The callback returns whether parsing should stop
+    /// (parsing can be resumed by a later call), an error it returns stops parsing and is
+    /// returned as is, parsing errors are converted into the error type of the callback.
+    pub fn process_vcd_commands<F, E>(&mut self, mut callback: F) -> Result<(), E>
+    where
+        F: FnMut(VcdCommand) -> Result<bool, E>,
+        E: From<VcdError>,
+    {
+        self.process_commands(|cmd| match cmd {
+            ParsedCommand::Borrowed(cmd) => callback(cmd),
+            ParsedCommand::Owned(cmd) => callback(cmd.command()),
+        })
+    }
+
+    /// Same as `process_vcd_commands`, giving owned commands (e.g., to send them to other
+    /// threads). Commands held back by `TimestampPolicy::Reorder` are already owned, they
+    /// are given as is instead of being copied again.
+    pub fn process_owned_commands<F, E>(&mut self, mut callback: F) -> Result<(), E>
+    where
+        F: FnMut(OwnedVcdCommand) -> Result<bool, E>,
+        E: From<VcdError>,
+    {
+        self.process_commands(|cmd| match cmd {
+            ParsedCommand::Borrowed(cmd) => callback(cmd.to_owned()),
+            ParsedCommand::Owned(cmd) => callback(cmd),
+        })
+    }
+
+    fn process_commands<F, E>(&mut self, mut callback: F) -> Result<(), E>
+    where
+        F: FnMut(ParsedCommand) -> Result<bool, E>,
+        E: From<VcdError>,
+    {
+        let mut should_stop = false;
+        let mut user_error = None;
+        let reorder = matches!(self.timestamp_policy, TimestampPolicy::Reorder(_));
+        self.load_event_ids();
+        // Timestamp read ahead by `decode_section`
+        if let Some(t) = self.next_section.take() {
+            should_stop = callback(ParsedCommand::Borrowed(VcdCommand::SetCycle(t)))?;
+        }
+        loop {
+            if reorder {
+                if self.buffer.done() {
+                    while !self.reorder.cycles.is_empty() {
+                        self.reorder.release();
+                    }
+                }
+                while !should_stop {
+                    let cmd = match self.reorder.ready.pop_front() {
+                        Some(c) => c,
+                        None => break,
+                    };
+                    if let OwnedVcdCommand::Bit(..)
+                    | OwnedVcdCommand::Vector(..)
+                    | OwnedVcdCommand::Real(..)
+                    | OwnedVcdCommand::Port(..)
+                    | OwnedVcdCommand::String(..)
+                    | OwnedVcdCommand::Event(..) = cmd
+                    {
+                        self.change_count += 1;
+                    }
+                    match callback(ParsedCommand::Owned(cmd)) {
+                        Ok(stop) => should_stop = stop || self.budget_exhausted(),
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            if should_stop || self.buffer.done() || self.budget_exhausted() {
+                break;
+            }
+            if self.buffer.buff.len() == 0 && self.buffer.refill(true)? == 0 {
+                if reorder && self.buffer.done() {
+                    continue;
+                }
+                return Ok(());
+            }
+            let header_parser = &mut self.header_parser;
+            let close_time = &mut self.close_time;
+            let policy = self.timestamp_policy;
+            let last_time = &mut self.last_time;
+            let reorder_buffer = &mut self.reorder;
+            let dump_section = &mut self.dump_section;
+            let change_count = &mut self.change_count;
+            let change_budget = self.change_budget;
+            let event_ids = &self.event_ids;
+            let mut violation = None;
+            let result = self.buffer.run_parser(|i| {
+                let (s, (cmd, text)) = body_command::<ByteError>(i)?;
+                let cmd = close_dump_section(dump_section, cmd);
+                let cmd = match cmd {
+                    VcdCommand::ValueChange(VcdChange {
+                        var_id,
+                        value: VcdValue::Bit('1'),
+                    }) if event_ids.as_ref().is_some_and(|e| e.contains(var_id)) => {
+                        VcdCommand::ValueChange(VcdChange {
+                            var_id,
+                            value: VcdValue::Event,
+                        })
+                    }
+                    cmd => cmd,
+                };
+                if let (VcdCommand::Directive(keyword), Some(text)) = (&cmd, text) {
+                    header_parser.unknown_directive(keyword, &String::from_utf8_lossy(text));
+                }
+                if let VcdCommand::VcdClose(t) = cmd {
+                    *close_time = Some(t);
+                }
+                if let VcdCommand::SetCycle(t) = cmd {
+                    match *last_time {
+                        Some(previous) if t < previous => match policy {
+                            TimestampPolicy::Error => {
+                                violation = Some((t, previous));
+                                return Ok((s.len(), ()));
+                            }
+                            TimestampPolicy::Clamp => return Ok((s.len(), ())),
+                            TimestampPolicy::Keep | TimestampPolicy::Reorder(_) => {}
+                        },
+                        _ => *last_time = Some(t),
+                    }
+                }
+                if reorder {
+                    if let Err(previous) = reorder_buffer.push(&cmd) {
+                        if let VcdCommand::SetCycle(t) = cmd {
+                            violation = Some((t, previous));
+                        }
+                    }
+                    return Ok((s.len(), ()));
+                }
+                if let VcdCommand::ValueChange(_) = cmd {
+                    *change_count += 1;
+                }
+                match callback(ParsedCommand::Borrowed(cmd)) {
+                    Ok(stop) => {
+                        should_stop = stop || change_budget.is_some_and(|b| *change_count >= b)
+                    }
+                    Err(e) => {
+                        user_error = Some(e);
+                        should_stop = true;
+                    }
+                }
+                Ok((s.len(), ()))
+            });
+            match result {
+                Err(e) if self.allow_truncation && self.buffer.cut_by_end(&e) => {
+                    self.truncation = Some(self.buffer.discard_rest());
+                }
+                r => r.map_err(|e| self.buffer.locate(e, false))?,
+            }
+            if let Some((time, previous)) = violation {
+                return Err(VcdError::NonMonotonicTime {
+                    time,
+                    previous,
+                    position: self.position(),
+                }
+                .into());
+            }
+            if user_error.is_some() {
+                break;
+            }
+        }
+        match user_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Error of the parsers run on the input buffer
+type ByteError<'a> = (&'a [u8], ErrorKind);
+
+/// Runs a parser of bytes on text, for the parsers taking `&str` input
+fn on_text<'a, O, E, F>(input: &'a str, mut parser: F) -> IResult<&'a str, O, E>
+where
+    E: ParseError<&'a str>,
+    F: FnMut(&'a [u8]) -> IResult<&'a [u8], O, ByteError<'a>>,
+{
+    let rest = |r: &'a [u8]| input.get(input.len() - r.len()..).unwrap_or_default();
+    match parser(input.as_bytes()) {
+        Ok((r, o)) => Ok((rest(r), o)),
+        Err(nom::Err::Incomplete(n)) => Err(nom::Err::Incomplete(n)),
+        Err(nom::Err::Error((r, kind))) => Err(nom::Err::Error(E::from_error_kind(rest(r), kind))),
+        Err(nom::Err::Failure((r, kind))) => {
+            Err(nom::Err::Failure(E::from_error_kind(rest(r), kind)))
+        }
+    }
+}
+
+/// Decodes the token recognized by a parser: tokens are ASCII, or UTF-8 in identifiers and
+/// names (only the header is checked when it is read, see `TextFilter`)
+fn utf8<'a, E, F>(parser: F) -> impl Fn(&'a [u8]) -> IResult<&'a [u8], &'a str, E>
+where
+    E: ParseError<&'a [u8]>,
+    F: Fn(&'a [u8]) -> IResult<&'a [u8], &'a [u8], E>,
+{
+    map_res(parser, str::from_utf8)
+}
+
+/// Parse whitespaces between VCD commands, this parser is **complete** (i.e., it succeeds on empty
+/// input)
+fn fill_ws1<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], &'a [u8], E> {
+    nom::character::complete::multispace1(input)
+}
+
+fn number<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], i64, E> {
+    let (input, c) = opt(char('-'))(input)?;
+    let sign = if c.is_some() { -1 } else { 1 };
+    map_res(utf8(digit1), i64::from_str)(input).map(|(r, x)| (r, sign * x))
+}
+
+fn var_width<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], i64, E> {
+    terminated(number, multispace0)(input)
+}
+
+fn var_range<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], Range, E> {
     let dual_range = map(
         separated_pair(var_width, terminated(char(':'), multispace0), var_width),
-        |r| Range::Range(r),
+        Range::Range,
     );
     let simple_range = map(var_width, |w| {
         assert!(w >= 0);
@@ -380,86 +2234,201 @@ fn var_range<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Ran
     )(input)
 }
 
-fn var_name<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, &str, E> {
+fn var_name<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], &'a str, E> {
     none_of("$")(input)?;
     terminated(
-        take_till1(|c: char| c.is_whitespace() || c == '['),
+        utf8(take_till1(|c: u8| c.is_ascii_whitespace() || c == b'[')),
         multispace0,
     )(input)
 }
 
 /// Any non whitespace stuff inside commands
-fn vcd_word<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, &'a str, E> {
+fn vcd_word<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], &'a str, E> {
     // FIXME: confirm that commenting this doesn't hurts
     // none_of("$")(input)?;
-    terminated(take_till1(|c: char| c.is_whitespace()), multispace1)(input)
+    terminated(
+        utf8(take_till1(|c: u8| c.is_ascii_whitespace())),
+        multispace1,
+    )(input)
 }
 
 /// Matches a VCD $end token
-fn vcd_end<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, &'a str, E> {
+fn vcd_end<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], &'a [u8], E> {
     const END_TAG: &str = "$end";
     terminated(tag(END_TAG), alt((fill_ws1, multispace1)))(input)
 }
 
 /// Ignores anything until a $end token is found
-fn skip_until_vcd_end<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, (), E> {
+fn skip_until_vcd_end<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], (), E> {
     let mut w = input;
     loop {
-        let (remaining, _) = take_till(|c: char| c == '$')(w)?;
+        let (remaining, _) = take_till(|c: u8| c == b'$')(w)?;
         let (remaining, v) = opt(vcd_end)(remaining)?;
-        if let Some(_) = v {
+        if v.is_some() {
             return Ok((remaining, ()));
         }
         let (remaining, _) = take(1usize)(remaining)?;
-        w = &remaining;
+        w = remaining;
     }
 }
 
-fn vcd_cycle<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, u64, E> {
-    map_res(delimited(char('#'), digit1, fill_ws1), |r| u64::from_str(r))(input)
+/// Text of a directive, up to (and excluding) its $end token. The text is not decoded:
+/// directives of the body are not checked when they are read.
+fn directive_text<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], &'a [u8], E> {
+    let (remaining, _) = skip_until_vcd_end(input)?;
+    let consumed = input[..input.len() - remaining.len()].trim_ascii_end();
+    let text = consumed.strip_suffix(b"$end").unwrap_or(consumed);
+    Ok((remaining, text.trim_ascii()))
+}
+
+fn vcd_cycle<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], u64, E> {
+    map_res(delimited(char('#'), utf8(digit1), fill_ws1), u64::from_str)(input)
 }
 
 /// Any non whitespace stuff inside commands
-fn vcd_varid<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, &'a str, E> {
-    terminated(take_till1(|c: char| c.is_whitespace()), fill_ws1)(input)
+fn vcd_varid<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], &'a str, E> {
+    terminated(utf8(take_till1(|c: u8| c.is_ascii_whitespace())), fill_ws1)(input)
 }
 
-fn vcd_bit_change<'a, E: ParseError<&'a str>>(
-    input: &'a str,
-) -> IResult<&'a str, (char, &'a str), E> {
-    tuple((one_of("01xXzZwWuU"), preceded(multispace0, vcd_varid)))(input)
+fn vcd_bit_change<'a, E: ParseError<&'a [u8]>>(
+    input: &'a [u8],
+) -> IResult<&'a [u8], (char, &'a str), E> {
+    tuple((one_of("01xXzZwWuUlLhH-"), preceded(multispace0, vcd_varid)))(input)
 }
 
-fn is_vcd_bit(c: char) -> bool {
-    return ['0', '1', 'x', 'X', 'z', 'Z', 'u', 'U', 'w', 'W'].contains(&c);
+/// Values of the bits: 0, 1, x and z of the VCD standard, and the other values of IEEE
+/// 1164 std_logic (U, W, L, H and -) written by VHDL simulators
+fn is_vcd_bit(c: u8) -> bool {
+    matches!(
+        c,
+        b'0' | b'1'
+            | b'x'
+            | b'X'
+            | b'z'
+            | b'Z'
+            | b'u'
+            | b'U'
+            | b'w'
+            | b'W'
+            | b'l'
+            | b'L'
+            | b'h'
+            | b'H'
+            | b'-'
+    )
 }
 
-fn vcd_bits<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, &'a str, E> {
-    terminated(take_till1(|c: char| !is_vcd_bit(c)), multispace0)(input)
+fn vcd_bits<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], &'a str, E> {
+    terminated(utf8(take_till1(|c| !is_vcd_bit(c))), multispace0)(input)
 }
 
-fn vcd_vec_change<'a, E: ParseError<&'a str>>(
-    input: &'a str,
-) -> IResult<&'a str, (&'a str, &'a str), E> {
+fn vcd_vec_change<'a, E: ParseError<&'a [u8]>>(
+    input: &'a [u8],
+) -> IResult<&'a [u8], (&'a str, &'a str), E> {
     preceded(
         char('b'),
         preceded(multispace0, tuple((vcd_bits, vcd_varid))),
     )(input)
 }
 
-fn vcd_real_change<'a, E: ParseError<&'a str>>(
-    input: &'a str,
-) -> IResult<&'a str, (&'a str, &'a str), E> {
+/// Parses the usual forms of scalar and vector changes (`1!`, `b0101 "`, followed by
+/// whitespace) without going through the combinators of `vcd_change`. Gives the same
+/// result as `vcd_change`, None for any other input.
+fn scalar_or_vector_change(input: &[u8]) -> Option<(&[u8], VcdChange<'_>)> {
+    let word = |start: usize| {
+        let len = input[start..]
+            .iter()
+            .position(|c| c.is_ascii_whitespace())?;
+        let rest = &input[start + len..];
+        let ws = rest
+            .iter()
+            .position(|c| !c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        Some((len, &rest[ws..]))
+    };
+    let (value, id_start) = match input {
+        [c, next, ..] if is_vcd_bit(*c) && !next.is_ascii_whitespace() => {
+            (VcdValue::Bit(*c as char), 1)
+        }
+        [b'b', c, ..] if is_vcd_bit(*c) => {
+            let bits = input[1..].iter().position(|c| !is_vcd_bit(*c))? + 1;
+            let ws = input[bits..]
+                .iter()
+                .position(|c| !c.is_ascii_whitespace())?;
+            if ws == 0 {
+                return None;
+            }
+            // Only made of bits, always ASCII
+            let bits = str::from_utf8(&input[1..bits]).ok()?;
+            (VcdValue::Vector(bits), bits.len() + 1 + ws)
+        }
+        _ => return None,
+    };
+    let (len, rest) = word(id_start)?;
+    if len == 0 {
+        return None;
+    }
+    let var_id = str::from_utf8(&input[id_start..id_start + len]).ok()?;
+    Some((rest, VcdChange { var_id, value }))
+}
+
+fn vcd_real_change<'a, E: ParseError<&'a [u8]>>(
+    input: &'a [u8],
+) -> IResult<&'a [u8], (&'a str, &'a str), E> {
     preceded(
         char('r'),
         preceded(
             multispace0,
-            tuple((terminated(recognize_float, multispace0), vcd_varid)),
+            tuple((terminated(utf8(recognize_float), multispace0), vcd_varid)),
+        ),
+    )(input)
+}
+
+fn vcd_string_change<'a, E: ParseError<&'a [u8]>>(
+    input: &'a [u8],
+) -> IResult<&'a [u8], (&'a str, &'a str), E> {
+    preceded(
+        char('s'),
+        tuple((
+            terminated(
+                utf8(take_till(|c: u8| c.is_ascii_whitespace())),
+                multispace1,
+            ),
+            vcd_varid,
+        )),
+    )(input)
+}
+
+/// Port states of an extended VCD (IEEE 1364 §18.4.3.1)
+fn is_port_state(c: u8) -> bool {
+    b"DdUuNZLlHhXT01?FAaBbCcf".contains(&c)
+}
+
+fn port_strength<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], u8, E> {
+    terminated(map_res(utf8(digit1), u8::from_str), multispace1)(input)
+}
+
+fn vcd_port_change<'a, E: ParseError<&'a [u8]>>(
+    input: &'a [u8],
+) -> IResult<&'a [u8], (PortValue<'a>, &'a str), E> {
+    let states = terminated(utf8(take_till1(|c| !is_port_state(c))), multispace1);
+    map(
+        preceded(
+            char('p'),
+            tuple((states, port_strength, port_strength, vcd_varid)),
         ),
+        |(states, strength0, strength1, var_id)| {
+            let port = PortValue {
+                states,
+                strength0,
+                strength1,
+            };
+            (port, var_id)
+        },
     )(input)
 }
 
-fn vcd_change<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, VcdChange<'a>, E> {
+fn vcd_change<'a, E: ParseError<&'a [u8]>>(input: &'a [u8]) -> IResult<&'a [u8], VcdChange<'a>, E> {
     alt((
         map(vcd_bit_change, |(c, var_id)| VcdChange {
             var_id,
@@ -473,32 +2442,83 @@ fn vcd_change<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Vc
             var_id,
             value: VcdValue::Real(value),
         }),
+        map(vcd_port_change, |(port, var_id)| VcdChange {
+            var_id,
+            value: VcdValue::Port(port),
+        }),
+        map(vcd_string_change, |(value, var_id)| VcdChange {
+            var_id,
+            value: VcdValue::String(value),
+        }),
     ))(input)
 }
 
-fn vcd_directive<'a, E: ParseError<&'a str>>(
-    input: &'a str,
-) -> IResult<&'a str, VcdCommand<'a>, E> {
-    let (remaining, cmd) = terminated(preceded(char('$'), alphanumeric1), fill_ws1)(input)?;
+/// Keeps track of the open dump section: its `$end` becomes `VcdCommand::EndDump`
+fn close_dump_section<'a>(
+    section: &mut Option<DumpSection>,
+    cmd: VcdCommand<'a>,
+) -> VcdCommand<'a> {
     match cmd {
-        "end" => Ok((remaining, VcdCommand::VcdEnd)),
-        "comment" => {
-            let (remaining, _) = skip_until_vcd_end(remaining)?;
-            Ok((remaining, VcdCommand::Directive(cmd)))
+        VcdCommand::BeginDump(d) => {
+            *section = Some(d);
+            cmd
         }
-        _ => Ok((remaining, VcdCommand::Directive(cmd))),
+        VcdCommand::VcdEnd => match section.take() {
+            Some(d) => VcdCommand::EndDump(d),
+            None => cmd,
+        },
+        _ => cmd,
+    }
+}
+
+/// Command of the body, with the text of a skipped directive (still to be decoded)
+type BodyCommand<'a> = (VcdCommand<'a>, Option<&'a [u8]>);
+
+/// Parses a directive of the body, with its text for directives skipped up to their `$end`
+/// (all but `$end`, the simulation keywords and a valid `$vcdclose`)
+fn vcd_directive<'a, E: ParseError<&'a [u8]>>(
+    input: &'a [u8],
+) -> IResult<&'a [u8], BodyCommand<'a>, E> {
+    let (remaining, cmd) = terminated(preceded(char('$'), utf8(alphanumeric1)), fill_ws1)(input)?;
+    match cmd {
+        "end" => Ok((remaining, (VcdCommand::VcdEnd, None))),
+        _ => match DumpSection::from_keyword(cmd) {
+            Some(d) => Ok((remaining, (VcdCommand::BeginDump(d), None))),
+            None => {
+                let (remaining, text) = directive_text(remaining)?;
+                let close_time = match cmd {
+                    "vcdclose" => str::from_utf8(text)
+                        .ok()
+                        .and_then(|t| t.trim_start_matches('#').trim().parse().ok()),
+                    _ => None,
+                };
+                match close_time {
+                    Some(t) => Ok((remaining, (VcdCommand::VcdClose(t), None))),
+                    None => Ok((remaining, (VcdCommand::Directive(cmd), Some(text)))),
+                }
+            }
+        },
     }
 }
 
-/// Parse the next VCD Command (i.e., stuff not in the VCD header) found in the given string
-fn vcd_command<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, VcdCommand<'a>, E> {
+/// Parses the next command of the body, with the text of skipped directives
+fn body_command<'a, E: ParseError<&'a [u8]>>(
+    input: &'a [u8],
+) -> IResult<&'a [u8], BodyCommand<'a>, E> {
     alt((
-        map(vcd_change, VcdCommand::ValueChange),
-        map(vcd_cycle, VcdCommand::SetCycle),
+        map(vcd_change, |c| (VcdCommand::ValueChange(c), None)),
+        map(vcd_cycle, |c| (VcdCommand::SetCycle(c), None)),
         vcd_directive,
     ))(input)
 }
 
+/// Parse the next VCD Command (i.e., stuff not in the VCD header) found in the given input
+fn vcd_command<'a, E: ParseError<&'a [u8]>>(
+    input: &'a [u8],
+) -> IResult<&'a [u8], VcdCommand<'a>, E> {
+    map(body_command, |(cmd, _)| cmd)(input)
+}
+
 /// Loop on VCD commands and execute a given callback for each one of them
 pub fn process_vcd_commands<'a, E: ParseError<&'a str>, F>(
     input: &'a str,
@@ -507,41 +2527,52 @@ pub fn process_vcd_commands<'a, E: ParseError<&'a str>, F>(
 where
     F: FnMut(VcdCommand) -> bool,
 {
-    let mut w = input;
-    loop {
-        let (remaining, cmd) = vcd_command(w)?;
-        w = remaining;
-        if callback(cmd) {
-            return Ok((w, ()));
-        }
-        if remaining.is_empty() {
-            break;
+    on_text(input, |input| {
+        let mut w = input;
+        let mut section = None;
+        loop {
+            let (remaining, cmd) = vcd_command(w)?;
+            w = remaining;
+            if callback(close_dump_section(&mut section, cmd)) {
+                return Ok((w, ()));
+            }
+            if remaining.is_empty() {
+                break;
+            }
         }
-    }
-    Ok((w, ()))
+        Ok((w, ()))
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    type E<'a> = (&'a str, ErrorKind);
+
+    /// Runs a parser on text, to compare its results with text
+    fn text<'a, O, P>(parser: P, input: &'a str) -> IResult<&'a str, O, E<'a>>
+    where
+        P: Fn(&'a [u8]) -> IResult<&'a [u8], O, ByteError<'a>>,
+    {
+        on_text(input, parser)
+    }
+
     #[test]
     fn test_var_width() {
-        type E<'a> = (&'a str, ErrorKind);
-        assert_eq!(var_width::<E>("1209   ..."), Ok(("...", 1209)));
-        assert_eq!(var_width::<E>("3\n\t   ..."), Ok(("...", 3)));
-        assert_eq!(var_width::<E>("43xx "), Ok(("xx ", 43)));
-        assert_eq!(var_width::<E>("1 a"), Ok(("a", 1)));
+        assert_eq!(text(var_width, "1209   ..."), Ok(("...", 1209)));
+        assert_eq!(text(var_width, "3\n\t   ..."), Ok(("...", 3)));
+        assert_eq!(text(var_width, "43xx "), Ok(("xx ", 43)));
+        assert_eq!(text(var_width, "1 a"), Ok(("a", 1)));
         // Cannot fit in an u64
-        assert!(var_width::<E>("184467440737095516160000").is_err());
-        assert!(var_width::<E>(" 3").is_err());
+        assert!(text(var_width, "184467440737095516160000").is_err());
+        assert!(text(var_width, " 3").is_err());
     }
 
     #[test]
     fn test_var_range() {
-        type E<'a> = (&'a str, ErrorKind);
         for v in ["[ 4  ]  ...", "[4 ]\n...", "[4]\t..."].iter() {
-            assert_eq!(var_range::<E>(v), Ok(("...", Range::Bit(4))));
+            assert_eq!(text(var_range, v), Ok(("...", Range::Bit(4))));
         }
         let w = [
             "[12:0]xx",
@@ -551,81 +2582,75 @@ mod tests {
             "[ 12 : 0 ]\nxx",
         ];
         for v in w.iter() {
-            assert_eq!(var_range::<E>(v), Ok(("xx", Range::Range((12, 0)))));
+            assert_eq!(text(var_range, v), Ok(("xx", Range::Range((12, 0)))));
         }
         assert_eq!(
-            var_range::<E>("[-1: 0] xx"),
+            text(var_range, "[-1: 0] xx"),
             Ok(("xx", Range::Range((-1, 0))))
         );
     }
 
     #[test]
     fn test_vcd_end() {
-        type E<'a> = (&'a str, ErrorKind);
-        assert_eq!(vcd_end::<E>("$end "), Ok(("", "$end")));
-        assert_eq!(vcd_end::<E>("$end \nab"), Ok(("ab", "$end")));
-        assert!(vcd_end::<E>("$enddefinition \nab").is_err());
+        assert_eq!(text(vcd_end, "$end "), Ok(("", &b"$end"[..])));
+        assert_eq!(text(vcd_end, "$end \nab"), Ok(("ab", &b"$end"[..])));
+        assert!(text(vcd_end, "$enddefinition \nab").is_err());
     }
 
     #[test]
     fn test_var_name() {
-        type E<'a> = (&'a str, ErrorKind);
-        assert_eq!(var_name::<E>("foo \nab"), Ok(("ab", "foo")));
-        assert_eq!(var_name::<E>("foo[7] \nab"), Ok(("[7] \nab", "foo")));
-        assert!(var_name::<E>("$foo[7] \nab").is_err());
-        assert!(var_name::<E>(" foo[7] \nab").is_err());
-        assert!(var_name::<E>("[foo[7] \nab").is_err());
+        assert_eq!(text(var_name, "foo \nab"), Ok(("ab", "foo")));
+        assert_eq!(text(var_name, "foo[7] \nab"), Ok(("[7] \nab", "foo")));
+        assert!(text(var_name, "$foo[7] \nab").is_err());
+        assert!(text(var_name, " foo[7] \nab").is_err());
+        assert!(text(var_name, "[foo[7] \nab").is_err());
     }
 
     #[test]
     fn test_vcd_word() {
-        type E<'a> = (&'a str, ErrorKind);
-        assert_eq!(vcd_word::<E>("foo $xxx "), Ok(("$xxx ", "foo")));
-        assert_eq!(vcd_word::<E>("$foo aa"), Ok(("aa", "$foo")));
+        assert_eq!(text(vcd_word, "foo $xxx "), Ok(("$xxx ", "foo")));
+        assert_eq!(text(vcd_word, "$foo aa"), Ok(("aa", "$foo")));
     }
 
     #[test]
     fn test_skip_until_end() {
-        type E<'a> = (&'a str, ErrorKind);
-        assert_eq!(skip_until_vcd_end::<E>("foo$hello $end "), Ok(("", ())));
+        assert_eq!(text(skip_until_vcd_end, "foo$hello $end "), Ok(("", ())));
         assert_eq!(
-            skip_until_vcd_end::<E>("body \n\n hello $date $end \t x"),
+            text(skip_until_vcd_end, "body \n\n hello $date $end \t x"),
             Ok(("x", ()))
         );
     }
 
     #[test]
     fn test_vcd_cycle() {
-        type E<'a> = (&'a str, ErrorKind);
-        assert_eq!(vcd_cycle::<E>("#1244 $end"), Ok(("$end", 1244)));
-        assert_eq!(vcd_cycle::<E>("#123456789 "), Ok(("", 123456789)));
-        assert!(vcd_cycle::<E>("#bla $end").is_err());
-        assert!(vcd_cycle::<E>("# 12 $end").is_err());
+        assert_eq!(text(vcd_cycle, "#1244 $end"), Ok(("$end", 1244)));
+        assert_eq!(text(vcd_cycle, "#123456789 "), Ok(("", 123456789)));
+        assert!(text(vcd_cycle, "#bla $end").is_err());
+        assert!(text(vcd_cycle, "# 12 $end").is_err());
     }
 
     #[test]
     fn test_vcd_change() {
-        type E<'a> = (&'a str, ErrorKind);
-        assert_eq!(vcd_bit_change::<E>("x!! #2"), Ok(("#2", ('x', "!!"))));
+        assert_eq!(text(vcd_bit_change, "x!! #2"), Ok(("#2", ('x', "!!"))));
         assert_eq!(
-            vcd_bit_change::<E>("1 hhhxr' 0"),
+            text(vcd_bit_change, "1 hhhxr' 0"),
             Ok(("0", ('1', "hhhxr'")))
         );
-        assert_eq!(vcd_vec_change::<E>("b1 x "), Ok(("", ("1", "x"))));
+        assert_eq!(text(vcd_vec_change, "b1 x "), Ok(("", ("1", "x"))));
         assert_eq!(
-            vcd_vec_change::<E>("b1001101 lala "),
+            text(vcd_vec_change, "b1001101 lala "),
             Ok(("", ("1001101", "lala")))
         );
         assert_eq!(
-            vcd_vec_change::<E>("bZzXxUu01 vid ..."),
+            text(vcd_vec_change, "bZzXxUu01 vid ..."),
             Ok(("...", ("ZzXxUu01", "vid")))
         );
         assert_eq!(
-            vcd_real_change::<E>("r3.22 # oups"),
+            text(vcd_real_change, "r3.22 # oups"),
             Ok(("oups", ("3.22", "#")))
         );
         assert_eq!(
-            vcd_change::<E>("b01110 ! "), // TODO: support without space
+            text(vcd_change, "b01110 ! "), // TODO: support without space
             Ok((
                 "",
                 VcdChange {
@@ -0,0 +1,347 @@
+//! Conversions between the header and command types of this crate and the ones of the
+//! [vcd](https://crates.io/crates/vcd) crate (`vcd-interop` feature), so both parsers can be
+//! mixed in a project.
+//!
+//! Headers and type enums convert with `From`. Commands of a body are converted by a
+//! `CommandConverter`, as the `vcd` crate pairs each `$end` with the command it closes and
+//! owns its values, while `VcdCommand` borrows them.
+use std::convert::TryFrom;
+
+use rust_vcd::{
+    Command, Header, IdCode, ReferenceIndex, ScopeItem, ScopeType, SimulationCommand,
+    TimescaleUnit, Value, Var, VarType, Vector,
+};
+
+use crate::hierarchy::ScopeNode;
+use crate::types::{
+    Direction, Range, Scope, ScopeKind, TimeUnit, Timescale, VariableInfo, VariableKind,
+};
+use crate::vcd::{DumpSection, VcdChange, VcdCommand, VcdError, VcdHeader, VcdMetadata, VcdValue};
+
+impl From<TimeUnit> for TimescaleUnit {
+    fn from(unit: TimeUnit) -> Self {
+        match unit {
+            TimeUnit::S => TimescaleUnit::S,
+            TimeUnit::Ms => TimescaleUnit::MS,
+            TimeUnit::Us => TimescaleUnit::US,
+            TimeUnit::Ns => TimescaleUnit::NS,
+            TimeUnit::Ps => TimescaleUnit::PS,
+            TimeUnit::Fs => TimescaleUnit::FS,
+        }
+    }
+}
+
+impl From<TimescaleUnit> for TimeUnit {
+    fn from(unit: TimescaleUnit) -> Self {
+        match unit {
+            TimescaleUnit::S => TimeUnit::S,
+            TimescaleUnit::MS => TimeUnit::Ms,
+            TimescaleUnit::US => TimeUnit::Us,
+            TimescaleUnit::NS => TimeUnit::Ns,
+            TimescaleUnit::PS => TimeUnit::Ps,
+            TimescaleUnit::FS => TimeUnit::Fs,
+        }
+    }
+}
+
+impl From<&ScopeKind> for ScopeType {
+    /// Kinds without equivalent (e.g., VHDL ones) become modules
+    fn from(kind: &ScopeKind) -> Self {
+        match kind {
+            ScopeKind::VcdTask => ScopeType::Task,
+            ScopeKind::VcdFunction => ScopeType::Function,
+            ScopeKind::VcdBegin => ScopeType::Begin,
+            ScopeKind::VcdFork => ScopeType::Fork,
+            _ => ScopeType::Module,
+        }
+    }
+}
+
+impl From<ScopeType> for ScopeKind {
+    fn from(kind: ScopeType) -> Self {
+        match kind {
+            ScopeType::Module => ScopeKind::VcdModule,
+            ScopeType::Task => ScopeKind::VcdTask,
+            ScopeType::Function => ScopeKind::VcdFunction,
+            ScopeType::Begin => ScopeKind::VcdBegin,
+            ScopeType::Fork => ScopeKind::VcdFork,
+            _ => ScopeKind::Other,
+        }
+    }
+}
+
+impl From<&VariableKind> for VarType {
+    /// Kinds without equivalent become wires (see `VariableKind::vcd_keyword`)
+    fn from(kind: &VariableKind) -> Self {
+        match kind {
+            VariableKind::GenString => VarType::String,
+            VariableKind::VcdRealtime => VarType::Real,
+            // All the other keywords are known by the `vcd` crate
+            k => k.vcd_keyword().parse().unwrap_or(VarType::Wire),
+        }
+    }
+}
+
+impl From<VarType> for VariableKind {
+    fn from(kind: VarType) -> Self {
+        match kind {
+            VarType::String => VariableKind::GenString,
+            VarType::WAnd => VariableKind::VcdWand,
+            k => VariableKind::from(k.to_string().as_str()),
+        }
+    }
+}
+
+impl From<&Range> for ReferenceIndex {
+    fn from(range: &Range) -> Self {
+        match range {
+            Range::Bit(b) => ReferenceIndex::BitSelect(*b as i32),
+            Range::Range((msb, lsb)) => ReferenceIndex::Range(*msb as i32, *lsb as i32),
+        }
+    }
+}
+
+impl From<ReferenceIndex> for Range {
+    fn from(index: ReferenceIndex) -> Self {
+        match index {
+            ReferenceIndex::BitSelect(b) => Range::Bit(b.max(0) as u64),
+            ReferenceIndex::Range(msb, lsb) => Range::Range((msb as i64, lsb as i64)),
+        }
+    }
+}
+
+impl From<&VcdHeader> for Header {
+    /// Builds the scope tree of the header. Variables with an identifier the `vcd` crate
+    /// does not accept (i.e., not made of printable ASCII characters) are left out.
+    fn from(header: &VcdHeader) -> Self {
+        let mut out = Header::default();
+        out.timescale = header.timescale.map(|t| (t.magnitude, t.unit.into()));
+        out.date = header.metadata.date.clone();
+        out.version = header.metadata.version.clone();
+        for c in &header.metadata.comments {
+            out.items.push(ScopeItem::Comment(c.clone()));
+        }
+        for v in &header.variables {
+            let code: IdCode = match v.id.parse() {
+                Ok(c) => c,
+                Err(_) => continue,
+            };
+            let mut items = &mut out.items;
+            for s in &v.scope {
+                let kind = ScopeType::from(&s.kind);
+                // Consecutive variables of a scope share the same node
+                let reuse = matches!(items.last(), Some(ScopeItem::Scope(last))
+                    if last.identifier == s.name && last.scope_type == kind);
+                if !reuse {
+                    items.push(ScopeItem::Scope(rust_vcd::Scope::new(kind, s.name.clone())));
+                }
+                items = match items.last_mut() {
+                    Some(ScopeItem::Scope(last)) => &mut last.items,
+                    _ => unreachable!(),
+                };
+            }
+            items.push(ScopeItem::Var(Var::new(
+                VarType::from(&v.kind),
+                v.width,
+                code,
+                v.name.clone(),
+                v.range.as_ref().map(ReferenceIndex::from),
+            )));
+        }
+        out
+    }
+}
+
+fn collect_variables(items: &[ScopeItem], scope: &mut Vec<Scope>, out: &mut Vec<VariableInfo>) {
+    for item in items {
+        match item {
+            ScopeItem::Scope(s) => {
+                scope.push(Scope {
+                    kind: s.scope_type.into(),
+                    name: s.identifier.clone(),
+                });
+                collect_variables(&s.items, scope, out);
+                scope.pop();
+            }
+            ScopeItem::Var(v) => out.push(VariableInfo {
+                id: v.code.to_string(),
+                name: v.reference.clone(),
+                direction: Direction::Implicit,
+                kind: v.var_type.into(),
+                width: v.size,
+                range: v.index.map(Range::from),
+                handle: 0,
+                scope: scope.clone(),
+            }),
+            _ => {}
+        }
+    }
+}
+
+impl From<&Header> for VcdHeader {
+    fn from(header: &Header) -> Self {
+        let mut variables = Vec::new();
+        collect_variables(&header.items, &mut Vec::new(), &mut variables);
+        let comments = header.items.iter().filter_map(|item| match item {
+            ScopeItem::Comment(c) => Some(c.trim().to_string()),
+            _ => None,
+        });
+        VcdHeader {
+            scopes: ScopeNode::from_variables(&variables),
+            variables,
+            time_zero: 0,
+            metadata: VcdMetadata {
+                date: header.date.as_ref().map(|d| d.trim().to_string()),
+                version: header.version.as_ref().map(|v| v.trim().to_string()),
+                comments: comments.collect(),
+            },
+            timescale: header.timescale.map(|(magnitude, unit)| Timescale {
+                magnitude,
+                unit: unit.into(),
+            }),
+        }
+    }
+}
+
+impl TryFrom<&VcdChange<'_>> for Command {
+    type Error = VcdError;
+
+    /// Fails on values the `vcd` crate can not represent (i.e., with bits other than `0`,
+    /// `1`, `x` and `z`) and on invalid identifiers
+    fn try_from(change: &VcdChange<'_>) -> Result<Self, Self::Error> {
+        let id: IdCode = change.var_id.parse().map_err(|_| VcdError::ParseError)?;
+        Ok(match &change.value {
+            VcdValue::Bit(c) => {
+                let value: Value = c.to_string().parse().map_err(|_| VcdError::ParseError)?;
+                Command::ChangeScalar(id, value)
+            }
+            VcdValue::Vector(v) => {
+                let value: Vector = v.parse().map_err(|_| VcdError::ParseError)?;
+                Command::ChangeVector(id, value)
+            }
+            VcdValue::Real(r) => {
+                Command::ChangeReal(id, r.parse().map_err(|_| VcdError::ParseError)?)
+            }
+            VcdValue::String(x) => Command::ChangeString(id, x.to_string()),
+            VcdValue::Event => Command::ChangeScalar(id, Value::V1),
+            VcdValue::Port(p) => {
+                let levels = p.levels();
+                let value = match levels.as_bytes() {
+                    [c] => VcdValue::Bit(*c as char),
+                    _ => VcdValue::Vector(&levels),
+                };
+                let var_id = change.var_id;
+                return Command::try_from(&VcdChange { var_id, value });
+            }
+        })
+    }
+}
+
+fn simulation_command(section: DumpSection) -> SimulationCommand {
+    match section {
+        DumpSection::All | DumpSection::PortsAll => SimulationCommand::Dumpall,
+        DumpSection::Off | DumpSection::PortsOff => SimulationCommand::Dumpoff,
+        DumpSection::On | DumpSection::PortsOn => SimulationCommand::Dumpon,
+        DumpSection::Vars | DumpSection::Ports => SimulationCommand::Dumpvars,
+    }
+}
+
+fn dump_section(cmd: SimulationCommand) -> Option<DumpSection> {
+    match cmd {
+        SimulationCommand::Dumpall => Some(DumpSection::All),
+        SimulationCommand::Dumpoff => Some(DumpSection::Off),
+        SimulationCommand::Dumpon => Some(DumpSection::On),
+        SimulationCommand::Dumpvars => Some(DumpSection::Vars),
+        _ => None,
+    }
+}
+
+/// Converts the commands of a body, in order, from or to the `vcd` crate
+#[derive(Debug, Default)]
+pub struct CommandConverter {
+    /// Simulation command waiting for its `$end`
+    section: Option<SimulationCommand>,
+    id: String,
+    value: String,
+}
+
+impl CommandConverter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Converts a command given by `VcdParser::process_vcd_commands`. Returns None for
+    /// commands without equivalent (a `$end` that closes nothing, `$vcdclose`, vendor
+    /// directives). Extended VCD sections and port values become their standard VCD
+    /// equivalent.
+    ///
+    /// The parser does not keep the text of `$comment` directives, they become empty
+    /// comments.
+    pub fn to_vcd_crate(&mut self, cmd: &VcdCommand) -> Result<Option<Command>, VcdError> {
+        Ok(match cmd {
+            VcdCommand::SetCycle(t) => Some(Command::Timestamp(*t)),
+            VcdCommand::ValueChange(c) => Some(Command::try_from(c)?),
+            VcdCommand::VcdEnd => self.section.take().map(Command::End),
+            VcdCommand::EndDump(d) => {
+                self.section = None;
+                Some(Command::End(simulation_command(*d)))
+            }
+            VcdCommand::Directive("comment") => Some(Command::Comment(String::new())),
+            VcdCommand::Directive(_) | VcdCommand::VcdClose(_) => None,
+            VcdCommand::BeginDump(d) => {
+                let c = simulation_command(*d);
+                self.section = Some(c);
+                Some(Command::Begin(c))
+            }
+        })
+    }
+
+    /// Converts a command given by the `vcd` crate parser. Returns None for header commands,
+    /// which have no equivalent in a body.
+    pub fn from_vcd_crate(&mut self, cmd: &Command) -> Option<VcdCommand<'_>> {
+        use std::fmt::Write;
+
+        let id = match cmd {
+            Command::Timestamp(t) => return Some(VcdCommand::SetCycle(*t)),
+            Command::Comment(_) => return Some(VcdCommand::Directive("comment")),
+            Command::Begin(c) => return Some(VcdCommand::BeginDump(dump_section(*c)?)),
+            Command::End(c) => {
+                return Some(match dump_section(*c) {
+                    Some(d) => VcdCommand::EndDump(d),
+                    None => VcdCommand::VcdEnd,
+                })
+            }
+            Command::ChangeScalar(id, _)
+            | Command::ChangeVector(id, _)
+            | Command::ChangeReal(id, _)
+            | Command::ChangeString(id, _) => id,
+            _ => return None,
+        };
+        self.id.clear();
+        self.value.clear();
+        write!(self.id, "{}", id).ok()?;
+        let value = match cmd {
+            Command::ChangeScalar(_, v) => {
+                write!(self.value, "{}", v).ok()?;
+                VcdValue::Bit(self.value.chars().next()?)
+            }
+            Command::ChangeVector(_, v) => {
+                write!(self.value, "{}", v).ok()?;
+                VcdValue::Vector(&self.value)
+            }
+            Command::ChangeReal(_, v) => {
+                write!(self.value, "{}", v).ok()?;
+                VcdValue::Real(&self.value)
+            }
+            Command::ChangeString(_, v) => {
+                self.value.push_str(v);
+                VcdValue::String(&self.value)
+            }
+            _ => return None,
+        };
+        Some(VcdCommand::ValueChange(VcdChange {
+            var_id: &self.id,
+            value,
+        }))
+    }
+}
@@ -0,0 +1,451 @@
+//! Streaming writer for VCD files.
+//!
+//! ```no_run
+//! # use wavetk::types::{ScopeKind, VariableKind};
+//! # use wavetk::vcd::VcdValue;
+//! # use wavetk::VcdWriter;
+//! # fn main() -> std::io::Result<()> {
+//! let mut writer = VcdWriter::new(std::io::stdout());
+//! let (clk, data) = {
+//!     let mut top = writer.scope(ScopeKind::VcdModule, "top")?;
+//!     let clk = top.add_var(VariableKind::VcdWire, "clk", 1, None)?;
+//!     let data = top.add_var(VariableKind::VcdReg, "data", 8, None)?;
+//!     (clk, data)
+//! }; // `$upscope` written here
+//! writer.enddefinitions()?;
+//! writer.timestamp(0)?;
+//! writer.change(&clk, &VcdValue::Bit('0'))?;
+//! writer.change(&data, &VcdValue::Vector("1010"))?;
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::ops::{Deref, DerefMut};
+
+use crate::simulation::logic_char;
+use crate::types::{Direction, Range, Scope, ScopeKind, Timescale, VariableInfo, VariableKind};
+use crate::vcd::{VcdCommand, VcdValue};
+
+/// Character written for a logic level of the simulation state, levels without VCD
+/// representation are written as `x`
+fn vcd_char(level: i8) -> char {
+    match logic_char(level) {
+        '?' => 'x',
+        c => c.to_ascii_lowercase(),
+    }
+}
+
+/// Shortest identifier for the n-th variable, made of printable ASCII characters (`!` to
+/// `~`)
+pub(crate) fn short_id(mut n: u64) -> String {
+    const FIRST: u8 = b'!';
+    const BASE: u64 = (b'~' - b'!') as u64 + 1;
+    let mut id = String::new();
+    loop {
+        id.push((FIRST + (n % BASE) as u8) as char);
+        n /= BASE;
+        if n == 0 {
+            return id;
+        }
+        n -= 1;
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Writes VCD commands as they are called.
+///
+/// The writer checks the structure of the file: declarations before `enddefinitions`,
+/// value changes after it and in time order, values matching the width declared for their
+/// variable (vectors may be shorter, they are left-extended by readers). Commands breaking
+/// these rules fail with an `io::ErrorKind::InvalidInput` error and are not written.
+pub struct VcdWriter<W: Write> {
+    out: W,
+    // Width of the declared variables (None for reals)
+    widths: HashMap<String, Option<u32>>,
+    next_id: u64,
+    depth: usize,
+    definitions_done: bool,
+    time: Option<u64>,
+    // Error raised while closing a scope from a guard, reported by the next call
+    error: Option<io::Error>,
+}
+
+impl<W: Write> VcdWriter<W> {
+    pub fn new(out: W) -> Self {
+        VcdWriter {
+            out,
+            widths: HashMap::new(),
+            next_id: 0,
+            depth: 0,
+            definitions_done: false,
+            time: None,
+            error: None,
+        }
+    }
+
+    fn check_definitions(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if self.definitions_done {
+            return Err(invalid("declaration after $enddefinitions".into()));
+        }
+        Ok(())
+    }
+
+    fn check_body(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if !self.definitions_done {
+            return Err(invalid("value change before $enddefinitions".into()));
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, keyword: &str, text: &str) -> io::Result<()> {
+        self.check_definitions()?;
+        writeln!(self.out, "${} {} $end", keyword, text)
+    }
+
+    pub fn date(&mut self, text: &str) -> io::Result<()> {
+        self.directive("date", text)
+    }
+
+    pub fn version(&mut self, text: &str) -> io::Result<()> {
+        self.directive("version", text)
+    }
+
+    pub fn comment(&mut self, text: &str) -> io::Result<()> {
+        self.directive("comment", text)
+    }
+
+    pub fn timescale(&mut self, timescale: &Timescale) -> io::Result<()> {
+        self.directive("timescale", &timescale.to_string())
+    }
+
+    pub fn time_zero(&mut self, time_zero: i64) -> io::Result<()> {
+        self.directive("timezero", &time_zero.to_string())
+    }
+
+    fn open_scope(&mut self, kind: &ScopeKind, name: &str) -> io::Result<()> {
+        self.check_definitions()?;
+        writeln!(self.out, "$scope {} {} $end", kind.vcd_keyword(), name)?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn close_scope(&mut self) -> io::Result<()> {
+        self.depth -= 1;
+        writeln!(self.out, "$upscope $end")
+    }
+
+    /// Opens a scope, closed (`$upscope`) when the returned guard is dropped or with
+    /// `ScopeGuard::upscope`. Variables and sub-scopes are declared through the guard.
+    pub fn scope(&mut self, kind: ScopeKind, name: &str) -> io::Result<ScopeGuard<'_, W>> {
+        self.open_scope(&kind, name)?;
+        Ok(ScopeGuard {
+            writer: self,
+            closed: false,
+        })
+    }
+
+    fn declare(&mut self, v: &VariableInfo) -> io::Result<()> {
+        write!(
+            self.out,
+            "$var {} {} {} {}",
+            v.kind.vcd_keyword(),
+            v.width,
+            v.id,
+            v.name
+        )?;
+        if let Some(r) = &v.range {
+            write!(self.out, " {}", r)?;
+        }
+        writeln!(self.out, " $end")
+    }
+
+    fn register(&mut self, v: &VariableInfo) -> io::Result<()> {
+        let width = match v.kind {
+            VariableKind::VcdReal | VariableKind::VcdRealParameter | VariableKind::VcdRealtime => {
+                None
+            }
+            _ => Some(v.width),
+        };
+        if v.id.is_empty() || v.id.chars().any(|c| !c.is_ascii_graphic()) {
+            return Err(invalid(format!("invalid identifier {:?}", v.id)));
+        }
+        if width == Some(0) {
+            return Err(invalid(format!("variable {} has a zero width", v.name)));
+        }
+        match self.widths.get(&v.id) {
+            // Aliases must agree on the width
+            Some(w) if *w != width => Err(invalid(format!(
+                "variable {} aliases {} with another width",
+                v.name, v.id
+            ))),
+            _ => {
+                self.widths.insert(v.id.clone(), width);
+                Ok(())
+            }
+        }
+    }
+
+    /// Declares a variable with the id given in `v` (the `scope` field is ignored), several
+    /// variables may share the same id
+    pub fn var(&mut self, v: &VariableInfo) -> io::Result<()> {
+        self.check_definitions()?;
+        self.register(v)?;
+        self.declare(v)
+    }
+
+    /// Declares a variable in the current scope and returns the identifier assigned to
+    /// it, the shortest one not used yet
+    pub fn add_var(
+        &mut self,
+        kind: VariableKind,
+        name: &str,
+        width: u32,
+        range: Option<Range>,
+    ) -> io::Result<String> {
+        let id = loop {
+            let id = short_id(self.next_id);
+            self.next_id += 1;
+            if !self.widths.contains_key(&id) {
+                break id;
+            }
+        };
+        self.var(&VariableInfo {
+            id: id.clone(),
+            name: name.to_string(),
+            direction: Direction::Implicit,
+            kind,
+            width,
+            range,
+            handle: 0,
+            scope: Vec::new(),
+        })?;
+        Ok(id)
+    }
+
+    /// Declares variables along with their scopes, opening and closing scopes as needed
+    /// between two consecutive variables. The scopes opened are closed at the end.
+    pub fn variables<'a, I>(&mut self, variables: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = &'a VariableInfo>,
+    {
+        let mut scopes: Vec<&Scope> = Vec::new();
+        for v in variables {
+            let common = scopes
+                .iter()
+                .zip(&v.scope)
+                .take_while(|(a, b)| **a == *b)
+                .count();
+            while scopes.len() > common {
+                scopes.pop();
+                self.close_scope()?;
+            }
+            for s in &v.scope[common..] {
+                self.open_scope(&s.kind, &s.name)?;
+                scopes.push(s);
+            }
+            self.var(v)?;
+        }
+        for _ in scopes {
+            self.close_scope()?;
+        }
+        Ok(())
+    }
+
+    pub fn enddefinitions(&mut self) -> io::Result<()> {
+        self.check_definitions()?;
+        if self.depth > 0 {
+            return Err(invalid(format!("{} scope(s) left open", self.depth)));
+        }
+        self.definitions_done = true;
+        writeln!(self.out, "$enddefinitions $end")
+    }
+
+    pub fn timestamp(&mut self, time: u64) -> io::Result<()> {
+        self.check_body()?;
+        if let Some(t) = self.time {
+            if time < t {
+                return Err(invalid(format!(
+                    "time going backward (#{} after #{})",
+                    time, t
+                )));
+            }
+        }
+        self.time = Some(time);
+        writeln!(self.out, "#{}", time)
+    }
+
+    /// Opens a `$dumpvars` block, to be closed with `end`
+    pub fn dumpvars(&mut self) -> io::Result<()> {
+        self.check_body()?;
+        writeln!(self.out, "$dumpvars")
+    }
+
+    pub fn end(&mut self) -> io::Result<()> {
+        self.check_body()?;
+        writeln!(self.out, "$end")
+    }
+
+    /// Writes a command of the body of a file, as given by `VcdParser::process_vcd_commands`
+    /// (the parser does not keep the text of `$comment` directives, an empty comment is
+    /// written, other directives with text, e.g., vendor ones, are dropped). Extended VCD
+    /// sections and port values are written as their standard VCD equivalent.
+    pub fn command(&mut self, cmd: &VcdCommand) -> io::Result<()> {
+        match cmd {
+            VcdCommand::SetCycle(t) => self.timestamp(*t),
+            VcdCommand::ValueChange(c) => self.change(c.var_id, &c.value),
+            VcdCommand::VcdEnd | VcdCommand::EndDump(_) => self.end(),
+            VcdCommand::Directive("comment") => {
+                self.check_body()?;
+                writeln!(self.out, "$comment $end")
+            }
+            VcdCommand::BeginDump(d) => {
+                self.check_body()?;
+                writeln!(self.out, "${}", d.vcd_equivalent().keyword())
+            }
+            VcdCommand::VcdClose(t) => {
+                self.check_body()?;
+                writeln!(self.out, "$vcdclose #{} $end", t)
+            }
+            VcdCommand::Directive(_) => Ok(()),
+        }
+    }
+
+    /// Checks that a value of the given width can be written to a variable
+    fn check_value(&self, var_id: &str, width: Option<usize>) -> io::Result<()> {
+        let declared = match self.widths.get(var_id) {
+            Some(w) => *w,
+            None => return Err(invalid(format!("undeclared variable {:?}", var_id))),
+        };
+        let ok = match (declared, width) {
+            (None, None) => true,
+            (Some(d), Some(w)) => w > 0 && w <= d as usize,
+            _ => false,
+        };
+        if !ok {
+            return Err(invalid(format!(
+                "value of width {:?} written to {:?} of width {:?}",
+                width, var_id, declared
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn change(&mut self, var_id: &str, value: &VcdValue) -> io::Result<()> {
+        self.check_body()?;
+        match value {
+            VcdValue::Bit(c) => {
+                // A scalar change is only valid on 1-bit variables
+                match self.widths.get(var_id) {
+                    Some(Some(1)) => {}
+                    Some(w) => {
+                        return Err(invalid(format!(
+                            "scalar value written to {:?} of width {:?}",
+                            var_id, w
+                        )))
+                    }
+                    None => return Err(invalid(format!("undeclared variable {:?}", var_id))),
+                }
+                writeln!(self.out, "{}{}", c, var_id)
+            }
+            VcdValue::Vector(x) => {
+                self.check_value(var_id, Some(x.len()))?;
+                writeln!(self.out, "b{} {}", x, var_id)
+            }
+            VcdValue::Real(x) => {
+                self.check_value(var_id, None)?;
+                writeln!(self.out, "r{} {}", x, var_id)
+            }
+            VcdValue::String(x) => {
+                if !self.widths.contains_key(var_id) {
+                    return Err(invalid(format!("undeclared variable {:?}", var_id)));
+                }
+                if x.is_empty() || x.contains(char::is_whitespace) {
+                    return Err(invalid(format!("invalid string value {:?}", x)));
+                }
+                writeln!(self.out, "s{} {}", x, var_id)
+            }
+            VcdValue::Port(p) => {
+                let levels = p.levels();
+                match self.widths.get(var_id) {
+                    Some(Some(1)) if levels.len() == 1 => {
+                        self.change(var_id, &VcdValue::Bit(levels.as_bytes()[0] as char))
+                    }
+                    _ => self.change(var_id, &VcdValue::Vector(&levels)),
+                }
+            }
+            // Events are triggered with a `1` change
+            VcdValue::Event => self.change(var_id, &VcdValue::Bit('1')),
+        }
+    }
+
+    /// Writes a value given as logic levels of the simulation state (leftmost bit first)
+    pub fn change_levels(&mut self, var_id: &str, levels: &[i8]) -> io::Result<()> {
+        self.check_body()?;
+        self.check_value(var_id, Some(levels.len()))?;
+        if levels.len() == 1 && self.widths.get(var_id) == Some(&Some(1)) {
+            return writeln!(self.out, "{}{}", vcd_char(levels[0]), var_id);
+        }
+        let bits: String = levels.iter().map(|l| vcd_char(*l)).collect();
+        writeln!(self.out, "b{} {}", bits, var_id)
+    }
+
+    /// Flushes and returns the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// An open scope of a `VcdWriter` (see `VcdWriter::scope`), gives access to the writer
+pub struct ScopeGuard<'a, W: Write> {
+    writer: &'a mut VcdWriter<W>,
+    closed: bool,
+}
+
+impl<'a, W: Write> ScopeGuard<'a, W> {
+    /// Closes the scope, reporting write errors (they are deferred to the next call to the
+    /// writer when the guard is dropped)
+    pub fn upscope(mut self) -> io::Result<()> {
+        self.closed = true;
+        self.writer.close_scope()
+    }
+}
+
+impl<'a, W: Write> Deref for ScopeGuard<'a, W> {
+    type Target = VcdWriter<W>;
+
+    fn deref(&self) -> &Self::Target {
+        self.writer
+    }
+}
+
+impl<'a, W: Write> DerefMut for ScopeGuard<'a, W> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.writer
+    }
+}
+
+impl<'a, W: Write> Drop for ScopeGuard<'a, W> {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        if let Err(e) = self.writer.close_scope() {
+            self.writer.error.get_or_insert(e);
+        }
+    }
+}
@@ -0,0 +1,861 @@
+//! A simple native container for waveforms, optimized for random access.
+//!
+//! The file layout is the following:
+//!
+//! ```text
+//! +------------------+
+//! | magic (8 bytes)  |
+//! +------------------+
+//! | change blocks    |  per-signal, each holding up to `block_size` changes
+//! +------------------+
+//! | block tables     |  per-signal list of blocks, 5 u64 (little endian) per block
+//! +------------------+
+//! | index (JSON)     |  header variables + location of the table of each signal
+//! +------------------+
+//! | index offset u64 |  little endian
+//! | index size   u64 |
+//! | magic (8 bytes)  |
+//! +------------------+
+//! ```
+//!
+//! Inside a block, each change is encoded as a LEB128 time delta (relative to the previous
+//! change of the block, the first one is relative to `first_time`) followed by the value.
+//! Values made only of `0`/`1` bits are packed 8 bits per byte, other values are kept as
+//! raw text.
+//!
+//! Only the index, whose size does not depend on the length of the dump, is loaded when
+//! opening a `WaveformDb`. The block table of a signal is read the first time the signal
+//! is queried, and blocks are only read when a query overlaps them. Decoded blocks are
+//! kept in a least recently used cache, bounded by a memory budget (see
+//! `WaveformDb::set_cache_budget`), so that exploring the same region of a large file does
+//! not read it again.
+//!
+//! Since blocks are never rewritten, a conversion can be interrupted and resumed: the
+//! output is truncated to the last checkpoint (see `convert_vcd_file`) and the writer
+//! continues from there.
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::VariableInfo;
+use crate::utils::{read_varint, write_varint};
+use crate::vcd::{VcdCommand, VcdError, VcdHeader, VcdParser, VcdValue};
+
+const MAGIC: &[u8; 8] = b"WTKDB\0\0\x02";
+const FOOTER_SIZE: u64 = 24;
+/// Size of a block in a block table
+const BLOCK_ENTRY_SIZE: u64 = 40;
+const VALUE_PACKED: u8 = 0;
+const VALUE_RAW: u8 = 1;
+pub const CONVERSION_CHECKPOINT_VERSION: u32 = 2;
+/// Default memory budget of the block cache of `WaveformDb`
+pub const DEFAULT_CACHE_BUDGET: usize = 64 << 20;
+
+#[derive(Debug)]
+pub enum WdbError {
+    IoError(io::Error),
+    InvalidFile,
+    UnknownSignal,
+}
+
+impl std::fmt::Display for WdbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            WdbError::IoError(e) => e.fmt(f),
+            x => write!(f, "{:?}", x),
+        }
+    }
+}
+
+impl std::error::Error for WdbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WdbError::IoError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WdbError {
+    fn from(e: io::Error) -> Self {
+        WdbError::IoError(e)
+    }
+}
+
+/// Location of a change block in the file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockInfo {
+    pub offset: u64,
+    pub size: u64,
+    pub first_time: u64,
+    pub last_time: u64,
+    pub count: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SignalInfo {
+    pub id: String,
+    pub width: u32,
+    pub blocks: Vec<BlockInfo>,
+}
+
+/// Location of the block table of a signal
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SignalEntry {
+    id: String,
+    table_offset: u64,
+    block_count: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct WdbIndex {
+    variables: Vec<VariableInfo>,
+    signals: Vec<SignalEntry>,
+    end_time: u64,
+}
+
+fn encode_value(out: &mut Vec<u8>, value: &[u8]) {
+    if value.iter().all(|c| *c == b'0' || *c == b'1') {
+        out.push(VALUE_PACKED);
+        write_varint(out, value.len() as u64);
+        for chunk in value.chunks(8) {
+            let b = chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |b, (i, c)| b | ((c - b'0') << i));
+            out.push(b);
+        }
+    } else {
+        out.push(VALUE_RAW);
+        write_varint(out, value.len() as u64);
+        out.extend_from_slice(value);
+    }
+}
+
+fn decode_value(data: &[u8]) -> Option<(Arc<str>, usize)> {
+    let tag = *data.first()?;
+    let (n, k) = read_varint(&data[1..])?;
+    let n = n as usize;
+    let start = 1 + k;
+    match tag {
+        VALUE_PACKED => {
+            let n_bytes = n.div_ceil(8);
+            let end = start.checked_add(n_bytes)?;
+            let bytes = data.get(start..end)?;
+            let s: String = (0..n)
+                .map(|i| {
+                    if bytes[i / 8] >> (i % 8) & 1 == 1 {
+                        '1'
+                    } else {
+                        '0'
+                    }
+                })
+                .collect();
+            Some((s.into(), end))
+        }
+        VALUE_RAW => {
+            let end = start.checked_add(n)?;
+            let bytes = data.get(start..end)?;
+            Some((String::from_utf8_lossy(bytes).into(), end))
+        }
+        _ => None,
+    }
+}
+
+/// State of a `WdbWriter` with no pending change, enough to continue writing to an output
+/// truncated to `position` bytes
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WdbWriterState {
+    pub position: u64,
+    pub block_size: u64,
+    pub signals: Vec<SignalInfo>,
+    pub end_time: u64,
+}
+
+#[derive(Default)]
+struct PendingBlock {
+    data: Vec<u8>,
+    first_time: u64,
+    last_time: u64,
+    count: u64,
+}
+
+/// Streaming writer for the native format.
+///
+/// Value changes must be pushed in time order, they are accumulated per signal and
+/// written to the output each time a signal block is full.
+pub struct WdbWriter<W> {
+    out: W,
+    position: u64,
+    block_size: u64,
+    variables: Vec<VariableInfo>,
+    signal_ids: HashMap<String, usize>,
+    signals: Vec<SignalInfo>,
+    pending: Vec<PendingBlock>,
+    end_time: u64,
+}
+
+impl<W: Write> WdbWriter<W> {
+    pub fn new(out: W, variables: &[VariableInfo]) -> io::Result<Self> {
+        Self::with_block_size(4096, out, variables)
+    }
+
+    /// Creates a writer holding at most `block_size` changes per block, smaller blocks make
+    /// windowed reads cheaper at the cost of a larger index. A `block_size` of 0 fails with
+    /// an `io::ErrorKind::InvalidInput` error.
+    pub fn with_block_size(
+        block_size: usize,
+        mut out: W,
+        variables: &[VariableInfo],
+    ) -> io::Result<Self> {
+        if block_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "blocks must hold at least one change",
+            ));
+        }
+        out.write_all(MAGIC)?;
+        let mut signal_ids = HashMap::with_capacity(variables.len());
+        let mut signals = Vec::with_capacity(variables.len());
+        for v in variables {
+            if signal_ids.contains_key(&v.id) {
+                continue;
+            }
+            signal_ids.insert(v.id.clone(), signals.len());
+            signals.push(SignalInfo {
+                id: v.id.clone(),
+                width: v.width,
+                blocks: Vec::new(),
+            });
+        }
+        let pending = signals.iter().map(|_| PendingBlock::default()).collect();
+        Ok(WdbWriter {
+            out,
+            position: MAGIC.len() as u64,
+            block_size: block_size as u64,
+            variables: variables.to_vec(),
+            signal_ids,
+            signals,
+            pending,
+            end_time: 0,
+        })
+    }
+
+    /// Continues writing a file from a saved state, `out` must hold the first
+    /// `state.position` bytes of the file (and nothing else)
+    pub fn resume(out: W, variables: &[VariableInfo], state: WdbWriterState) -> Self {
+        let signal_ids = state
+            .signals
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.id.clone(), i))
+            .collect();
+        let pending = state
+            .signals
+            .iter()
+            .map(|_| PendingBlock::default())
+            .collect();
+        WdbWriter {
+            out,
+            position: state.position,
+            block_size: state.block_size,
+            variables: variables.to_vec(),
+            signal_ids,
+            signals: state.signals,
+            pending,
+            end_time: state.end_time,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    /// Flushes all pending blocks (even if they are not full) and returns the state from
+    /// which the writer can be resumed
+    pub fn checkpoint(&mut self) -> io::Result<WdbWriterState> {
+        for i in 0..self.signals.len() {
+            self.flush_block(i)?;
+        }
+        self.out.flush()?;
+        Ok(WdbWriterState {
+            position: self.position,
+            block_size: self.block_size,
+            signals: self.signals.clone(),
+            end_time: self.end_time,
+        })
+    }
+
+    fn flush_block(&mut self, signal: usize) -> io::Result<()> {
+        let block = std::mem::take(&mut self.pending[signal]);
+        if block.count == 0 {
+            return Ok(());
+        }
+        self.out.write_all(&block.data)?;
+        self.signals[signal].blocks.push(BlockInfo {
+            offset: self.position,
+            size: block.data.len() as u64,
+            first_time: block.first_time,
+            last_time: block.last_time,
+            count: block.count,
+        });
+        self.position += block.data.len() as u64;
+        Ok(())
+    }
+
+    /// Records a new value (one ASCII character per bit, or the text of a real) for the
+    /// variable with the given id. Unknown ids are ignored, a time lower than the previous
+    /// change of the variable fails with an `io::ErrorKind::InvalidInput` error.
+    pub fn push_change(&mut self, time: u64, var_id: &str, value: &[u8]) -> io::Result<()> {
+        let signal = match self.signal_ids.get(var_id) {
+            Some(s) => *s,
+            None => return Ok(()),
+        };
+        let block = &mut self.pending[signal];
+        let previous = match block.count {
+            0 => self.signals[signal].blocks.last().map(|b| b.last_time),
+            _ => Some(block.last_time),
+        };
+        if previous.is_some_and(|t| time < t) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "change of {} at time {} before the previous one",
+                    var_id, time
+                ),
+            ));
+        }
+        self.end_time = self.end_time.max(time);
+        if block.count == 0 {
+            block.first_time = time;
+            block.last_time = time;
+        }
+        write_varint(&mut block.data, time - block.last_time);
+        encode_value(&mut block.data, value);
+        block.last_time = time;
+        block.count += 1;
+        if block.count >= self.block_size {
+            self.flush_block(signal)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes all pending blocks, writes the block tables and the index and returns the
+    /// underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        for i in 0..self.signals.len() {
+            self.flush_block(i)?;
+        }
+        let mut entries = Vec::with_capacity(self.signals.len());
+        let mut table = Vec::new();
+        for s in std::mem::take(&mut self.signals) {
+            table.clear();
+            for b in &s.blocks {
+                for word in &[b.offset, b.size, b.first_time, b.last_time, b.count] {
+                    table.extend_from_slice(&word.to_le_bytes());
+                }
+            }
+            self.out.write_all(&table)?;
+            entries.push(SignalEntry {
+                id: s.id,
+                table_offset: self.position,
+                block_count: s.blocks.len() as u64,
+            });
+            self.position += table.len() as u64;
+        }
+        let index = WdbIndex {
+            variables: std::mem::take(&mut self.variables),
+            signals: entries,
+            end_time: self.end_time,
+        };
+        let index_data = serde_json::to_vec(&index)?;
+        self.out.write_all(&index_data)?;
+        self.out.write_all(&self.position.to_le_bytes())?;
+        self.out
+            .write_all(&(index_data.len() as u64).to_le_bytes())?;
+        self.out.write_all(MAGIC)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Converts the body of a VCD file into the native format, the header of the parser must
+/// have been loaded.
+pub fn convert_vcd<R: Read, W: Write>(parser: &mut VcdParser<R>, out: W) -> Result<W, VcdError> {
+    let variables = parser
+        .header()
+        .map(|h: &VcdHeader| h.variables.clone())
+        .ok_or(VcdError::PartialHeader)?;
+    let mut writer = WdbWriter::new(out, &variables)?;
+    let mut time = 0u64;
+    parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+        match cmd {
+            VcdCommand::SetCycle(c) => time = c,
+            VcdCommand::ValueChange(v) => match v.value {
+                VcdValue::Bit(c) => writer.push_change(time, v.var_id, &[c as u8])?,
+                VcdValue::Event => writer.push_change(time, v.var_id, b"1")?,
+                VcdValue::Vector(x) | VcdValue::Real(x) | VcdValue::String(x) => {
+                    writer.push_change(time, v.var_id, x.as_bytes())?
+                }
+                VcdValue::Port(p) => writer.push_change(time, v.var_id, p.levels().as_bytes())?,
+            },
+            _ => {}
+        }
+        Ok(false)
+    })?;
+    Ok(writer.finish()?)
+}
+
+/// Progress of a conversion started by `convert_vcd_file`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConversionCheckpoint {
+    pub version: u32,
+    pub header: VcdHeader,
+    /// Bytes of the VCD file consumed (right after a timestamp)
+    pub input_offset: u64,
+    /// Last timestamp read
+    pub time: u64,
+    pub writer: WdbWriterState,
+}
+
+fn save_checkpoint(path: &Path, checkpoint: &ConversionCheckpoint) -> io::Result<()> {
+    // Written aside then renamed, so an interruption never leaves a partial checkpoint
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let file = File::create(&tmp)?;
+    serde_json::to_writer(&file, checkpoint)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+fn load_checkpoint(path: &Path) -> Result<Option<ConversionCheckpoint>, VcdError> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let checkpoint: ConversionCheckpoint =
+        serde_json::from_reader(file).map_err(|e| VcdError::IoError(e.into()))?;
+    if checkpoint.version != CONVERSION_CHECKPOINT_VERSION {
+        return Err(VcdError::ParseError);
+    }
+    Ok(Some(checkpoint))
+}
+
+/// Converts a VCD file into the native format, saving the progress to `checkpoint` about
+/// every `interval` value changes.
+///
+/// When `checkpoint` exists, a previous conversion of the same input was interrupted: the
+/// output is truncated to the size it had at the last checkpoint and the conversion
+/// continues from there. The checkpoint is removed once the output is complete.
+pub fn convert_vcd_file<P, Q, C>(
+    input: P,
+    output: Q,
+    checkpoint: C,
+    interval: u64,
+) -> Result<(), VcdError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    C: AsRef<Path>,
+{
+    let checkpoint = checkpoint.as_ref();
+    let mut input = File::open(input)?;
+    let (mut parser, mut writer, mut time) = match load_checkpoint(checkpoint)? {
+        Some(c) => {
+            input.seek(SeekFrom::Start(c.input_offset))?;
+            let mut out = OpenOptions::new().write(true).open(output)?;
+            out.set_len(c.writer.position)?;
+            out.seek(SeekFrom::End(0))?;
+            let writer = WdbWriter::resume(out, &c.header.variables, c.writer);
+            let parser = VcdParser::with_header(4096, input, c.header);
+            (parser, writer, c.time)
+        }
+        None => {
+            let mut parser = VcdParser::with_chunk_size(4096, input);
+            parser.load_header()?;
+            let variables = parser.header().unwrap().variables.clone();
+            let writer = WdbWriter::new(File::create(output)?, &variables)?;
+            (parser, writer, 0)
+        }
+    };
+
+    while !parser.done() {
+        let mut changes = 0u64;
+        parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+            match cmd {
+                VcdCommand::SetCycle(c) => {
+                    time = c;
+                    // Stop right after a timestamp, where parsing can be resumed
+                    return Ok(changes >= interval);
+                }
+                VcdCommand::ValueChange(v) => {
+                    changes += 1;
+                    match v.value {
+                        VcdValue::Bit(c) => writer.push_change(time, v.var_id, &[c as u8])?,
+                        VcdValue::Event => writer.push_change(time, v.var_id, b"1")?,
+                        VcdValue::Vector(x) | VcdValue::Real(x) | VcdValue::String(x) => {
+                            writer.push_change(time, v.var_id, x.as_bytes())?
+                        }
+                        VcdValue::Port(p) => {
+                            writer.push_change(time, v.var_id, p.levels().as_bytes())?
+                        }
+                    }
+                }
+                _ => {}
+            }
+            Ok(false)
+        })?;
+        if !parser.done() {
+            let state = writer.checkpoint()?;
+            writer.get_ref().sync_data()?;
+            save_checkpoint(
+                checkpoint,
+                &ConversionCheckpoint {
+                    version: CONVERSION_CHECKPOINT_VERSION,
+                    header: parser.header().unwrap().clone(),
+                    input_offset: parser.position(),
+                    time,
+                    writer: state,
+                },
+            )?;
+        }
+    }
+    writer.finish()?.sync_all()?;
+    match std::fs::remove_file(checkpoint) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Changes of a block, decoded
+type DecodedBlock = Arc<Vec<(u64, Arc<str>)>>;
+
+/// Counters of the block cache of a `WaveformDb`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Blocks found in the cache
+    pub hits: u64,
+    /// Blocks read from the file
+    pub misses: u64,
+    /// Blocks dropped to stay within the budget
+    pub evictions: u64,
+    /// Blocks currently cached
+    pub blocks: usize,
+    /// Estimated memory used by the cached blocks, in bytes
+    pub bytes: usize,
+}
+
+struct CachedBlock {
+    changes: DecodedBlock,
+    size: usize,
+    last_use: u64,
+}
+
+/// Decoded blocks, indexed by (signal, block), evicted in least recently used order
+#[derive(Default)]
+struct BlockCache {
+    budget: usize,
+    blocks: HashMap<(usize, usize), CachedBlock>,
+    /// Cached blocks by time of last use
+    lru: BTreeMap<u64, (usize, usize)>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+impl BlockCache {
+    fn get(&mut self, key: (usize, usize)) -> Option<DecodedBlock> {
+        let b = self.blocks.get_mut(&key)?;
+        self.lru.remove(&b.last_use);
+        self.clock += 1;
+        b.last_use = self.clock;
+        self.lru.insert(self.clock, key);
+        self.stats.hits += 1;
+        Some(b.changes.clone())
+    }
+
+    fn insert(&mut self, key: (usize, usize), changes: DecodedBlock) {
+        // Only the strings (and their counters) are counted beyond the entries, allocator
+        // overhead is ignored
+        let size = std::mem::size_of::<CachedBlock>()
+            + changes.capacity() * std::mem::size_of::<(u64, Arc<str>)>()
+            + changes
+                .iter()
+                .map(|(_, v)| 2 * std::mem::size_of::<usize>() + v.len())
+                .sum::<usize>();
+        if size > self.budget {
+            return;
+        }
+        self.shrink(self.budget - size);
+        self.clock += 1;
+        self.lru.insert(self.clock, key);
+        self.blocks.insert(
+            key,
+            CachedBlock {
+                changes,
+                size,
+                last_use: self.clock,
+            },
+        );
+        self.stats.bytes += size;
+        self.stats.blocks += 1;
+    }
+
+    /// Evicts the least recently used blocks until at most `bytes` are used
+    fn shrink(&mut self, bytes: usize) {
+        while self.stats.bytes > bytes {
+            let (_, key) = match self.lru.pop_first() {
+                Some(e) => e,
+                None => break,
+            };
+            if let Some(b) = self.blocks.remove(&key) {
+                self.stats.bytes -= b.size;
+                self.stats.blocks -= 1;
+                self.stats.evictions += 1;
+            }
+        }
+    }
+}
+
+/// Random-access reader for the native format
+pub struct WaveformDb {
+    file: File,
+    index: WdbIndex,
+    /// Block tables and blocks lie before it
+    index_offset: u64,
+    signal_ids: HashMap<String, usize>,
+    /// Blocks of each signal, read the first time the signal is queried
+    tables: Vec<Option<Vec<BlockInfo>>>,
+    cache: BlockCache,
+}
+
+impl WaveformDb {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, WdbError> {
+        let mut file = File::open(path)?;
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(WdbError::InvalidFile);
+        }
+        let file_size = file.seek(SeekFrom::End(0))?;
+        if file_size < MAGIC.len() as u64 + FOOTER_SIZE {
+            return Err(WdbError::InvalidFile);
+        }
+        file.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+        let mut footer = [0u8; FOOTER_SIZE as usize];
+        file.read_exact(&mut footer)?;
+        if &footer[16..] != MAGIC {
+            return Err(WdbError::InvalidFile);
+        }
+        let mut word = [0u8; 8];
+        word.copy_from_slice(&footer[0..8]);
+        let index_offset = u64::from_le_bytes(word);
+        word.copy_from_slice(&footer[8..16]);
+        let index_size = u64::from_le_bytes(word);
+        let index_end = index_offset
+            .checked_add(index_size)
+            .and_then(|end| end.checked_add(FOOTER_SIZE));
+        if index_offset < MAGIC.len() as u64 || index_end != Some(file_size) {
+            return Err(WdbError::InvalidFile);
+        }
+
+        file.seek(SeekFrom::Start(index_offset))?;
+        let mut index_data = vec![0u8; index_size as usize];
+        file.read_exact(&mut index_data)?;
+        let index: WdbIndex =
+            serde_json::from_slice(&index_data).map_err(|_| WdbError::InvalidFile)?;
+        // Tables are read with the sizes of the index, which must lie before it
+        let valid_table = |s: &SignalEntry| {
+            s.table_offset >= MAGIC.len() as u64
+                && s.block_count
+                    .checked_mul(BLOCK_ENTRY_SIZE)
+                    .and_then(|size| s.table_offset.checked_add(size))
+                    .is_some_and(|end| end <= index_offset)
+        };
+        if !index.signals.iter().all(valid_table) {
+            return Err(WdbError::InvalidFile);
+        }
+        let signal_ids = index
+            .signals
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.id.clone(), i))
+            .collect();
+        let tables = index.signals.iter().map(|_| None).collect();
+        Ok(WaveformDb {
+            file,
+            index,
+            index_offset,
+            signal_ids,
+            tables,
+            cache: BlockCache {
+                budget: DEFAULT_CACHE_BUDGET,
+                ..BlockCache::default()
+            },
+        })
+    }
+
+    /// Sets the memory budget of the block cache, in bytes (`DEFAULT_CACHE_BUDGET` by
+    /// default). Blocks are dropped in least recently used order when it is exceeded, a
+    /// budget of 0 disables the cache.
+    pub fn set_cache_budget(&mut self, bytes: usize) {
+        self.cache.budget = bytes;
+        self.cache.shrink(bytes);
+    }
+
+    /// Counters of the block cache, since the file was opened
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats
+    }
+
+    /// Variables declared in the original header (several may share the same id)
+    pub fn variables(&self) -> &[VariableInfo] {
+        &self.index.variables
+    }
+
+    /// Blocks of a variable, in time order
+    pub fn blocks(&mut self, var_id: &str) -> Result<&[BlockInfo], WdbError> {
+        let signal = *self.signal_ids.get(var_id).ok_or(WdbError::UnknownSignal)?;
+        self.table(signal)
+    }
+
+    /// Time of the last value change in the file
+    pub fn end_time(&self) -> u64 {
+        self.index.end_time
+    }
+
+    /// Block table of a signal, read from the file on first use
+    fn table(&mut self, signal: usize) -> Result<&[BlockInfo], WdbError> {
+        if self.tables[signal].is_none() {
+            let entry = &self.index.signals[signal];
+            let mut data = vec![0u8; (entry.block_count * BLOCK_ENTRY_SIZE) as usize];
+            self.file.seek(SeekFrom::Start(entry.table_offset))?;
+            self.file.read_exact(&mut data)?;
+            let blocks: Vec<BlockInfo> = data
+                .chunks_exact(BLOCK_ENTRY_SIZE as usize)
+                .map(|b| {
+                    let word =
+                        |i: usize| u64::from_le_bytes(b[8 * i..8 * i + 8].try_into().unwrap());
+                    BlockInfo {
+                        offset: word(0),
+                        size: word(1),
+                        first_time: word(2),
+                        last_time: word(3),
+                        count: word(4),
+                    }
+                })
+                .collect();
+            // Blocks are read with the sizes of the table, which must lie before the index
+            // (and hold at least a byte per change)
+            let index_offset = self.index_offset;
+            let valid_block = |b: &BlockInfo| {
+                b.offset >= MAGIC.len() as u64
+                    && b.offset
+                        .checked_add(b.size)
+                        .is_some_and(|end| end <= index_offset)
+                    && b.count <= b.size
+            };
+            if !blocks.iter().all(valid_block) {
+                return Err(WdbError::InvalidFile);
+            }
+            self.tables[signal] = Some(blocks);
+        }
+        Ok(self.tables[signal].as_deref().unwrap_or_default())
+    }
+
+    /// Changes of the `i`-th block of a signal, from the cache when possible
+    fn block(&mut self, signal: usize, i: usize) -> Result<DecodedBlock, WdbError> {
+        if let Some(changes) = self.cache.get((signal, i)) {
+            return Ok(changes);
+        }
+        self.cache.stats.misses += 1;
+        let block = self.table(signal)?[i].clone();
+        let changes = Arc::new(self.read_block(&block)?);
+        self.cache.insert((signal, i), changes.clone());
+        Ok(changes)
+    }
+
+    fn read_block(&mut self, block: &BlockInfo) -> Result<Vec<(u64, Arc<str>)>, WdbError> {
+        let mut data = vec![0u8; block.size as usize];
+        self.file.seek(SeekFrom::Start(block.offset))?;
+        self.file.read_exact(&mut data)?;
+
+        let mut changes = Vec::with_capacity(block.count as usize);
+        let mut time = block.first_time;
+        let mut pos = 0;
+        for _ in 0..block.count {
+            let (delta, n) = read_varint(&data[pos..]).ok_or(WdbError::InvalidFile)?;
+            pos += n;
+            let (value, n) = decode_value(&data[pos..]).ok_or(WdbError::InvalidFile)?;
+            pos += n;
+            // Times must stay within the bounds given by the block table
+            time = time.checked_add(delta).ok_or(WdbError::InvalidFile)?;
+            if time > block.last_time {
+                return Err(WdbError::InvalidFile);
+            }
+            changes.push((time, value));
+        }
+        Ok(changes)
+    }
+
+    /// Returns the changes of a variable in the time window `[start, end]`.
+    ///
+    /// The value held at `start` (i.e., the last change before the window) is included
+    /// as the first element when it exists. Only the blocks overlapping the window are
+    /// read from the file, values are shared with the cached blocks.
+    pub fn changes(
+        &mut self,
+        var_id: &str,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<(u64, Arc<str>)>, WdbError> {
+        let signal = *self.signal_ids.get(var_id).ok_or(WdbError::UnknownSignal)?;
+        let blocks = self.table(signal)?;
+        // First block that may hold the value at `start`
+        let first = blocks
+            .iter()
+            .rposition(|b| b.first_time <= start)
+            .unwrap_or(0);
+        let last = first
+            + blocks[first..]
+                .iter()
+                .take_while(|b| b.first_time <= end)
+                .count();
+
+        let mut result = Vec::new();
+        for i in first..last {
+            for (t, v) in self.block(signal, i)?.iter() {
+                if *t > end {
+                    break;
+                }
+                if *t <= start {
+                    result.clear();
+                }
+                result.push((*t, v.clone()));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns all the changes of a variable, including the ones sharing the same time
+    pub fn all_changes(&mut self, var_id: &str) -> Result<Vec<(u64, Arc<str>)>, WdbError> {
+        let signal = *self.signal_ids.get(var_id).ok_or(WdbError::UnknownSignal)?;
+        let mut result = Vec::new();
+        for i in 0..self.table(signal)?.len() {
+            result.extend(self.block(signal, i)?.iter().cloned());
+        }
+        Ok(result)
+    }
+
+    /// Value of a variable at a given time (None if it was never assigned before)
+    pub fn value_at(&mut self, var_id: &str, time: u64) -> Result<Option<Arc<str>>, WdbError> {
+        let mut changes = self.changes(var_id, time, time)?;
+        Ok(changes.pop().map(|(_, v)| v))
+    }
+}
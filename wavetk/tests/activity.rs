@@ -0,0 +1,133 @@
+use std::io::Cursor;
+
+use wavetk::activity::{idle_intervals, vcd_change_counts, ChangeCount, IdleInterval};
+use wavetk::hierarchy::Demangler;
+use wavetk::simulation::StateSimulation;
+use wavetk::vcd::VcdParser;
+
+const IDLE_VCD: &str = "$timescale 1ns $end
+$scope module top $end
+$var wire 1 ! clk $end
+$scope module dma $end
+$var wire 1 \" busy $end
+$var wire 8 # addr [7:0] $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpvars
+0!
+0\"
+b0 #
+$end
+#10
+1!
+1\"
+#20
+0!
+b1 #
+#30
+1!
+0\"
+#40
+0!
+#50
+1!
+#60
+0!
+#70
+1!
+1\"
+#80
+0!
+b10 #
+";
+
+#[test]
+fn idle_signals_and_scopes() -> Result<(), Box<dyn std::error::Error>> {
+    let mut sim = StateSimulation::from_reader(Cursor::new(IDLE_VCD));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let report = idle_intervals(&mut sim, 10, 2)?;
+    assert_eq!((report.start_time, report.end_time), (0, 80));
+
+    let paths: Vec<&str> = report.signals.iter().map(|s| s.path.as_str()).collect();
+    assert_eq!(paths, ["top.clk", "top.dma.busy", "top.dma.addr"]);
+    let clk = &report.signals[0];
+    assert_eq!(clk.changes, 9);
+    assert_eq!(clk.total_idle, 0);
+    assert!(clk.longest.is_empty());
+
+    let busy = &report.signals[1];
+    assert_eq!(busy.total_idle, 20 + 40);
+    assert_eq!(
+        busy.longest,
+        [
+            IdleInterval { start: 30, end: 70 },
+            IdleInterval { start: 10, end: 30 }
+        ]
+    );
+
+    let addr = &report.signals[2];
+    assert_eq!(addr.total_idle, 20 + 60);
+    assert_eq!(addr.longest[0], IdleInterval { start: 20, end: 80 });
+    assert_eq!(addr.longest[1], IdleInterval { start: 0, end: 20 });
+
+    let scopes: Vec<&str> = report.scopes.iter().map(|s| s.path.as_str()).collect();
+    assert_eq!(scopes, ["top", "top.dma"]);
+    assert_eq!(report.scopes[0].total_idle, 0);
+    assert_eq!(
+        report.scopes[1].longest,
+        [IdleInterval { start: 30, end: 70 }]
+    );
+    assert_eq!(report.scopes[1].total_idle, 40);
+    Ok(())
+}
+
+#[test]
+fn change_counts() -> Result<(), Box<dyn std::error::Error>> {
+    let mut parser = VcdParser::with_chunk_size(64, Cursor::new(IDLE_VCD));
+    parser.load_header()?;
+    let report = vcd_change_counts(&mut parser, &Demangler::default())?;
+    let count = |path: &str, changes, bytes| ChangeCount {
+        path: path.to_string(),
+        changes,
+        bytes,
+    };
+    assert_eq!(
+        report.signals,
+        [
+            count("top.clk", 9, 27),
+            count("top.dma.busy", 4, 12),
+            count("top.dma.addr", 3, 16)
+        ]
+    );
+    assert_eq!(
+        report.scopes,
+        [count("top", 16, 55), count("top.dma", 7, 28)]
+    );
+    // Signals and scopes named after the demangling rules
+    let mut parser = VcdParser::with_chunk_size(64, Cursor::new(IDLE_VCD));
+    parser.load_header()?;
+    let report = vcd_change_counts(&mut parser, &"strip:top".parse()?)?;
+    assert_eq!(report.signals[1], count("dma.busy", 4, 12));
+    assert_eq!(report.scopes, [count("dma", 7, 28)]);
+    Ok(())
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_fst_change_counts() -> Result<(), Box<dyn std::error::Error>> {
+    use wavetk::activity::{fst_change_counts, par_fst_change_counts};
+    use wavetk::fst::FstReader;
+
+    let path = format!("{}/../assets/fst/dlx.fst", env!("CARGO_MANIFEST_DIR"));
+    let mut reader = FstReader::from_file(&path, false).unwrap();
+    let expected = fst_change_counts(&mut reader, &Demangler::default());
+    assert!(expected.signals.iter().any(|s| s.changes > 0));
+    for jobs in [1, 3, 8] {
+        let report = par_fst_change_counts(&path, jobs, &Demangler::default()).unwrap();
+        assert_eq!(report, expected);
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use std::convert::TryFrom;
+
+use wavetk::types::BitVector;
+use wavetk::vcd::VcdValue;
+
+fn bv(s: &str) -> BitVector {
+    BitVector::try_from(&VcdValue::Vector(s)).unwrap()
+}
+
+#[test]
+fn bit_vector_conversions() {
+    let v = BitVector::from(&[1, 0, -4, -3, 1][..]);
+    assert_eq!(v.width(), 5);
+    assert_eq!(v.to_string(), "10xx1");
+    assert_eq!(v, bv("10xz1"));
+    assert_eq!(v.bit(0), Some(true));
+    assert_eq!(v.bit(1), None);
+    assert!(!v.is_known());
+    assert_eq!(v.to_u64(), None);
+    assert_eq!(v.to_levels(), [1, 0, -4, -4, 1]);
+
+    assert_eq!(BitVector::from_u64(0xff, 4).to_string(), "1111");
+    assert_eq!(bv("1011").to_u64(), Some(11));
+    assert_eq!(
+        BitVector::try_from(&VcdValue::Bit('z')),
+        Ok(BitVector::unknown(1))
+    );
+    assert!(BitVector::try_from(&VcdValue::Real("1.0")).is_err());
+
+    let wide = BitVector::from_u64(1, 100).concat(&BitVector::from_u64(5, 3));
+    assert_eq!(wide.width(), 103);
+    assert_eq!(wide.to_u64(), Some(0b1101));
+    assert_eq!(
+        (&wide | &BitVector::from_u64(1, 1).concat(&BitVector::zeros(65))).to_u64(),
+        None
+    );
+    assert_eq!(wide.slice(3, 0).to_u64(), Some(0b1101));
+    assert_eq!(wide.slice(102, 3).to_u64(), Some(1));
+    assert_eq!(wide.resize(3).to_u64(), Some(5));
+    assert_eq!(
+        BitVector::unknown(70).resize(80).slice(79, 68).to_string(),
+        "0000000000xx"
+    );
+}
+
+#[test]
+fn bit_vector_operations() {
+    let a = bv("0011xx");
+    let b = bv("0101x0");
+    assert_eq!((&a & &b).to_string(), "0001x0");
+    assert_eq!((&a | &b).to_string(), "0111xx");
+    assert_eq!((&a ^ &b).to_string(), "0110xx");
+    assert_eq!((!&a).to_string(), "1100xx");
+
+    // Zero-extension of the narrowest operand
+    assert_eq!((&bv("1111") & &bv("10")).to_string(), "0010");
+    assert_eq!((&bv("1x00") | &bv("1")).to_string(), "1x01");
+
+    let x = BitVector::from_u64(u64::MAX, 130);
+    assert_eq!((!&x).to_string(), "1".repeat(66) + &"0".repeat(64));
+    assert_eq!((&x ^ &x), BitVector::zeros(130));
+}
@@ -0,0 +1,97 @@
+use std::io::{Cursor, Read};
+
+use wavetk::compression::{decompress, is_stdin, open_input, Compression};
+
+const VCD: &str = "$var wire 1 ! clk $end\n$var wire 4 \" cnt $end\n$enddefinitions $end\n\
+                   #0\n0!\nb0 \"\n#5\n1!\nb0001 \"\n#10\n0!\n";
+
+#[test]
+fn detect_compression() {
+    assert_eq!(Compression::detect(&[0x1f, 0x8b, 8, 0]), Compression::Gzip);
+    assert_eq!(
+        Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd]),
+        Compression::Zstd
+    );
+    assert_eq!(Compression::detect(b"\xfd7zXZ\x00"), Compression::Xz);
+    assert_eq!(Compression::detect(b"BZh91AY"), Compression::Bzip2);
+    assert_eq!(Compression::detect(b"$date"), Compression::None);
+    assert_eq!(Compression::detect(b""), Compression::None);
+}
+
+#[test]
+fn decompress_plain_input() -> Result<(), Box<dyn std::error::Error>> {
+    // Inputs shorter than the magic are kept as is
+    for input in [VCD, "$e", ""] {
+        let mut out = String::new();
+        decompress(Cursor::new(input.to_string()))?.read_to_string(&mut out)?;
+        assert_eq!(out, input);
+    }
+    Ok(())
+}
+
+#[test]
+fn open_input_file() -> Result<(), Box<dyn std::error::Error>> {
+    assert!(is_stdin("-"));
+    assert!(!is_stdin("./-"));
+    assert!(!is_stdin("dump.vcd"));
+
+    let mut path = std::env::temp_dir();
+    path.push(format!("wavetk-{}-input.vcd", std::process::id()));
+    std::fs::write(&path, VCD)?;
+    let mut out = String::new();
+    open_input(&path)?.read_to_string(&mut out)?;
+    assert_eq!(out, VCD);
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "zstd"))]
+#[test]
+fn decompress_unsupported() {
+    let r = decompress(Cursor::new(vec![0x28, 0xb5, 0x2f, 0xfd, 0, 0]));
+    assert_eq!(
+        r.err().map(|e| e.kind()),
+        Some(std::io::ErrorKind::Unsupported)
+    );
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn simulate_gzip_input() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::Write;
+
+    use wavetk::simulation::StateSimulation;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    encoder.write_all(VCD.as_bytes())?;
+    let data = encoder.finish()?;
+
+    let mut path = std::env::temp_dir();
+    path.push(format!("wavetk-{}-counter.vcd.gz", std::process::id()));
+    std::fs::write(&path, data)?;
+    let mut sim = StateSimulation::new(&path)?;
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let mut cycles = Vec::new();
+    while !sim.done() {
+        cycles.push(sim.next_cycle()?.cycle);
+    }
+    assert_eq!(cycles, [-1, 0, 5, 10]);
+    assert_eq!(sim.value("\""), Some(&[0, 0, 0, 1][..]));
+
+    // Checkpoints of compressed inputs resume in the decompressed stream
+    let mut sim = StateSimulation::new(&path)?;
+    sim.load_header()?;
+    sim.allocate_state()?;
+    sim.next_cycle()?;
+    sim.next_cycle()?;
+    let mut resumed = StateSimulation::resume(&path, &sim.checkpoint()?)?;
+    let mut cycles = Vec::new();
+    while !resumed.done() {
+        cycles.push(resumed.next_cycle()?.cycle);
+    }
+    assert_eq!(cycles, [5, 10]);
+    assert_eq!(resumed.value("\""), Some(&[0, 0, 0, 1][..]));
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
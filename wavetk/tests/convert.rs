@@ -0,0 +1,180 @@
+use std::fs::File;
+use std::path::PathBuf;
+
+use wavetk::convert::{
+    convert_file, digest_file, verify_conversion, ConvertError, Discrepancy, Format,
+};
+use wavetk::vcd::{VcdCommand, VcdError, VcdParser, VcdValue};
+use wavetk::wdb::WaveformDb;
+use wavetk::FstReader;
+
+const VCD: &str = "$timescale 10 ns $end\n$scope module top $end\n$var wire 1 ! clk $end\n\
+                   $var wire 1 ! clk_alias $end\n$scope module cpu $end\n\
+                   $var reg 8 \" data [7:0] $end\n$var real 64 # r $end\n$upscope $end\n\
+                   $upscope $end\n$enddefinitions $end\n\
+                   #0\n$dumpvars\n0!\nb0 \"\nr0.5 #\n$end\n#5\n1!\nb1x1 \"\n\
+                   #10\n$dumpoff\nx!\nbx \"\n$end\n#20\n$dumpon\n0!\nb101 \"\nr1.25 #\n$end\n";
+
+fn tmp_file(name: &str) -> PathBuf {
+    let mut path = std::env::temp_dir();
+    path.push(format!("wavetk-{}-{}", std::process::id(), name));
+    path
+}
+
+/// All the commands of a VCD file, formatted
+fn vcd_commands(path: &PathBuf) -> Vec<String> {
+    let mut parser = VcdParser::with_chunk_size(4096, File::open(path).unwrap());
+    parser.load_header().unwrap();
+    let mut commands = Vec::new();
+    parser
+        .process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+            commands.push(match cmd {
+                VcdCommand::SetCycle(t) => format!("#{}", t),
+                VcdCommand::ValueChange(c) => match c.value {
+                    VcdValue::Bit(b) => format!("{}{}", b, c.var_id),
+                    VcdValue::Vector(x) => format!("b{} {}", x, c.var_id),
+                    VcdValue::Real(x) => format!("r{} {}", x, c.var_id),
+                    VcdValue::String(x) => format!("s{} {}", x, c.var_id),
+                    VcdValue::Event => format!("1{}", c.var_id),
+                    VcdValue::Port(p) => {
+                        format!("p{} {} {} {}", p.states, p.strength0, p.strength1, c.var_id)
+                    }
+                },
+                VcdCommand::BeginDump(d) => format!("${}", d.keyword()),
+                VcdCommand::EndDump(_) | VcdCommand::VcdEnd => "$end".to_string(),
+                VcdCommand::Directive(d) => format!("${}", d),
+                VcdCommand::VcdClose(t) => format!("$vcdclose #{} $end", t),
+            });
+            Ok(false)
+        })
+        .unwrap();
+    commands
+}
+
+#[test]
+fn convert_formats() {
+    assert_eq!(Format::from_path("a/b.FST".as_ref()), Format::Fst);
+    assert_eq!(Format::from_path("b.vcd.gz".as_ref()), Format::Vcd);
+    assert_eq!("wdb".parse(), Ok(Format::Wdb));
+    assert!("txt".parse::<Format>().is_err());
+}
+
+#[test]
+fn convert_vcd_fst_round_trip() -> Result<(), ConvertError> {
+    let (vcd, fst, back) = (
+        tmp_file("round_trip.vcd"),
+        tmp_file("round_trip.fst"),
+        tmp_file("round_trip_back.vcd"),
+    );
+    std::fs::write(&vcd, VCD)?;
+    convert_file(&vcd, &fst, None)?;
+
+    let mut reader = FstReader::from_file(fst.to_str().unwrap(), false)?;
+    assert_eq!(reader.timescale(), -8);
+    assert_eq!(reader.var_count(), 4);
+    assert_eq!(reader.alias_count(), 1);
+    assert_eq!(reader.dump_activity_changes(), [(10, false), (20, true)]);
+    let header = reader.load_header();
+    assert_eq!(header.variables[2].scope[1].name, "cpu");
+    let data = reader.changes_of(header.variables[2].handle)?;
+    assert_eq!(
+        data,
+        [(0, "00000000"), (5, "000001x1"), (20, "00000101")].map(|(t, v)| (t, v.to_string()))
+    );
+
+    // fstapi gives the changes of a time step in its own order
+    convert_file(&fst, &back, None)?;
+    assert_eq!(
+        vcd_commands(&back),
+        [
+            "#0",
+            "r0.5 #",
+            "b00000000 \"",
+            "0!",
+            "#5",
+            "1!",
+            "b000001x1 \"",
+            "#10",
+            "$dumpoff",
+            "$end",
+            "#20",
+            "$dumpon",
+            "$end",
+            "b00000101 \"",
+            "0!",
+            "r1.25 #"
+        ]
+    );
+    for p in [vcd, fst, back] {
+        std::fs::remove_file(p)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn convert_to_wdb() -> Result<(), Box<dyn std::error::Error>> {
+    let (vcd, fst) = (tmp_file("to_wdb.vcd"), tmp_file("to_wdb.fst"));
+    let (from_vcd, from_fst) = (tmp_file("from_vcd.wdb"), tmp_file("from_fst.wdb"));
+    std::fs::write(&vcd, VCD)?;
+    convert_file(&vcd, &fst, None)?;
+    convert_file(&vcd, &from_vcd, None)?;
+    convert_file(&fst, &from_fst, None)?;
+
+    let mut db = WaveformDb::open(&from_vcd)?;
+    assert_eq!(db.changes("\"", 0, 100)?.len(), 4);
+    let mut db = WaveformDb::open(&from_fst)?;
+    assert_eq!(db.variables().len(), 4);
+    // Ids derive from the FST handles, `$dumpoff` values are not part of the FST file
+    assert_eq!(db.changes("\"", 0, 100)?.len(), 3);
+    assert_eq!(db.value_at("#", 20)?.as_deref(), Some("1.25"));
+
+    assert!(matches!(
+        convert_file(&from_vcd, tmp_file("back.vcd"), None),
+        Err(ConvertError::UnsupportedConversion(
+            Format::Wdb,
+            Format::Vcd
+        ))
+    ));
+    for p in [vcd, fst, from_vcd, from_fst] {
+        std::fs::remove_file(p)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn convert_verify() -> Result<(), ConvertError> {
+    let (vcd, fst, wdb) = (
+        tmp_file("verify.vcd"),
+        tmp_file("verify.fst"),
+        tmp_file("verify.wdb"),
+    );
+    std::fs::write(&vcd, VCD)?;
+    convert_file(&vcd, &fst, None)?;
+    convert_file(&vcd, &wdb, None)?;
+    assert_eq!(verify_conversion(&vcd, &fst)?, []);
+    assert_eq!(verify_conversion(&vcd, &wdb)?, []);
+    assert_eq!(verify_conversion(&fst, &wdb)?.len(), 3);
+    let digest = digest_file(&vcd, false)?;
+    assert_eq!(digest["top.clk"], digest["top.clk_alias"]);
+    assert_eq!(digest["top.cpu.data"].changes, 3);
+    assert_eq!(digest_file(&vcd, true)?["top.cpu.data"].changes, 4);
+
+    // Same number of changes, a different value, and a renamed signal
+    let other = tmp_file("verify_other.vcd");
+    let modified = VCD.replace("b101", "b100").replace(" r $end", " s $end");
+    std::fs::write(&other, modified)?;
+    let other_wdb = tmp_file("verify_other.wdb");
+    convert_file(&other, &other_wdb, None)?;
+    assert_eq!(
+        verify_conversion(&vcd, &other_wdb)?,
+        [
+            Discrepancy::Values("top.cpu.data".to_string()),
+            Discrepancy::Missing("top.cpu.r".to_string()),
+            Discrepancy::Unexpected("top.cpu.s".to_string()),
+        ]
+    );
+    for p in [vcd, fst, wdb, other, other_wdb] {
+        std::fs::remove_file(p)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use std::io::Cursor;
+
+use wavetk::coverage::{toggle_coverage, value_coverage, Bin, ToggleCoverage, ValueCoverage};
+use wavetk::simulation::StateSimulation;
+
+const COUNTER_VCD: &str = "$timescale 1ns $end
+$scope module top $end
+$var wire 1 ! clk $end
+$scope module cnt $end
+$var reg 2 \" q [1:0] $end
+$var wire 1 ! clk $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpvars
+0!
+b00 \"
+$end
+#5
+1!
+b01 \"
+#10
+0!
+b10 \"
+#15
+1!
+b1x \"
+";
+
+fn simulation(vcd: &'static str) -> StateSimulation {
+    let mut sim = StateSimulation::from_reader(Cursor::new(vcd));
+    sim.load_header().unwrap();
+    sim.allocate_state().unwrap();
+    sim
+}
+
+#[test]
+fn toggle_report() -> Result<(), Box<dyn std::error::Error>> {
+    let mut sim = simulation(COUNTER_VCD);
+    let report = toggle_coverage(&mut sim)?;
+    assert_eq!(report.scopes.len(), 2);
+    assert_eq!(report.scopes[0].path, "top");
+    assert_eq!(report.scopes[1].path, "top.cnt");
+
+    let q = &report.scopes[1].nets[0];
+    assert_eq!(q.name, "q");
+    // MSB rises (01 -> 10), LSB rises (00 -> 01) and falls (01 -> 10)
+    assert_eq!(q.rise, vec![true, true]);
+    assert_eq!(q.fall, vec![false, true]);
+
+    let clk = &report.scopes[0].nets[0];
+    assert_eq!(clk.rise, vec![true]);
+    assert_eq!(clk.fall, vec![true]);
+    // The aliased clock is reported in both scopes
+    assert_eq!(report.scopes[1].summary.bits, 3);
+    assert_eq!(report.scopes[1].summary.covered, 2);
+    assert_eq!(report.total.bits, 4);
+    assert_eq!(report.total.covered, 3);
+
+    let json = serde_json::to_value(&report)?;
+    assert_eq!(json["version"], 1);
+    assert_eq!(json["scopes"][1]["nets"][0]["fall"][1], true);
+    Ok(())
+}
+
+#[test]
+fn value_bins() -> Result<(), Box<dyn std::error::Error>> {
+    let mut sim = simulation(COUNTER_VCD);
+    let mut bins = ValueCoverage::new();
+    bins.add_bins(
+        "\"",
+        vec![
+            ("zero", Bin::Value(0)),
+            ("low", Bin::Range(0, 1)),
+            ("three", Bin::Value(3)),
+            ("others", Bin::Default),
+        ],
+    );
+    let bins = value_coverage(&mut sim, bins)?;
+    let q = bins.bus("\"").unwrap();
+    let hits: Vec<u64> = q.bins.iter().map(|b| b.hits).collect();
+    // Values seen: 00 (initial state, unchanged at #0), 01, 10 and 1x
+    assert_eq!(hits, vec![1, 2, 0, 1]);
+    assert_eq!(q.unknown, 1);
+    let holes: Vec<&str> = q.holes().map(|b| b.name.as_str()).collect();
+    assert_eq!(holes, vec!["three"]);
+    Ok(())
+}
+
+#[test]
+fn scope_rollups() -> Result<(), Box<dyn std::error::Error>> {
+    let mut sim = StateSimulation::from_reader(Cursor::new(COUNTER_VCD));
+    sim.load_header()?;
+    sim.set_stats(true);
+    sim.allocate_state()?;
+    let mut coverage = ToggleCoverage::new();
+    while !sim.done() {
+        let state = sim.next_cycle()?.state;
+        coverage.update(state);
+    }
+
+    let toggles = coverage.scope_rollup(&sim)?;
+    let paths: Vec<&str> = toggles.iter().map(|s| s.path.as_str()).collect();
+    assert_eq!(paths, ["top", "top.cnt"]);
+    assert_eq!((toggles[0].total.bits, toggles[0].total.covered), (4, 3));
+    assert_eq!((toggles[1].total.bits, toggles[1].total.covered), (3, 2));
+
+    let stats = sim.scope_stats();
+    let (clk, q) = (sim.stats("!").unwrap(), sim.stats("\"").unwrap());
+    assert_eq!(stats[0].path, "top");
+    assert_eq!(stats[0].total.changes, clk.changes + q.changes);
+    assert_eq!(stats[0].total.last_change, Some(15));
+    assert_eq!(stats[1].path, "top.cnt");
+    assert_eq!(stats[1].total, q);
+    Ok(())
+}
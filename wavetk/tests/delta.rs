@@ -0,0 +1,80 @@
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use wavetk::delta::{stream_deltas, DeltaReader};
+use wavetk::simulation::{CycleStep, StateSimulation};
+
+fn vcd_asset(rel_path: &str) -> PathBuf {
+    let mut path = PathBuf::from(file!());
+    path.pop();
+    path.pop();
+    path.pop();
+    path.push("assets/vcd");
+    path.push(rel_path);
+    path
+}
+
+fn simulation() -> Result<StateSimulation, Box<dyn std::error::Error>> {
+    let mut sim = StateSimulation::new(vcd_asset("good/verilator_riscv.vcd"))?;
+    sim.load_header()?;
+    sim.allocate_state()?;
+    Ok(sim)
+}
+
+#[test]
+fn delta_stream_replays_states() -> Result<(), Box<dyn std::error::Error>> {
+    let stream = stream_deltas(&mut simulation()?, Vec::new())?;
+    let mut reader = DeltaReader::new(Cursor::new(&stream))?;
+    let mut sim = simulation()?;
+    assert_eq!(reader.state().len(), sim.state().len());
+    let mut frames = 0;
+    while let Some(cycle) = reader.next_frame()? {
+        let CycleStep {
+            cycle: expected,
+            state,
+            ..
+        } = sim.next_cycle()?;
+        assert_eq!(cycle, expected);
+        assert_eq!(reader.state(), state);
+        frames += 1;
+    }
+    assert!(sim.done());
+    assert!(frames > 10);
+    // Only changes are sent after the first frame
+    assert!(stream.len() < frames * sim.state().len());
+    Ok(())
+}
+
+#[test]
+fn delta_stream_rejects_other_data() {
+    assert!(DeltaReader::new(Cursor::new(b"WTKX\x01\0\0\0")).is_err());
+    // States too large to be allocated
+    let mut stream = b"WTKD\x01\0\0\0".to_vec();
+    stream.extend_from_slice(&u64::MAX.to_le_bytes());
+    let e = DeltaReader::new(Cursor::new(&stream)).err().unwrap();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    let mut stream = b"WTKD\x01\0\0\0".to_vec();
+    stream.extend_from_slice(&16u64.to_le_bytes());
+    let e = DeltaReader::with_max_state_size(Cursor::new(&stream), 15)
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    assert!(DeltaReader::with_max_state_size(Cursor::new(&stream), 16).is_ok());
+}
+
+#[test]
+fn delta_stream_rejects_entries_out_of_the_state() -> Result<(), Box<dyn std::error::Error>> {
+    for (offset, width) in [(2u64, 2u32), (u64::MAX, 2)] {
+        let mut stream = b"WTKD\x01\0\0\0".to_vec();
+        stream.extend_from_slice(&3u64.to_le_bytes());
+        stream.extend_from_slice(&0i64.to_le_bytes());
+        stream.extend_from_slice(&1u32.to_le_bytes());
+        stream.extend_from_slice(&offset.to_le_bytes());
+        stream.extend_from_slice(&width.to_le_bytes());
+        stream.extend_from_slice(&[1, 1]);
+        let mut reader = DeltaReader::new(Cursor::new(stream))?;
+        let e = reader.next_frame().unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    }
+    Ok(())
+}
@@ -0,0 +1,191 @@
+use std::io::Cursor;
+
+use wavetk::derived::{DerivedError, DerivedSignal};
+use wavetk::simulation::StateSimulation;
+
+const FIFO_VCD: &str = "$timescale 1ns $end
+$scope module top $end
+$scope module fifo $end
+$var wire 2 ! wr_ptr [1:0] $end
+$var wire 2 \" rd_ptr [1:0] $end
+$var wire 1 # empty $end
+$upscope $end
+$var wire 1 $ empty $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpvars
+b00 !
+b00 \"
+1#
+0$
+$end
+#10
+b01 !
+0#
+#20
+b10 !
+#30
+bx \"
+#40
+b01 \"
+#50
+b10 \"
+1#
+";
+
+/// Simulation of `FIFO_VCD` tracking the given signals and derived signals
+fn fifo_sim(
+    tracked: &[&str],
+    derived: &[&str],
+) -> Result<StateSimulation, Box<dyn std::error::Error>> {
+    let mut sim = StateSimulation::from_reader(Cursor::new(FIFO_VCD));
+    sim.load_header()?;
+    sim.track_variables(tracked);
+    for d in derived {
+        sim.add_derived_signal(d.parse()?)?;
+    }
+    sim.allocate_state()?;
+    Ok(sim)
+}
+
+/// Changes of a signal, as `cycle: value`
+fn changes(
+    sim: &mut StateSimulation,
+    path: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let id = sim
+        .variables()
+        .iter()
+        .find(|v| sim.path_of(v) == path)
+        .map(|v| v.id.clone())
+        .unwrap();
+    let mut changes = Vec::new();
+    while !sim.done() {
+        let cycle = sim.next_cycle()?.cycle;
+        if sim.last_change(&id) == Some(cycle) {
+            changes.push(format!("{}: {}", cycle, sim.bit_vector(&id).unwrap()));
+        }
+    }
+    Ok(changes)
+}
+
+#[test]
+fn derived_parse() {
+    let d: DerivedSignal = "top.full = wr_ptr - rd_ptr == 3 && !fifo.empty"
+        .parse()
+        .unwrap();
+    assert_eq!(d.name(), "top.full");
+    assert_eq!(
+        d.signals().collect::<Vec<_>>(),
+        ["wr_ptr", "rd_ptr", "fifo.empty"]
+    );
+    assert_eq!(d.width(), None);
+    for bad in [
+        "x", "= a", "a == b", "y = a +", "y = (a", "y = a b", "y = 0xg",
+    ] {
+        assert!(
+            matches!(bad.parse::<DerivedSignal>(), Err(DerivedError::Syntax(_))),
+            "{}",
+            bad
+        );
+    }
+}
+
+#[test]
+fn derived_signals() -> Result<(), Box<dyn std::error::Error>> {
+    let full = "top.full = wr_ptr == rd_ptr + 2 && !fifo.empty";
+    let mut sim = fifo_sim(&[], &[full])?;
+    let (offset, info) = sim.tracked_variables().last().unwrap().clone();
+    assert_eq!((offset, info.name.as_str(), info.width), (6, "full", 1));
+    assert_eq!(
+        changes(&mut sim, "top.full")?,
+        ["-1: 0", "20: 1", "30: x", "40: 0"]
+    );
+
+    // Operands are tracked along with the derived signal, which may use earlier ones
+    let used = "top.fifo.used = (wr_ptr - rd_ptr) & 0b11";
+    let mut sim = fifo_sim(&["$"], &[used, "top.half = used[1:0] >= 2 || fifo.empty"]);
+    assert!(matches!(
+        sim.as_ref()
+            .err()
+            .and_then(|e| e.downcast_ref::<DerivedError>()),
+        Some(DerivedError::UnknownSignal(_))
+    ));
+    sim = fifo_sim(&["$"], &[used, "top.half = used >= 2 || fifo.empty"]);
+    let mut sim = sim?;
+    assert_eq!(sim.tracked_variables().len(), 6);
+    assert_eq!(
+        changes(&mut sim, "top.fifo.used")?,
+        ["-1: 00", "10: 01", "20: 10", "30: xx", "40: 01", "50: 00"]
+    );
+
+    let mut sim = StateSimulation::from_reader(Cursor::new(FIFO_VCD));
+    sim.load_header()?;
+    let errors = [
+        (
+            "a = empty",
+            DerivedError::AmbiguousSignal("empty".to_string(), vec![]),
+        ),
+        ("a = nope", DerivedError::UnknownSignal("nope".to_string())),
+        (
+            "top.empty = 1",
+            DerivedError::DuplicateName("top.empty".to_string()),
+        ),
+    ];
+    for (text, expected) in errors {
+        let result = sim.add_derived_signal(text.parse()?);
+        match (result, expected) {
+            (Err(DerivedError::AmbiguousSignal(s, paths)), DerivedError::AmbiguousSignal(e, _)) => {
+                assert_eq!((s, paths.len()), (e, 2))
+            }
+            (result, expected) => assert_eq!(result, Err(expected)),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn derived_unallocated_signal() -> Result<(), Box<dyn std::error::Error>> {
+    use wavetk::vcd::VcdError;
+
+    let vcd = "$scope module top $end\n$var wire 1 ! d [0] $end\n$var wire 1 \" d [1] $end\n\
+               $upscope $end\n$enddefinitions $end\n#0\n1!\n0\"\n";
+    let mut sim = StateSimulation::from_reader(Cursor::new(vcd));
+    sim.set_bus_reconstruction(true);
+    sim.load_header()?;
+    sim.allocate_state()?;
+    sim.add_derived_signal("top.one = d == 1".parse()?)?;
+    // The bus used by the expression is gone without the reconstruction
+    sim.set_bus_reconstruction(false);
+    match sim.allocate_state() {
+        Err(VcdError::User(e)) => assert_eq!(
+            e.downcast_ref::<DerivedError>(),
+            Some(&DerivedError::UnallocatedSignal("d".to_string()))
+        ),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    sim.set_bus_reconstruction(true);
+    sim.allocate_state()?;
+    sim.next_cycle()?;
+    let step = sim.next_cycle()?;
+    assert_eq!((step.cycle, step.state.last()), (0, Some(&1)));
+    Ok(())
+}
+
+#[test]
+fn derived_write_tracked() -> Result<(), Box<dyn std::error::Error>> {
+    let mut sim = fifo_sim(&["!"], &["top.fifo.half = wr_ptr >= 2"])?;
+    let vcd = String::from_utf8(sim.write_tracked_to(Vec::new())?)?;
+    let mut written = StateSimulation::from_reader(Cursor::new(vcd));
+    written.load_header()?;
+    written.allocate_state()?;
+    let paths: Vec<String> = written
+        .variables()
+        .iter()
+        .map(|v| written.path_of(v))
+        .collect();
+    assert_eq!(paths, ["top.fifo.wr_ptr", "top.fifo.half"]);
+    assert_eq!(changes(&mut written, "top.fifo.half")?, ["-1: 0", "20: 1"]);
+    Ok(())
+}
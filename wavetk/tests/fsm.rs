@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use wavetk::fsm::extract_fsm;
+use wavetk::simulation::StateSimulation;
+use wavetk::vcd::VcdError;
+
+const FSM_VCD: &str = "$timescale 1ns $end
+$scope module top $end
+$var reg 2 # state [1:0] $end
+$upscope $end
+$enddefinitions $end
+#0
+b00 #
+#10
+b01 #
+#20
+b10 #
+#30
+b00 #
+#40
+b01 #
+#50
+b11 #
+#60
+b1x #
+";
+
+#[test]
+fn fsm_graph() -> Result<(), Box<dyn std::error::Error>> {
+    let mut sim = StateSimulation::from_reader(Cursor::new(FSM_VCD));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let mut names = HashMap::new();
+    names.insert(0, "IDLE".to_string());
+    names.insert(1, "REQ".to_string());
+    let graph = extract_fsm(&mut sim, "#", &names)?;
+
+    let labels: Vec<&str> = graph.states.iter().map(|s| s.label.as_str()).collect();
+    assert_eq!(labels, vec!["IDLE", "REQ", "2", "3", "1X"]);
+    assert_eq!(graph.states[0].visits, 2);
+    assert_eq!(graph.states[3].first_time, 50);
+    assert_eq!(graph.states[4].value, None);
+
+    let idle_req = &graph.transitions[0];
+    assert_eq!((idle_req.from, idle_req.to), (0, 1));
+    assert_eq!(idle_req.count, 2);
+    assert_eq!(idle_req.first_time, 10);
+    assert_eq!(graph.transitions.len(), 5);
+
+    let dot = graph.to_dot("top.state");
+    assert!(dot.starts_with("digraph \"top.state\" {"));
+    assert!(dot.contains("s0 -> s1 [label=\"2 (@10)\"];"));
+    Ok(())
+}
+
+#[test]
+fn fsm_states_by_value() -> Result<(), Box<dyn std::error::Error>> {
+    // A name equal to the label of another encoding, and a name shared by two encodings
+    let mut sim = StateSimulation::from_reader(Cursor::new(FSM_VCD));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let mut names = HashMap::new();
+    names.insert(0, "2".to_string());
+    names.insert(1, "BUSY".to_string());
+    names.insert(3, "BUSY".to_string());
+    let graph = extract_fsm(&mut sim, "#", &names)?;
+    let states: Vec<(Option<u64>, &str)> = graph
+        .states
+        .iter()
+        .map(|s| (s.value, s.label.as_str()))
+        .collect();
+    assert_eq!(
+        states,
+        [
+            (Some(0), "2"),
+            (Some(1), "BUSY"),
+            (Some(2), "2"),
+            (Some(3), "BUSY"),
+            (None, "1X")
+        ]
+    );
+    assert_eq!(graph.transitions.len(), 5);
+
+    // Backslashes are escaped before quotes
+    let dot = graph.to_dot("top\\\"state");
+    assert!(dot.starts_with("digraph \"top\\\\\\\"state\" {"));
+    Ok(())
+}
+
+#[test]
+fn fsm_unknown_register() {
+    let mut sim = StateSimulation::from_reader(Cursor::new(FSM_VCD));
+    sim.load_header().unwrap();
+    sim.allocate_state().unwrap();
+    assert!(matches!(
+        extract_fsm(&mut sim, "nope", &HashMap::new()),
+        Err(VcdError::UnknownVariable)
+    ));
+}
@@ -0,0 +1,284 @@
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use wavetk::fst::{
+    FstError, FstPackType, FstReader, FstSimulation, FstWriter, FST_WRITER_PARALLEL,
+};
+use wavetk::simulation::{LEVEL_0, LEVEL_1};
+use wavetk::types::{Direction, ScopeKind, VariableKind};
+
+fn tmp_file(name: &str) -> PathBuf {
+    let mut path = std::env::temp_dir();
+    path.push(format!("wavetk-{}-{}", std::process::id(), name));
+    path
+}
+
+fn write_counter(path: &Path, pack_type: FstPackType, parallel: bool, repack: bool) {
+    let mut writer = FstWriter::create(path.to_str().unwrap(), true).unwrap();
+    writer.set_pack_type(pack_type);
+    match writer.set_parallel_mode(parallel) {
+        Err(FstError::Unsupported) => assert!(parallel && !FST_WRITER_PARALLEL),
+        r => r.unwrap(),
+    }
+    writer.set_repack_on_close(repack);
+    writer.set_timescale(-9);
+    writer.push_scope(ScopeKind::VcdModule, "top").unwrap();
+    let clk = writer
+        .create_var(VariableKind::VcdWire, Direction::Implicit, 1, "clk", None)
+        .unwrap();
+    let cnt = writer
+        .create_var(VariableKind::VcdReg, Direction::Implicit, 4, "cnt", None)
+        .unwrap();
+    writer.pop_scope();
+
+    for t in 0..16u64 {
+        writer.emit_time_change(t * 10);
+        writer.emit_value_change(clk, b"1").unwrap();
+        let value = format!("{:04b}", t);
+        writer.emit_value_change(cnt, value.as_bytes()).unwrap();
+        writer.emit_time_change(t * 10 + 5);
+        writer.emit_value_change(clk, b"0").unwrap();
+    }
+    assert!(writer.emit_value_change(cnt, b"1").is_err());
+}
+
+#[test]
+fn fst_write_read_back() {
+    let configs = [
+        (FstPackType::Zlib, false, false),
+        (FstPackType::FastLz, true, false),
+        (FstPackType::Lz4, false, true),
+    ];
+    for (i, (pack_type, parallel, repack)) in configs.iter().enumerate() {
+        let path = tmp_file(&format!("counter_{}.fst", i));
+        write_counter(&path, *pack_type, *parallel, *repack);
+
+        let mut reader = FstReader::from_file(path.to_str().unwrap(), false).unwrap();
+        assert_eq!(reader.timescale(), -9);
+        assert_eq!(reader.end_time(), 155);
+        let t = reader.timestamp(155);
+        assert!((t.ns().unwrap() - 155.0).abs() < 1e-9);
+        let header = reader.load_header();
+        let names: Vec<&str> = header.variables.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["clk", "cnt"]);
+        assert_eq!(header.variables[1].scope[0].name, "top");
+
+        let mut last_cnt = String::new();
+        reader.iter_blocks(|_, handle, value| {
+            if handle == header.variables[1].handle {
+                let v = unsafe { CStr::from_ptr(value as *const c_char) };
+                last_cnt = v.to_str().unwrap().to_string();
+            }
+        });
+        assert_eq!(last_cnt, "1111");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
+
+#[test]
+fn fst_statistics() {
+    let path = tmp_file("statistics.fst");
+    {
+        let mut writer = FstWriter::create(path.to_str().unwrap(), false).unwrap();
+        writer.push_scope(ScopeKind::VcdModule, "top").unwrap();
+        let clk = writer
+            .create_var(VariableKind::VcdWire, Direction::Implicit, 1, "clk", None)
+            .unwrap();
+        writer
+            .create_var(
+                VariableKind::VcdWire,
+                Direction::Implicit,
+                1,
+                "ck",
+                Some(clk),
+            )
+            .unwrap();
+        writer.pop_scope();
+        for t in 0..4u64 {
+            writer.emit_time_change(t * 10);
+            if t == 2 {
+                writer.emit_dump_active(false);
+            }
+            writer.emit_value_change(clk, b"1").unwrap();
+            writer.flush();
+        }
+    }
+    let reader = FstReader::from_file(path.to_str().unwrap(), false).unwrap();
+    assert_eq!(reader.var_count(), 2);
+    assert_eq!(reader.alias_count(), 1);
+    // fstapi may merge consecutive flushes into a single block
+    assert!(reader.value_change_section_count() > 1);
+    assert!(reader.memory_used_by_writer() > 0);
+    assert_eq!(reader.dump_activity_changes(), [(20, false)]);
+    assert!(reader.double_endian_match());
+    assert!(!reader.fseek_failed());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn fst_follow() {
+    let path = tmp_file("follow.fst");
+    let name = path.to_str().unwrap();
+    let mut writer = FstWriter::create(name, false).unwrap();
+    writer.push_scope(ScopeKind::VcdModule, "top").unwrap();
+    let clk = writer
+        .create_var(VariableKind::VcdWire, Direction::Implicit, 1, "clk", None)
+        .unwrap();
+    let cnt = writer
+        .create_var(VariableKind::VcdReg, Direction::Implicit, 4, "cnt", None)
+        .unwrap();
+    writer.pop_scope();
+    let emit = |writer: &mut FstWriter, times: std::ops::Range<u64>| {
+        for t in times.clone() {
+            writer.emit_time_change(t * 10);
+            writer.emit_value_change(clk, b"1").unwrap();
+            if t % 2 == 0 {
+                writer
+                    .emit_value_change(cnt, format!("{:04b}", t).as_bytes())
+                    .unwrap();
+            }
+        }
+        // The block is written at the next time change
+        writer.flush();
+        writer.emit_time_change(times.end * 10);
+    };
+    emit(&mut writer, 0..4);
+
+    let mut reader = FstReader::from_file(name, false).unwrap();
+    let mut seen = Vec::new();
+    reader.iter_new_blocks(|time, handle, _| seen.push((time, handle)));
+    assert_eq!(seen.len(), 6);
+    assert_eq!(reader.iter_new_blocks(|_, _, _| panic!("no new change")), 0);
+    assert!(!reader.refresh().unwrap());
+
+    emit(&mut writer, 4..7);
+    assert!(reader.refresh().unwrap());
+    seen.clear();
+    reader.iter_new_blocks(|time, handle, _| seen.push((time, handle)));
+    assert_eq!(
+        seen,
+        [(40, cnt), (40, clk), (50, clk), (60, clk), (60, cnt)]
+    );
+
+    // Stops at the first change of `cnt` after 60
+    emit(&mut writer, 7..9);
+    let mut stopped = None;
+    reader.follow(Duration::from_millis(1), None, |time, handle, _| {
+        stopped = Some(time);
+        handle == cnt
+    });
+    assert_eq!(stopped, Some(80));
+    let start = Instant::now();
+    reader.follow(
+        Duration::from_millis(1),
+        Some(Duration::from_millis(20)),
+        |_, _, _| panic!("no new change"),
+    );
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    drop(writer);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn fst_changes_of() {
+    let path = tmp_file("changes_of.fst");
+    write_counter(&path, FstPackType::Zlib, false, false);
+    let mut reader = FstReader::from_file(path.to_str().unwrap(), false).unwrap();
+    let header = reader.load_header();
+    let cnt = reader.changes_of(header.variables[1].handle).unwrap();
+    assert_eq!(cnt.len(), 16);
+    assert_eq!(cnt[3], (30, "0011".to_string()));
+    let clk = reader.changes_of(header.variables[0].handle).unwrap();
+    assert_eq!(clk.len(), 32);
+    assert_eq!(clk[1], (5, "0".to_string()));
+    assert_eq!(reader.changes_of(3), Err(FstError::UnknownHandle));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn fst_tracked_handles() {
+    let path = tmp_file("tracked.fst");
+    write_counter(&path, FstPackType::Zlib, false, false);
+    let mut reader = FstReader::from_file(path.to_str().unwrap(), false).unwrap();
+    let header = reader.load_header();
+    let cnt = header.variables[1].handle;
+    reader.track_handles(&[cnt]);
+    assert_eq!(reader.tracked_handles(), [cnt]);
+    let mut seen = Vec::new();
+    reader.iter_blocks(|_, handle, _| seen.push(handle));
+    assert_eq!(seen, vec![cnt; 16]);
+    // The selection is kept across calls, including after `changes_of`
+    assert_eq!(
+        reader.changes_of(header.variables[0].handle).unwrap().len(),
+        32
+    );
+    let mut n = 0;
+    reader.iter_blocks(|_, _, _| n += 1);
+    assert_eq!(n, 16);
+
+    // Replaced by the handles of paths, unknown paths leave the selection as is
+    let clk = header.variables[0].handle;
+    reader.set_tracked_handles(&[]);
+    assert_eq!(
+        reader.track_variables(&["top.clk", "top.nope"]),
+        Err(FstError::UnknownVariable)
+    );
+    assert!(reader.tracked_handles().is_empty());
+    reader.track_variables(&["top.clk", "top.clk"]).unwrap();
+    assert_eq!(reader.tracked_handles(), [clk]);
+    let mut seen = Vec::new();
+    reader.iter_blocks(|_, handle, _| seen.push(handle));
+    assert_eq!(seen, vec![clk; 32]);
+    reader.set_tracked_handles(&[cnt]);
+    assert_eq!(reader.tracked_handles(), [cnt]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn fst_simulation_follow() {
+    let path = tmp_file("simulation.fst");
+    write_counter(&path, FstPackType::Zlib, false, false);
+    let mut sim = FstSimulation::from_file(path.to_str().unwrap()).unwrap();
+    assert_eq!(
+        sim.track_variables(&["top.cnt", "top.nope"]),
+        Err(FstError::UnknownVariable)
+    );
+    assert!(sim.tracked_variables().is_empty());
+    sim.track_variables(&["top.cnt", "top.clk", "top.cnt"])
+        .unwrap();
+    assert_eq!(sim.slot_of("top.cnt"), Some((0, 4)));
+    assert_eq!(sim.slot_of("top.clk"), Some((4, 1)));
+    assert_eq!(sim.slot_of("top.nope"), None);
+
+    let mut seen = Vec::new();
+    sim.follow(
+        Duration::from_millis(1),
+        Some(Duration::from_millis(10)),
+        |time, state| {
+            seen.push((time, state.to_vec()));
+            false
+        },
+    );
+    assert_eq!(seen.len(), 32);
+    assert_eq!(
+        seen[6],
+        (30, vec![LEVEL_0, LEVEL_0, LEVEL_1, LEVEL_1, LEVEL_1])
+    );
+    assert_eq!(
+        seen[7],
+        (35, vec![LEVEL_0, LEVEL_0, LEVEL_1, LEVEL_1, LEVEL_0])
+    );
+
+    // Stops when `f` returns true
+    let mut sim = FstSimulation::from_file(path.to_str().unwrap()).unwrap();
+    sim.track_variables(&["top.cnt"]).unwrap();
+    let mut last = None;
+    sim.follow(Duration::from_millis(1), None, |time, state| {
+        last = Some(time);
+        state == [LEVEL_0, LEVEL_1, LEVEL_0, LEVEL_1]
+    });
+    assert_eq!(last, Some(50));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,109 @@
+use std::io::Cursor;
+
+use wavetk::groups::{GroupError, SignalGroups};
+use wavetk::hierarchy::{Demangler, HierarchyChange};
+use wavetk::simulation::StateSimulation;
+use wavetk::VcdParser;
+
+const AXI_VCD: &str = "$scope module top $end
+$var wire 1 ! clk $end
+$scope module axi $end
+$var wire 1 \" arvalid $end
+$var wire 1 # arready $end
+$var wire 32 $ araddr [31:0] $end
+$var wire 1 ! clk $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+0\"
+1#
+b0 $
+";
+
+fn groups() -> SignalGroups {
+    let mut groups = SignalGroups::new();
+    groups.define("axi_rd", &["axi.arvalid", "arready", "araddr"]);
+    groups.define("clocks", &["clk"]);
+    groups
+}
+
+#[test]
+fn group_files() -> Result<(), Box<dyn std::error::Error>> {
+    let mut groups = groups();
+    assert_eq!(groups.names().collect::<Vec<_>>(), ["axi_rd", "clocks"]);
+    assert_eq!(
+        groups.expand(&["@clocks", "top.axi.araddr"])?,
+        ["clk", "top.axi.araddr"]
+    );
+    assert!(matches!(
+        groups.expand(&["@debug"]),
+        Err(GroupError::UnknownGroup(g)) if g == "debug"
+    ));
+
+    let path = std::env::temp_dir().join(format!("wavetk-{}-groups.json", std::process::id()));
+    groups.save(&path)?;
+    assert_eq!(SignalGroups::load(&path)?, groups);
+    assert!(groups.remove("clocks"));
+    assert!(!groups.remove("clocks"));
+    assert_eq!(groups.len(), 1);
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn group_operations() -> Result<(), Box<dyn std::error::Error>> {
+    let groups = groups();
+    let mut parser = VcdParser::with_chunk_size(4096, AXI_VCD.as_bytes());
+    let variables = parser.load_header()?.variables.clone();
+    assert_eq!(groups.select("axi_rd", &variables)?, [1, 2, 3]);
+    // Both declarations of the aliased clock
+    assert_eq!(groups.select("clocks", &variables)?, [0, 4]);
+
+    let mut sim = StateSimulation::from_reader(Cursor::new(AXI_VCD));
+    sim.load_header()?;
+    assert_eq!(groups.track("clocks", &mut sim)?, ["!"]);
+    assert_eq!(groups.track("axi_rd", &mut sim)?, ["\"", "#", "$"]);
+    sim.allocate_state()?;
+    assert_eq!(sim.tracked_variables().len(), 4);
+
+    // A dump where arready is gone and araddr got narrower
+    let narrow = AXI_VCD
+        .replace("$var wire 1 # arready $end\n", "")
+        .replace("32 $ araddr [31:0]", "16 $ araddr [15:0]");
+    let mut parser = VcdParser::with_chunk_size(4096, narrow.as_bytes());
+    let new = parser.load_header()?.variables.clone();
+    assert!(matches!(
+        groups.select("axi_rd", &new),
+        Err(GroupError::UnknownSignal(s)) if s == "arready"
+    ));
+    assert_eq!(
+        groups.diff("axi_rd", &variables, &new)?,
+        [
+            HierarchyChange::WidthChanged {
+                path: "top.axi.araddr".to_string(),
+                from: 32,
+                to: 16
+            },
+            HierarchyChange::Removed("top.axi.arready".to_string()),
+        ]
+    );
+    assert!(groups.diff("clocks", &variables, &new)?.is_empty());
+
+    // Signals matched and reported by their demangled names
+    let stripped: Demangler = "strip:top".parse()?;
+    assert_eq!(
+        groups.diff_with("axi_rd", &variables, &new, &stripped)?[0],
+        HierarchyChange::WidthChanged {
+            path: "axi.araddr".to_string(),
+            from: 32,
+            to: 16
+        }
+    );
+    let mut rooted = SignalGroups::new();
+    rooted.define("rd", &["top.axi.arvalid"]);
+    assert_eq!(rooted.select("rd", &variables)?, [1]);
+    assert!(rooted.select_with("rd", &variables, &stripped).is_err());
+    Ok(())
+}
@@ -0,0 +1,207 @@
+use wavetk::hierarchy::{diff_hierarchies, Completion, HierarchyChange, NameIndex, ScopeNode};
+use wavetk::types::{ScopeKind, VariableInfo};
+use wavetk::{VcdError, VcdParser};
+
+fn variables(vcd: &str) -> Result<Vec<VariableInfo>, VcdError> {
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    Ok(parser.load_header()?.variables.clone())
+}
+
+#[test]
+fn hierarchy_diff() -> Result<(), VcdError> {
+    let old = variables(
+        "$scope module top $end\n$var wire 1 ! clk $end\n$var wire 1 \" irq $end\n\
+         $var wire 2 # gone $end\n$scope module cpu $end\n$var reg 8 $ pc $end\n\
+         $scope module alu $end\n$var reg 8 % a $end\n$upscope $end\n$upscope $end\n\
+         $scope module mem $end\n$var wire 32 & data $end\n$var wire 10 ' addr $end\n\
+         $upscope $end\n$upscope $end\n$enddefinitions $end\n",
+    )?;
+    let new = variables(
+        "$scope module top $end\n$var wire 1 ! clk $end\n$var wire 4 \" status $end\n\
+         $scope module soc $end\n$scope module cpu $end\n$var reg 8 $ pc $end\n\
+         $scope module alu $end\n$var reg 16 % a $end\n$upscope $end\n$upscope $end\n\
+         $upscope $end\n$scope module mem $end\n$var wire 64 & data $end\n\
+         $var wire 10 ' address $end\n$upscope $end\n$scope module intc $end\n\
+         $var wire 1 ( irq $end\n$upscope $end\n$upscope $end\n$enddefinitions $end\n",
+    )?;
+    let changes = diff_hierarchies(&old, &new);
+    assert_eq!(
+        changes,
+        [
+            HierarchyChange::ScopeMoved {
+                from: "top.cpu".to_string(),
+                to: "top.soc.cpu".to_string()
+            },
+            HierarchyChange::Moved {
+                from: "top.irq".to_string(),
+                to: "top.intc.irq".to_string()
+            },
+            HierarchyChange::Renamed {
+                from: "top.mem.addr".to_string(),
+                to: "top.mem.address".to_string()
+            },
+            HierarchyChange::WidthChanged {
+                path: "top.mem.data".to_string(),
+                from: 32,
+                to: 64
+            },
+            HierarchyChange::WidthChanged {
+                path: "top.soc.cpu.alu.a".to_string(),
+                from: 8,
+                to: 16
+            },
+            HierarchyChange::Removed("top.gone".to_string()),
+            HierarchyChange::Added("top.status".to_string()),
+        ]
+    );
+    assert_eq!(changes[0].to_string(), "moved scope top.cpu to top.soc.cpu");
+    assert!(diff_hierarchies(&old, &old).is_empty());
+    Ok(())
+}
+
+#[test]
+fn name_completion() -> Result<(), VcdError> {
+    let index = NameIndex::new(&variables(
+        "$scope module top $end\n$var wire 1 ! clk $end\n$scope module cpu $end\n\
+         $var reg 8 \" alu_out $end\n$var reg 8 # pc $end\n$scope module alu $end\n\
+         $var reg 8 $ a $end\n$var reg 8 % b $end\n$upscope $end\n$upscope $end\n\
+         $upscope $end\n$enddefinitions $end\n",
+    )?);
+    assert_eq!(index.len(), 8);
+    let paths = |prefix: &str| -> Vec<String> {
+        let candidates = index.complete(prefix);
+        candidates.iter().map(|c| c.path.to_string()).collect()
+    };
+    assert_eq!(
+        index.complete("top.cpu.al"),
+        [
+            Completion {
+                path: "top.cpu.alu",
+                is_scope: true
+            },
+            Completion {
+                path: "top.cpu.alu_out",
+                is_scope: false
+            }
+        ]
+    );
+    assert_eq!(paths("top."), ["top.clk", "top.cpu"]);
+    assert_eq!(paths(""), ["top"]);
+    assert_eq!(paths("top.cpu.alu."), ["top.cpu.alu.a", "top.cpu.alu.b"]);
+    assert!(index.complete("top.mem").is_empty());
+    let under_cpu: Vec<_> = index.starting_with("top.cpu.").map(|c| c.path).collect();
+    assert_eq!(
+        under_cpu,
+        [
+            "top.cpu.alu",
+            "top.cpu.alu.a",
+            "top.cpu.alu.b",
+            "top.cpu.alu_out",
+            "top.cpu.pc"
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn scope_tree() -> Result<(), VcdError> {
+    let vcd = "$var wire 1 ! reset $end\n$scope module top $end\n$var wire 1 \" clk $end\n\
+               $scope task init $end\n$upscope $end\n$scope module cpu $end\n\
+               $var reg 8 # pc $end\n$upscope $end\n$var wire 1 $ irq $end\n$upscope $end\n\
+               $scope module top $end\n$scope module cpu $end\n$var reg 1 % halt $end\n\
+               $upscope $end\n$upscope $end\n$enddefinitions $end\n";
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    let header = parser.load_header()?;
+    let names = |node: &ScopeNode| -> Vec<String> {
+        header.variables_of(node).map(|v| v.name.clone()).collect()
+    };
+    let root = &header.scopes;
+    assert_eq!(root.scope_count(), 3);
+    assert_eq!(names(root), ["reset"]);
+    let top = header.scope("top").unwrap();
+    assert_eq!(top.kind, Some(ScopeKind::VcdModule));
+    assert_eq!(names(top), ["clk", "irq"]);
+    // Scopes declared without variables are kept, scopes declared again are merged
+    let init = header.scope("top.init").unwrap();
+    assert_eq!(init.kind, Some(ScopeKind::VcdTask));
+    assert!(init.variables.is_empty());
+    assert_eq!(names(header.scope("top.cpu").unwrap()), ["pc", "halt"]);
+    assert!(header.scope("top.alu").is_none());
+    assert!(std::ptr::eq(root.get("").unwrap(), root));
+    let paths: Vec<String> = root.descendants().into_iter().map(|(p, _)| p).collect();
+    assert_eq!(paths, ["top", "top.init", "top.cpu"]);
+
+    // Built back from the variables, without the empty scopes
+    let rebuilt = ScopeNode::from_variables(&header.variables);
+    assert_eq!(rebuilt.scope_count(), 2);
+    assert_eq!(rebuilt.get("top.cpu"), header.scope("top.cpu"));
+
+    // A scope name holding a dot is not mistaken for a nested scope
+    let vcd = "$scope module a $end\n$scope module c $end\n$upscope $end\n\
+               $scope module b $end\n$upscope $end\n$upscope $end\n\
+               $scope module a.b $end\n$var wire 1 ! x $end\n$upscope $end\n\
+               $enddefinitions $end\n";
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    let header = parser.load_header()?;
+    let root = &header.scopes;
+    assert_eq!(root.scope_count(), 4);
+    assert_eq!(root.children[1].name, "a.b");
+    assert_eq!(root.children[1].variables, [0]);
+    assert!(root.get("a.b").unwrap().variables.is_empty());
+    Ok(())
+}
+
+#[test]
+fn demangled_hierarchy() -> Result<(), Box<dyn std::error::Error>> {
+    use wavetk::hierarchy::{
+        diff_hierarchies_with, find_bit_blasted_buses_with, rollup, rollup_with, Demangler,
+    };
+
+    let icarus = variables(
+        "$scope module top $end\n$var wire 1 ! clk $end\n$var wire 1 \" d [0] $end\n\
+         $var wire 1 # d [1] $end\n$scope module u $end\n$var reg 8 $ cnt $end\n\
+         $upscope $end\n$upscope $end\n$enddefinitions $end\n",
+    )?;
+    let verilator = variables(
+        "$scope module TOP $end\n$scope module top $end\n$var wire 1 ! clk $end\n\
+         $var wire 1 \" d [0] $end\n$var wire 1 # d [1] $end\n\
+         $var reg 8 $ u__DOT__cnt $end\n$upscope $end\n$upscope $end\n\
+         $enddefinitions $end\n",
+    )?;
+    let rules: Demangler = "verilator".parse()?;
+    assert_eq!(rules, Demangler::verilator());
+    assert_eq!(
+        "unescape, strip:tb.dut".parse::<Demangler>()?,
+        Demangler {
+            strip_prefixes: vec!["tb.dut".to_string()],
+            unescape: true,
+        }
+    );
+    assert!("strip:".parse::<Demangler>().is_err());
+    assert_eq!(rules.scope_path(&verilator[0].scope), "top");
+    assert_eq!(rules.scope_path(&verilator[0].scope[..1]), "");
+
+    assert!(!diff_hierarchies(&icarus, &verilator).is_empty());
+    assert!(diff_hierarchies_with(&icarus, &verilator, &rules).is_empty());
+    assert_eq!(
+        diff_hierarchies_with(&icarus[..3], &verilator, &rules),
+        [HierarchyChange::Added("top.u.cnt".to_string())]
+    );
+
+    // The TOP scope is left out of the names
+    let index = NameIndex::with_demangler(&verilator, &rules);
+    let names: Vec<&str> = index.starting_with("").map(|c| c.path).collect();
+    assert_eq!(names, ["top", "top.clk", "top.d", "top.u.cnt"]);
+
+    let buses = find_bit_blasted_buses_with(&verilator, &rules);
+    assert_eq!(buses.len(), 1);
+    assert_eq!(rules.path(&buses[0].info), "top.d");
+
+    let scopes = |totals: Vec<wavetk::hierarchy::ScopeTotal<u64>>| -> Vec<String> {
+        totals.into_iter().map(|s| s.path).collect()
+    };
+    let items = || verilator.iter().map(|v| (v.scope.as_slice(), 1u64));
+    assert_eq!(scopes(rollup(items())), ["TOP", "TOP.top"]);
+    assert_eq!(scopes(rollup_with(items(), &rules)), ["top"]);
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use std::convert::TryFrom;
+
+use bitvec::prelude::*;
+use num_bigint::BigUint;
+use wavetk::types::BitVector;
+
+#[test]
+fn bitvec_conversions() {
+    let bits = bitvec![0, 1, 1, 0, 1];
+    let v = BitVector::from(bits.as_bitslice());
+    assert_eq!(v.to_string(), "10110");
+    assert_eq!(BitVec::try_from(&v), Ok(bits));
+    assert_eq!(BitVector::from(bits![u8, Msb0; 1, 0]).to_u64(), Some(1));
+    assert!(BitVec::try_from(&BitVector::unknown(3)).is_err());
+}
+
+#[test]
+fn biguint_conversions() {
+    let x = BigUint::from(1u8) << 100u32 | BigUint::from(0xf0u8);
+    let v = BitVector::from(&x);
+    assert_eq!(v.width(), 101);
+    assert_eq!(v.slice(7, 0).to_u64(), Some(0xf0));
+    assert_eq!(BigUint::try_from(&v), Ok(x.clone()));
+    assert_eq!(BitVector::from_biguint(&x, 6).to_string(), "110000");
+    assert_eq!(BitVector::from(&BigUint::from(0u8)).width(), 1);
+    assert!(BigUint::try_from(&BitVector::unknown(2)).is_err());
+}
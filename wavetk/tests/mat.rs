@@ -0,0 +1,107 @@
+use std::convert::TryInto;
+use std::io::Cursor;
+
+use wavetk::mat::{export_mat, matlab_name};
+use wavetk::simulation::StateSimulation;
+use wavetk::vcd::VcdError;
+
+const VCD: &str = "$scope module top $end\n$var wire 1 ! clk $end\n\
+                   $scope module cpu $end\n$var wire 4 \" cnt $end\n$upscope $end\n\
+                   $upscope $end\n$enddefinitions $end\n\
+                   #0\n0!\nb0 \"\n#5\n1!\nb0011 \"\n#10\n0!\nbx \"\n";
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+/// Returns the (name, fields) of the structures of a MAT-file written by `export_mat`
+fn read_structures(data: &[u8]) -> Vec<(String, Vec<Vec<f64>>)> {
+    // Skips a data element, returns its content
+    fn element(data: &[u8], pos: &mut usize) -> (u32, Vec<u8>) {
+        let (ty, size) = (u32_at(data, *pos), u32_at(data, *pos + 4) as usize);
+        let content = data[*pos + 8..*pos + 8 + size].to_vec();
+        *pos += 8 + size.div_ceil(8) * 8;
+        (ty, content)
+    }
+    assert_eq!(&data[..6], b"MATLAB");
+    assert_eq!(&data[124..128], &[0, 1, b'I', b'M']);
+    let mut structures = Vec::new();
+    let mut pos = 128;
+    while pos < data.len() {
+        let (ty, content) = element(data, &mut pos);
+        assert_eq!(ty, 14);
+        let mut p = 0;
+        let (_, flags) = element(&content, &mut p);
+        assert_eq!(flags[0], 2);
+        let (_, dims) = element(&content, &mut p);
+        assert_eq!(dims, [1, 0, 0, 0, 1, 0, 0, 0]);
+        let (_, name) = element(&content, &mut p);
+        // Field name length (small element)
+        assert_eq!(u32_at(&content, p + 4), 32);
+        p += 8;
+        let (_, names) = element(&content, &mut p);
+        assert_eq!(&names[..4], b"time");
+        assert_eq!(&names[32..37], b"value");
+        let mut fields = Vec::new();
+        while p < content.len() {
+            let (_, field) = element(&content, &mut p);
+            let mut q = 0;
+            let (_, flags) = element(&field, &mut q);
+            assert_eq!(flags[0], 6);
+            element(&field, &mut q);
+            element(&field, &mut q);
+            let (ty, values) = element(&field, &mut q);
+            assert_eq!(ty, 9);
+            fields.push(
+                values
+                    .chunks(8)
+                    .map(|x| f64::from_le_bytes(x.try_into().unwrap()))
+                    .collect(),
+            );
+        }
+        structures.push((String::from_utf8(name).unwrap(), fields));
+    }
+    structures
+}
+
+#[test]
+fn mat_names() {
+    assert_eq!(matlab_name("top.cpu.pc"), "top_cpu_pc");
+    assert_eq!(matlab_name("0bad[3]"), "s0bad_3_");
+    assert_eq!(matlab_name(&"a".repeat(100)).len(), 63);
+}
+
+#[test]
+fn mat_export() -> Result<(), VcdError> {
+    let mut sim = StateSimulation::from_reader(Cursor::new(VCD.as_bytes().to_vec()));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let data = export_mat(&mut sim, &["\"", "!"], Vec::new())?;
+    assert_eq!(data.len() % 8, 0);
+    let structures = read_structures(&data);
+    assert_eq!(structures.len(), 2);
+
+    let (name, fields) = &structures[0];
+    assert_eq!(name, "top_cpu_cnt");
+    assert_eq!(fields[0], [0.0, 5.0, 10.0]);
+    assert_eq!(fields[1][..2], [0.0, 3.0]);
+    assert!(fields[1][2].is_nan());
+
+    let (name, fields) = &structures[1];
+    assert_eq!(name, "top_clk");
+    assert_eq!(fields[0], [0.0, 5.0, 10.0]);
+    assert_eq!(fields[1], [0.0, 1.0, 0.0]);
+    Ok(())
+}
+
+#[test]
+fn mat_unknown_variable() -> Result<(), VcdError> {
+    let mut sim = StateSimulation::from_reader(Cursor::new(VCD.as_bytes().to_vec()));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    assert!(matches!(
+        export_mat(&mut sim, &["?"], Vec::new()),
+        Err(VcdError::UnknownVariable)
+    ));
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use std::io::Cursor;
+
+use wavetk::pattern::{find_first, Pattern, PatternError, XzMatch};
+use wavetk::simulation::StateSimulation;
+
+const BUS_VCD: &str = "$timescale 1ns $end
+$scope module top $end
+$var wire 32 # data [31:0] $end
+$var wire 4 $ nib [3:0] $end
+$upscope $end
+$enddefinitions $end
+#0
+b0 #
+bx $
+#10
+b1 #
+b1x10 $
+#20
+b11011110101011011011111011101111 #
+b1010 $
+#30
+b0 #
+";
+
+fn simulation() -> Result<StateSimulation, Box<dyn std::error::Error>> {
+    let mut sim = StateSimulation::from_reader(Cursor::new(BUS_VCD));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    Ok(sim)
+}
+
+#[test]
+fn pattern_parse() {
+    let p: Pattern = "0x1?".parse().unwrap();
+    assert_eq!(p.bits().len(), 8);
+    assert!(p.matches(&[0, 0, 0, 1, 1, 0, -4, 1]));
+    assert!(!p.matches(&[0, 0, 1, 1, 1, 0, 1, 1]));
+    assert!("1xx0".parse::<Pattern>().unwrap().matches(&[1, 0, 1, 0]));
+    assert_eq!("".parse::<Pattern>(), Err(PatternError::Empty));
+    assert_eq!(
+        "10z".parse::<Pattern>(),
+        Err(PatternError::InvalidCharacter('z'))
+    );
+
+    // Zero extension on both sides
+    let p: Pattern = "11".parse().unwrap();
+    assert!(p.matches(&[0, 0, 1, 1]));
+    assert!(!p.matches(&[1, 0, 1, 1]));
+    assert!("0011".parse::<Pattern>().unwrap().matches(&[1, 1]));
+}
+
+#[test]
+fn pattern_find_first() -> Result<(), Box<dyn std::error::Error>> {
+    let mut sim = simulation()?;
+    let p = "0xDEAD_BEEF".parse()?;
+    assert_eq!(find_first(&mut sim, "#", &p)?, Some(20));
+
+    // X bits only match don't-care positions
+    let mut sim = simulation()?;
+    assert_eq!(find_first(&mut sim, "$", &"1x1x".parse()?)?, Some(10));
+    let mut sim = simulation()?;
+    assert_eq!(find_first(&mut sim, "$", &"1?10".parse()?)?, Some(10));
+    let mut sim = simulation()?;
+    assert_eq!(find_first(&mut sim, "$", &"0x0".parse()?)?, None);
+    Ok(())
+}
+
+#[test]
+fn pattern_xz_match() -> Result<(), Box<dyn std::error::Error>> {
+    let x = -4;
+    let z = -3;
+    let mut p: Pattern = "1?10".parse()?;
+    assert!(p.matches(&[1, x, 1, 0]));
+    assert!(!p.matches(&[1, 0, z, 0]));
+    p.set_xz_match(XzMatch::Any);
+    assert!(p.matches(&[1, 0, z, 0]));
+    p.set_xz_match(XzMatch::Never);
+    assert!(!p.matches(&[1, x, 1, 0]));
+    assert!(p.matches(&[1, 1, 1, 0]));
+
+    // X bits of `nib` at time 0 match the all-zero pattern only with `Any`
+    let mut p: Pattern = "0000".parse()?;
+    p.set_xz_match(XzMatch::Any);
+    let mut sim = simulation()?;
+    assert_eq!(find_first(&mut sim, "$", &p)?, Some(0));
+
+    assert!(XzMatch::Any.levels_equal(x, 1));
+    assert!(XzMatch::DontCare.levels_equal(x, x));
+    assert!(!XzMatch::DontCare.levels_equal(x, z));
+    assert!(!XzMatch::Never.levels_equal(x, x));
+    Ok(())
+}
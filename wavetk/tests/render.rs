@@ -0,0 +1,144 @@
+use std::path::PathBuf;
+
+use wavetk::hierarchy::Demangler;
+use wavetk::render::{
+    bus_label, load_traces, load_traces_with, load_vcd_traces, timescale_of, RenderError,
+    SvgRenderer, Trace,
+};
+use wavetk::types::{TimeUnit, Timescale};
+use wavetk::VcdParser;
+
+const VCD: &str = "$timescale 1 ns $end\n$scope module top $end\n$var wire 1 ! clk $end\n\
+                   $scope module cpu $end\n$var wire 8 \" data [7:0] $end\n\
+                   $var wire 1 ! clk $end\n$upscope $end\n$upscope $end\n$enddefinitions $end\n\
+                   #0\n$dumpvars\n0!\nbx \"\n$end\n#5\n1!\n#10\n0!\nb1010 \"\n#15\n1!\n\
+                   #20\n0!\nb1z0z0000 \"\n#25\n1!\n";
+
+fn tmp_file(name: &str) -> PathBuf {
+    let mut path = std::env::temp_dir();
+    path.push(format!("wavetk-{}-{}", std::process::id(), name));
+    path
+}
+
+#[test]
+fn render_labels() {
+    assert_eq!(bus_label("1010", 8, false), "0a");
+    assert_eq!(bus_label("x", 8, false), "xx");
+    assert_eq!(bus_label("1z0z0000", 8, false), "x0");
+    assert_eq!(bus_label("zzzz1", 5, false), "zx");
+    assert_eq!(bus_label("1.5", 64, true), "1.5");
+
+    let ts = Timescale {
+        magnitude: 10,
+        unit: TimeUnit::Ps,
+    };
+    assert_eq!(ts.parse_time("2us"), Some(200_000));
+    assert_eq!(ts.parse_time("1.5 ns"), Some(150));
+    assert_eq!(ts.parse_time("42"), Some(42));
+    assert_eq!(ts.parse_time("3 days"), None);
+}
+
+#[test]
+fn render_vcd_window() -> Result<(), Box<dyn std::error::Error>> {
+    let path = tmp_file("render.vcd");
+    std::fs::write(&path, VCD)?;
+    assert_eq!(timescale_of(&path)?, Timescale::parse("1ns"));
+
+    let traces = load_traces(&path, &["cpu.data", "top.clk"], 12, 20)?;
+    assert_eq!(traces.len(), 2);
+    assert_eq!(traces[0].name, "top.cpu.data");
+    assert_eq!(
+        traces[0].changes,
+        [(10, "1010"), (20, "1z0z0000")].map(|(t, v)| (t, v.to_string()))
+    );
+    assert_eq!(traces[1].changes.len(), 3);
+    // `clk` names both top.clk and top.cpu.clk
+    assert_eq!(load_traces(&path, &["clk"], 0, 100)?.len(), 2);
+    // Signals selected and labeled by their demangled names
+    let stripped = load_traces_with(&path, &["cpu.data"], 12, 20, &"strip:top".parse()?)?;
+    assert_eq!(stripped[0].name, "cpu.data");
+    assert_eq!(stripped[0].changes, traces[0].changes);
+    // Same traces from a parser, as for the standard input
+    let mut parser = VcdParser::with_chunk_size(64, VCD.as_bytes());
+    parser.load_header()?;
+    let signals = ["cpu.data", "top.clk"];
+    let from_parser = load_vcd_traces(&mut parser, &signals, 12, 20, &Demangler::default())?;
+    assert_eq!(from_parser.len(), 2);
+    assert_eq!(from_parser[0].changes, traces[0].changes);
+    assert_eq!(from_parser[1].changes, traces[1].changes);
+    assert!(matches!(
+        load_traces(&path, &["pc"], 0, 100),
+        Err(RenderError::UnknownSignal(s)) if s == "pc"
+    ));
+
+    let mut renderer = SvgRenderer::new();
+    renderer.set_width(600);
+    renderer.set_timescale(timescale_of(&path)?);
+    let svg = renderer.render(&traces, 12, 20)?;
+    assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+    assert!(svg.contains("width=\"600\" height=\"88\""));
+    assert!(svg.contains(">top.cpu.data</text>"));
+    assert!(svg.contains(">0a</text>"));
+    assert!(svg.contains(">14 ns</text>"));
+    assert!(matches!(
+        renderer.render(&traces, 20, 20),
+        Err(RenderError::EmptyWindow)
+    ));
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn resample_trace() {
+    let counter = Trace {
+        name: "count".to_string(),
+        width: 16,
+        real: false,
+        changes: (0..10_000u64)
+            .map(|t| (10 * t, format!("{:b}", t)))
+            .collect(),
+    };
+    let buckets = counter.resample(1000, 50_999, 100);
+    assert_eq!(buckets.len(), 100);
+    assert_eq!(buckets.iter().map(|b| b.changes).sum::<usize>(), 5000);
+    assert_eq!((buckets[0].start, buckets[0].end), (1000, 1500));
+    assert_eq!((buckets[0].min, buckets[0].max), (Some(100.0), Some(149.0)));
+    assert_eq!(buckets[99].last, format!("{:b}", 5099));
+    assert!(buckets.windows(2).all(|w| w[0].end == w[1].start));
+
+    let trace = Trace {
+        name: "data".to_string(),
+        width: 8,
+        real: false,
+        changes: [(0, "0"), (5, "11"), (6, "x"), (7, "10"), (30, "1")]
+            .map(|(t, v)| (t, v.to_string()))
+            .to_vec(),
+    };
+    let buckets = trace.resample(2, 21, 4);
+    // Buckets of 5 time units: the value held at the start and two changes, then one change
+    assert_eq!(buckets.len(), 2);
+    let b = &buckets[0];
+    assert_eq!(
+        (b.first.as_str(), b.last.as_str(), b.changes),
+        ("0", "x", 2)
+    );
+    let b = &buckets[1];
+    assert_eq!((b.start, b.end), (7, 12));
+    assert_eq!((b.first.as_str(), b.last.as_str()), ("10", "10"));
+    // The value of the previous bucket is held until the change at 30
+    let b = &trace.resample(0, 39, 4)[1];
+    assert_eq!((b.start, b.end), (30, 40));
+    let b = &trace.resample(0, 39, 2)[1];
+    assert_eq!(
+        (b.start, b.first.as_str(), b.last.as_str()),
+        (20, "10", "1")
+    );
+    assert_eq!(
+        (b.min, b.max, b.changes, b.unknown),
+        (Some(1.0), Some(2.0), 1, false)
+    );
+    assert_eq!(trace.resample(2, 21, 0), []);
+    let b = &trace.resample(0, 9, 1)[0];
+    assert_eq!((b.min, b.max, b.changes), (Some(0.0), Some(3.0), 4));
+    assert!(b.unknown);
+}
@@ -0,0 +1,122 @@
+use std::io::Cursor;
+
+use wavetk::sampling::{sample_domains, Edge, MultiClockSampler};
+use wavetk::simulation::StateSimulation;
+use wavetk::vcd::VcdError;
+
+// Two clocks: `a` (period 10) drives `cnt`, `b` (period 16) drives `rx`
+const TWO_CLOCKS_VCD: &str = "$timescale 1ns $end
+$scope module top $end
+$var wire 1 ! clk_a $end
+$var wire 1 \" clk_b $end
+$var wire 2 # cnt [1:0] $end
+$var wire 1 $ rx $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+0\"
+b0 #
+0$
+#5
+1!
+b01 #
+#8
+1\"
+1$
+#10
+0!
+#15
+1!
+b10 #
+#16
+0\"
+#20
+0!
+#24
+1\"
+0$
+#25
+1!
+b11 #
+";
+
+#[test]
+fn sample_two_domains() -> Result<(), Box<dyn std::error::Error>> {
+    let mut sim = StateSimulation::from_reader(Cursor::new(TWO_CLOCKS_VCD));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let mut sampler = MultiClockSampler::new();
+    sampler.add_domain("a", "!", Edge::Rising, &["#"]);
+    sampler.add_domain("b", "\"", Edge::Rising, &["$", "#"]);
+    let sampler = sample_domains(&mut sim, sampler)?;
+
+    let a = sampler.domain("a").unwrap();
+    let times: Vec<i64> = a.samples.iter().map(|s| s.time).collect();
+    assert_eq!(times, [5, 15, 25]);
+    // Values just before each edge
+    let cnt: Vec<&[i8]> = a.samples.iter().map(|s| s.values[0].as_slice()).collect();
+    assert_eq!(cnt, [&[0, 0][..], &[0, 1], &[1, 0]]);
+
+    let b = sampler.domain("b").unwrap();
+    let times: Vec<i64> = b.samples.iter().map(|s| s.time).collect();
+    assert_eq!(times, [8, 24]);
+    assert_eq!(b.samples[0].values, [vec![0], vec![0, 1]]);
+    assert_eq!(b.samples[1].values, [vec![1], vec![1, 0]]);
+    Ok(())
+}
+
+#[test]
+fn sample_unknown_clock() -> Result<(), Box<dyn std::error::Error>> {
+    let mut sim = StateSimulation::from_reader(Cursor::new(TWO_CLOCKS_VCD));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let mut sampler = MultiClockSampler::new();
+    sampler.add_domain("c", "%", Edge::Both, &["#"]);
+    assert!(matches!(
+        sample_domains(&mut sim, sampler),
+        Err(VcdError::UnknownVariable)
+    ));
+    Ok(())
+}
+
+#[test]
+fn sample_on_clock() -> Result<(), Box<dyn std::error::Error>> {
+    let mut sim = StateSimulation::from_reader(Cursor::new(TWO_CLOCKS_VCD));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let mut view = sim.sample_on("top.clk_a", Edge::Rising);
+    let mut samples = Vec::new();
+    while let Some(step) = view.next()? {
+        samples.push((step.edge, step.index, step.state[2..4].to_vec()));
+    }
+    // Changes at the time of the edge are included
+    assert_eq!(
+        samples,
+        [(5, 0, vec![0, 1]), (15, 1, vec![1, 0]), (25, 2, vec![1, 1])]
+    );
+
+    // `cnt` 3 ns after each edge of clk_b, the change at 25 is seen by the edge at 24
+    let mut sim = StateSimulation::from_reader(Cursor::new(TWO_CLOCKS_VCD));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let mut view = sim.sample_on("\"", Edge::Both).offset_ns(3.0);
+    let mut samples = Vec::new();
+    while let Some(step) = view.next()? {
+        samples.push((step.edge, view.sim().value("#").unwrap().to_vec()));
+    }
+    assert_eq!(
+        samples,
+        [(8, vec![0, 1]), (16, vec![1, 0]), (24, vec![1, 1])]
+    );
+
+    let mut sim = StateSimulation::from_reader(Cursor::new(TWO_CLOCKS_VCD));
+    sim.load_header()?;
+    sim.track_variables(&["#"]);
+    sim.allocate_state()?;
+    assert!(matches!(
+        sim.sample_on("top.clk_a", Edge::Rising).next(),
+        Err(VcdError::UnknownVariable)
+    ));
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use std::path::PathBuf;
+
+use wavetk::session::{Session, SessionError};
+
+fn asset(rel_path: &str) -> PathBuf {
+    let mut path = PathBuf::from(file!());
+    path.pop();
+    path.pop();
+    path.pop();
+    path.push("assets");
+    path.push(rel_path);
+    path
+}
+
+#[test]
+fn session_vcd_and_fst() -> Result<(), Box<dyn std::error::Error>> {
+    let mut session = Session::new();
+    session.open("ghdl", asset("vcd/good/ghdl_0.vcd"))?;
+    session.open("des", asset("fst/des.fst"))?;
+    assert!(matches!(
+        session.open("des", asset("fst/dlx.fst")),
+        Err(SessionError::DuplicateName)
+    ));
+    assert_eq!(session.dumps().collect::<Vec<_>>(), ["ghdl", "des"]);
+    let paths = session.paths();
+    assert_eq!(
+        paths.len(),
+        session.variables("ghdl").unwrap().len() + session.variables("des").unwrap().len()
+    );
+
+    assert_eq!(session.find("ghdl:system_clk")?.id, "!");
+    let clk = session.changes("ghdl:system_clk")?;
+    assert_eq!(clk[1], (5000000, "1".to_string()));
+    assert_eq!(
+        session.value_at("ghdl:alu_instance.left", 0)?.as_deref(),
+        Some("00000000000000000000000000001110")
+    );
+
+    let fst_var = paths.iter().find(|p| p.starts_with("des:")).unwrap();
+    let changes = session.changes(fst_var)?;
+    assert!(!changes.is_empty());
+    let (t, v) = changes.last().unwrap().clone();
+    assert_eq!(session.value_at(fst_var, t)?, Some(v));
+
+    assert!(matches!(
+        session.find("other:system_clk"),
+        Err(SessionError::UnknownDump)
+    ));
+    assert!(matches!(
+        session.changes("ghdl:nope"),
+        Err(SessionError::UnknownVariable)
+    ));
+    Ok(())
+}
+
+#[test]
+fn session_vcd_data() -> Result<(), Box<dyn std::error::Error>> {
+    let data = std::fs::read(asset("vcd/good/ghdl_0.vcd"))?;
+    let mut session = Session::new();
+    session.open("file", asset("vcd/good/ghdl_0.vcd"))?;
+    session.open_vcd_data("data", data.clone())?;
+    assert!(matches!(
+        session.open_vcd_data("data", data),
+        Err(SessionError::DuplicateName)
+    ));
+    assert_eq!(session.variables("data"), session.variables("file"));
+    // The data is kept for each query
+    for _ in 0..2 {
+        assert_eq!(
+            session.changes("data:system_clk")?,
+            session.changes("file:system_clk")?
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn session_demangling() -> Result<(), Box<dyn std::error::Error>> {
+    use wavetk::hierarchy::{unescape_name, Demangler};
+
+    assert_eq!(unescape_name("gen__BRA__2__KET__"), "gen[2]");
+    assert_eq!(unescape_name("u__DOT__x__024y"), "u.x$y");
+    assert_eq!(unescape_name("\\gen[1] "), "gen[1]");
+    assert_eq!(unescape_name("gen(3)"), "gen[3]");
+    assert_eq!(unescape_name("f(x)"), "f(x)");
+    assert_eq!(unescape_name("a__b___c"), "a__b___c");
+
+    let dumps = [
+        (
+            "verilator",
+            "$scope module TOP $end\n$scope module top $end\n\
+             $scope module gen__BRA__1__KET__ $end\n$var wire 1 ! q $end\n\
+             $upscope $end\n$upscope $end\n$upscope $end\n$enddefinitions $end\n#0\n1!\n",
+        ),
+        (
+            "iverilog",
+            "$scope module top $end\n$scope begin \\gen[1] $end\n$var wire 1 ! q $end\n\
+             $upscope $end\n$upscope $end\n$enddefinitions $end\n#0\n1!\n",
+        ),
+    ];
+    let mut session = Session::new();
+    let mut files = Vec::new();
+    for (name, vcd) in dumps.iter() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("wavetk-{}-{}.vcd", std::process::id(), name));
+        std::fs::write(&path, vcd)?;
+        session.open(name, &path)?;
+        files.push(path);
+    }
+    assert!(session.find("verilator:top.gen[1].q").is_err());
+    session.set_demangler("verilator", Demangler::verilator())?;
+    session.set_demangler("iverilog", Demangler::unescaping())?;
+    assert!(matches!(
+        session.set_demangler("other", Demangler::default()),
+        Err(SessionError::UnknownDump)
+    ));
+    for name in ["verilator", "iverilog"].iter() {
+        let path = format!("{}:top.gen[1].q", name);
+        assert_eq!(session.value_at(&path, 0)?.as_deref(), Some("1"));
+    }
+    for path in files {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
@@ -1,5 +1,11 @@
 use std::path::PathBuf;
-use wavetk::simulation::StateSimulation;
+use wavetk::simulation::{
+    decode_gray, decode_one_hot, decode_signed, logic_char, Checkpoint, CycleStep, InitialCycle,
+    StateSimulation, Step, LEVEL_0, LEVEL_1, LEVEL_DONT_CARE, LEVEL_H, LEVEL_L, LEVEL_NO_DATA,
+    LEVEL_OTHER, LEVEL_X, LEVEL_Z, LOGIC_LEVELS,
+};
+use wavetk::types::{Range, TimeUnit, Timescale};
+use wavetk::vcd::{ChangeProblem, TimestampPolicy, VcdError};
 
 fn vcd_asset(rel_path: &str) -> PathBuf {
     let mut path = PathBuf::from(file!());
@@ -17,24 +23,29 @@ fn sim_ghdl_0() -> Result<(), Box<dyn std::error::Error>> {
     let mut sim = StateSimulation::new(f.to_str().unwrap())?;
     sim.load_header()?;
     sim.allocate_state()?;
-    let clk_id = sim.header_info()?.get("!").unwrap().0.unwrap();
+    let clk_id = sim.variable_offset("!").unwrap();
 
-    let (c, d) = sim.next_cycle()?;
+    let CycleStep {
+        cycle: c, state: d, ..
+    } = sim.next_cycle()?;
     assert_eq!(c, -1);
     assert_eq!(d.len(), 289);
     assert_eq!(d[clk_id], 0);
 
-    let (c, d) = sim.next_cycle()?;
+    let CycleStep {
+        cycle: c, state: d, ..
+    } = sim.next_cycle()?;
     assert_eq!(c, 0);
     assert_eq!(d[clk_id], 0);
 
-    let (c, d) = sim.next_cycle()?;
+    let CycleStep {
+        cycle: c, state: d, ..
+    } = sim.next_cycle()?;
     assert_eq!(c, 5000000);
     assert_eq!(d[clk_id], 1);
     Ok(())
 }
 
-
 #[test]
 fn sim_picorv32() -> Result<(), Box<dyn std::error::Error>> {
     // NOTE: this test is constructed from a file that failed initialy.
@@ -45,19 +56,1290 @@ fn sim_picorv32() -> Result<(), Box<dyn std::error::Error>> {
     sim.load_header()?;
     sim.allocate_state()?;
 
-    let sig = sim.header_info()?.get("a\"").unwrap().0.unwrap();
+    let sig = sim.variable_offset("a\"").unwrap();
     let sig_w = 128usize;
 
-    let (c, d) = sim.next_cycle()?;
+    let CycleStep {
+        cycle: c, state: d, ..
+    } = sim.next_cycle()?;
     assert_eq!(c, -1);
     assert!(d[sig..sig + sig_w].iter().all(|x| *x == 0));
 
     // Check left extension worked
-    let (c, d) = sim.next_cycle()?;
+    let CycleStep {
+        cycle: c, state: d, ..
+    } = sim.next_cycle()?;
     assert_eq!(c, 0);
     assert!(d[sig..sig + 91].iter().all(|x| *x == 1));
 
-    let (c, _) = sim.next_cycle()?;
+    let c = sim.next_cycle()?.cycle;
     assert_eq!(c, 5000);
     Ok(())
-}
\ No newline at end of file
+}
+
+#[test]
+fn sim_tracked_variables() -> Result<(), Box<dyn std::error::Error>> {
+    let f = vcd_asset("good/ghdl_0.vcd");
+    let mut sim = StateSimulation::new(f.to_str().unwrap())?;
+    sim.load_header()?;
+    assert!(sim.tracked_variables().is_empty());
+
+    sim.track_variables(&["!", "\""]);
+    sim.allocate_state()?;
+    let tracked = sim.tracked_variables();
+    assert_eq!(tracked.len(), 2);
+    assert_eq!(tracked[0].0, 0);
+    assert_eq!(tracked[0].1.id, "!");
+    assert_eq!(tracked[1].0, tracked[0].1.width as usize);
+    assert_eq!(tracked[1].1.id, "\"");
+    assert_eq!(
+        sim.state().len(),
+        tracked[1].0 + tracked[1].1.width as usize
+    );
+    Ok(())
+}
+
+#[test]
+fn sim_memory_limit() -> Result<(), Box<dyn std::error::Error>> {
+    let f = vcd_asset("good/ghdl_0.vcd");
+    let mut sim = StateSimulation::new(f.to_str().unwrap())?;
+    sim.load_header()?;
+    sim.set_memory_limit(Some(256));
+    match sim.allocate_state() {
+        Err(VcdError::MemoryLimit) => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    sim.set_memory_limit(Some(1 << 20));
+    sim.allocate_state()?;
+    while !sim.done() {
+        sim.next_cycle()?;
+    }
+    Ok(())
+}
+
+#[test]
+fn sim_from_reader() -> Result<(), Box<dyn std::error::Error>> {
+    let data = std::fs::read(vcd_asset("good/ghdl_0.vcd"))?;
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(data));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let clk_id = sim.variable_offset("!").unwrap();
+
+    let c = sim.next_cycle()?.cycle;
+    assert_eq!(c, -1);
+    let c = sim.next_cycle()?.cycle;
+    assert_eq!(c, 0);
+    let CycleStep {
+        cycle: c, state: d, ..
+    } = sim.next_cycle()?;
+    assert_eq!(c, 5000000);
+    assert_eq!(d[clk_id], 1);
+    Ok(())
+}
+
+const BIT_BLASTED_VCD: &str = "$scope module top $end
+$var wire 1 ! clk $end
+$var wire 1 a data [2] $end
+$var wire 1 b data [0] $end
+$var wire 1 c data [1] $end
+$var wire 1 d sparse [0] $end
+$var wire 1 e sparse [2] $end
+$upscope $end
+$enddefinitions $end
+#0
+1!
+1a
+0b
+1c
+#1
+0a
+";
+
+#[test]
+fn sim_bus_reconstruction() -> Result<(), Box<dyn std::error::Error>> {
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(BIT_BLASTED_VCD));
+    sim.load_header()?;
+    sim.set_bus_reconstruction(true);
+    sim.track_variables(&["b"]);
+    sim.allocate_state()?;
+
+    assert_eq!(sim.buses().len(), 1);
+    let tracked = sim.tracked_variables();
+    assert_eq!(tracked.len(), 1);
+    let (offset, bus) = tracked[0].clone();
+    assert_eq!(bus.name, "data");
+    assert_eq!(bus.width, 3);
+    assert_eq!(bus.range, Some(Range::Range((2, 0))));
+    assert_eq!(sim.variable_offset("a"), Some(offset));
+    assert_eq!(sim.variable_offset("c"), Some(offset + 1));
+    assert_eq!(sim.variable_offset("b"), Some(offset + 2));
+    let info = sim.header_info()?.find(|(id, _)| *id == bus.id).unwrap();
+    assert_eq!(info.1, (Some(offset), &bus));
+    assert_eq!(
+        sim.header_info()?.count(),
+        sim.header().unwrap().variables.len() + 1
+    );
+
+    let names: Vec<&str> = sim.variables().iter().map(|v| v.name.as_str()).collect();
+    assert_eq!(names, vec!["clk", "data", "sparse", "sparse"]);
+
+    sim.next_cycle()?;
+    let d = sim.next_cycle()?.state;
+    assert_eq!(&d[offset..offset + 3], &[1, 1, 0]);
+    let d = sim.next_cycle()?.state;
+    assert_eq!(&d[offset..offset + 3], &[0, 1, 0]);
+    Ok(())
+}
+
+#[test]
+fn sim_aliases() -> Result<(), Box<dyn std::error::Error>> {
+    let f = vcd_asset("good/verilator_riscv.vcd");
+    let mut sim = StateSimulation::new(f.to_str().unwrap())?;
+    sim.load_header()?;
+    let aliases = sim.aliases()?;
+    let header = sim.header().unwrap();
+    assert_eq!(
+        aliases.values().map(|v| v.len()).sum::<usize>(),
+        header.variables.len()
+    );
+    let clk = &header.variables[0];
+    let clk_aliases = &aliases[clk.id.as_str()];
+    assert!(clk_aliases.len() > 1);
+    assert_eq!(clk_aliases[0], wavetk::hierarchy::variable_path(clk));
+    Ok(())
+}
+
+#[test]
+fn sim_dirty_bitmap() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! clk $end\n$var wire 4 \" cnt $end\n$var wire 1 # en $end\n\
+               $enddefinitions $end\n#0\n$dumpvars\n0!\nb0 \"\n1#\n$end\n\
+               #5\n1!\nb0 \"\n#10\n0!\nb11 \"\n";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    assert_eq!(sim.dirty_bitmap().len(), 1);
+
+    let mut dirty = Vec::new();
+    while !sim.done() {
+        sim.next_cycle()?;
+        dirty.push(sim.dirty_variables().collect::<Vec<_>>());
+    }
+    // Initial values are all marked, rewriting `cnt` with the same value is not a change
+    assert_eq!(dirty[1], vec![0, 1, 2]);
+    assert_eq!(dirty[2], vec![0]);
+    assert_eq!(dirty[3], vec![0, 1]);
+    assert!(sim.is_dirty(1) && !sim.is_dirty(2));
+    Ok(())
+}
+
+#[test]
+fn sim_sparse_ids() -> Result<(), Box<dyn std::error::Error>> {
+    // Dense ids mixed with ids far from the numbering of simulators, and too long to be
+    // numbered
+    let vcd = "$var wire 1 ! a $end\n$var wire 2 ~~~ b $end\n$var wire 3 sig_c c $end\n\
+               $var wire 1 \" d $end\n$enddefinitions $end\n#0\n1!\nb10 ~~~\nb101 sig_c\n0\"\n\
+               #1\nb1 ~~~\nb0 sig_c\n1\"\n";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    sim.next_cycle()?;
+    sim.next_cycle()?;
+    assert_eq!(sim.value("~~~"), Some(&[1, 0][..]));
+    assert_eq!(sim.value("sig_c"), Some(&[1, 0, 1][..]));
+    sim.next_cycle()?;
+    assert_eq!(sim.state(), [1, 1, 1, 0, 0, 0, 1]);
+    assert_eq!(sim.dirty_variables().collect::<Vec<_>>(), [1, 2, 3]);
+    assert_eq!(sim.variable_offset("sig_c"), Some(3));
+    assert_eq!(sim.value("#"), None);
+    assert_eq!(sim.value("sig_d"), None);
+    assert_eq!(sim.value("~~~~"), None);
+    Ok(())
+}
+
+#[test]
+fn sim_history() -> Result<(), Box<dyn std::error::Error>> {
+    let f = vcd_asset("good/ghdl_0.vcd");
+    let mut sim = StateSimulation::new(f.to_str().unwrap())?;
+    sim.load_header()?;
+    sim.set_history_depth(3);
+    sim.allocate_state()?;
+    let clk = sim.variable_offset("!").unwrap();
+    let clk_index = sim
+        .tracked_variables()
+        .iter()
+        .position(|(_, v)| v.id == "!")
+        .unwrap();
+    let mut clk_times = Vec::new();
+    for _ in 0..10 {
+        let c = sim.next_cycle()?.cycle;
+        if sim.is_dirty(clk_index) {
+            clk_times.push((c, sim.state()[clk]));
+        }
+    }
+    let h = sim.history("!").unwrap();
+    assert_eq!(h.len(), 3);
+    let last: Vec<_> = h.iter().map(|(t, v)| (*t, v[0])).collect();
+    assert_eq!(last, clk_times[clk_times.len() - 3..]);
+    assert_eq!(sim.value_ago("!", 0), Some((last[2].0, &[last[2].1][..])));
+    assert_eq!(sim.value_ago("!", 2).unwrap().0, last[0].0);
+    assert_eq!(sim.value_ago("!", 3), None);
+    Ok(())
+}
+
+#[test]
+fn sim_timestamps() -> Result<(), Box<dyn std::error::Error>> {
+    let f = vcd_asset("good/ghdl_0.vcd");
+    let mut sim = StateSimulation::new(f.to_str().unwrap())?;
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let timescale = sim.timescale().unwrap();
+    assert_eq!((timescale.magnitude, timescale.unit), (1, TimeUnit::Fs));
+    assert_eq!(timescale.exponent(), -15);
+    sim.next_cycle()?;
+    sim.next_cycle()?;
+    let t = sim.next_cycle()?.time;
+    assert_eq!(t.raw, 5000000);
+    assert!((t.ns().unwrap() - 5.0).abs() < 1e-9);
+
+    let ts = Timescale::parse("100ps").unwrap();
+    assert_eq!(
+        (ts.magnitude, ts.unit, ts.exponent()),
+        (100, TimeUnit::Ps, -10)
+    );
+    assert_eq!(Timescale::from_exponent(-10), ts);
+    assert_eq!(Timescale::from_exponent(-9).unit, TimeUnit::Ns);
+    assert!(Timescale::parse("3 ns").is_none());
+    Ok(())
+}
+
+#[test]
+fn sim_initial_cycle() -> Result<(), Box<dyn std::error::Error>> {
+    let f = vcd_asset("good/ghdl_0.vcd");
+    let open = |mode| -> Result<StateSimulation, Box<dyn std::error::Error>> {
+        let mut sim = StateSimulation::new(f.to_str().unwrap())?;
+        sim.load_header()?;
+        sim.set_initial_cycle(mode);
+        sim.allocate_state()?;
+        Ok(sim)
+    };
+
+    let mut sim = open(InitialCycle::Merge)?;
+    let clk = sim.variable_offset("!").unwrap();
+    let CycleStep {
+        cycle: c, state: d, ..
+    } = sim.next_cycle()?;
+    assert_eq!((c, d[clk]), (0, 0));
+    let CycleStep {
+        cycle: c, state: d, ..
+    } = sim.next_cycle()?;
+    assert_eq!((c, d[clk]), (5000000, 1));
+
+    let mut sim = open(InitialCycle::Event)?;
+    assert_eq!(sim.next_step()?.0, Step::Initial);
+    assert_eq!(sim.next_step()?.0, Step::Cycle(0));
+    assert_eq!(sim.next_step()?.0, Step::Cycle(5000000));
+
+    let mut sim = open(InitialCycle::Separate)?;
+    assert_eq!(sim.next_step()?.0, Step::Cycle(-1));
+    assert_eq!(sim.next_step()?.0, Step::Cycle(0));
+    Ok(())
+}
+
+#[test]
+fn sim_initial_layouts() -> Result<(), Box<dyn std::error::Error>> {
+    const HEADER: &str = "$var wire 1 ! a $end\n$enddefinitions $end\n";
+    let run = |body: &str, mode| -> Result<_, Box<dyn std::error::Error>> {
+        let vcd = format!("{}{}", HEADER, body);
+        let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+        sim.load_header()?;
+        sim.set_initial_cycle(mode);
+        sim.allocate_state()?;
+        let mut cycles = Vec::new();
+        while !sim.done() {
+            let CycleStep {
+                cycle: c, state: d, ..
+            } = sim.next_cycle()?;
+            cycles.push((c, d[0]));
+        }
+        Ok((sim.initial_snapshot(), cycles))
+    };
+
+    // $dumpvars before any timestamp, no #0
+    let body = "$dumpvars\n1!\n$end\n#5\n0!\n";
+    let (snapshot, cycles) = run(body, InitialCycle::Separate)?;
+    assert_eq!(snapshot, Some(true));
+    assert_eq!(cycles, [(-1, 1), (5, 0)]);
+    let (_, cycles) = run(body, InitialCycle::Merge)?;
+    assert_eq!(cycles, [(0, 1), (5, 0)]);
+
+    // Changes without any timestamp
+    let (snapshot, cycles) = run("1!\n", InitialCycle::Merge)?;
+    assert_eq!(snapshot, Some(true));
+    assert_eq!(cycles, [(0, 1)]);
+
+    // Initial values at #0
+    let body = "#0\n1!\n#5\n0!\n";
+    let (snapshot, cycles) = run(body, InitialCycle::Separate)?;
+    assert_eq!(snapshot, Some(false));
+    assert_eq!(cycles, [(-1, 0), (0, 1), (5, 0)]);
+    let (_, cycles) = run(body, InitialCycle::Merge)?;
+    assert_eq!(cycles, [(0, 1), (5, 0)]);
+
+    // No initial values at all
+    let (snapshot, cycles) = run("#5\n1!\n", InitialCycle::Merge)?;
+    assert_eq!(snapshot, Some(false));
+    assert_eq!(cycles, [(5, 1)]);
+    Ok(())
+}
+
+#[test]
+fn sim_checkpoint_resume() -> Result<(), Box<dyn std::error::Error>> {
+    let f = vcd_asset("good/verilator_riscv.vcd");
+    let mut sim = StateSimulation::new(&f)?;
+    sim.load_header()?;
+    sim.set_bus_reconstruction(true);
+    sim.allocate_state()?;
+    for _ in 0..100 {
+        sim.next_cycle()?;
+    }
+
+    let mut saved = Vec::new();
+    sim.checkpoint()?.save(&mut saved)?;
+    let checkpoint = Checkpoint::load(saved.as_slice())?;
+    assert_eq!(checkpoint.state, sim.state());
+    let mut resumed = StateSimulation::resume(&f, &checkpoint)?;
+    assert_eq!(resumed.tracked_variables(), sim.tracked_variables());
+
+    let mut n = 0;
+    while !sim.done() {
+        let CycleStep {
+            cycle: c, state: d, ..
+        } = sim.next_cycle()?;
+        let d = d.to_vec();
+        let CycleStep {
+            cycle: c_resumed,
+            state: d_resumed,
+            ..
+        } = resumed.next_cycle()?;
+        assert_eq!((c, d.as_slice()), (c_resumed, d_resumed));
+        n += 1;
+    }
+    assert!(resumed.done());
+    assert!(n > 0);
+    for (_, v) in sim.tracked_variables() {
+        assert_eq!(resumed.last_change(&v.id), sim.last_change(&v.id));
+    }
+
+    // Parts missing from older checkpoints are accepted, truncated ones are not
+    let mut older = checkpoint.clone();
+    older.last_change.clear();
+    older.assigned.clear();
+    StateSimulation::resume(&f, &older)?;
+    let mut truncated = checkpoint.clone();
+    truncated.last_change.pop();
+    assert!(matches!(
+        StateSimulation::resume(&f, &truncated),
+        Err(VcdError::ParseError)
+    ));
+    let mut truncated = checkpoint.clone();
+    truncated.assigned.push(0);
+    assert!(matches!(
+        StateSimulation::resume(&f, &truncated),
+        Err(VcdError::ParseError)
+    ));
+    Ok(())
+}
+
+#[test]
+fn sim_checkpoint_stats_history() -> Result<(), Box<dyn std::error::Error>> {
+    let f = vcd_asset("good/verilator_riscv.vcd");
+    let mut sim = StateSimulation::new(&f)?;
+    sim.load_header()?;
+    sim.set_initial_fill(LEVEL_X)?;
+    sim.set_history_depth(4);
+    sim.set_stats(true);
+    sim.allocate_state()?;
+    for _ in 0..50 {
+        sim.next_cycle()?;
+    }
+
+    let mut saved = Vec::new();
+    sim.checkpoint()?.save(&mut saved)?;
+    let checkpoint = Checkpoint::load(saved.as_slice())?;
+    let truncations: [fn(&mut Checkpoint); 2] =
+        [|c| c.history.truncate(1), |c| c.stats.truncate(1)];
+    for truncate in truncations {
+        let mut truncated = checkpoint.clone();
+        truncate(&mut truncated);
+        assert!(matches!(
+            StateSimulation::resume(&f, &truncated),
+            Err(VcdError::ParseError)
+        ));
+    }
+    let mut resumed = StateSimulation::resume(&f, &checkpoint)?;
+    assert_eq!(resumed.initial_fill(), LEVEL_X);
+    while !sim.done() {
+        sim.next_cycle()?;
+        resumed.next_cycle()?;
+    }
+    for (_, v) in sim.tracked_variables() {
+        assert_eq!(resumed.stats(&v.id), sim.stats(&v.id));
+        assert_eq!(resumed.history(&v.id), sim.history(&v.id));
+    }
+    Ok(())
+}
+
+#[test]
+fn sim_checkpoint_reorder() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end\n$var wire 2 \" b $end\n$enddefinitions $end\n\
+               #0\n0!\nb10 \"\n#20\n1!\n#10\nb01 \"\n#30\n0!\n#40\nb11 \"\n#35\n1!\n";
+    let mut path = std::env::temp_dir();
+    path.push(format!(
+        "wavetk-{}-checkpoint-reorder.vcd",
+        std::process::id()
+    ));
+    std::fs::write(&path, vcd)?;
+
+    let mut sim = StateSimulation::new(&path)?;
+    sim.set_timestamp_policy(TimestampPolicy::Reorder(2));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    sim.next_cycle()?;
+    sim.next_cycle()?;
+    // Cycles held back when the checkpoint is taken are given out by the resumed simulation
+    let mut saved = Vec::new();
+    sim.checkpoint()?.save(&mut saved)?;
+    let mut resumed = StateSimulation::resume(&path, &Checkpoint::load(saved.as_slice())?)?;
+    let mut steps = Vec::new();
+    while !sim.done() {
+        let step = sim.next_cycle()?;
+        steps.push((step.cycle, step.state.to_vec()));
+    }
+    let mut resumed_steps = Vec::new();
+    while !resumed.done() {
+        let step = resumed.next_cycle()?;
+        resumed_steps.push((step.cycle, step.state.to_vec()));
+    }
+    assert_eq!(resumed_steps, steps);
+    assert_eq!(
+        steps.iter().map(|s| s.0).collect::<Vec<_>>(),
+        [10, 20, 30, 35, 40]
+    );
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn sim_lazy_input() -> Result<(), Box<dyn std::error::Error>> {
+    for f in &["good/verilator_riscv.vcd", "good/simple_crlf_0.vcd"] {
+        let f = vcd_asset(f);
+        let mut eager = StateSimulation::new(&f)?;
+        let mut lazy = StateSimulation::new(&f)?;
+        lazy.set_lazy_input(true);
+        for sim in [&mut eager, &mut lazy] {
+            sim.load_header()?;
+            sim.allocate_state()?;
+        }
+        while !eager.done() {
+            let CycleStep {
+                cycle: c, state, ..
+            } = eager.next_cycle()?;
+            let state = state.to_vec();
+            let CycleStep {
+                cycle: c_lazy,
+                state: state_lazy,
+                ..
+            } = lazy.next_cycle()?;
+            assert_eq!((c, state.as_slice()), (c_lazy, state_lazy));
+        }
+        assert!(lazy.done());
+    }
+    Ok(())
+}
+
+#[test]
+fn sim_write_tracked() -> Result<(), Box<dyn std::error::Error>> {
+    let f = vcd_asset("good/verilator_riscv.vcd");
+    let open = |skip: usize| -> Result<(StateSimulation, i64), Box<dyn std::error::Error>> {
+        let mut sim = StateSimulation::new(&f)?;
+        sim.load_header()?;
+        let ids: Vec<String> = sim
+            .header()
+            .unwrap()
+            .variables
+            .iter()
+            .step_by(40)
+            .map(|v| v.id.clone())
+            .collect();
+        sim.track_variables(&ids.iter().map(|s| s.as_str()).collect::<Vec<_>>());
+        sim.allocate_state()?;
+        let mut last = -1;
+        for _ in 0..skip {
+            last = sim.next_cycle()?.cycle;
+        }
+        Ok((sim, last))
+    };
+
+    for skip in [0, 20] {
+        let mut filtered = Vec::new();
+        open(skip)?.0.write_tracked_to(&mut filtered)?;
+        let (mut expected, last) = open(skip)?;
+        let mut sim = StateSimulation::from_reader(std::io::Cursor::new(filtered));
+        sim.load_header()?;
+        assert_eq!(
+            sim.header().unwrap().timescale,
+            expected.header().unwrap().timescale
+        );
+        sim.allocate_state()?;
+        assert_eq!(sim.tracked_variables(), expected.tracked_variables());
+        if skip > 0 {
+            // Initial snapshot of the already reconstructed state, at the time of the last
+            // cycle (after an empty cycle -1)
+            assert_eq!(sim.next_cycle()?.cycle, -1);
+            let CycleStep {
+                cycle: c, state, ..
+            } = sim.next_cycle()?;
+            assert_eq!((c, state), (last, expected.state()));
+        }
+        let mut n = 0;
+        while !expected.done() {
+            let CycleStep {
+                cycle: c, state, ..
+            } = expected.next_cycle()?;
+            let state = state.to_vec();
+            let step = sim.next_cycle()?;
+            assert_eq!((step.cycle, step.state), (c, state.as_slice()));
+            n += 1;
+        }
+        assert!(n > 100);
+    }
+    Ok(())
+}
+
+#[test]
+fn decode_gray_and_one_hot() -> Result<(), Box<dyn std::error::Error>> {
+    let gray = [0b000, 0b001, 0b011, 0b010, 0b110, 0b111, 0b101, 0b100];
+    for (i, g) in gray.iter().enumerate() {
+        let bits: Vec<i8> = (0..3).rev().map(|k| (g >> k) & 1).collect();
+        assert_eq!(decode_gray(&bits), Some(i as u64));
+    }
+    assert_eq!(decode_gray(&[1; 64]), Some(0xaaaa_aaaa_aaaa_aaaa));
+    assert_eq!(decode_gray(&[1, -4, 0]), None);
+
+    assert_eq!(decode_one_hot(&[0, 0, 1]), Some(0));
+    assert_eq!(decode_one_hot(&[1, 0, 0, 0]), Some(3));
+    assert_eq!(decode_one_hot(&[0, 0, 0]), None);
+    assert_eq!(decode_one_hot(&[0, 1, 1]), None);
+    assert_eq!(decode_one_hot(&[0, -3, 1]), None);
+
+    let f = vcd_asset("good/ghdl_0.vcd");
+    let mut sim = StateSimulation::new(f.to_str().unwrap())?;
+    sim.load_header()?;
+    sim.allocate_state()?;
+    sim.next_cycle()?;
+    sim.next_cycle()?;
+    let clk = sim.variable_offset("!").unwrap();
+    assert_eq!(sim.value("!"), Some(&sim.state()[clk..clk + 1]));
+    assert_eq!(sim.value("nope"), None);
+    Ok(())
+}
+
+#[test]
+fn sim_value_i64() -> Result<(), Box<dyn std::error::Error>> {
+    assert_eq!(decode_signed(&[1, 1, 0]), Some(-2));
+    assert_eq!(decode_signed(&[0, 1, 1]), Some(3));
+    assert_eq!(decode_signed(&[1; 100]), Some(-1));
+    assert_eq!(decode_signed(&[0, 1, -4]), None);
+
+    let vcd = "$var wire 8 ! a [7:0] $end
+$var integer 32 \" i $end
+$var wire 4 # n [3:0] $end
+$enddefinitions $end
+#0
+b10000000 !
+b101 \"
+b0111 #
+#1
+b1 !
+b0 \"
+b1110 #
+#2
+bx1 #
+";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    sim.next_cycle()?;
+    let values = |sim: &StateSimulation| ["!", "\"", "#"].map(|id| sim.value_i64(id));
+    sim.next_cycle()?;
+    assert_eq!(values(&sim), [Some(-128), Some(-3), Some(7)]);
+    sim.next_cycle()?;
+    assert_eq!(values(&sim), [Some(-1), Some(0), Some(-2)]);
+    sim.next_cycle()?;
+    assert_eq!(values(&sim)[2], None);
+    assert_eq!(sim.value_i64("$"), None);
+    Ok(())
+}
+
+#[test]
+fn sim_last_change() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end
+$var wire 2 \" b $end
+$var wire 1 # c $end
+$enddefinitions $end
+$dumpvars
+0!
+b00 \"
+$end
+#10
+1!
+#20
+b01 \"
+1!
+#30
+0!
+";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let last = |sim: &StateSimulation| ["!", "\"", "#"].map(|id| sim.last_change(id));
+    assert_eq!(last(&sim), [None, None, None]);
+    sim.next_cycle()?;
+    assert_eq!(last(&sim), [Some(-1), Some(-1), None]);
+    assert_eq!(sim.next_cycle()?.cycle, 10);
+    assert_eq!(last(&sim), [Some(10), Some(-1), None]);
+    sim.next_cycle()?;
+    assert_eq!(last(&sim), [Some(10), Some(20), None]);
+    sim.next_cycle()?;
+    assert_eq!(last(&sim), [Some(30), Some(20), None]);
+    assert_eq!(sim.last_change("$"), None);
+    Ok(())
+}
+
+#[test]
+fn sim_stats() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end
+$var wire 2 \" b $end
+$var wire 1 # c $end
+$enddefinitions $end
+$dumpvars
+0!
+b00 \"
+$end
+#10
+1!
+#20
+b01 \"
+#30
+0!
+bx1 \"
+#40
+1!
+#50
+";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.set_stats(true);
+    sim.allocate_state()?;
+    while !sim.done() {
+        sim.next_cycle()?;
+    }
+    let a = sim.stats("!").unwrap();
+    assert_eq!(
+        (a.changes, a.first_change, a.last_change),
+        (4, Some(-1), Some(40))
+    );
+    assert_eq!((a.time_in(0), a.time_in(1), a.time_in(-4)), (20, 20, 0));
+    let b = sim.stats("\"").unwrap();
+    assert_eq!(
+        (b.changes, b.first_change, b.last_change),
+        (3, Some(-1), Some(30))
+    );
+    assert_eq!((b.time_in(0), b.time_in(1), b.time_in(-4)), (50, 20, 10));
+    let c = sim.stats("#").unwrap();
+    assert_eq!((c.changes, c.level_time), (0, [0; LOGIC_LEVELS]));
+    assert!(sim.stats("$").is_none());
+    Ok(())
+}
+
+#[test]
+fn sim_cycle_step() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$timescale 10ns $end
+$var wire 1 ! a $end
+$var wire 1 \" b $end
+$enddefinitions $end
+#0
+0!
+0\"
+#10
+1\"
+#20
+$dumpoff
+x!
+x\"
+$end
+#30
+$dumpon
+1!
+0\"
+$end
+#40
+";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    sim.next_cycle()?;
+    let step = sim.next_cycle()?;
+    assert_eq!(
+        (step.cycle, step.state, step.blackout),
+        (0, &[0, 0][..], false)
+    );
+    let step = sim.next_cycle()?;
+    assert_eq!(step.time.raw, 10);
+    assert!((step.time.ns().unwrap() - 100.0).abs() < 1e-9);
+    assert_eq!(step.changed().collect::<Vec<_>>(), [1]);
+    let step = sim.next_cycle()?;
+    assert_eq!(
+        (step.cycle, step.state, step.blackout),
+        (20, &[LEVEL_NO_DATA, LEVEL_NO_DATA][..], true)
+    );
+    assert_eq!(step.changed().count(), 2);
+    assert_eq!(sim.blackouts(), [(20, None)]);
+    let step = sim.next_cycle()?;
+    assert_eq!(
+        (step.cycle, step.state, step.blackout),
+        (30, &[1, 0][..], false)
+    );
+    assert_eq!(step.changed().count(), 2);
+    assert_eq!(sim.blackouts(), [(20, Some(30))]);
+    Ok(())
+}
+
+#[test]
+fn sim_time_range() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end
+$var wire 1 \" b $end
+$enddefinitions $end
+#0
+0!
+0\"
+#10
+1!
+#20
+1\"
+#30
+0!
+#40
+0\"
+";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    sim.set_time_range(15, 30);
+    let mut cycles = Vec::new();
+    while !sim.done() {
+        let step = sim.next_cycle()?;
+        cycles.push((step.cycle, step.state.to_vec(), step.changed().count()));
+    }
+    // Cycles 0 and 10 are applied to the first cycle returned
+    assert_eq!(cycles, [(20, vec![1, 1], 2), (30, vec![0, 1], 1)]);
+    Ok(())
+}
+
+#[test]
+fn sim_reset() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end\n$var wire 2 \" b $end\n$enddefinitions $end\n\
+               #0\n0!\nb10 \"\n#10\n1!\n#20\nb01 \"\n";
+    let mut path = std::env::temp_dir();
+    path.push(format!("wavetk-{}-reset.vcd", std::process::id()));
+    std::fs::write(&path, vcd)?;
+
+    let mut sim = StateSimulation::new(&path)?;
+    sim.load_header()?;
+    sim.set_stats(true);
+    sim.allocate_state()?;
+    let run = |sim: &mut StateSimulation| -> Result<_, VcdError> {
+        let mut steps = Vec::new();
+        while !sim.done() {
+            let step = sim.next_cycle()?;
+            steps.push((step.cycle, step.state.to_vec(), step.changed().count()));
+        }
+        Ok(steps)
+    };
+    let first = run(&mut sim)?;
+    let stats = sim.stats("\"");
+    assert_eq!(first.last(), Some(&(20, vec![1, 0, 1], 1)));
+    sim.reset()?;
+    assert_eq!(sim.state(), [0, 0, 0]);
+    assert_eq!(sim.last_change("!"), None);
+    assert_eq!(run(&mut sim)?, first);
+    assert_eq!(sim.stats("\""), stats);
+    std::fs::remove_file(&path)?;
+
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    assert!(matches!(sim.reset(), Err(VcdError::IoError(_))));
+    Ok(())
+}
+
+#[test]
+fn sim_reset_options() -> Result<(), Box<dyn std::error::Error>> {
+    // No `$enddefinitions`, a decreasing timestamp and a truncated last line
+    let vcd = "$var wire 1 ! a $end\n$var wire 2 \" b $end\n\
+               #0\n0!\nb10 \"\n#20\n1!\n#10\nb01 \"\n#30\n0!\nb1";
+    let mut path = std::env::temp_dir();
+    path.push(format!("wavetk-{}-reset-options.vcd", std::process::id()));
+    std::fs::write(&path, vcd)?;
+
+    let mut sim = StateSimulation::new(&path)?;
+    sim.set_strict(true);
+    sim.set_allow_missing_enddefinitions(true);
+    sim.set_lazy_input(true);
+    sim.set_allow_truncation(true);
+    sim.set_timestamp_policy(TimestampPolicy::Clamp);
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let run = |sim: &mut StateSimulation| -> Result<_, VcdError> {
+        let mut steps = Vec::new();
+        while !sim.done() {
+            let step = sim.next_cycle()?;
+            steps.push((step.cycle, step.state.to_vec()));
+        }
+        Ok(steps)
+    };
+    let first = run(&mut sim)?;
+    assert_eq!(first.last(), Some(&(30, vec![0, 0, 1])));
+    sim.reset()?;
+    assert_eq!(run(&mut sim)?, first);
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn sim_header_only() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end\n$var wire 2 \" b $end\n$enddefinitions $end\n\
+               #0\n0!\nb10 \"\n#10\n1!\n#20\nb01 \"\n";
+    let mut path = std::env::temp_dir();
+    path.push(format!("wavetk-{}-header-only.vcd", std::process::id()));
+    std::fs::write(&path, vcd)?;
+
+    let cycles = |sim: &mut StateSimulation| -> Result<_, VcdError> {
+        let mut cycles = Vec::new();
+        while !sim.done() {
+            cycles.push(sim.next_cycle()?.cycle);
+        }
+        Ok(cycles)
+    };
+    let mut sim = StateSimulation::new(&path)?;
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let expected = cycles(&mut sim)?;
+    assert!(expected.ends_with(&[0, 10, 20]));
+
+    let mut sim = StateSimulation::header_only(&path)?;
+    assert_eq!(sim.variables().len(), 2);
+    sim.allocate_state()?;
+    assert!(sim.done());
+    // The body can still be simulated from the start
+    sim.reset()?;
+    assert_eq!(cycles(&mut sim)?, expected);
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn sim_time_zero() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$timescale 1ns $end\n$timezero -5 $end\n$var wire 1 ! a $end\n\
+               $enddefinitions $end\n#0\n0!\n#10\n1!\n";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    assert_eq!(sim.time_zero(), 0);
+    sim.load_header()?;
+    sim.allocate_state()?;
+    assert_eq!(sim.time_zero(), -5);
+    let t = sim.timestamp(10);
+    assert_eq!(t.raw, 10);
+    assert!((t.ns().unwrap() - 5.0).abs() < 1e-9);
+
+    let out = String::from_utf8(sim.write_tracked_to(Vec::new())?)?;
+    assert!(out.contains("$timezero -5 $end"), "{}", out);
+    Ok(())
+}
+
+#[test]
+fn sim_invalid_changes() -> Result<(), Box<dyn std::error::Error>> {
+    let header = "$scope module top $end\n$var wire 2 ! a [1:0] $end\n$var wire 1 \" b $end\n\
+                  $upscope $end\n$enddefinitions $end\n";
+    let run = |body: &str, tracked: &[&str]| -> Result<(), VcdError> {
+        let vcd = format!("{}{}", header, body);
+        let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+        sim.load_header()?;
+        sim.track_variables(tracked);
+        sim.allocate_state()?;
+        while !sim.done() {
+            sim.next_cycle()?;
+        }
+        Ok(())
+    };
+    match run("#0\nb01 !\n#7\nb101 !\n", &[]) {
+        Err(VcdError::InvalidChange(c)) => {
+            assert_eq!((c.path.as_str(), c.id.as_str(), c.time), ("top.a", "!", 7));
+            assert_eq!(c.value, "101");
+            assert_eq!(c.reason, ChangeProblem::WidthMismatch { width: 2 });
+            assert_eq!(
+                c.to_string(),
+                "invalid change \"101\" of top.a (id \"!\") at time 7: 3 bits given for a \
+                 variable of width 2"
+            );
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+    match run("$dumpvars\n1#\n$end\n", &[]) {
+        Err(VcdError::InvalidChange(c)) => {
+            assert_eq!((c.path.as_str(), c.id.as_str(), c.time), ("", "#", -1));
+            assert_eq!(c.reason, ChangeProblem::UnknownId);
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+    // Changes of declared but untracked variables are skipped
+    run("#0\nb101 !\n0\"\n", &["\""])?;
+    Ok(())
+}
+
+#[test]
+fn sim_ieee_1164_levels() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 2 ! a $end\n$var wire 1 \" b $end\n$enddefinitions $end\n\
+               #0\nbLH !\nh\"\n#10\nb-1 !\n-\"\n#20\nbl0 !\nL\"\n";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    sim.next_cycle()?;
+    assert_eq!(sim.next_cycle()?.state, [LEVEL_L, LEVEL_H, LEVEL_H]);
+    assert_eq!(
+        sim.next_cycle()?.state,
+        [LEVEL_DONT_CARE, LEVEL_1, LEVEL_DONT_CARE]
+    );
+    assert_eq!(sim.next_cycle()?.state, [LEVEL_L, LEVEL_0, LEVEL_L]);
+    let chars: String = [LEVEL_L, LEVEL_H, LEVEL_DONT_CARE]
+        .iter()
+        .map(|l| logic_char(*l))
+        .collect();
+    assert_eq!(chars, "LH-");
+    Ok(())
+}
+
+#[test]
+fn sim_state_of_scope() -> Result<(), Box<dyn std::error::Error>> {
+    // `top.cpu` is declared twice, `clk` is also seen from `top.mem`
+    let vcd = "$scope module top $end\n$var wire 1 ! clk $end\n\
+               $scope module cpu $end\n$var wire 4 \" pc $end\n$upscope $end\n\
+               $scope module mem $end\n$var wire 2 # addr $end\n$var wire 1 ! clk $end\n\
+               $upscope $end\n$scope module cpu $end\n$scope module alu $end\n\
+               $var wire 1 $ carry $end\n$upscope $end\n$upscope $end\n$upscope $end\n\
+               $enddefinitions $end\n#0\n1!\nb1010 \"\nb01 #\n0$\n";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let layout: Vec<(usize, &str)> = sim
+        .tracked_variables()
+        .iter()
+        .map(|(o, v)| (*o, v.name.as_str()))
+        .collect();
+    assert_eq!(layout, [(0, "clk"), (1, "pc"), (5, "carry"), (6, "addr")]);
+    assert_eq!(sim.scope_range("top"), Some(0..8));
+    assert_eq!(sim.scope_range("top.cpu"), Some(1..6));
+    assert_eq!(sim.scope_range("top.mem"), Some(6..8));
+    assert_eq!(sim.scope_range("top.cpu.alu.x"), None);
+    while !sim.done() {
+        sim.next_cycle()?;
+    }
+    assert_eq!(sim.state_of_scope("top.cpu"), Some(&[1, 0, 1, 0, 0][..]));
+    assert_eq!(sim.state_of_scope("top.mem"), Some(&[0, 1][..]));
+
+    // Scopes are named like the paths of the demangled variables
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.set_demangler("strip:top".parse()?);
+    sim.load_header()?;
+    sim.allocate_state()?;
+    assert_eq!(sim.scope_range("top"), None);
+    assert_eq!(sim.scope_range(""), None);
+    assert_eq!(sim.scope_range("cpu"), Some(1..6));
+    assert_eq!(sim.scope_range("cpu.alu"), Some(5..6));
+    assert_eq!(sim.scope_range("mem"), Some(6..8));
+
+    // `top.cpu` and `cpu` are both named `cpu` once `top` is stripped, `mem` is declared
+    // between them
+    let vcd = "$scope module top $end\n$scope module cpu $end\n$var wire 1 ! pc $end\n\
+               $upscope $end\n$scope module mem $end\n$var wire 2 \" addr $end\n\
+               $upscope $end\n$upscope $end\n$scope module cpu $end\n\
+               $var wire 1 # irq $end\n$upscope $end\n$enddefinitions $end\n#0\n";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.set_demangler("strip:top".parse()?);
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let layout: Vec<(usize, &str)> = sim
+        .tracked_variables()
+        .iter()
+        .map(|(o, v)| (*o, v.name.as_str()))
+        .collect();
+    assert_eq!(layout, [(0, "pc"), (1, "irq"), (2, "addr")]);
+    assert_eq!(sim.scope_range("cpu"), Some(0..2));
+    assert_eq!(sim.scope_range("mem"), Some(2..4));
+    Ok(())
+}
+
+#[test]
+fn sim_initial_fill() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$scope module top $end\n$var wire 1 ! a $end\n$var wire 2 \" b $end\n\
+               $var wire 1 # c $end\n$upscope $end\n$enddefinitions $end\n\
+               #0\n0!\n#10\nbx \"\n#20\n1!\n";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    for level in [
+        LEVEL_1 + 1,
+        LEVEL_DONT_CARE - 1,
+        i8::MIN,
+        LEVEL_OTHER,
+        LEVEL_NO_DATA,
+    ] {
+        assert!(matches!(
+            sim.set_initial_fill(level),
+            Err(VcdError::User(_))
+        ));
+    }
+    assert_eq!(sim.initial_fill(), LEVEL_0);
+    sim.set_initial_fill(LEVEL_X)?;
+    sim.load_header()?;
+    sim.allocate_state()?;
+    assert_eq!(sim.state(), [LEVEL_X; 4]);
+    let mut assigned = Vec::new();
+    while !sim.done() {
+        let cycle = sim.next_cycle()?.cycle;
+        let ids = ["!", "\"", "#"].map(|id| sim.is_assigned(id).unwrap());
+        assigned.push((cycle, ids));
+    }
+    assert_eq!(
+        assigned,
+        [
+            (-1, [false, false, false]),
+            (0, [true, false, false]),
+            // Assigned to its initial fill, without changing the state
+            (10, [true, true, false]),
+            (20, [true, true, false]),
+        ]
+    );
+    assert_eq!(sim.state(), [LEVEL_1, LEVEL_X, LEVEL_X, LEVEL_X]);
+    assert_eq!(sim.assigned_bitmap(), [0b011]);
+    assert_eq!(sim.is_assigned("?"), None);
+
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    assert_eq!(sim.state(), [LEVEL_0; 4]);
+    Ok(())
+}
+
+#[test]
+fn sim_extended_vcd() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$scope module top $end\n$var port [3:0] <0 addr $end\n$upscope $end\n\
+               $enddefinitions $end\n#0\n$dumpports\npDUZN 6 6 <0\n$end\n#10\npLHlh 6 6 <0\n";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    sim.next_cycle()?;
+    sim.next_cycle()?;
+    assert_eq!(sim.state(), [LEVEL_0, LEVEL_1, LEVEL_Z, LEVEL_X]);
+    sim.next_cycle()?;
+    assert_eq!(sim.state(), [LEVEL_0, LEVEL_1, LEVEL_0, LEVEL_1]);
+    Ok(())
+}
+
+#[test]
+fn sim_string_values() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var string 1 ! msg $end\n$var wire 1 \" clk $end\n$enddefinitions $end\n\
+               #0\nsidle !\n0\"\n#10\nsbusy !\n1\"\n#20\nsidle !\n";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    sim.next_cycle()?;
+    assert_eq!(sim.string_value("!"), None);
+    sim.next_cycle()?;
+    assert_eq!(sim.state(), [LEVEL_0]);
+    assert_eq!(sim.string_value("!"), Some("idle"));
+    sim.next_cycle()?;
+    assert_eq!(sim.string_value("!"), Some("busy"));
+    sim.next_cycle()?;
+    assert_eq!(sim.string_index("!"), Some(0));
+    assert_eq!(sim.string_table(), ["idle", "busy"]);
+    assert_eq!(
+        sim.checkpoint()?.strings,
+        [("!".to_string(), "idle".to_string())]
+    );
+    Ok(())
+}
+
+#[test]
+fn sim_string_memory_limit() -> Result<(), Box<dyn std::error::Error>> {
+    // 200 distinct texts of 64 bytes, each one is stored twice
+    let mut vcd = "$var string 1 ! msg $end\n$enddefinitions $end\n".to_string();
+    for t in 0..200 {
+        vcd += &format!("#{}\ns{:064} !\n", t, t);
+    }
+    let run = |limit| -> Result<usize, VcdError> {
+        let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd.clone()));
+        sim.set_memory_limit(Some(limit));
+        sim.load_header()?;
+        sim.allocate_state()?;
+        while !sim.done() {
+            sim.next_cycle()?;
+        }
+        Ok(sim.string_table().len())
+    };
+    assert_eq!(run(1 << 20)?, 200);
+    match run(16 << 10) {
+        Err(VcdError::MemoryLimit) => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+    Ok(())
+}
+
+#[test]
+fn sim_header_info_json() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$date today $end\n$version sim 1.0 $end\n$comment seed 42 $end\n\
+               $scope module top $end\n$var wire 1 ! clk $end\n$var wire 2 \" bus $end\n\
+               $upscope $end\n$enddefinitions $end\n#0\n";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.track_variables(&["\""]);
+    sim.allocate_state()?;
+    let mut out = Vec::new();
+    sim.save_header_info(&mut out)?;
+    let json: serde_json::Value = serde_json::from_slice(&out)?;
+    assert_eq!(
+        json["metadata"],
+        serde_json::json!({"date": "today", "version": "sim 1.0", "comments": ["seed 42"]})
+    );
+    let variables = json["variables"].as_object().unwrap();
+    assert_eq!(variables.len(), 2);
+    assert_eq!(variables["!"][0], serde_json::Value::Null);
+    assert_eq!(variables["\""][0], 0);
+    assert_eq!(variables["\""][1]["name"], "bus");
+    Ok(())
+}
+
+#[test]
+fn sim_event_counts() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var event 1 ! ev $end\n$var wire 1 \" clk $end\n$enddefinitions $end\n\
+               #0\n$dumpvars\nx!\n0\"\n$end\n#10\n1!\n1!\n1\"\n#20\n0\"\n";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    sim.next_cycle()?;
+    sim.next_cycle()?;
+    assert_eq!(sim.state(), [LEVEL_0]);
+    assert_eq!(sim.event_count("!"), Some(0));
+    sim.next_cycle()?;
+    assert_eq!(sim.state(), [LEVEL_1]);
+    assert_eq!(sim.event_count("!"), Some(2));
+    assert_eq!(sim.triggered_events().collect::<Vec<_>>(), ["!"]);
+    sim.next_cycle()?;
+    assert_eq!(sim.event_count("!"), Some(0));
+    assert_eq!(sim.event_count("\""), None);
+    Ok(())
+}
+
+#[test]
+fn sim_event_order() -> Result<(), Box<dyn std::error::Error>> {
+    // Events are listed in declaration order, whatever the order of their changes
+    let ids = ["e", "a", "z", "c", "m", "b", "y", "d"];
+    let mut vcd = String::new();
+    for id in &ids {
+        vcd += &format!("$var event 1 {} ev_{} $end\n", id, id);
+    }
+    vcd += "$enddefinitions $end\n#10\n";
+    for id in ids.iter().rev() {
+        vcd += &format!("1{}\n", id);
+    }
+    let open = || -> Result<StateSimulation, VcdError> {
+        let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd.clone()));
+        sim.load_header()?;
+        sim.allocate_state()?;
+        Ok(sim)
+    };
+    let mut sim = open()?;
+    while !sim.done() {
+        sim.next_cycle()?;
+    }
+    assert_eq!(sim.triggered_events().collect::<Vec<_>>(), ids);
+
+    let out = String::from_utf8(open()?.write_tracked_to(Vec::new())?)?;
+    let changes: Vec<&str> = out.lines().filter(|l| l.starts_with('1')).collect();
+    let expected: Vec<String> = ids.iter().map(|id| format!("1{}", id)).collect();
+    assert_eq!(changes, expected);
+    Ok(())
+}
+
+#[test]
+fn sim_cycle_hooks() -> Result<(), Box<dyn std::error::Error>> {
+    use std::sync::{Arc, Mutex};
+    let vcd = "$var wire 1 ! a $end\n$var wire 1 \" b $end\n$enddefinitions $end\n\
+               #0\n0!\n0\"\n#10\n1\"\n#20\n1!\n0\"\n";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let cycles = Arc::new(Mutex::new(Vec::new()));
+    let changes = Arc::new(Mutex::new(0));
+    let c = cycles.clone();
+    sim.on_cycle(move |step| c.lock().unwrap().push((step.cycle, step.state.to_vec())));
+    let n = changes.clone();
+    sim.on_cycle(move |step| *n.lock().unwrap() += step.changed().count());
+    while !sim.done() {
+        sim.next_cycle()?;
+    }
+    assert_eq!(
+        *cycles.lock().unwrap(),
+        [
+            (-1, vec![0, 0]),
+            (0, vec![0, 0]),
+            (10, vec![0, 1]),
+            (20, vec![1, 0])
+        ]
+    );
+    assert_eq!(*changes.lock().unwrap(), 5);
+    Ok(())
+}
+
+#[test]
+fn sim_run_to_end() -> Result<(), Box<dyn std::error::Error>> {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    let vcd = "$var wire 1 ! a $end\n$var wire 1 \" b $end\n$enddefinitions $end\n\
+               #0\n0!\n0\"\n#10\n1\"\n#20\n1!\n0\"\n";
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(vcd));
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let seen = Arc::new(AtomicU64::new(0));
+    let s = seen.clone();
+    sim.on_cycle(move |_| {
+        s.fetch_add(1, Ordering::Relaxed);
+    });
+    let summary = sim.run_to_end()?;
+    assert_eq!(summary.cycles, 4);
+    assert_eq!(seen.load(Ordering::Relaxed), 4);
+    assert_eq!(summary.end_time.map(|t| t.raw), Some(20));
+    assert_eq!((summary.commands, summary.changes), (8, 5));
+    // The whole body was read
+    assert!(summary.bytes >= (vcd.len() - vcd.find('#').unwrap()) as u64);
+    assert!(sim.done());
+    assert_eq!(summary.truncation, None);
+
+    // Dump of a crashed simulation, cut in a value change
+    let mut sim = StateSimulation::from_reader(std::io::Cursor::new(&vcd[..vcd.len() - 2]));
+    sim.set_allow_truncation(true);
+    sim.load_header()?;
+    sim.allocate_state()?;
+    let summary = sim.run_to_end()?;
+    assert_eq!(summary.cycles, 4);
+    assert_eq!(summary.changes, 4);
+    assert_eq!(summary.truncation.map(|t| t.discarded), Some(1));
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use std::fs::File;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use wavetk::transform::VcdTransform;
+use wavetk::vcd::{VcdCommand, VcdValue};
+use wavetk::{VcdParser, VcdWriter};
+
+fn vcd_asset(rel_path: &str) -> PathBuf {
+    let mut path = PathBuf::from(file!());
+    path.pop();
+    path.pop();
+    path.pop();
+    path.push("assets/vcd");
+    path.push(rel_path);
+    path
+}
+
+fn transform(mut t: VcdTransform) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let f = File::open(vcd_asset("good/picorv32_iverilog.vcd"))?;
+    let mut parser = VcdParser::with_chunk_size(4096, f);
+    Ok(t.run(&mut parser, VcdWriter::new(Vec::new()))?)
+}
+
+fn changes(vcd: &[u8], id: &str) -> Result<Vec<(u64, String)>, Box<dyn std::error::Error>> {
+    let mut parser = VcdParser::with_chunk_size(4096, Cursor::new(vcd.to_vec()));
+    parser.load_header()?;
+    Ok(parser.changes_of(id)?)
+}
+
+#[test]
+fn transform_identity() -> Result<(), Box<dyn std::error::Error>> {
+    let input = std::fs::read(vcd_asset("good/picorv32_iverilog.vcd"))?;
+    let output = transform(VcdTransform::new())?;
+    let mut parser = VcdParser::with_chunk_size(4096, Cursor::new(&output));
+    let header = parser.load_header()?.clone();
+    let mut expected = VcdParser::with_chunk_size(4096, Cursor::new(&input));
+    assert_eq!(header.variables, expected.load_header()?.variables);
+    for v in &header.variables {
+        assert_eq!(changes(&output, &v.id)?, changes(&input, &v.id)?);
+    }
+    Ok(())
+}
+
+#[test]
+fn transform_hooks() -> Result<(), Box<dyn std::error::Error>> {
+    let mut t = VcdTransform::new();
+    t.drop_commands(|cmd| matches!(cmd, VcdCommand::ValueChange(c) if c.var_id == "!"));
+    t.modify_values(|id, value| match (id, value) {
+        ("-", VcdValue::Vector(_)) => Some("x".to_string()),
+        _ => None,
+    });
+    t.remap_ids(|id| match id {
+        "$" => Some("clk".to_string()),
+        _ => None,
+    });
+    let output = transform(t)?;
+
+    let mut parser = VcdParser::with_chunk_size(4096, Cursor::new(&output));
+    let header = parser.load_header()?;
+    assert!(header
+        .variables
+        .iter()
+        .any(|v| v.id == "clk" && v.name == "clk"));
+    assert!(header.variables.iter().all(|v| v.id != "$"));
+    assert!(changes(&output, "!")?.is_empty());
+    let masked = changes(&output, "-")?;
+    assert!(!masked.is_empty());
+    assert!(masked.iter().all(|(_, v)| v == "x"));
+    let input = std::fs::read(vcd_asset("good/picorv32_iverilog.vcd"))?;
+    assert_eq!(changes(&output, "clk")?, changes(&input, "$")?);
+    Ok(())
+}
+
+#[test]
+fn transform_checks_output() {
+    let mut t = VcdTransform::new();
+    t.modify_values(|_, value| match value {
+        VcdValue::Bit(_) => Some("01".to_string()),
+        _ => None,
+    });
+    assert!(transform(t).is_err());
+}
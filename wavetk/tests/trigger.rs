@@ -0,0 +1,79 @@
+use std::io::Cursor;
+
+use wavetk::simulation::{CycleStep, StateSimulation};
+use wavetk::trigger::{Trigger, TriggerError};
+
+const TRIGGER_VCD: &str = "$timescale 1ns $end
+$scope module top $end
+$var wire 1 ! err $end
+$var wire 4 \" state [3:0] $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpvars
+0!
+bx \"
+$end
+#10
+b0110 \"
+#20
+b1100 \"
+#30
+1!
+#40
+0!
+b1110 \"
+";
+
+fn firing_times(expr: &str) -> Result<Vec<i64>, Box<dyn std::error::Error>> {
+    let mut trigger: Trigger = expr.parse()?;
+    let mut sim = StateSimulation::from_reader(Cursor::new(TRIGGER_VCD));
+    sim.load_header()?;
+    let ids = trigger.variable_ids(&sim)?;
+    assert_eq!(ids.len(), trigger.signals().count());
+    sim.allocate_state()?;
+    trigger.bind(&sim)?;
+    let mut times = Vec::new();
+    while !sim.done() {
+        let CycleStep {
+            cycle: time, state, ..
+        } = sim.next_cycle()?;
+        if time >= 0 && trigger.eval(state) {
+            times.push(time);
+        }
+    }
+    Ok(times)
+}
+
+#[test]
+fn trigger_conditions() -> Result<(), Box<dyn std::error::Error>> {
+    assert_eq!(firing_times("top.err == 1")?, [30]);
+    assert_eq!(firing_times("top.err != 1")?, [0, 10, 20, 40]);
+    assert_eq!(firing_times("top.state == 0bx1x0")?, [10, 20, 30, 40]);
+    assert_eq!(firing_times("top.state >= 12")?, [20, 30, 40]);
+    assert_eq!(firing_times("top.state<0xc")?, [10]);
+    assert_eq!(
+        firing_times("top.err == 1 || top.state == 6 && top.err == 0")?,
+        [10, 30]
+    );
+    Ok(())
+}
+
+#[test]
+fn trigger_errors() {
+    assert!(matches!(
+        "top.err".parse::<Trigger>(),
+        Err(TriggerError::Syntax(_))
+    ));
+    assert!(matches!(
+        "top.state > 0b1x".parse::<Trigger>(),
+        Err(TriggerError::Syntax(_))
+    ));
+    let trigger: Trigger = "top.nothing == 1".parse().unwrap();
+    let mut sim = StateSimulation::from_reader(Cursor::new(TRIGGER_VCD));
+    sim.load_header().unwrap();
+    assert_eq!(
+        trigger.variable_ids(&sim),
+        Err(TriggerError::UnknownSignal("top.nothing".to_string()))
+    );
+}
@@ -0,0 +1,87 @@
+use std::convert::TryFrom;
+
+use rust_vcd::{Command, Header, Parser, ScopeItem, SimulationCommand, TimescaleUnit};
+use wavetk::vcd::{VcdChange, VcdError, VcdHeader, VcdParser, VcdValue};
+use wavetk::vcd_interop::CommandConverter;
+
+const VCD: &str = "$timescale 10 ns $end\n$scope module top $end\n$var wire 1 ! clk $end\n\
+                   $scope task t $end\n$var reg 8 \" data [7:0] $end\n$upscope $end\n\
+                   $var real 1 # r $end\n$upscope $end\n$enddefinitions $end\n\
+                   #0\n$dumpvars\n0!\nb0 \"\nr0.5 #\n$end\n#5\n1!\nbx1 \"\n$comment hi $end\n\
+                   #10\n0!\nr1.25 #\n";
+
+#[test]
+fn header_conversions() -> Result<(), Box<dyn std::error::Error>> {
+    let mut parser = VcdParser::with_chunk_size(4096, VCD.as_bytes());
+    let header = parser.load_header()?.clone();
+
+    let converted = Header::from(&header);
+    assert_eq!(converted.timescale, Some((10, TimescaleUnit::NS)));
+    let expected = Parser::new(VCD.as_bytes()).parse_header()?;
+    assert_eq!(converted.items, expected.items);
+    match &converted.items[..] {
+        [ScopeItem::Scope(top)] => assert_eq!(top.items.len(), 3),
+        items => panic!("unexpected items {:?}", items),
+    }
+
+    assert_eq!(VcdHeader::from(&converted), header);
+    Ok(())
+}
+
+#[test]
+fn command_conversions() -> Result<(), Box<dyn std::error::Error>> {
+    let mut parser = Parser::new(VCD.as_bytes());
+    parser.parse_header()?;
+    let expected = parser
+        .filter(|c| !matches!(c, Ok(Command::Comment(_))))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut parser = VcdParser::with_chunk_size(4096, VCD.as_bytes());
+    parser.load_header()?;
+    let mut converter = CommandConverter::new();
+    let mut commands = Vec::new();
+    parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+        if let Some(c) = converter.to_vcd_crate(&cmd)? {
+            commands.push(c);
+        }
+        Ok(false)
+    })?;
+    commands.retain(|c| !matches!(c, Command::Comment(_)));
+    assert_eq!(commands, expected);
+    assert_eq!(commands[1], Command::Begin(SimulationCommand::Dumpvars));
+
+    // And back
+    let mut back = CommandConverter::new();
+    let cmd = back.from_vcd_crate(&commands[8]);
+    assert_eq!(
+        format!("{:?}", cmd),
+        format!(
+            "{:?}",
+            Some(wavetk::vcd::VcdCommand::ValueChange(VcdChange {
+                var_id: "\"",
+                value: VcdValue::Vector("x1")
+            }))
+        )
+    );
+    let cmd = back.from_vcd_crate(&commands[11]);
+    assert!(matches!(
+        cmd,
+        Some(wavetk::vcd::VcdCommand::ValueChange(VcdChange {
+            value: VcdValue::Real("1.25"),
+            ..
+        }))
+    ));
+    Ok(())
+}
+
+#[test]
+fn unsupported_values() {
+    let change = VcdChange {
+        var_id: "!",
+        value: VcdValue::Bit('u'),
+    };
+    assert!(matches!(
+        Command::try_from(&change),
+        Err(VcdError::ParseError)
+    ));
+}
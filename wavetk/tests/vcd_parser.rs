@@ -1,7 +1,11 @@
 use std::fs::File;
 use std::path::PathBuf;
 
-use wavetk::vcd::{VcdHeader, VcdParser};
+use wavetk::types::{Range, VariableKind};
+use wavetk::vcd::{
+    ChangeBatch, DumpSection, Expected, HeaderViolation, OwnedVcdCommand, ParserOptions, PortValue,
+    TimestampPolicy, VcdChange, VcdCommand, VcdError, VcdHeader, VcdParser, VcdValue,
+};
 
 fn vcd_asset(rel_path: &str) -> PathBuf {
     let mut path = PathBuf::from(file!());
@@ -21,9 +25,9 @@ fn parse_file(
     let mut parser = VcdParser::with_chunk_size(chunk_size, f);
     let header = parser.load_header()?.clone();
     let mut cnt = 0;
-    parser.process_vcd_commands(|_cmd| {
+    parser.process_vcd_commands(|_cmd| -> Result<bool, VcdError> {
         cnt += 1;
-        false
+        Ok(false)
     })?;
     Ok((header, cnt))
 }
@@ -73,3 +77,1076 @@ fn parse_header_0() -> Result<(), Box<dyn std::error::Error>> {
     assert_eq!(n_cmd, 3);
     Ok(())
 }
+
+#[test]
+fn parse_memory_limit() -> Result<(), Box<dyn std::error::Error>> {
+    let f = File::open(vcd_asset("good/verilator_riscv.vcd"))?;
+    let mut parser = VcdParser::with_chunk_size(256, f);
+    parser.set_memory_limit(Some(128));
+    match parser.load_header() {
+        Err(VcdError::MemoryLimit) => {}
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+
+    // Out of memory errors of the input are not the limit of the parser
+    struct OutOfMemory;
+    impl std::io::Read for OutOfMemory {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::OutOfMemory.into())
+        }
+    }
+    let mut parser = VcdParser::with_chunk_size(256, OutOfMemory);
+    parser.set_memory_limit(Some(1024));
+    match parser.load_header() {
+        Err(VcdError::IoError(e)) if e.kind() == std::io::ErrorKind::OutOfMemory => Ok(()),
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn parse_changes_of() -> Result<(), Box<dyn std::error::Error>> {
+    let f = File::open(vcd_asset("good/ghdl_0.vcd"))?;
+    let mut parser = VcdParser::with_chunk_size(4096, f);
+    parser.load_header()?;
+    let clk = parser.changes_of("!")?;
+    assert_eq!(clk[0], (0, "0".to_string()));
+    assert_eq!(clk[1], (5000000, "1".to_string()));
+    assert!(parser.done());
+
+    let mut parser = VcdParser::with_chunk_size(4096, File::open(vcd_asset("good/ghdl_0.vcd"))?);
+    assert!(matches!(
+        parser.changes_of("!"),
+        Err(VcdError::PartialHeader)
+    ));
+    parser.load_header()?;
+    assert!(matches!(
+        parser.changes_of("not an id"),
+        Err(VcdError::UnknownVariable)
+    ));
+    Ok(())
+}
+
+#[derive(Debug)]
+enum CallbackError {
+    Parse(VcdError),
+    TooLate(u64),
+}
+
+impl From<VcdError> for CallbackError {
+    fn from(e: VcdError) -> Self {
+        CallbackError::Parse(e)
+    }
+}
+
+#[test]
+fn parse_callback_errors() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end\n$enddefinitions $end\n#0\n1!\n#5\n0!\n#10\n1!\n";
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.load_header()?;
+    let mut n = 0;
+    let r = parser.process_vcd_commands(|cmd| match cmd {
+        VcdCommand::SetCycle(t) if t > 4 => Err(CallbackError::TooLate(t)),
+        _ => {
+            n += 1;
+            Ok(false)
+        }
+    });
+    assert!(matches!(r, Err(CallbackError::TooLate(5))));
+    assert_eq!(n, 2);
+    // Parsing resumes after the failing command
+    let mut rest = 0;
+    parser.process_vcd_commands(|_| -> Result<bool, VcdError> {
+        rest += 1;
+        Ok(false)
+    })?;
+    assert_eq!(rest, 3);
+
+    let vcd = "$var wire 1 ! a $end\n$enddefinitions $end\n#0\n1!\n#zz\n";
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.load_header()?;
+    let r = parser.process_vcd_commands(|_| Ok(false));
+    assert!(matches!(r, Err(CallbackError::Parse(VcdError::Syntax(_)))));
+    Ok(())
+}
+
+#[test]
+fn parse_syntax_errors() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end\n$var wire 4 \" v $end\n$enddefinitions $end\n#0\n1!\n  #1x0 b0101 \"\n";
+    for chunk_size in [4, 7, 13, 32, 4096] {
+        let mut parser = VcdParser::with_chunk_size(chunk_size, vcd.as_bytes());
+        parser.load_header()?;
+        let r = parser.process_vcd_commands(|_| -> Result<bool, VcdError> { Ok(false) });
+        let VcdError::Syntax(e) = r.unwrap_err() else {
+            panic!("expected a syntax error")
+        };
+        assert_eq!((e.line, e.column, e.position), (6, 3, 71));
+        assert_eq!(e.expected, Expected::Timestamp);
+        assert_eq!(e.excerpt, "#1x0 b0101 \"");
+        assert_eq!(
+            e.to_string(),
+            "syntax error at line 6, column 3 (byte 71): expected a timestamp, \
+             found \"#1x0 b0101 \\\"\""
+        );
+    }
+
+    let vcd = "$timescale 1ps $end\n$var wire 1 ! a $end\nfoo\n$enddefinitions $end\n";
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    let VcdError::Syntax(e) = parser.load_header().unwrap_err() else {
+        panic!("expected a syntax error")
+    };
+    assert_eq!((e.line, e.column), (3, 1));
+    assert_eq!(e.expected, Expected::HeaderCommand);
+    assert_eq!(e.excerpt, "foo");
+    Ok(())
+}
+
+#[test]
+fn parse_user_errors() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end\n$enddefinitions $end\n#0\n1!\n#5\nx!\n";
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.load_header()?;
+    let r = parser.process_vcd_commands(|cmd| match cmd {
+        VcdCommand::ValueChange(v) if v.value == VcdValue::Bit('x') => {
+            Err(VcdError::user(format!("unknown value for {}", v.var_id)))
+        }
+        _ => Ok(false),
+    });
+    let e = r.unwrap_err();
+    assert!(matches!(e, VcdError::User(_)));
+    assert_eq!(e.to_string(), "unknown value for !");
+    assert!(std::error::Error::source(&e).is_some());
+    Ok(())
+}
+
+#[test]
+fn parse_push_mode() -> Result<(), Box<dyn std::error::Error>> {
+    let data = std::fs::read(vcd_asset("good/ghdl_0.vcd"))?;
+    let mut parser = VcdParser::with_chunk_size(4096, data.as_slice());
+    let header = parser.load_header()?.clone();
+    let expected = parser.changes_of("!")?;
+
+    for chunk_size in [1, 37, 4096] {
+        let mut parser = VcdParser::push_mode(64);
+        let mut loaded = false;
+        let mut changes = Vec::new();
+        let mut time = 0;
+        let mut process = |parser: &mut VcdParser<_>| loop {
+            let r = parser.process_vcd_commands(|cmd| {
+                match cmd {
+                    VcdCommand::SetCycle(t) => time = t,
+                    VcdCommand::ValueChange(v) if v.var_id == "!" => {
+                        if let VcdValue::Bit(c) = v.value {
+                            changes.push((time, c.to_string()));
+                        }
+                    }
+                    _ => {}
+                }
+                Ok(false)
+            });
+            match r {
+                Ok(()) if parser.done() => break,
+                Ok(()) => {}
+                Err(VcdError::MissingData) => break,
+                Err(e) => panic!("{:?}", e),
+            }
+        };
+        for chunk in data.chunks(chunk_size) {
+            parser.feed(chunk);
+            if !loaded {
+                match parser.load_header() {
+                    Ok(h) => assert_eq!(h, &header),
+                    Err(VcdError::MissingData) => continue,
+                    Err(e) => return Err(e.into()),
+                }
+                loaded = true;
+            }
+            process(&mut parser);
+        }
+        parser.finish();
+        process(&mut parser);
+        assert!(parser.done());
+        assert_eq!(changes, expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn parse_non_ascii_text() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$date\n  Mardi 3 février 2026\n$end\n$version Synthé ® $end\n\
+               $var wire 1 ! a $end\n$enddefinitions $end\n#0\n1!\n\
+               $comment écrit par l'outil $end\n#5\n0!\n";
+    // Small chunks so that multi-byte characters straddle refills
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.load_header()?;
+    assert_eq!(
+        parser.changes_of("!")?,
+        [(0, "1".to_string()), (5, "0".to_string())]
+    );
+
+    // Only the header is checked, the body is decoded as it is parsed
+    let vcd = "$var wire 1 é a $end\n$enddefinitions $end\n#0\n1!\n";
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    assert!(matches!(parser.load_header(), Err(VcdError::Utf8Error)));
+    let vcd = "$var wire 1 ! a $end\n$enddefinitions $end\n#0\n1!\né\n";
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.load_header()?;
+    let r = parser.process_vcd_commands(|_| -> Result<bool, VcdError> { Ok(false) });
+    assert!(matches!(r, Err(VcdError::Syntax(e)) if e.excerpt == "é"));
+
+    // UTF-8 values, and any bytes in the text of directives, are accepted in the body
+    let vcd: &[u8] = b"$var string 1 ! s $end\n$enddefinitions $end\n#0\nsgr\xc3\xbc\xc3\x9fe !\n\
+                       $comment caf\xe9 $end\n#5\n";
+    let mut parser = VcdParser::with_chunk_size(4, vcd);
+    parser.load_header()?;
+    let mut values = vec![];
+    parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+        if let VcdCommand::ValueChange(c) = cmd {
+            values.push(format!("{:?}", c.value));
+        }
+        Ok(false)
+    })?;
+    assert_eq!(values, ["String(\"grüße\")"]);
+    Ok(())
+}
+
+#[test]
+fn parse_utf8_identifiers() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$comment généré $end\n$scope module dépôt $end\n$var wire 1 é données $end\n\
+               $var wire 2 ∆ état [1:0] $end\n$upscope $end\n$enddefinitions $end\n\
+               #0\n1é\nb10 ∆\n#5\n0é\n";
+    for chunk_size in [3, 5, 4096] {
+        let mut parser = VcdParser::with_chunk_size(chunk_size, vcd.as_bytes());
+        parser.set_utf8(true);
+        let header = parser.load_header()?;
+        assert_eq!(header.variables[0].name, "données");
+        assert_eq!(header.variables[1].scope[0].name, "dépôt");
+        assert_eq!(
+            parser.changes_of("é")?,
+            [(0, "1".to_string()), (5, "0".to_string())]
+        );
+    }
+
+    // Invalid UTF-8 is still rejected
+    let mut data = b"$var wire 1 ! a $end\n$enddefinitions $end\n#0\n1!\n".to_vec();
+    data.insert(3, 0xff);
+    let mut parser = VcdParser::with_chunk_size(4096, data.as_slice());
+    parser.set_utf8(true);
+    assert!(matches!(parser.load_header(), Err(VcdError::Utf8Error)));
+    Ok(())
+}
+
+#[test]
+fn parse_lossy_input() -> Result<(), Box<dyn std::error::Error>> {
+    // Latin-1 text in a comment and in a name, UTF-8 in the date
+    let vcd: &[u8] = b"$date 3 f\xc3\xa9vrier $end\n$comment caf\xe9 $end\n\
+                       $var wire 1 ! caf\xe9 $end\n$enddefinitions $end\n#0\n1!\n#5\n0!\n";
+    for (utf8, chunk_size) in [(false, 3), (false, 4096), (true, 3), (true, 4096)] {
+        let mut parser = VcdParser::with_chunk_size(chunk_size, vcd);
+        parser.set_utf8(utf8);
+        assert!(matches!(parser.load_header(), Err(VcdError::Utf8Error)));
+
+        let mut parser = VcdParser::with_chunk_size(chunk_size, vcd);
+        parser.set_utf8(utf8);
+        parser.set_lossy(true);
+        let header = parser.load_header()?;
+        assert_eq!(header.variables[0].name, "caf?");
+        let date = header.metadata.date.as_deref().unwrap_or_default();
+        assert_eq!(date, if utf8 { "3 février" } else { "3 f??vrier" });
+        assert_eq!(
+            parser.changes_of("!")?,
+            [(0, "1".to_string()), (5, "0".to_string())]
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn parse_truncated_header() {
+    let header = "$date today $end\n$scope module top $end\n$var wire 1 ! a $end\n\
+                  $scope module cpu $end\n$var wire 8 \" pc $end\n";
+    let n = header.len() as u64;
+    let cases = [
+        (
+            format!("{}$var wi", header),
+            Some("var"),
+            &["top", "cpu"][..],
+            n,
+        ),
+        (header.to_string(), None, &["top", "cpu"][..], n),
+        (
+            format!("{}$upscope $end\n$upscope", header),
+            Some("upscope"),
+            &["top"][..],
+            n + 14,
+        ),
+    ];
+    for (vcd, command, scopes, position) in &cases {
+        for chunk_size in [4, 4096] {
+            let mut parser = VcdParser::with_chunk_size(chunk_size, vcd.as_bytes());
+            match parser.load_header() {
+                Err(VcdError::TruncatedHeader(t)) => {
+                    assert_eq!(t.command.as_deref(), *command);
+                    assert_eq!(t.open_scopes, *scopes);
+                    assert_eq!(t.position, *position);
+                    assert_eq!(t.variables, 2);
+                }
+                r => panic!("unexpected result {:?}", r.map(|_| ())),
+            };
+            let partial = parser.partial_header();
+            assert_eq!(partial.metadata.date.as_deref(), Some("today"));
+            assert_eq!(partial.variables[1].name, "pc");
+            assert!(parser.header().is_none());
+        }
+    }
+}
+
+/// Input counting the bytes read from it
+struct CountingReader<R> {
+    inner: R,
+    read: usize,
+}
+
+impl<R: std::io::Read> std::io::Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n;
+        Ok(n)
+    }
+}
+
+#[test]
+fn parse_header_only() -> Result<(), Box<dyn std::error::Error>> {
+    let header = "$scope module top $end\n$var wire 1 ! a $end\n$upscope $end\n\
+                  $enddefinitions $end\n";
+    // A body that can not be parsed, and is much larger than a chunk
+    let vcd = format!("{}#0\n{}", header, "#zz\n".repeat(100_000));
+    let mut input = CountingReader {
+        inner: vcd.as_bytes(),
+        read: 0,
+    };
+    let mut parser = VcdParser::with_chunk_size(4096, &mut input);
+    assert_eq!(parser.parse_header_only()?.variables[0].name, "a");
+    assert!(parser.done());
+    let mut commands = 0;
+    parser.process_vcd_commands(|_| -> Result<bool, VcdError> {
+        commands += 1;
+        Ok(false)
+    })?;
+    assert_eq!(commands, 0);
+    assert!(parser.header().is_some());
+    assert!(
+        input.read <= header.len() + 4096,
+        "{} bytes read",
+        input.read
+    );
+    Ok(())
+}
+
+#[test]
+fn parse_missing_enddefinitions() -> Result<(), Box<dyn std::error::Error>> {
+    let header = "\n$timescale 1ns $end\n$scope module top $end\n$var wire 1 ! a $end\n\
+                  $var wire 4 \" v $end\n$upscope $end\n";
+    let bodies = [
+        "#0\n1!\nb0101 \"\n#10\n0!\n",
+        "$dumpvars\n1!\nb0101 \"\n$end\n#10\n0!\n",
+        "1!\n#10\n0!\n",
+        "",
+        "  \n",
+    ];
+    for body in bodies {
+        for chunk_size in [4, 4096] {
+            let vcd = format!("{}{}", header, body);
+            let mut parser = VcdParser::with_chunk_size(chunk_size, vcd.as_bytes());
+            assert!(parser.load_header().is_err());
+
+            let mut parser = VcdParser::with_chunk_size(chunk_size, vcd.as_bytes());
+            parser.set_allow_missing_enddefinitions(true);
+            let header = parser.load_header()?;
+            assert_eq!(header.variables.len(), 2);
+            assert!(header.timescale.is_some());
+            let mut commands = vec![];
+            parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+                commands.push(format!("{:?}", cmd));
+                Ok(false)
+            })?;
+            let changes = body.matches('!').count() + body.matches('"').count();
+            let found = commands
+                .iter()
+                .filter(|c| c.contains("ValueChange"))
+                .count();
+            assert_eq!(found, changes);
+        }
+    }
+
+    // The body is not checked as header text, even in the chunk where the header ends
+    for body in ["#0\n1!\nsxé !\n", "$dumpvars\nsé !\n$end\n"] {
+        for chunk_size in [4, 4096] {
+            let vcd = format!("{}{}", header, body);
+            let mut parser = VcdParser::with_chunk_size(chunk_size, vcd.as_bytes());
+            parser.set_allow_missing_enddefinitions(true);
+            parser.load_header()?;
+            let mut values = vec![];
+            parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+                if let VcdCommand::ValueChange(VcdChange {
+                    value: VcdValue::String(s),
+                    ..
+                }) = cmd
+                {
+                    values.push(s.to_string());
+                }
+                Ok(false)
+            })?;
+            assert_eq!(values.len(), 1);
+            assert!(values[0].ends_with('é'));
+        }
+    }
+
+    // A header cut in the middle of a command is still truncated
+    let vcd = format!("{}$var wi", header);
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.set_allow_missing_enddefinitions(true);
+    assert!(matches!(
+        parser.load_header(),
+        Err(VcdError::TruncatedHeader(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn parse_truncated_body() -> Result<(), Box<dyn std::error::Error>> {
+    let header = "$var wire 1 ! a $end\n$var wire 4 \" v $end\n$enddefinitions $end\n";
+    let body = "#0\n$dumpvars\n1!\nb0101 \"\n$end\n#10\n0!\nb1x \"\n";
+    for end in 0..=body.len() {
+        for chunk_size in [4, 4096] {
+            let vcd = format!("{}{}", header, &body[..end]);
+            let mut parser = VcdParser::with_chunk_size(chunk_size, vcd.as_bytes());
+            parser.set_allow_truncation(true);
+            parser.load_header()?;
+            let mut commands = 0;
+            parser.process_vcd_commands(|_| -> Result<bool, VcdError> {
+                commands += 1;
+                Ok(false)
+            })?;
+            assert!(parser.done());
+            let t = parser.truncation();
+            let discarded = t.map_or(0, |t| t.discarded);
+            assert_eq!(
+                t.map_or(vcd.len() as u64, |t| t.position + discarded),
+                vcd.len() as u64
+            );
+            assert!(discarded <= 11, "{} bytes discarded", discarded);
+        }
+    }
+
+    let vcd = format!("{}#0\n1!\n#10\nb01", header);
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.load_header()?;
+    assert!(matches!(
+        parser.process_vcd_commands(|_| -> Result<bool, VcdError> { Ok(false) }),
+        Err(VcdError::MissingData)
+    ));
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.set_allow_truncation(true);
+    parser.load_header()?;
+    let mut times = vec![];
+    parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+        if let VcdCommand::SetCycle(t) = cmd {
+            times.push(t);
+        }
+        Ok(false)
+    })?;
+    assert_eq!(times, [0, 10]);
+    let t = parser.truncation().unwrap();
+    assert_eq!((t.position, t.discarded), (header.len() as u64 + 10, 3));
+    assert_eq!(
+        t.to_string(),
+        "body truncated at byte 73 (3 bytes discarded)"
+    );
+    Ok(())
+}
+
+fn strict_violation(vcd: &str) -> Option<HeaderViolation> {
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.set_strict(true);
+    match parser.load_header() {
+        Ok(_) => None,
+        Err(VcdError::InvalidHeader(v)) => Some(v),
+        Err(e) => panic!("unexpected error {:?}", e),
+    }
+}
+
+#[test]
+fn parse_strict_header() -> Result<(), Box<dyn std::error::Error>> {
+    assert_eq!(
+        strict_violation(
+            "$scope module a $end\n$var wire 1 ! x $end\n$upscope $end\n$enddefinitions $end\n"
+        ),
+        None
+    );
+    assert_eq!(
+        strict_violation("$var wire 0 ! x $end\n$enddefinitions $end\n"),
+        Some(HeaderViolation::NonPositiveWidth {
+            name: "x".to_string(),
+            width: 0
+        })
+    );
+    assert_eq!(
+        strict_violation("$var wire 1 !\x7f x $end\n$enddefinitions $end\n"),
+        Some(HeaderViolation::InvalidId {
+            id: "!\x7f".to_string(),
+            name: "x".to_string()
+        })
+    );
+    assert_eq!(
+        strict_violation("$var wire 1 ! x $end\n$upscope $end\n$enddefinitions $end\n"),
+        Some(HeaderViolation::UnbalancedUpscope)
+    );
+    let v = strict_violation(
+        "$scope module a $end\n$scope module b $end\n$upscope $end\n$enddefinitions $end\n",
+    );
+    assert_eq!(
+        v,
+        Some(HeaderViolation::UnclosedScopes(vec!["a".to_string()]))
+    );
+    assert!(v.unwrap().to_string().contains("not closed"));
+
+    // Accepted in the default (lenient) mode
+    let vcd = "$var wire 1 ! x $end\n$upscope $end\n$enddefinitions $end\n";
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.load_header()?;
+    Ok(())
+}
+
+#[test]
+fn parse_vendor_directives() -> Result<(), Box<dyn std::error::Error>> {
+    use std::sync::{Arc, Mutex};
+
+    let vcd = "$timezero 0 $end\n$attrbegin misc 07 top.v 12 $end\n$var wire 1 ! a $end\n\
+               $foo 1 2 $end\n$enddefinitions $end\n#0\n$dumpvars\n0!\n$end\n\
+               $attrbegin misc 03 x $end\n#10\n$bar #5 $end\n1!\n$vcdclose #30 $end\n";
+    let unknown = Arc::new(Mutex::new(Vec::new()));
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    let u = unknown.clone();
+    parser.set_directive_hook(Box::new(move |keyword, text| {
+        u.lock().unwrap().push(format!("{}: {}", keyword, text))
+    }));
+    assert_eq!(parser.load_header()?.variables.len(), 1);
+    assert_eq!(parser.close_time(), None);
+    let mut commands = Vec::new();
+    while !parser.done() {
+        parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+            commands.push(format!("{:?}", cmd));
+            Ok(false)
+        })?;
+    }
+    let cycles: Vec<_> = commands
+        .iter()
+        .filter(|c| c.starts_with("SetCycle"))
+        .collect();
+    assert_eq!(cycles, ["SetCycle(0)", "SetCycle(10)"]);
+    assert_eq!(commands.len(), 9);
+    assert_eq!(parser.close_time(), Some(30));
+    assert_eq!(*unknown.lock().unwrap(), ["foo: 1 2", "bar: #5"]);
+    Ok(())
+}
+
+/// Timestamps and changes (`#t` and `<value><id>`) given by a parser with a policy
+#[test]
+fn parser_options() {
+    let mut parser = VcdParser::with_chunk_size(16, "".as_bytes());
+    assert_eq!(parser.options(), ParserOptions::default());
+    let options = ParserOptions {
+        utf8: true,
+        lossy: true,
+        lazy_input: true,
+        strict: true,
+        allow_missing_enddefinitions: true,
+        allow_truncation: true,
+        timestamp_policy: TimestampPolicy::Reorder(3),
+        change_budget: Some(10),
+        memory_limit: Some(1 << 20),
+    };
+    parser.apply_options(&options);
+    assert_eq!(parser.options(), options);
+    parser.set_directive_hook(Box::new(|_, _| {}));
+    assert!(parser.take_directive_hook().is_some());
+    assert!(parser.take_directive_hook().is_none());
+}
+
+fn body_with_policy(vcd: &str, policy: TimestampPolicy) -> Result<Vec<String>, VcdError> {
+    let mut parser = VcdParser::with_chunk_size(16, vcd.as_bytes());
+    parser.set_timestamp_policy(policy);
+    parser.load_header()?;
+    let mut body = Vec::new();
+    while !parser.done() {
+        parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+            match cmd {
+                VcdCommand::SetCycle(t) => body.push(format!("#{}", t)),
+                VcdCommand::ValueChange(c) => match c.value {
+                    VcdValue::Bit(b) => body.push(format!("{}{}", b, c.var_id)),
+                    v => body.push(format!("{:?}{}", v, c.var_id)),
+                },
+                _ => {}
+            }
+            Ok(false)
+        })?;
+    }
+    Ok(body)
+}
+
+#[test]
+fn parse_non_monotonic_time() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end\n$enddefinitions $end\n\
+               #0\n0!\n#10\n1!\n#5\n0!\n#20\n1!\n#10\nx!\n";
+    match body_with_policy(vcd, TimestampPolicy::Error) {
+        Err(VcdError::NonMonotonicTime {
+            time: 5,
+            previous: 10,
+            position: 58,
+        }) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+    let keep = body_with_policy(vcd, TimestampPolicy::Keep)?;
+    assert_eq!(
+        keep,
+        ["#0", "0!", "#10", "1!", "#5", "0!", "#20", "1!", "#10", "x!"]
+    );
+    let clamp = body_with_policy(vcd, TimestampPolicy::Clamp)?;
+    assert_eq!(clamp, ["#0", "0!", "#10", "1!", "0!", "#20", "1!", "x!"]);
+
+    // The changes of cycles with the same time are merged
+    let reorder = body_with_policy(vcd, TimestampPolicy::Reorder(4))?;
+    assert_eq!(
+        reorder,
+        ["#0", "0!", "#5", "0!", "#10", "1!", "x!", "#20", "1!"]
+    );
+    // Without a window, `#5` arrives after cycle 10 was given out
+    assert!(matches!(
+        body_with_policy(vcd, TimestampPolicy::Reorder(0)),
+        Err(VcdError::NonMonotonicTime {
+            time: 5,
+            previous: 10,
+            ..
+        })
+    ));
+    Ok(())
+}
+
+#[test]
+fn parse_metadata() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$date\n   Mon Oct 12 10:00:00 2026\n$end\n$version Icarus Verilog $end\n\
+               $comment first $end\n$scope module top $end\n$comment in top $end\n\
+               $var wire 1 ! a $end\n$upscope $end\n$comment\n  second\n$end\n\
+               $enddefinitions $end\n#0\n";
+    let mut parser = VcdParser::with_chunk_size(16, vcd.as_bytes());
+    let metadata = &parser.load_header()?.metadata;
+    assert_eq!(metadata.date.as_deref(), Some("Mon Oct 12 10:00:00 2026"));
+    assert_eq!(metadata.version.as_deref(), Some("Icarus Verilog"));
+    assert_eq!(metadata.comments, ["first", "second"]);
+    Ok(())
+}
+
+#[test]
+fn parse_dump_sections() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end\n$enddefinitions $end\n#0\n$dumpvars\n0!\n$end\n\
+               #10\n$dumpoff\nx!\n$end\n$end\n#20\n$dumpon 1! $end\n$dumpall 1! $end\n";
+    let mut parser = VcdParser::with_chunk_size(8, vcd.as_bytes());
+    parser.load_header()?;
+    let mut sections = Vec::new();
+    while !parser.done() {
+        parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+            match cmd {
+                VcdCommand::BeginDump(d) => sections.push(format!("${}", d.keyword())),
+                VcdCommand::EndDump(d) => sections.push(format!("$end ({})", d.keyword())),
+                VcdCommand::VcdEnd => sections.push("$end".to_string()),
+                _ => {}
+            }
+            Ok(false)
+        })?;
+    }
+    assert_eq!(
+        sections,
+        [
+            "$dumpvars",
+            "$end (dumpvars)",
+            "$dumpoff",
+            "$end (dumpoff)",
+            "$end",
+            "$dumpon",
+            "$end (dumpon)",
+            "$dumpall",
+            "$end (dumpall)"
+        ]
+    );
+    assert_eq!(DumpSection::from_keyword("dumpoff"), Some(DumpSection::Off));
+    assert_eq!(DumpSection::from_keyword("comment"), None);
+    Ok(())
+}
+
+#[test]
+fn parse_change_budget() -> Result<(), VcdError> {
+    let vcd = "$var wire 1 ! a $end\n$var wire 1 \" b $end\n$enddefinitions $end\n\
+               #0\n0!\n0\"\n#10\n1!\n#20\n0!\n1\"\n#30\n1!\n";
+    let mut parser = VcdParser::with_chunk_size(16, vcd.as_bytes());
+    parser.load_header()?;
+    let mut body = Vec::new();
+    let count = parser.process_first_n_changes(3, |cmd| -> Result<bool, VcdError> {
+        if let VcdCommand::ValueChange(c) = cmd {
+            body.push(format!("{:?}{}", c.value, c.var_id));
+        }
+        Ok(false)
+    })?;
+    assert_eq!((count, body.len(), parser.change_count()), (3, 3, 3));
+    assert!(!parser.done());
+    // The rest of the input, up to the end
+    let count = parser.process_first_n_changes(10, |_| -> Result<bool, VcdError> { Ok(false) })?;
+    assert_eq!((count, parser.change_count()), (3, 6));
+    assert!(parser.done());
+
+    let mut parser = VcdParser::with_chunk_size(16, vcd.as_bytes());
+    parser.set_change_budget(Some(2));
+    parser.load_header()?;
+    let mut changes = 0;
+    while !parser.done() {
+        parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+            changes += matches!(cmd, VcdCommand::ValueChange(_)) as u64;
+            Ok(false)
+        })?;
+    }
+    assert_eq!(changes, 2);
+    assert!(parser.budget_exhausted());
+    Ok(())
+}
+
+#[test]
+fn parse_extended_vcd() -> Result<(), VcdError> {
+    let vcd = "$scope module top $end\n$var port [3:0] <0 addr $end\n$var port 1 <1 clk $end\n\
+               $upscope $end\n$enddefinitions $end\n#0\n$dumpports\npDDUZ 6 6 <0\npN 6 6 <1\n\
+               $end\n#100\npLLHT 0 6 <0\np1 7 7 <1\n$dumpportsoff\npXXXX 6 6 <0\n$end\n\
+               $vcdclose #200 $end\n";
+    let mut parser = VcdParser::with_chunk_size(16, vcd.as_bytes());
+    let header = parser.load_header()?;
+    assert_eq!(header.variables[0].kind, VariableKind::VcdPort);
+    assert_eq!(
+        (header.variables[0].width, &header.variables[0].range),
+        (4, &Some(Range::Range((3, 0))))
+    );
+    let mut commands = Vec::new();
+    while !parser.done() {
+        parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+            commands.push(match cmd {
+                VcdCommand::ValueChange(c) => match c.value {
+                    VcdValue::Port(p) => format!("{:?} {}", p, c.var_id),
+                    v => panic!("unexpected value: {:?}", v),
+                },
+                cmd => format!("{:?}", cmd),
+            });
+            Ok(false)
+        })?;
+    }
+    let port = |states, strength0, strength1, id| {
+        let p = PortValue {
+            states,
+            strength0,
+            strength1,
+        };
+        format!("{:?} {}", p, id)
+    };
+    assert_eq!(
+        commands,
+        [
+            "SetCycle(0)".to_string(),
+            "BeginDump(Ports)".to_string(),
+            port("DDUZ", 6, 6, "<0"),
+            port("N", 6, 6, "<1"),
+            "EndDump(Ports)".to_string(),
+            "SetCycle(100)".to_string(),
+            port("LLHT", 0, 6, "<0"),
+            port("1", 7, 7, "<1"),
+            "BeginDump(PortsOff)".to_string(),
+            port("XXXX", 6, 6, "<0"),
+            "EndDump(PortsOff)".to_string(),
+            "VcdClose(200)".to_string(),
+        ]
+    );
+    assert_eq!(parser.close_time(), Some(200));
+    assert_eq!(DumpSection::PortsOff.vcd_equivalent(), DumpSection::Off);
+    Ok(())
+}
+
+#[test]
+fn parse_string_values() -> Result<(), VcdError> {
+    let vcd = "$var string 1 ! msg $end\n$var wire 1 \" clk $end\n$enddefinitions $end\n\
+               #0\nshello !\n1\"\n#10\ns !\n";
+    let mut parser = VcdParser::with_chunk_size(8, vcd.as_bytes());
+    let header = parser.load_header()?;
+    assert_eq!(header.variables[0].kind, VariableKind::GenString);
+    let mut values = Vec::new();
+    while !parser.done() {
+        parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+            if let VcdCommand::ValueChange(c) = cmd {
+                values.push(format!("{:?} {}", c.value, c.var_id));
+            }
+            Ok(false)
+        })?;
+    }
+    assert_eq!(
+        values,
+        ["String(\"hello\") !", "Bit('1') \"", "String(\"\") !"]
+    );
+    Ok(())
+}
+
+#[test]
+fn parse_event_values() -> Result<(), VcdError> {
+    let vcd = "$var event 1 ! ev $end\n$var wire 1 \" clk $end\n$enddefinitions $end\n\
+               #0\n$dumpvars\nx!\n1\"\n$end\n#10\n1!\n0\"\n";
+    let expected = ["#0", "x!", "1\"", "#10", "Event!", "0\""];
+    assert_eq!(body_with_policy(vcd, TimestampPolicy::Error)?, expected);
+    assert_eq!(
+        body_with_policy(vcd, TimestampPolicy::Reorder(2))?,
+        expected
+    );
+    Ok(())
+}
+
+#[test]
+fn decode_sections() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end\n$var wire 4 \" v $end\n$var real 1 # r $end\n\
+               $enddefinitions $end\n1!\n#0\nb0101 \"\nr1.5 #\n#10\n#20\n0!\nbx1 \"\n";
+    for chunk_size in [4, 4096] {
+        let mut parser = VcdParser::with_chunk_size(chunk_size, vcd.as_bytes());
+        parser.load_header()?;
+        let mut batch = ChangeBatch::default();
+        let mut sections = vec![];
+        while parser.decode_section(&mut batch)? {
+            let changes: Vec<_> = batch.iter().map(|c| format!("{:?}", c)).collect();
+            sections.push((batch.time(), changes));
+        }
+        assert!(batch.is_empty());
+        let change = |var_id, value| format!("{:?}", VcdChange { var_id, value });
+        assert_eq!(
+            sections,
+            [
+                (None, vec![change("!", VcdValue::Bit('1'))]),
+                (
+                    Some(0),
+                    vec![
+                        change("\"", VcdValue::Vector("0101")),
+                        change("#", VcdValue::Real("1.5"))
+                    ]
+                ),
+                (Some(10), vec![]),
+                (
+                    Some(20),
+                    vec![
+                        change("!", VcdValue::Bit('0')),
+                        change("\"", VcdValue::Vector("x1"))
+                    ]
+                ),
+            ]
+        );
+    }
+
+    let mut parser =
+        VcdParser::with_chunk_size(4096, File::open(vcd_asset("good/verilator_riscv.vcd"))?);
+    parser.load_header()?;
+    let mut batch = ChangeBatch::default();
+    let mut decoded = vec![];
+    while parser.decode_section(&mut batch)? {
+        decoded.extend(batch.iter().map(|c| format!("{:?}", c)));
+    }
+    assert_eq!(decoded.len() as u64, parser.change_count());
+    let mut parser =
+        VcdParser::with_chunk_size(4096, File::open(vcd_asset("good/verilator_riscv.vcd"))?);
+    parser.load_header()?;
+    let mut expected = vec![];
+    parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+        if let VcdCommand::ValueChange(c) = cmd {
+            expected.push(format!("{:?}", c));
+        }
+        Ok(false)
+    })?;
+    assert_eq!(decoded, expected);
+    Ok(())
+}
+
+#[test]
+fn decode_sections_mixed() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end\n$enddefinitions $end\n#0\n1!\n#10\n0!\n#20\n1!\n";
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.load_header()?;
+    let mut batch = ChangeBatch::default();
+    assert!(parser.decode_section(&mut batch)?);
+    assert_eq!((batch.time(), batch.len()), (Some(0), 1));
+    // The timestamp read ahead opens the rest of the body
+    let mut rest = vec![];
+    parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+        rest.push(format!("{:?}", cmd));
+        Ok(rest.len() == 2)
+    })?;
+    assert_eq!(rest.len(), 2);
+    assert_eq!(rest[0], "SetCycle(10)");
+    assert!(rest[1].contains("Bit('0')"));
+    assert!(parser.decode_section(&mut batch)?);
+    assert_eq!((batch.time(), batch.len()), (Some(20), 1));
+    assert!(parser.done());
+
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.load_header()?;
+    parser.decode_section(&mut batch)?;
+    let commands = parser.commands().collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(commands.len(), 4);
+    assert_eq!(format!("{:?}", commands[0].command()), "SetCycle(10)");
+    Ok(())
+}
+
+#[test]
+fn decode_sections_spacing() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end\n$var wire 4 \" b $end\n$enddefinitions $end\n\
+               #0\n1 !\nb01\"\nb 1x \"\n0!\tb0101\t\"\r\nz!";
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.load_header()?;
+    let mut batch = ChangeBatch::default();
+    assert!(parser.decode_section(&mut batch)?);
+    let decoded: Vec<String> = batch.iter().map(|c| format!("{:?}", c)).collect();
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.load_header()?;
+    let mut expected = vec![];
+    parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+        if let VcdCommand::ValueChange(c) = cmd {
+            expected.push(format!("{:?}", c));
+        }
+        Ok(false)
+    })?;
+    assert_eq!(expected.len(), 6);
+    assert_eq!(decoded, expected);
+    Ok(())
+}
+
+#[test]
+fn iterate_commands() -> Result<(), Box<dyn std::error::Error>> {
+    let path = vcd_asset("good/verilator_riscv.vcd");
+    let mut parser = VcdParser::with_chunk_size(4096, File::open(&path)?);
+    parser.load_header()?;
+    let mut expected = vec![];
+    parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+        expected.push(format!("{:?}", cmd));
+        Ok(false)
+    })?;
+    let mut parser = VcdParser::with_chunk_size(4096, File::open(&path)?);
+    parser.load_header()?;
+    let mut commands = vec![];
+    for cmd in parser.commands() {
+        commands.push(format!("{:?}", cmd?.command()));
+    }
+    assert!(expected.len() > 1024);
+    assert_eq!(commands, expected);
+
+    // Commands not taken from the iterator are left to the parser
+    let mut parser = VcdParser::with_chunk_size(4096, File::open(&path)?);
+    parser.load_header()?;
+    let mut commands = vec![];
+    for cmd in parser.commands().take(3) {
+        commands.push(format!("{:?}", cmd?.command()));
+    }
+    parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+        commands.push(format!("{:?}", cmd));
+        Ok(false)
+    })?;
+    assert_eq!(commands, expected);
+
+    let vcd = "$var wire 1 ! a $end\n$enddefinitions $end\n#0\n1!\n#10\n0!\n#20\n1!\n";
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.load_header()?;
+    let times: Vec<_> = parser
+        .commands()
+        .filter_map(|cmd| match cmd {
+            Ok(OwnedVcdCommand::SetCycle(t)) => Some(t),
+            _ => None,
+        })
+        .take_while(|&t| t < 20)
+        .collect();
+    assert_eq!(times, [0, 10]);
+
+    let vcd = "$var wire 1 ! a $end\n$enddefinitions $end\n#0\n1!\n#zz\n0!\n";
+    let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+    parser.load_header()?;
+    let mut commands = parser.commands();
+    assert_eq!(
+        commands.next().transpose()?,
+        Some(OwnedVcdCommand::SetCycle(0))
+    );
+    assert_eq!(
+        commands.next().transpose()?,
+        Some(OwnedVcdCommand::Bit("!".into(), '1'))
+    );
+    assert!(matches!(commands.next(), Some(Err(VcdError::Syntax(_)))));
+    assert!(commands.next().is_none());
+    Ok(())
+}
+
+#[test]
+fn send_owned_commands() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = "$var wire 1 ! a $end\n$var wire 24 \" v $end\n$var real 1 # r $end\n\
+               $enddefinitions $end\n#0\n1!\nb010101010101010101010101 \"\n\
+               #20\nr2.5 #\n#10\n0!\n#30\nb1 \"\n";
+    for policy in [TimestampPolicy::Keep, TimestampPolicy::Reorder(2)] {
+        let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+        parser.set_timestamp_policy(policy);
+        parser.load_header()?;
+        let mut expected = vec![];
+        parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+            expected.push(cmd.to_owned());
+            Ok(false)
+        })?;
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let worker = std::thread::spawn(move || receiver.iter().collect::<Vec<_>>());
+        let mut parser = VcdParser::with_chunk_size(4096, vcd.as_bytes());
+        parser.set_timestamp_policy(policy);
+        parser.load_header()?;
+        parser.process_owned_commands(|cmd| -> Result<bool, VcdError> {
+            sender.send(cmd).map_err(|_| VcdError::MissingData)?;
+            Ok(false)
+        })?;
+        drop(sender);
+        let received = worker.join().expect("worker panicked");
+        assert_eq!(received, expected);
+        assert!(received.contains(&OwnedVcdCommand::Vector(
+            "\"".into(),
+            "010101010101010101010101".into()
+        )));
+    }
+    Ok(())
+}
+
+#[test]
+fn position_at_end_of_input() -> Result<(), Box<dyn std::error::Error>> {
+    // No line feed after the last command: a separator is added to the buffer to end it,
+    // which is not part of the input
+    let vcd = "$var wire 1 ! a $end\n$enddefinitions $end\n#0\n1!\n#10\n0!\n1";
+    // Some chunk sizes leave the buffer full at the end of the input
+    for chunk_size in 16..64 {
+        let mut parser = VcdParser::with_chunk_size(chunk_size, vcd.as_bytes());
+        parser.set_allow_truncation(true);
+        parser.load_header()?;
+        let mut changes = vec![];
+        while !parser.done() {
+            parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+                if let VcdCommand::ValueChange(c) = cmd {
+                    changes.push(format!("{:?}", c.value));
+                }
+                Ok(false)
+            })?;
+            assert!(parser.position() <= vcd.len() as u64);
+        }
+        assert_eq!(changes, ["Bit('1')", "Bit('0')"]);
+        // Only the last byte, cut from its variable, is dropped
+        let truncation = parser.truncation().unwrap();
+        assert_eq!(truncation.position, vcd.len() as u64 - 1);
+        assert_eq!(truncation.discarded, 1);
+        assert_eq!(parser.position(), vcd.len() as u64);
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use std::collections::HashSet;
+use std::io::{Cursor, ErrorKind};
+
+use wavetk::simulation::StateSimulation;
+use wavetk::types::{ScopeKind, VariableKind};
+use wavetk::vcd::VcdValue;
+use wavetk::VcdWriter;
+
+#[test]
+fn writer_scopes_and_ids() -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = VcdWriter::new(Vec::new());
+    let mut ids = Vec::new();
+    {
+        let mut top = writer.scope(ScopeKind::VcdModule, "top")?;
+        ids.push(top.add_var(VariableKind::VcdWire, "clk", 1, None)?);
+        {
+            let mut cpu = top.scope(ScopeKind::VcdModule, "cpu")?;
+            for i in 0..200 {
+                ids.push(cpu.add_var(VariableKind::VcdReg, &format!("r{}", i), 4, None)?);
+            }
+        }
+        ids.push(top.add_var(VariableKind::VcdWire, "rst", 1, None)?);
+    }
+    assert_eq!(&ids[..3], ["!", "\"", "#"]);
+    assert_eq!(ids[94], "!!");
+    assert!(ids.iter().all(|id| id.len() <= 2));
+    assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+
+    writer.enddefinitions()?;
+    writer.timestamp(0)?;
+    writer.change(&ids[0], &VcdValue::Bit('1'))?;
+    writer.change(&ids[1], &VcdValue::Vector("1x"))?;
+    writer.change_levels(&ids[201], &[0])?;
+    let out = writer.finish()?;
+
+    let mut sim = StateSimulation::from_reader(Cursor::new(out));
+    sim.load_header()?;
+    let paths: Vec<String> = sim
+        .variables()
+        .iter()
+        .map(|v| wavetk::hierarchy::variable_path(v))
+        .collect();
+    assert_eq!(paths.len(), 202);
+    assert_eq!(paths[0], "top.clk");
+    assert_eq!(paths[1], "top.cpu.r0");
+    assert_eq!(paths[201], "top.rst");
+    sim.allocate_state()?;
+    sim.next_cycle()?;
+    let state = sim.next_cycle()?.state;
+    assert_eq!(&state[..5], [1, 1, 1, 1, -4]);
+    Ok(())
+}
+
+#[test]
+fn writer_rejects_malformed_output() -> Result<(), Box<dyn std::error::Error>> {
+    let invalid =
+        |r: std::io::Result<()>| matches!(r, Err(e) if e.kind() == ErrorKind::InvalidInput);
+    let mut writer = VcdWriter::new(Vec::new());
+    let clk = writer.add_var(VariableKind::VcdWire, "clk", 1, None)?;
+    assert!(invalid(writer.change(&clk, &VcdValue::Bit('0'))));
+    {
+        let _open = writer.scope(ScopeKind::VcdModule, "top")?;
+    }
+    let scope = writer.scope(ScopeKind::VcdModule, "top")?;
+    std::mem::forget(scope);
+    assert!(invalid(writer.enddefinitions()));
+
+    let mut writer = VcdWriter::new(Vec::new());
+    let clk = writer.add_var(VariableKind::VcdWire, "clk", 1, None)?;
+    let bus = writer.add_var(VariableKind::VcdWire, "bus", 4, None)?;
+    let real = writer.add_var(VariableKind::VcdReal, "level", 64, None)?;
+    writer.enddefinitions()?;
+    assert!(invalid(
+        writer
+            .add_var(VariableKind::VcdWire, "late", 1, None)
+            .map(|_| ())
+    ));
+    writer.timestamp(10)?;
+    assert!(invalid(writer.timestamp(5)));
+    assert!(invalid(writer.change(&bus, &VcdValue::Bit('1'))));
+    assert!(invalid(writer.change(&bus, &VcdValue::Vector("10101"))));
+    assert!(invalid(writer.change(&clk, &VcdValue::Real("1.5"))));
+    assert!(invalid(writer.change(&real, &VcdValue::Vector("1"))));
+    assert!(invalid(writer.change("?", &VcdValue::Bit('1'))));
+    writer.change(&bus, &VcdValue::Vector("101"))?;
+    writer.change(&real, &VcdValue::Real("1.5"))?;
+    writer.finish()?;
+    Ok(())
+}
@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use wavetk::vcd::{VcdCommand, VcdError, VcdParser, VcdValue};
+use wavetk::wdb::{
+    convert_vcd, convert_vcd_file, ConversionCheckpoint, WaveformDb, WdbError, WdbWriter,
+    CONVERSION_CHECKPOINT_VERSION,
+};
+
+fn vcd_asset(rel_path: &str) -> PathBuf {
+    let mut path = PathBuf::from(file!());
+    path.pop();
+    path.pop();
+    path.pop();
+    path.push("assets/vcd");
+    path.push(rel_path);
+    path
+}
+
+fn tmp_file(name: &str) -> PathBuf {
+    let mut path = std::env::temp_dir();
+    path.push(format!("wavetk-{}-{}", std::process::id(), name));
+    path
+}
+
+fn owned(changes: Vec<(u64, Arc<str>)>) -> Vec<(u64, String)> {
+    changes
+        .into_iter()
+        .map(|(t, v)| (t, v.to_string()))
+        .collect()
+}
+
+/// Collects all the changes of a VCD file, grouped by id
+fn vcd_changes(path: &PathBuf) -> HashMap<String, Vec<(u64, String)>> {
+    let mut parser = VcdParser::with_chunk_size(4096, File::open(path).unwrap());
+    parser.load_header().unwrap();
+    let mut changes: HashMap<String, Vec<(u64, String)>> = HashMap::new();
+    let mut time = 0;
+    parser
+        .process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+            match cmd {
+                VcdCommand::SetCycle(c) => time = c,
+                VcdCommand::ValueChange(v) => {
+                    let value = match v.value {
+                        VcdValue::Bit(c) => c.to_string(),
+                        VcdValue::Vector(x) | VcdValue::Real(x) | VcdValue::String(x) => {
+                            x.to_string()
+                        }
+                        VcdValue::Port(p) => p.levels(),
+                        VcdValue::Event => "1".to_string(),
+                    };
+                    changes
+                        .entry(v.var_id.to_string())
+                        .or_default()
+                        .push((time, value));
+                }
+                _ => {}
+            }
+            Ok(false)
+        })
+        .unwrap();
+    changes
+}
+
+#[test]
+fn wdb_convert_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = vcd_asset("good/verilator_riscv.vcd");
+    let out = tmp_file("verilator_riscv.wdb");
+    let mut parser = VcdParser::with_chunk_size(4096, File::open(&vcd)?);
+    parser.load_header()?;
+    convert_vcd(&mut parser, File::create(&out)?)?;
+
+    let expected = vcd_changes(&vcd);
+    let mut db = WaveformDb::open(&out)?;
+    assert_eq!(db.variables().len(), 2102);
+    let end = db.end_time();
+    for (id, changes) in expected.iter() {
+        assert_eq!(&owned(db.changes(id, 0, end)?), changes);
+    }
+    assert!(matches!(
+        db.changes("not an id", 0, end),
+        Err(WdbError::UnknownSignal)
+    ));
+    std::fs::remove_file(&out)?;
+    Ok(())
+}
+
+#[test]
+fn wdb_windowed_reads() -> Result<(), Box<dyn std::error::Error>> {
+    let mut parser = VcdParser::with_chunk_size(4096, File::open(vcd_asset("good/ghdl_0.vcd"))?);
+    let variables = parser.load_header()?.variables.clone();
+    let out = tmp_file("windowed.wdb");
+    let mut writer = WdbWriter::with_block_size(2, File::create(&out)?, &variables)?;
+    for t in 0..10u64 {
+        writer.push_change(t * 10, "!", if t % 2 == 0 { b"0" } else { b"1" })?;
+    }
+    writer.push_change(95, "\"", b"x01z")?;
+    writer.finish()?;
+
+    let mut db = WaveformDb::open(&out)?;
+    assert_eq!(db.blocks("!")?.len(), 5);
+    assert_eq!(
+        owned(db.changes("!", 25, 50)?),
+        vec![
+            (20, "0".to_string()),
+            (30, "1".to_string()),
+            (40, "0".to_string()),
+            (50, "1".to_string())
+        ]
+    );
+    assert_eq!(db.value_at("!", 79)?.as_deref(), Some("1"));
+    assert_eq!(db.value_at("\"", 90)?, None);
+    assert_eq!(db.value_at("\"", 1000)?.as_deref(), Some("x01z"));
+    std::fs::remove_file(&out)?;
+    Ok(())
+}
+
+#[test]
+fn wdb_invalid_input() -> Result<(), Box<dyn std::error::Error>> {
+    let mut parser = VcdParser::with_chunk_size(4096, File::open(vcd_asset("good/ghdl_0.vcd"))?);
+    let variables = parser.load_header()?.variables.clone();
+    let e = WdbWriter::with_block_size(0, Vec::new(), &variables)
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    let mut writer = WdbWriter::with_block_size(2, Vec::new(), &variables)?;
+    writer.push_change(10, "!", b"0")?;
+    writer.push_change(20, "!", b"1")?;
+    // Before the last change of a flushed block, then of the pending one
+    let e = writer.push_change(15, "!", b"0").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    writer.push_change(30, "!", b"0")?;
+    let e = writer.push_change(25, "!", b"1").unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    // Other variables have their own times
+    writer.push_change(5, "\"", b"0101")?;
+    let data = writer.finish()?;
+
+    // Sizes of the footer and of the index beyond the file
+    let out = tmp_file("invalid.wdb");
+    let footer = data.len() - 24;
+    let index_offset = u64::from_le_bytes(data[footer..footer + 8].try_into()?) as usize;
+    let mut corrupted = data.clone();
+    corrupted[footer + 8..footer + 16].copy_from_slice(&u64::MAX.to_le_bytes());
+    std::fs::write(&out, &corrupted)?;
+    assert!(matches!(WaveformDb::open(&out), Err(WdbError::InvalidFile)));
+    let index = String::from_utf8(data[index_offset..footer].to_vec())?;
+    let patched = index.replacen("\"block_count\":", "\"block_count\":1000000000", 1);
+    let mut corrupted = data[..index_offset].to_vec();
+    corrupted.extend_from_slice(patched.as_bytes());
+    corrupted.extend_from_slice(&(index_offset as u64).to_le_bytes());
+    corrupted.extend_from_slice(&(patched.len() as u64).to_le_bytes());
+    corrupted.extend_from_slice(&data[footer + 16..]);
+    std::fs::write(&out, &corrupted)?;
+    assert!(matches!(WaveformDb::open(&out), Err(WdbError::InvalidFile)));
+
+    // Size of a block beyond the file, only checked once the table of `!` is read
+    let table = &index[index.find("\"table_offset\":").unwrap() + 15..];
+    let table: usize = table[..table.find(',').unwrap()].parse()?;
+    let mut corrupted = data.clone();
+    corrupted[table + 8..table + 16].copy_from_slice(&1000000000u64.to_le_bytes());
+    std::fs::write(&out, &corrupted)?;
+    let mut db = WaveformDb::open(&out)?;
+    assert!(matches!(
+        db.changes("!", 0, 100),
+        Err(WdbError::InvalidFile)
+    ));
+    assert_eq!(db.changes("\"", 0, 100)?.len(), 1);
+    std::fs::write(&out, &data)?;
+    assert_eq!(WaveformDb::open(&out)?.changes("!", 0, 100)?.len(), 3);
+
+    // Times of the changes beyond the last time of the block, or overflowing
+    let word = |w: usize| table + 8 * w..table + 8 * w + 8;
+    let mut corrupted = data.clone();
+    corrupted[word(3)].copy_from_slice(&15u64.to_le_bytes());
+    std::fs::write(&out, &corrupted)?;
+    assert!(matches!(
+        WaveformDb::open(&out)?.changes("!", 0, 100),
+        Err(WdbError::InvalidFile)
+    ));
+    let mut corrupted = data.clone();
+    corrupted[word(2)].copy_from_slice(&(u64::MAX - 5).to_le_bytes());
+    corrupted[word(3)].copy_from_slice(&u64::MAX.to_le_bytes());
+    std::fs::write(&out, &corrupted)?;
+    assert!(matches!(
+        WaveformDb::open(&out)?.changes("!", 0, u64::MAX),
+        Err(WdbError::InvalidFile)
+    ));
+
+    // Length of a raw value overflowing the block, in the same number of bytes
+    let mut writer = WdbWriter::new(Vec::new(), &variables)?;
+    writer.push_change(0, "!", b"xxxxxxxxxxxx")?;
+    let mut corrupted = writer.finish()?;
+    let value = corrupted
+        .windows(12)
+        .position(|w| w == b"xxxxxxxxxxxx")
+        .unwrap();
+    let length = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+    corrupted[value - 1..value + 9].copy_from_slice(&length);
+    std::fs::write(&out, &corrupted)?;
+    assert!(matches!(
+        WaveformDb::open(&out)?.changes("!", 0, 100),
+        Err(WdbError::InvalidFile)
+    ));
+    std::fs::remove_file(&out)?;
+    Ok(())
+}
+
+#[test]
+fn wdb_block_cache() -> Result<(), Box<dyn std::error::Error>> {
+    let mut parser = VcdParser::with_chunk_size(4096, File::open(vcd_asset("good/ghdl_0.vcd"))?);
+    let variables = parser.load_header()?.variables.clone();
+    let out = tmp_file("cache.wdb");
+    let mut writer = WdbWriter::with_block_size(2, File::create(&out)?, &variables)?;
+    for t in 0..10u64 {
+        writer.push_change(t * 10, "!", if t % 2 == 0 { b"0" } else { b"1" })?;
+    }
+    writer.finish()?;
+
+    let mut db = WaveformDb::open(&out)?;
+    let window = db.changes("!", 5, 50)?;
+    assert_eq!(db.changes("!", 5, 50)?, window);
+    let stats = db.cache_stats();
+    assert_eq!((stats.misses, stats.hits, stats.blocks), (3, 3, 3));
+
+    // A budget fitting about two blocks: the least recently used one is evicted
+    let block_size = stats.bytes / 3;
+    db.set_cache_budget(2 * block_size + block_size / 2);
+    assert_eq!(db.cache_stats().blocks, 2);
+    assert_eq!(db.cache_stats().evictions, 1);
+    assert_eq!(db.all_changes("!")?.len(), 10);
+    let stats = db.cache_stats();
+    assert!(stats.blocks == 2 && stats.bytes <= 2 * block_size + block_size / 2);
+
+    db.set_cache_budget(0);
+    assert_eq!(db.cache_stats().bytes, 0);
+    assert_eq!(db.changes("!", 5, 50)?, window);
+    assert_eq!(db.cache_stats().blocks, 0);
+    std::fs::remove_file(&out)?;
+    Ok(())
+}
+
+#[test]
+fn wdb_resume_conversion() -> Result<(), Box<dyn std::error::Error>> {
+    let vcd = vcd_asset("good/verilator_riscv.vcd");
+    let expected = vcd_changes(&vcd);
+    let check = |out: &PathBuf| -> Result<(), Box<dyn std::error::Error>> {
+        let mut db = WaveformDb::open(out)?;
+        let end = db.end_time();
+        for (id, changes) in expected.iter() {
+            assert_eq!(&owned(db.changes(id, 0, end)?), changes);
+        }
+        Ok(())
+    };
+
+    // Uninterrupted, with many intermediate checkpoints
+    let out = tmp_file("resume_full.wdb");
+    let checkpoint = tmp_file("resume_full.ckpt");
+    convert_vcd_file(&vcd, &out, &checkpoint, 50)?;
+    assert!(!checkpoint.exists());
+    check(&out)?;
+    std::fs::remove_file(&out)?;
+
+    // Interrupted after 20 timestamps, with garbage written after the checkpoint
+    let out = tmp_file("resume_partial.wdb");
+    let checkpoint = tmp_file("resume_partial.ckpt");
+    let mut parser = VcdParser::with_chunk_size(4096, File::open(&vcd)?);
+    let variables = parser.load_header()?.variables.clone();
+    let mut writer = WdbWriter::new(File::create(&out)?, &variables)?;
+    let (mut time, mut n) = (0, 0);
+    parser.process_vcd_commands(|cmd| -> Result<bool, VcdError> {
+        match cmd {
+            VcdCommand::SetCycle(c) => {
+                time = c;
+                n += 1;
+                return Ok(n == 20);
+            }
+            VcdCommand::ValueChange(v) => {
+                if let VcdValue::Bit(c) = v.value {
+                    writer.push_change(time, v.var_id, &[c as u8])?;
+                } else if let VcdValue::Vector(x) = v.value {
+                    writer.push_change(time, v.var_id, x.as_bytes())?;
+                }
+            }
+            _ => {}
+        }
+        Ok(false)
+    })?;
+    let state = writer.checkpoint()?;
+    let saved = ConversionCheckpoint {
+        version: CONVERSION_CHECKPOINT_VERSION,
+        header: parser.header().unwrap().clone(),
+        input_offset: parser.position(),
+        time,
+        writer: state,
+    };
+    serde_json::to_writer(File::create(&checkpoint)?, &saved)?;
+    let mut partial = std::fs::OpenOptions::new().append(true).open(&out)?;
+    partial.write_all(b"interrupted block")?;
+    drop(partial);
+
+    convert_vcd_file(&vcd, &out, &checkpoint, 1000)?;
+    assert!(!checkpoint.exists());
+    check(&out)?;
+    std::fs::remove_file(&out)?;
+    Ok(())
+}